@@ -0,0 +1,5 @@
+//! Bundles the builtins and host functions of `ninescript_compiler::abi`
+//! into `libninescript_runtime.a`, which `target::native` links into
+//! shared and static libraries.
+
+extern crate ninescript_compiler;
@@ -0,0 +1,662 @@
+//! The C ABI of the runtime: the builtins compiled code calls and the functions
+//! hosts of native artifacts call around `on_bar`. The JIT maps builtins to
+//! `symbol`, the `ninescript-runtime` static library exports all of it under
+//! the symbols `target` links against. `Executable` runs scripts compiled to
+//! code of this process through their exports.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    ptr,
+};
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+use std::{slice, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    declaration::ScriptMetadata,
+    drawings::DrawingKind,
+    error::{CodegenError, Diagnostic},
+    inputs::Input,
+    plots::Plot,
+    runtime::{Bar, BarState, Output, Runtime},
+    security::Request,
+    timeframe::Timeframe,
+};
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+use crate::{
+    error::InputError,
+    runtime::{RunResult, Series},
+    security::{self, Feeds},
+    types::Constant,
+};
+#[cfg(feature = "llvm")]
+use crate::{drawings, plots, runtime};
+
+/// Prefix of the symbols of the builtins, which compiled code names by their path.
+#[cfg(feature = "llvm")]
+pub const SYMBOL_PREFIX: &str = "ninescript.";
+
+/// Functions hosts call, which a library linking the runtime keeps.
+#[cfg(feature = "llvm")]
+pub const HOST_FUNCTIONS: [&str; 5] = ["ninescript_run_new", "ninescript_start_bar", "ninescript_end_bar", "ninescript_result", "ninescript_run_free"];
+
+/// Functions the loader of a WebAssembly module calls, see `wasm`.
+#[cfg(feature = "llvm")]
+pub const LOADER_FUNCTIONS: [&str; 10] = [
+    "ninescript_alloc",
+    "ninescript_free",
+    "ninescript_load",
+    "ninescript_run",
+    "ninescript_stream",
+    "ninescript_tick",
+    "ninescript_bar_close",
+    "ninescript_bars",
+    "ninescript_stream_result",
+    "ninescript_stream_free",
+];
+
+thread_local! {
+    /// State of the script running on this thread, builtins called by compiled code use it.
+    static RUNTIME: RefCell<Option<Runtime>> = const { RefCell::new(None) };
+    /// The first error of a builtin during the current bar, `State::bar` returns it.
+    static ERROR: RefCell<Option<CodegenError>> = const { RefCell::new(None) };
+}
+
+/// Records an error of a builtin, which cannot unwind through compiled code.
+fn fail(error: CodegenError) {
+    ERROR.with(|e| {
+        e.borrow_mut().get_or_insert(error);
+    });
+}
+
+fn with<T: Default>(f: impl FnOnce(&mut Runtime) -> T) -> T {
+    RUNTIME.with(|r| match r.borrow_mut().as_mut() {
+        Some(runtime) => f(runtime),
+        None => {
+            fail(CodegenError::Unsupported("builtins called outside of a run".to_string()));
+            T::default()
+        }
+    })
+}
+
+#[export_name = "ninescript.na"]
+extern "C" fn na(_: u32, x: f64) -> bool { Runtime::na(x) }
+#[export_name = "ninescript.nz"]
+extern "C" fn nz(_: u32, source: f64, replacement: f64) -> f64 { Runtime::nz(source, replacement) }
+#[export_name = "ninescript.math.abs"]
+extern "C" fn abs(_: u32, number: f64) -> f64 { number.abs() }
+#[export_name = "ninescript.math.max"]
+extern "C" fn max(_: u32, number0: f64, number1: f64) -> f64 { number0.max(number1) }
+#[export_name = "ninescript.math.min"]
+extern "C" fn min(_: u32, number0: f64, number1: f64) -> f64 { number0.min(number1) }
+#[export_name = "ninescript.math.sqrt"]
+extern "C" fn sqrt(_: u32, number: f64) -> f64 { number.sqrt() }
+#[export_name = "ninescript.color.new"]
+extern "C" fn color_new(_: u32, color: u32, transp: f64) -> u32 { Runtime::color_new(color, transp) }
+#[export_name = "ninescript.color.rgb"]
+extern "C" fn color_rgb(_: u32, red: f64, green: f64, blue: f64, transp: f64) -> u32 { Runtime::color_rgb(red, green, blue, transp) }
+#[export_name = "ninescript.color.r"]
+extern "C" fn color_r(_: u32, color: u32) -> f64 { Runtime::color_component(color, 0) }
+#[export_name = "ninescript.color.g"]
+extern "C" fn color_g(_: u32, color: u32) -> f64 { Runtime::color_component(color, 1) }
+#[export_name = "ninescript.color.b"]
+extern "C" fn color_b(_: u32, color: u32) -> f64 { Runtime::color_component(color, 2) }
+#[export_name = "ninescript.color.t"]
+extern "C" fn color_t(_: u32, color: u32) -> f64 { Runtime::color_component(color, 3) }
+#[export_name = "ninescript.color.from_gradient"]
+extern "C" fn from_gradient(_: u32, value: f64, bottom_value: f64, top_value: f64, bottom_color: u32, top_color: u32) -> u32 {
+    Runtime::color_from_gradient(value, bottom_value, top_value, bottom_color, top_color)
+}
+#[export_name = "ninescript.ta.sma"]
+extern "C" fn sma(site: u32, source: f64, length: i64) -> f64 { with(|r| r.sma(site, source, length)) }
+#[export_name = "ninescript.ta.ema"]
+extern "C" fn ema(site: u32, source: f64, length: i64) -> f64 { with(|r| r.ema(site, source, length)) }
+#[export_name = "ninescript.ta.rma"]
+extern "C" fn rma(site: u32, source: f64, length: i64) -> f64 { with(|r| r.rma(site, source, length)) }
+#[export_name = "ninescript.ta.wma"]
+extern "C" fn wma(site: u32, source: f64, length: i64) -> f64 { with(|r| r.wma(site, source, length)) }
+#[export_name = "ninescript.ta.rsi"]
+extern "C" fn rsi(site: u32, source: f64, length: i64) -> f64 { with(|r| r.rsi(site, source, length)) }
+#[export_name = "ninescript.ta.stdev"]
+extern "C" fn stdev(site: u32, source: f64, length: i64) -> f64 { with(|r| r.stdev(site, source, length)) }
+#[export_name = "ninescript.ta.atr"]
+extern "C" fn atr(site: u32, length: i64) -> f64 { with(|r| r.atr(site, length)) }
+#[export_name = "ninescript.ta.crossover"]
+extern "C" fn crossover(site: u32, source1: f64, source2: f64) -> bool { with(|r| r.crossover(site, source1, source2)) }
+#[export_name = "ninescript.ta.crossunder"]
+extern "C" fn crossunder(site: u32, source1: f64, source2: f64) -> bool { with(|r| r.crossunder(site, source1, source2)) }
+
+#[export_name = "ninescript.ta.macd"]
+unsafe extern "C" fn macd(site: u32, source: f64, fastlen: i64, slowlen: i64, siglen: i64, result: *mut [f64; 3]) {
+    *result = with(|r| r.macd(site, source, fastlen, slowlen, siglen));
+}
+
+#[export_name = "ninescript.ta.bb"]
+unsafe extern "C" fn bb(site: u32, series: f64, length: i64, mult: f64, result: *mut [f64; 3]) {
+    *result = with(|r| r.bb(site, series, length, mult));
+}
+
+#[export_name = "ninescript.ta.kc"]
+unsafe extern "C" fn kc(site: u32, series: f64, length: i64, mult: f64, result: *mut [f64; 3]) {
+    *result = with(|r| r.kc(site, series, length, mult));
+}
+
+#[export_name = "ninescript.ta.dmi"]
+unsafe extern "C" fn dmi(site: u32, di_length: i64, adx_smoothing: i64, result: *mut [f64; 3]) {
+    *result = with(|r| r.dmi(site, di_length, adx_smoothing));
+}
+
+#[export_name = "ninescript.strategy.entry"]
+unsafe extern "C" fn entry(_: u32, id: *const c_char, direction: i64, qty: f64) {
+    let id = string(id);
+    with(|r| r.entry(&id, direction, qty));
+}
+
+#[export_name = "ninescript.strategy.close"]
+unsafe extern "C" fn close(_: u32, id: *const c_char) {
+    let id = string(id);
+    with(|r| r.close(&id));
+}
+
+#[export_name = "ninescript.alert"]
+unsafe extern "C" fn alert(site: u32, message: *const c_char, freq: *const c_char) {
+    let (message, freq) = (string(message), string(freq));
+    with(|r| r.alert(site, &message, &freq));
+}
+
+#[export_name = "ninescript.alertcondition"]
+unsafe extern "C" fn alertcondition(_: u32, condition: bool, title: *const c_char, message: *const c_char) {
+    let (title, message) = (string(title), string(message));
+    with(|r| r.alertcondition(condition, &title, &message));
+}
+
+#[export_name = "ninescript.strategy.close_all"]
+extern "C" fn close_all(_: u32) { with(|r| r.close_all()) }
+#[export_name = "ninescript.strategy.position_size"]
+extern "C" fn position_size(_: u32) -> f64 { with(|r| r.position_size()) }
+
+fn is(f: fn(&Timeframe) -> bool) -> bool { with(|r| r.timeframe().is_some_and(f)) }
+#[export_name = "ninescript.timeframe.multiplier"]
+extern "C" fn multiplier(_: u32) -> i64 { with(|r| r.timeframe().map_or(0, |t| t.multiplier() as i64)) }
+#[export_name = "ninescript.timeframe.isticks"]
+extern "C" fn isticks(_: u32) -> bool { is(Timeframe::is_ticks) }
+#[export_name = "ninescript.timeframe.isseconds"]
+extern "C" fn isseconds(_: u32) -> bool { is(Timeframe::is_seconds) }
+#[export_name = "ninescript.timeframe.isminutes"]
+extern "C" fn isminutes(_: u32) -> bool { is(Timeframe::is_minutes) }
+#[export_name = "ninescript.timeframe.isintraday"]
+extern "C" fn isintraday(_: u32) -> bool { is(Timeframe::is_intraday) }
+#[export_name = "ninescript.timeframe.isdaily"]
+extern "C" fn isdaily(_: u32) -> bool { is(Timeframe::is_daily) }
+#[export_name = "ninescript.timeframe.isweekly"]
+extern "C" fn isweekly(_: u32) -> bool { is(Timeframe::is_weekly) }
+#[export_name = "ninescript.timeframe.ismonthly"]
+extern "C" fn ismonthly(_: u32) -> bool { is(Timeframe::is_monthly) }
+#[export_name = "ninescript.timeframe.isdwm"]
+extern "C" fn isdwm(_: u32) -> bool { is(Timeframe::is_dwm) }
+
+/// A string argument, empty for `na`.
+unsafe fn string(s: *const c_char) -> String {
+    if s.is_null() { String::new() } else { CStr::from_ptr(s).to_string_lossy().into_owned() }
+}
+
+#[export_name = "ninescript.timeframe.in_seconds"]
+unsafe extern "C" fn in_seconds(_: u32, timeframe: *const c_char) -> i64 {
+    let timeframe = string(timeframe);
+    with(|r| r.in_seconds(&timeframe))
+}
+
+#[export_name = "ninescript.time"]
+unsafe extern "C" fn time(_: u32, timeframe: *const c_char, session: *const c_char, timezone: *const c_char) -> i64 {
+    let (timeframe, session, timezone) = (string(timeframe), string(session), string(timezone));
+    with(|r| r.time(&timeframe, &session, &timezone))
+}
+
+unsafe fn field(name: &str, time: i64, timezone: *const c_char) -> i64 {
+    let timezone = string(timezone);
+    with(|r| r.calendar(name, time, &timezone))
+}
+#[export_name = "ninescript.year"]
+unsafe extern "C" fn year(_: u32, time: i64, timezone: *const c_char) -> i64 { field("year", time, timezone) }
+#[export_name = "ninescript.month"]
+unsafe extern "C" fn month(_: u32, time: i64, timezone: *const c_char) -> i64 { field("month", time, timezone) }
+#[export_name = "ninescript.weekofyear"]
+unsafe extern "C" fn weekofyear(_: u32, time: i64, timezone: *const c_char) -> i64 { field("weekofyear", time, timezone) }
+#[export_name = "ninescript.dayofmonth"]
+unsafe extern "C" fn dayofmonth(_: u32, time: i64, timezone: *const c_char) -> i64 { field("dayofmonth", time, timezone) }
+#[export_name = "ninescript.dayofweek"]
+unsafe extern "C" fn dayofweek(_: u32, time: i64, timezone: *const c_char) -> i64 { field("dayofweek", time, timezone) }
+#[export_name = "ninescript.hour"]
+unsafe extern "C" fn hour(_: u32, time: i64, timezone: *const c_char) -> i64 { field("hour", time, timezone) }
+#[export_name = "ninescript.minute"]
+unsafe extern "C" fn minute(_: u32, time: i64, timezone: *const c_char) -> i64 { field("minute", time, timezone) }
+#[export_name = "ninescript.second"]
+unsafe extern "C" fn second(_: u32, time: i64, timezone: *const c_char) -> i64 { field("second", time, timezone) }
+#[export_name = "ninescript.session.ismarket"]
+extern "C" fn ismarket(_: u32) -> bool { with(|r| r.is_market()) }
+#[export_name = "ninescript.session.ispremarket"]
+extern "C" fn ispremarket(_: u32) -> bool { with(|r| r.is_premarket()) }
+#[export_name = "ninescript.session.ispostmarket"]
+extern "C" fn ispostmarket(_: u32) -> bool { with(|r| r.is_postmarket()) }
+#[export_name = "ninescript.barstate.isnew"]
+extern "C" fn isnew(_: u32) -> bool { with(|r| r.barstate().new) }
+#[export_name = "ninescript.barstate.isconfirmed"]
+extern "C" fn isconfirmed(_: u32) -> bool { with(|r| r.barstate().confirmed) }
+#[export_name = "ninescript.barstate.isrealtime"]
+extern "C" fn isrealtime(_: u32) -> bool { with(|r| r.barstate().realtime) }
+#[export_name = "ninescript.barstate.ishistory"]
+extern "C" fn ishistory(_: u32) -> bool { with(|r| !r.barstate().realtime) }
+#[export_name = "ninescript.barstate.islast"]
+extern "C" fn islast(_: u32) -> bool { with(|r| r.barstate().last) }
+#[export_name = "ninescript.plot.record"]
+extern "C" fn plot(plot: u32, value: f64, color: u32) { with(|r| r.plot(plot, value, color)) }
+#[export_name = "ninescript.request.value"]
+extern "C" fn request(request: u32, value: f64) -> f64 { with(|r| r.security(request, value)) }
+
+#[export_name = "ninescript.drawing.new"]
+extern "C" fn drawing_new(kind: u32) -> i64 {
+    match DrawingKind::from_index(kind) {
+        Some(kind) => with(|r| r.drawings().create(kind)),
+        None => {
+            fail(CodegenError::Unsupported(format!("drawing kind {}", kind)));
+            0
+        }
+    }
+}
+#[export_name = "ninescript.drawing.set"]
+extern "C" fn drawing_set(property: u32, id: i64, value: f64) { with(|r| r.drawings().set(property, id, value)) }
+
+#[export_name = "ninescript.drawing.set_string"]
+unsafe extern "C" fn drawing_set_string(property: u32, id: i64, value: *const c_char) {
+    let value = string(value);
+    with(|r| r.drawings().set_string(property, id, &value));
+}
+
+#[export_name = "ninescript.loop.start"]
+extern "C" fn loop_start(site: u32) { with(|r| r.loop_start(site)) }
+
+#[export_name = "ninescript.loop.next"]
+extern "C" fn loop_next(site: u32) -> bool {
+    with(|r| match r.loop_next(site) {
+        Ok(()) => true,
+        Err(e) => {
+            fail(e);
+            false
+        }
+    })
+}
+
+#[export_name = "ninescript.drawing.get"]
+extern "C" fn drawing_get(property: u32, id: i64) -> f64 { with(|r| r.drawings().get(property, id)) }
+#[export_name = "ninescript.drawing.delete"]
+extern "C" fn drawing_delete(_: u32, id: i64) { with(|r| r.drawings().delete(id)) }
+#[export_name = "ninescript.drawing.cell"]
+extern "C" fn drawing_cell(_: u32, table: i64, column: i64, row: i64) -> i64 { with(|r| r.drawings().cell(table, column, row)) }
+#[export_name = "ninescript.drawing.point"]
+extern "C" fn drawing_point(_: u32, polyline: i64, point: i64) { with(|r| r.drawings().point(polyline, point)) }
+
+/// Address of the implementation of a builtin declared by compiled code.
+#[cfg(feature = "llvm")]
+pub fn symbol(name: &str) -> Option<usize> {
+    Some(match name {
+        "na" => na as *const () as usize,
+        "nz" => nz as *const () as usize,
+        "math.abs" => abs as *const () as usize,
+        "math.max" => max as *const () as usize,
+        "math.min" => min as *const () as usize,
+        "math.sqrt" => sqrt as *const () as usize,
+        "color.new" => color_new as *const () as usize,
+        "color.rgb" => color_rgb as *const () as usize,
+        "color.r" => color_r as *const () as usize,
+        "color.g" => color_g as *const () as usize,
+        "color.b" => color_b as *const () as usize,
+        "color.t" => color_t as *const () as usize,
+        "color.from_gradient" => from_gradient as *const () as usize,
+        "ta.sma" => sma as *const () as usize,
+        "ta.ema" => ema as *const () as usize,
+        "ta.rma" => rma as *const () as usize,
+        "ta.wma" => wma as *const () as usize,
+        "ta.rsi" => rsi as *const () as usize,
+        "ta.stdev" => stdev as *const () as usize,
+        "ta.atr" => atr as *const () as usize,
+        "ta.crossover" => crossover as *const () as usize,
+        "ta.crossunder" => crossunder as *const () as usize,
+        "ta.macd" => macd as *const () as usize,
+        "ta.bb" => bb as *const () as usize,
+        "ta.kc" => kc as *const () as usize,
+        "ta.dmi" => dmi as *const () as usize,
+        "alert" => alert as *const () as usize,
+        "alertcondition" => alertcondition as *const () as usize,
+        "strategy.entry" => entry as *const () as usize,
+        "strategy.close" => close as *const () as usize,
+        "strategy.close_all" => close_all as *const () as usize,
+        "strategy.position_size" => position_size as *const () as usize,
+        "timeframe.multiplier" => multiplier as *const () as usize,
+        "timeframe.isticks" => isticks as *const () as usize,
+        "timeframe.isseconds" => isseconds as *const () as usize,
+        "timeframe.isminutes" => isminutes as *const () as usize,
+        "timeframe.isintraday" => isintraday as *const () as usize,
+        "timeframe.isdaily" => isdaily as *const () as usize,
+        "timeframe.isweekly" => isweekly as *const () as usize,
+        "timeframe.ismonthly" => ismonthly as *const () as usize,
+        "timeframe.isdwm" => isdwm as *const () as usize,
+        "timeframe.in_seconds" => in_seconds as *const () as usize,
+        "time" => time as *const () as usize,
+        "year" => year as *const () as usize,
+        "month" => month as *const () as usize,
+        "weekofyear" => weekofyear as *const () as usize,
+        "dayofmonth" => dayofmonth as *const () as usize,
+        "dayofweek" => dayofweek as *const () as usize,
+        "hour" => hour as *const () as usize,
+        "minute" => minute as *const () as usize,
+        "second" => second as *const () as usize,
+        "session.ismarket" => ismarket as *const () as usize,
+        "session.ispremarket" => ispremarket as *const () as usize,
+        "session.ispostmarket" => ispostmarket as *const () as usize,
+        "barstate.isnew" => isnew as *const () as usize,
+        "barstate.isconfirmed" => isconfirmed as *const () as usize,
+        "barstate.isrealtime" => isrealtime as *const () as usize,
+        "barstate.ishistory" => ishistory as *const () as usize,
+        "barstate.islast" => islast as *const () as usize,
+        plots::RECORD => plot as *const () as usize,
+        security::REQUEST => request as *const () as usize,
+        runtime::LOOP_START => loop_start as *const () as usize,
+        runtime::LOOP_NEXT => loop_next as *const () as usize,
+        drawings::NEW => drawing_new as *const () as usize,
+        drawings::SET => drawing_set as *const () as usize,
+        drawings::SET_STRING => drawing_set_string as *const () as usize,
+        drawings::GET => drawing_get as *const () as usize,
+        drawings::DELETE => drawing_delete as *const () as usize,
+        drawings::CELL => drawing_cell as *const () as usize,
+        drawings::POINT => drawing_point as *const () as usize,
+        _ => return None
+    })
+}
+
+/// Runs `f`, compiled code running a bar, with `runtime` serving the builtins
+/// it calls on this thread. Returns the first error of a builtin instead of its result.
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+fn bar<T>(runtime: &mut Option<Runtime>, bar: &Bar, barstate: BarState, f: impl FnOnce() -> T) -> Result<T, CodegenError> {
+    start_bar(runtime, bar, barstate);
+    let value = f();
+    end_bar(runtime).map(|()| value)
+}
+
+fn start_bar(runtime: &mut Option<Runtime>, bar: &Bar, barstate: BarState) {
+    ERROR.with(|e| e.borrow_mut().take());
+    RUNTIME.with(|r| *r.borrow_mut() = runtime.take());
+    with(|r| r.start_bar(bar, barstate));
+}
+
+fn end_bar(runtime: &mut Option<Runtime>) -> Result<(), CodegenError> {
+    with(|r| r.end_bar());
+    *runtime = RUNTIME.with(|r| r.borrow_mut().take());
+    match ERROR.with(|e| e.borrow_mut().take()) {
+        Some(error) => Err(error),
+        None => Ok(())
+    }
+}
+
+/// What runs need to know of a compiled script besides its code, which hosts
+/// pass to `ninescript_run_new` as JSON, see `target::header`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScriptInfo {
+    pub metadata: ScriptMetadata,
+    pub inputs: Vec<Input>,
+    pub outputs: Vec<Output>,
+    pub plots: Vec<Plot>,
+    pub requests: Vec<Request>,
+}
+
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+pub type StateSizeFn = unsafe extern "C" fn() -> u64;
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+pub type InitFn = unsafe extern "C" fn(*mut u64, *const u64);
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+pub type OnBarFn = unsafe extern "C" fn(*mut u64, *const Bar);
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+pub type GetOutputsFn = unsafe extern "C" fn(*mut u64) -> *const f64;
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+pub type RollbackFn = unsafe extern "C" fn(*mut u64);
+
+/// The functions a script exports, see `Processor::compile`.
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+#[derive(Clone, Copy)]
+pub struct Exports {
+    pub state_size: StateSizeFn,
+    pub init: InitFn,
+    pub on_bar: OnBarFn,
+    pub get_outputs: GetOutputsFn,
+    pub save: RollbackFn,
+    pub restore: RollbackFn,
+}
+
+/// A script compiled to code of this process, which calls the builtins of this module.
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+pub struct Executable {
+    exports: Exports,
+    /// Words of the state block of a run.
+    state_size: usize,
+    info: ScriptInfo,
+    loop_limit: Duration,
+}
+
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+impl Executable {
+    /// # Safety
+    ///
+    /// `exports` are the functions of the script `info` describes and stay
+    /// callable for as long as the executable.
+    pub unsafe fn new(exports: Exports, info: ScriptInfo, loop_limit: Duration) -> Self {
+        let state_size = ((exports.state_size)() as usize).div_ceil(8);
+        Self { exports, state_size, info, loop_limit }
+    }
+
+    pub fn metadata(&self) -> &ScriptMetadata {
+        &self.info.metadata
+    }
+
+    pub fn inputs(&self) -> &[Input] {
+        &self.info.inputs
+    }
+
+    pub fn outputs(&self) -> &[Output] {
+        &self.info.outputs
+    }
+
+    pub fn plots(&self) -> &[Plot] {
+        &self.info.plots
+    }
+
+    #[cfg(feature = "llvm")]
+    pub fn requests(&self) -> &[Request] {
+        &self.info.requests
+    }
+
+    /// Runs the script from its first bar, serving `request.security()` from
+    /// `feeds`. `values` are input values in schema order, missing ones keep
+    /// their default.
+    pub fn run_with(&self, bars: &[Bar], values: &[Constant], feeds: &Feeds) -> Result<RunResult, CodegenError> {
+        let mut state = self.start(bars, values, feeds)?;
+        let outputs = state.execute(bars)?;
+        Ok(state.into_runtime().into_result(outputs))
+    }
+
+    /// The state of a run over `bars` before its first bar, with `request.security()`
+    /// prefetched. `values` are like for `run`.
+    pub fn start(&self, bars: &[Bar], values: &[Constant], feeds: &Feeds) -> Result<State<'_>, CodegenError> {
+        let values = self.info.inputs.iter().enumerate().map(|(i, input)| match values.get(i) {
+            Some(value) => input.validate(value.clone()),
+            None => Ok(input.default.clone())
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        let mut strings = vec![];
+        let mut slots = vec![];
+        for (input, value) in self.info.inputs.iter().zip(&values) {
+            let slot = match (input.slot(value), value) {
+                (Some(slot), _) => slot,
+                (_, value) => {
+                    let s = match value {
+                        Constant::String(s) => CString::new(s.as_str()).ok(),
+                        _ => None
+                    };
+                    let s = s.ok_or_else(|| InputError::InvalidValue { input: input.name.clone(), value: format!("{:?}", value) })?;
+                    strings.push(s);
+                    strings.last().unwrap().as_ptr() as u64
+                }
+            };
+            slots.push(slot);
+        }
+
+        let requested = security::prefetch(&self.info.requests, &values, feeds, bars, |feed, timeframe| {
+            let runtime = Runtime::new(&self.info.metadata)
+                .with_loop_limit(self.loop_limit)
+                .with_timeframe(timeframe)
+                .with_calendar(feeds.timezone, feeds.session.clone())
+                .recording(self.info.requests.len());
+            let mut state = self.state(&slots, runtime);
+            state.execute(feed)?;
+            Ok::<_, CodegenError>(state.into_runtime().into_recorded())
+        })?;
+        let runtime = Runtime::new(&self.info.metadata)
+            .with_loop_limit(self.loop_limit)
+            .with_symbol(&feeds.symbol)
+            .with_timeframe(&feeds.timeframe)
+            .with_calendar(feeds.timezone, feeds.session.clone())
+            .with_plots(&self.info.plots)
+            .with_requested(requested);
+        Ok(State { _strings: strings, ..self.state(&slots, runtime) })
+    }
+
+    /// A run from the first bar with the input `slots` and builtins of `runtime`.
+    fn state(&self, slots: &[u64], runtime: Runtime) -> State<'_> {
+        let mut memory = vec![0; self.state_size];
+        unsafe { (self.exports.init)(memory.as_mut_ptr(), slots.as_ptr()) };
+        State { script: self, memory, runtime: Some(runtime), saved: None, _strings: vec![] }
+    }
+}
+
+/// State of one run of an `Executable`, which goes through the bars one at a time.
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+pub struct State<'a> {
+    script: &'a Executable,
+    /// The variables of the run, see `Processor::compile`.
+    memory: Vec<u64>,
+    /// Moved into `RUNTIME` while a bar runs.
+    runtime: Option<Runtime>,
+    /// What `restore` returns to, along with the variables `save()` copied.
+    saved: Option<Runtime>,
+    /// String inputs, which `memory` points to.
+    _strings: Vec<CString>,
+}
+
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+impl State<'_> {
+    /// Runs the compiled code over the history `bars`, returning the outputs on every bar.
+    fn execute(&mut self, bars: &[Bar]) -> Result<Vec<Series>, CodegenError> {
+        let mut outputs = self.script.info.outputs.iter()
+            .map(|o| Series { name: o.name.clone(), values: Vec::with_capacity(bars.len()) })
+            .collect::<Vec<_>>();
+        for (i, bar) in bars.iter().enumerate() {
+            let values = self.bar(bar, BarState::history(i + 1 == bars.len()))?;
+            for (series, value) in outputs.iter_mut().zip(values) {
+                series.values.push(value);
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Runs the compiled code once on `bar`, returning the outputs or the first
+    /// error of a builtin.
+    pub fn bar(&mut self, bar: &Bar, barstate: BarState) -> Result<Vec<f64>, CodegenError> {
+        let (script, memory) = (self.script, &mut self.memory);
+        self::bar(&mut self.runtime, bar, barstate, || unsafe {
+            (script.exports.on_bar)(memory.as_mut_ptr(), bar);
+            slice::from_raw_parts((script.exports.get_outputs)(memory.as_mut_ptr()), script.info.outputs.len()).to_vec()
+        })
+    }
+
+    /// Remembers the state between two bars.
+    pub fn save(&mut self) {
+        self.saved = self.runtime.as_mut().map(Runtime::save);
+        unsafe { (self.script.exports.save)(self.memory.as_mut_ptr()) };
+    }
+
+    /// Goes back to the state last saved, except for `varip` variables.
+    pub fn restore(&mut self) {
+        if let (Some(runtime), Some(saved)) = (&mut self.runtime, &self.saved) {
+            runtime.restore(saved);
+            unsafe { (self.script.exports.restore)(self.memory.as_mut_ptr()) };
+        }
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        self.runtime.as_ref().unwrap()
+    }
+
+    fn into_runtime(self) -> Runtime {
+        self.runtime.unwrap()
+    }
+}
+
+/// A run of a native artifact, which the host drives bar by bar.
+struct Run {
+    /// Moved into `RUNTIME` while a bar runs.
+    runtime: Option<Runtime>,
+    /// The last string returned to the host, valid until the next call.
+    text: CString,
+}
+
+impl Run {
+    fn text(&mut self, text: String) -> *const c_char {
+        self.text = CString::new(text).unwrap_or_default();
+        self.text.as_ptr()
+    }
+}
+
+/// A run of the script `script` describes on bars of `symbol` and `timeframe`,
+/// null if `script` is invalid. `request.security()` is `na` in these runs.
+#[no_mangle]
+unsafe extern "C" fn ninescript_run_new(script: *const c_char, symbol: *const c_char, timeframe: *const c_char) -> *mut Run {
+    let Ok(script) = serde_json::from_str::<ScriptInfo>(&string(script)) else {
+        return ptr::null_mut();
+    };
+    let runtime = Runtime::new(&script.metadata)
+        .with_plots(&script.plots)
+        .with_symbol(&string(symbol))
+        .with_timeframe(&string(timeframe));
+    Box::into_raw(Box::new(Run { runtime: Some(runtime), text: CString::default() }))
+}
+
+/// Starts a bar of the history, builtins called on this thread use `run` until `ninescript_end_bar`.
+#[no_mangle]
+unsafe extern "C" fn ninescript_start_bar(run: *mut Run, bar: *const Bar, last: bool) {
+    start_bar(&mut (*run).runtime, &*bar, BarState::history(last));
+}
+
+/// Ends the bar, returns the first error of a builtin during it or null.
+#[no_mangle]
+unsafe extern "C" fn ninescript_end_bar(run: *mut Run) -> *const c_char {
+    let run = &mut *run;
+    match end_bar(&mut run.runtime) {
+        Ok(()) => ptr::null(),
+        Err(error) => run.text(Diagnostic::from(error).to_string())
+    }
+}
+
+/// The plots, drawings, strategy report and alerts of the run so far as JSON.
+#[no_mangle]
+unsafe extern "C" fn ninescript_result(run: *mut Run) -> *const c_char {
+    let run = &mut *run;
+    let result = run.runtime.clone().map(|r| r.into_result(vec![]));
+    run.text(serde_json::to_string(&result).unwrap_or_default())
+}
+
+#[no_mangle]
+unsafe extern "C" fn ninescript_run_free(run: *mut Run) {
+    if !run.is_null() {
+        drop(Box::from_raw(run));
+    }
+}
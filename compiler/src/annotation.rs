@@ -0,0 +1,123 @@
+use regex::Regex;
+
+use crate::{ast::{FunctionDoc, Program, Statement}, error::{AnnotationError, AnnotationErrorType, Warning}, location::Location};
+
+/// The language version scripts are written against.
+pub const VERSION: i32 = 5;
+
+#[derive(Debug, PartialEq)]
+pub enum Annotation {
+    Version(i32),
+    Description(String),
+    Function(String),
+    Param(String, String),
+    Returns(String),
+    /// Annotations documenting types, fields, enums and variables, kept for tooling.
+    Other(String, String),
+}
+
+pub type Annotations = (Vec<(Location, Annotation)>, Vec<Warning>);
+
+/// Reads the `//@` compiler annotations of a source, the lexer drops them as comments.
+pub fn parse(src: &str) -> Result<Annotations, AnnotationError> {
+    let mut annotations = vec![];
+    let mut warnings = vec![];
+
+    for (i, line) in src.split('\n').enumerate() {
+        let trimmed = line.trim_start();
+        let text = match trimmed.strip_prefix("//@") {
+            Some(text) => text.trim_end(),
+            None => continue
+        };
+        let location = Location::new(i + 1, line.len() - trimmed.len() + 1);
+
+        let key_end = text.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(text.len());
+        let (key, value) = text.split_at(key_end);
+        let value = value.trim_start_matches('=').trim();
+
+        let annotation = match key {
+            "version" => match value.parse::<i32>() {
+                Ok(v) => Annotation::Version(v),
+                Err(_) => return Err(AnnotationError { error: AnnotationErrorType::InvalidVersion(value.to_string()), location })
+            },
+            "description" => Annotation::Description(value.to_string()),
+            "function" => Annotation::Function(value.to_string()),
+            "param" => {
+                let (name, description) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+                Annotation::Param(name.to_string(), description.trim().to_string())
+            },
+            "returns" => Annotation::Returns(value.to_string()),
+            "type" | "field" | "enum" | "variable" | "strategy_alert_message" => Annotation::Other(key.to_string(), value.to_string()),
+            _ => {
+                warnings.push(Warning::UnknownAnnotation(key.to_string(), location));
+                continue;
+            }
+        };
+        annotations.push((location, annotation));
+    }
+
+    Ok((annotations, warnings))
+}
+
+impl Program {
+    /// Combines parsed statements with the annotations of their source.
+    ///
+    /// Scripts without `//@version` are assumed to target the current version,
+    /// older versions are rejected and newer ones only produce a warning.
+    pub fn new(src: &str, statements: Vec<Statement>) -> Result<Self, AnnotationError> {
+        let (annotations, mut warnings) = parse(src)?;
+        let lines = src.split('\n').collect::<Vec<_>>();
+        let function_def = Regex::new(r"^\s*(?:export\s+)?([_a-zA-Z][_a-zA-Z0-9]*)\s*\(.*\)\s*=>").unwrap();
+
+        let mut version = None;
+        let mut description = None;
+        let mut functions = vec![];
+        let mut pending: Option<(Location, FunctionDoc)> = None;
+
+        for (location, annotation) in annotations {
+            match annotation {
+                Annotation::Version(v) => {
+                    if version.is_some() {
+                        return Err(AnnotationError { error: AnnotationErrorType::DuplicateVersion, location });
+                    }
+                    if v < VERSION {
+                        return Err(AnnotationError { error: AnnotationErrorType::UnsupportedVersion(v), location });
+                    }
+                    if v > VERSION {
+                        warnings.push(Warning::NewerVersion(v));
+                    }
+                    version = Some(v);
+                },
+                Annotation::Description(d) => description = Some(d),
+                Annotation::Function(d) => pending.get_or_insert_with(|| (location, FunctionDoc::default())).1.description = Some(d),
+                Annotation::Param(name, d) => pending.get_or_insert_with(|| (location, FunctionDoc::default())).1.params.push((name, d)),
+                Annotation::Returns(d) => pending.get_or_insert_with(|| (location, FunctionDoc::default())).1.returns = Some(d),
+                Annotation::Other(..) => {}
+            }
+
+            /* A function documentation ends at the first line which is not a comment */
+            let block_ends = !lines.get(location.row()).is_some_and(|l| l.trim_start().starts_with("//"));
+            if !block_ends {
+                continue;
+            }
+            if let Some((start, mut doc)) = pending.take() {
+                let next = lines[location.row()..].iter().find(|l| !l.trim().is_empty() && !l.trim_start().starts_with("//"));
+                match next.and_then(|l| function_def.captures(l)) {
+                    Some(c) => {
+                        doc.name = c[1].to_string();
+                        functions.push(doc);
+                    },
+                    None => warnings.push(Warning::DetachedAnnotation("function".to_string(), start))
+                }
+            }
+        }
+
+        Ok(Program {
+            version: version.unwrap_or(VERSION),
+            description,
+            functions,
+            warnings,
+            statements,
+        })
+    }
+}
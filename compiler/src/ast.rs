@@ -1,18 +1,122 @@
-use crate::types::RGBA;
+use std::{fmt, hash::{Hash, Hasher}, ops::{Deref, DerefMut}};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{error::Warning, location::Location, types::RGBA};
 
 pub type VarType = (Option<String>, Option<String>);
-pub type VarName = String;
 
-#[derive(Debug)]
-pub struct Var(pub VarType, pub VarName);
+/// A name declared or referred to by a script, with where it starts in the
+/// source when it was parsed. Names equal, hash and serialize as their text.
+#[derive(Clone, Debug, Default)]
+pub struct Name {
+    text: String,
+    location: Option<Location>,
+}
 
-#[derive(Debug)]
+impl Name {
+    pub fn new(text: impl Into<String>, location: Option<Location>) -> Self {
+        Self { text: text.into(), location }
+    }
+
+    pub fn location(&self) -> Option<Location> {
+        self.location
+    }
+}
+
+impl Deref for Name {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.text
+    }
+}
+
+impl DerefMut for Name {
+    fn deref_mut(&mut self) -> &mut String {
+        &mut self.text
+    }
+}
+
+impl From<String> for Name {
+    fn from(text: String) -> Self {
+        Self::new(text, None)
+    }
+}
+
+impl From<&str> for Name {
+    fn from(text: &str) -> Self {
+        Self::new(text, None)
+    }
+}
+
+impl From<Name> for String {
+    fn from(name: Name) -> Self {
+        name.text
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Eq for Name {}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.text == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
+
+impl PartialEq<Name> for String {
+    fn eq(&self, other: &Name) -> bool {
+        *self == other.text
+    }
+}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.text.hash(state)
+    }
+}
+
+impl Serialize for Name {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Name::from)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Var(pub VarType, pub Name);
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct VarParam(pub Var, pub Option<Box<Expr>>);
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum Statement {
-    Import(String),
-    UnpackTuple(Vec<VarName>, Box<Expr>),
+    /// A `publisher/library/version` path and its alias.
+    Import(String, Option<String>),
+    UnpackTuple(Vec<Name>, Box<Expr>),
     ConstDef(Var, Box<Expr>),
     SeriesDef(Var, Box<Expr>),
     TypeDef(String, Vec<(String, String)>),
@@ -20,19 +124,21 @@ pub enum Statement {
     VarIpDef(Var, Box<Expr>),
     VarDef(Var, Box<Expr>),
     VarLet(Var, Box<Expr>),
-    VarAssign(VarName, Box<Expr>),
+    VarAssign(Name, Box<Expr>),
     ForTo(Var, Box<Expr>, Box<Expr>, Vec<Statement>, Option<Box<Expr>>),
     ForIn(Var, Box<Expr>, Vec<Statement>),
     While(Box<Expr>, Vec<Statement>),
     FnDef(String, Vec<VarParam>, Vec<Statement>),
+    /// A function, type, enum or constant a library exports.
+    Export(Box<Statement>),
     Expression(Box<Expr>),
 }
 
-pub type CallArguments = Vec<(Option<VarName>, Box<Expr>)>;
+pub type CallArguments = Vec<(Option<Name>, Box<Expr>)>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum Expr {
-    Identifier(String),
+    Identifier(Name),
     String(String),
     Bool(bool),
     Int(i64),
@@ -40,17 +146,17 @@ pub enum Expr {
     MakeTuple(Vec<Box<Expr>>),
     Op(Box<Expr>, Opcode, Box<Expr>), 
     If(Box<Expr>, Vec<Statement>, Option<Vec<Statement>>),
-    Index(String, Box<Expr>),
+    Index(Name, Box<Expr>),
     Switch(Option<Box<Expr>>, Vec<(Option<Box<Expr>>, Box<Statement>)>),
     Not(Box<Expr>),
     Negative(Box<Expr>),
     HashColor(RGBA),
-    FnCall(String, Option<Vec<String>>, CallArguments),
-    MethodCall(VarName, String, Option<String>, CallArguments),
-    PropertyAccess(VarName, String)
+    FnCall(Name, Option<Vec<String>>, CallArguments),
+    MethodCall(String, String, Option<String>, CallArguments),
+    PropertyAccess(Name, String)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum Opcode {
     TernaryIf,
     TernaryElse,
@@ -69,8 +175,204 @@ pub enum Opcode {
     Mod
 }
 
+/// Documentation of a function taken from its `//@function`, `//@param` and `//@returns` annotations.
+#[derive(Debug, Default, PartialEq)]
+pub struct FunctionDoc {
+    pub name: String,
+    pub description: Option<String>,
+    pub params: Vec<(String, String)>,
+    pub returns: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct Program {
-    version: i32,
-    exprs: Vec<Box<Expr>>
+    pub version: i32,
+    pub description: Option<String>,
+    pub functions: Vec<FunctionDoc>,
+    pub warnings: Vec<Warning>,
+    pub statements: Vec<Statement>,
+}
+
+/// A statement or expression met while walking the syntax tree.
+#[derive(Clone, Copy, Debug)]
+pub enum Node<'a> {
+    Statement(&'a Statement),
+    Expr(&'a Expr),
+}
+
+/// A statement or expression met while walking the syntax tree mutably.
+#[derive(Debug)]
+pub enum NodeMut<'a> {
+    Statement(&'a mut Statement),
+    Expr(&'a mut Expr),
+}
+
+impl Node<'_> {
+    /// Where the name the node starts with was parsed.
+    fn location(&self) -> Option<Location> {
+        match self {
+            Node::Statement(Statement::ConstDef(var, _))
+            | Node::Statement(Statement::SeriesDef(var, _))
+            | Node::Statement(Statement::VarIpDef(var, _))
+            | Node::Statement(Statement::VarDef(var, _))
+            | Node::Statement(Statement::VarLet(var, _))
+            | Node::Statement(Statement::ForTo(var, ..))
+            | Node::Statement(Statement::ForIn(var, ..)) => var.1.location(),
+            Node::Statement(Statement::VarAssign(name, _)) => name.location(),
+            Node::Statement(Statement::UnpackTuple(names, _)) => names.first().and_then(Name::location),
+            Node::Expr(Expr::Identifier(name))
+            | Node::Expr(Expr::Index(name, _))
+            | Node::Expr(Expr::FnCall(name, ..))
+            | Node::Expr(Expr::PropertyAccess(name, _)) => name.location(),
+            _ => None
+        }
+    }
+}
+
+impl Statement {
+    /// Where the first name of the statement was parsed, which locates errors in it.
+    pub fn location(&self) -> Option<Location> {
+        let mut location = None;
+        self.walk(&mut |node| location = location.or_else(|| node.location()));
+        location
+    }
+
+    /// Visits the statement and everything nested in it, parents first, in source order.
+    pub fn walk<'a, F: FnMut(Node<'a>)>(&'a self, f: &mut F) {
+        f(Node::Statement(self));
+        match self {
+            Statement::Import(..) | Statement::TypeDef(..) | Statement::EnumDef(..) => {},
+            Statement::UnpackTuple(_, e)
+            | Statement::ConstDef(_, e)
+            | Statement::SeriesDef(_, e)
+            | Statement::VarIpDef(_, e)
+            | Statement::VarDef(_, e)
+            | Statement::VarLet(_, e)
+            | Statement::VarAssign(_, e)
+            | Statement::Expression(e) => e.walk(f),
+            Statement::Export(definition) => definition.walk(f),
+            Statement::ForTo(_, start, end, body, by) => {
+                start.walk(f);
+                end.walk(f);
+                if let Some(by) = by {
+                    by.walk(f);
+                }
+                body.iter().for_each(|s| s.walk(f));
+            },
+            Statement::ForIn(_, e, body) | Statement::While(e, body) => {
+                e.walk(f);
+                body.iter().for_each(|s| s.walk(f));
+            },
+            Statement::FnDef(_, params, body) => {
+                params.iter().filter_map(|p| p.1.as_ref()).for_each(|e| e.walk(f));
+                body.iter().for_each(|s| s.walk(f));
+            },
+        }
+    }
+
+    /// Visits the statement like `walk`, allowing every node to be rewritten before its children.
+    pub fn walk_mut<F: FnMut(NodeMut)>(&mut self, f: &mut F) {
+        f(NodeMut::Statement(self));
+        match self {
+            Statement::Import(..) | Statement::TypeDef(..) | Statement::EnumDef(..) => {},
+            Statement::UnpackTuple(_, e)
+            | Statement::ConstDef(_, e)
+            | Statement::SeriesDef(_, e)
+            | Statement::VarIpDef(_, e)
+            | Statement::VarDef(_, e)
+            | Statement::VarLet(_, e)
+            | Statement::VarAssign(_, e)
+            | Statement::Expression(e) => e.walk_mut(f),
+            Statement::Export(definition) => definition.walk_mut(f),
+            Statement::ForTo(_, start, end, body, by) => {
+                start.walk_mut(f);
+                end.walk_mut(f);
+                if let Some(by) = by {
+                    by.walk_mut(f);
+                }
+                body.iter_mut().for_each(|s| s.walk_mut(f));
+            },
+            Statement::ForIn(_, e, body) | Statement::While(e, body) => {
+                e.walk_mut(f);
+                body.iter_mut().for_each(|s| s.walk_mut(f));
+            },
+            Statement::FnDef(_, params, body) => {
+                params.iter_mut().filter_map(|p| p.1.as_mut()).for_each(|e| e.walk_mut(f));
+                body.iter_mut().for_each(|s| s.walk_mut(f));
+            },
+        }
+    }
+}
+
+impl Expr {
+    /// Where the first name of the expression was parsed, see `Statement::location`.
+    pub fn location(&self) -> Option<Location> {
+        let mut location = None;
+        self.walk(&mut |node| location = location.or_else(|| node.location()));
+        location
+    }
+
+    /// Visits the expression and everything nested in it, parents first, in source order.
+    pub fn walk<'a, F: FnMut(Node<'a>)>(&'a self, f: &mut F) {
+        f(Node::Expr(self));
+        match self {
+            Expr::Identifier(_) | Expr::String(_) | Expr::Bool(_) | Expr::Int(_) | Expr::Float(_)
+            | Expr::HashColor(_) | Expr::PropertyAccess(..) => {},
+            Expr::MakeTuple(items) => items.iter().for_each(|e| e.walk(f)),
+            Expr::Op(left, _, right) => {
+                left.walk(f);
+                right.walk(f);
+            },
+            Expr::If(condition, then, otherwise) => {
+                condition.walk(f);
+                then.iter().for_each(|s| s.walk(f));
+                otherwise.iter().flatten().for_each(|s| s.walk(f));
+            },
+            Expr::Index(_, e) | Expr::Not(e) | Expr::Negative(e) => e.walk(f),
+            Expr::Switch(subject, variants) => {
+                if let Some(subject) = subject {
+                    subject.walk(f);
+                }
+                for (case, body) in variants {
+                    if let Some(case) = case {
+                        case.walk(f);
+                    }
+                    body.walk(f);
+                }
+            },
+            Expr::FnCall(_, _, args) | Expr::MethodCall(_, _, _, args) => args.iter().for_each(|a| a.1.walk(f)),
+        }
+    }
+
+    /// Visits the expression like `walk`, allowing every node to be rewritten before its children.
+    pub fn walk_mut<F: FnMut(NodeMut)>(&mut self, f: &mut F) {
+        f(NodeMut::Expr(self));
+        match self {
+            Expr::Identifier(_) | Expr::String(_) | Expr::Bool(_) | Expr::Int(_) | Expr::Float(_)
+            | Expr::HashColor(_) | Expr::PropertyAccess(..) => {},
+            Expr::MakeTuple(items) => items.iter_mut().for_each(|e| e.walk_mut(f)),
+            Expr::Op(left, _, right) => {
+                left.walk_mut(f);
+                right.walk_mut(f);
+            },
+            Expr::If(condition, then, otherwise) => {
+                condition.walk_mut(f);
+                then.iter_mut().for_each(|s| s.walk_mut(f));
+                otherwise.iter_mut().flatten().for_each(|s| s.walk_mut(f));
+            },
+            Expr::Index(_, e) | Expr::Not(e) | Expr::Negative(e) => e.walk_mut(f),
+            Expr::Switch(subject, variants) => {
+                if let Some(subject) = subject {
+                    subject.walk_mut(f);
+                }
+                for (case, body) in variants {
+                    if let Some(case) = case {
+                        case.walk_mut(f);
+                    }
+                    body.walk_mut(f);
+                }
+            },
+            Expr::FnCall(_, _, args) | Expr::MethodCall(_, _, _, args) => args.iter_mut().for_each(|a| a.1.walk_mut(f)),
+        }
+    }
 }
@@ -0,0 +1,179 @@
+use std::fmt::Write;
+
+use serde_json::json;
+
+use crate::{
+    abi::ScriptInfo,
+    declaration::ScriptMetadata,
+    inputs::{Input, InputKind, SOURCES},
+    plots::Plot,
+    runtime::Output,
+    security::Request,
+    types::Type,
+};
+
+/// `load()`, which instantiates the module and passes JSON to the functions of `wasm`.
+const LOADER: &str = include_str!("bindings/loader.js");
+
+fn ts_type(t: &Type) -> &'static str {
+    match t {
+        Type::Bool => "boolean",
+        Type::Color => "string",
+        _ => "number"
+    }
+}
+
+/// Quotes names that are not valid identifiers.
+fn property(name: &str) -> String {
+    let identifier = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if identifier { name.to_string() } else { json!(name).to_string() }
+}
+
+fn input_type(input: &Input) -> String {
+    if !input.options.is_empty() {
+        return input.options.iter().map(|o| json!(o).to_string()).collect::<Vec<_>>().join(" | ");
+    }
+    match input.kind {
+        InputKind::Int | InputKind::Float => "number".to_string(),
+        InputKind::Bool => "boolean".to_string(),
+        InputKind::Source => "Source".to_string(),
+        InputKind::String | InputKind::Timeframe | InputKind::Color => "string".to_string(),
+    }
+}
+
+/// A doc comment with the title, default and range of an input.
+fn input_doc(input: &Input) -> String {
+    let mut facts = vec![format!("Default {}", json!(input.default))];
+    match (input.min, input.max) {
+        (Some(min), Some(max)) => facts.push(format!("from {} to {}", min, max)),
+        (Some(min), None) => facts.push(format!("at least {}", min)),
+        (None, Some(max)) => facts.push(format!("at most {}", max)),
+        (None, None) => {}
+    }
+    match input.kind {
+        InputKind::Int => facts.push("an integer".to_string()),
+        InputKind::Color => facts.push("as #RRGGBB or #RRGGBBAA".to_string()),
+        _ => {}
+    }
+    let sentences = input.title.iter().chain(&input.tooltip)
+        .map(|s| s.trim_end_matches('.').to_string())
+        .chain([facts.join(", ")])
+        .collect::<Vec<_>>();
+    format!("    /** {}. */", sentences.join(". ").replace("*/", "* /"))
+}
+
+/// TypeScript declarations of the module `javascript` generates for a script.
+pub fn typescript(metadata: &ScriptMetadata, inputs: &[Input], outputs: &[Output], plots: &[Plot]) -> String {
+    let mut ts = String::new();
+    writeln!(ts, "/* Bindings of the WebAssembly build of {}. */\n", json!(metadata.title)).unwrap();
+    writeln!(ts, "export interface Bar {{\n    /** Unix time in milliseconds */\n    time: number;").unwrap();
+    for field in ["open", "high", "low", "close", "volume"] {
+        writeln!(ts, "    {}: number;", field).unwrap();
+    }
+    writeln!(ts, "}}\n").unwrap();
+
+    let sources = SOURCES.iter().map(|s| json!(s).to_string()).collect::<Vec<_>>();
+    writeln!(ts, "export type Source = {};\n", sources.join(" | ")).unwrap();
+
+    writeln!(ts, "/** Values overriding the defaults of the inputs. */\nexport interface Inputs {{").unwrap();
+    for input in inputs {
+        writeln!(ts, "{}", input_doc(input)).unwrap();
+        writeln!(ts, "    {}?: {};", property(&input.name), input_type(input)).unwrap();
+    }
+    writeln!(ts, "}}\n").unwrap();
+
+    writeln!(ts, "/** Value of every output after each bar, colors as #RRGGBBAA. */\nexport interface Outputs {{").unwrap();
+    for output in outputs {
+        writeln!(ts, "    {}: {}[];", property(&output.name), ts_type(&output.t)).unwrap();
+    }
+    writeln!(ts, "}}\n").unwrap();
+
+    writeln!(ts, "export interface Plot {{").unwrap();
+    writeln!(ts, "    kind: \"plot\" | \"plotshape\" | \"plotchar\" | \"hline\" | \"bgcolor\" | \"barcolor\" | \"fill\";").unwrap();
+    writeln!(ts, "    name: string;\n    title: string | null;").unwrap();
+    writeln!(ts, "    /** Arguments like `linewidth` or `style` */\n    style: {{ [argument: string]: number | boolean | string }};").unwrap();
+    writeln!(ts, "    /** Indices of the plots a fill is drawn between */\n    between: [number, number] | null;").unwrap();
+    writeln!(ts, "    /** Value drawn on each bar, null where nothing was drawn, absent for bgcolor, barcolor and fill */").unwrap();
+    writeln!(ts, "    values?: (number | null)[];").unwrap();
+    writeln!(ts, "    /** Color on each bar as #RRGGBBAA, null for na */\n    colors: (string | null)[];\n}}\n").unwrap();
+
+    writeln!(ts, "export interface ChartPoint {{\n    time?: number;\n    index?: number;\n    price?: number;\n}}\n").unwrap();
+    writeln!(ts, "/** A drawing object left at the end of the run, properties which are not na are named like the").unwrap();
+    writeln!(ts, "    parameters of `label.new()`, `line.new()`, `box.new()`, `table.new()`, `table.cell()` or `polyline.new()`. */").unwrap();
+    writeln!(ts, "export interface Drawing {{\n    id: number;").unwrap();
+    writeln!(ts, "    kind: \"label\" | \"line\" | \"box\" | \"table\" | \"cell\" | \"polyline\";").unwrap();
+    writeln!(ts, "    /** Cells of a table, by row and column */\n    cells?: Drawing[];").unwrap();
+    writeln!(ts, "    /** Points of a polyline */\n    points?: ChartPoint[];").unwrap();
+    writeln!(ts, "    [property: string]: number | boolean | string | Drawing[] | ChartPoint[] | undefined;\n}}\n").unwrap();
+
+    writeln!(ts, "export interface InputSchema {{").unwrap();
+    writeln!(ts, "    name: string;\n    title: string | null;").unwrap();
+    writeln!(ts, "    kind: \"int\" | \"float\" | \"bool\" | \"string\" | \"source\" | \"timeframe\" | \"color\";").unwrap();
+    writeln!(ts, "    default: number | boolean | string;").unwrap();
+    writeln!(ts, "    min: number | null;\n    max: number | null;\n    step: number | null;").unwrap();
+    writeln!(ts, "    options: (number | boolean | string)[];").unwrap();
+    writeln!(ts, "    group: string | null;\n    tooltip: string | null;\n}}\n").unwrap();
+
+    writeln!(ts, "export interface Alert {{\n    bar_index: number;\n    /** Unix time in milliseconds of the bar */\n    time: number;").unwrap();
+    writeln!(ts, "    /** The title of an `alertcondition()`, null for `alert()` */\n    title: string | null;").unwrap();
+    writeln!(ts, "    /** With its placeholders replaced */\n    message: string;\n}}\n").unwrap();
+
+    let names = plots.iter().enumerate().map(|(i, p)| p.name(i)).collect::<Vec<_>>();
+    let plots = format!("    /** {} */\n    plots: Plot[];\n    drawings: Drawing[];\n    alerts: Alert[];", if names.is_empty() { "No plots".to_string() } else { names.join(", ") }.replace("*/", "* /"));
+    let result = if metadata.is_strategy() {
+        writeln!(ts, "export interface Trade {{").unwrap();
+        writeln!(ts, "    id: string;\n    /** Positive for long trades, negative for short ones */\n    qty: number;").unwrap();
+        writeln!(ts, "    entry_bar: number;\n    entry_price: number;").unwrap();
+        writeln!(ts, "    /** null while the trade is open */\n    exit_bar: number | null;").unwrap();
+        writeln!(ts, "    exit_price: number | null;").unwrap();
+        writeln!(ts, "    /** Realized profit after commission */\n    profit: number | null;\n}}\n").unwrap();
+
+        writeln!(ts, "export interface Metrics {{\n    net_profit: number;").unwrap();
+        writeln!(ts, "    /** Mean over standard deviation of per bar returns, not annualized */\n    sharpe: number;").unwrap();
+        writeln!(ts, "    max_drawdown: number;\n}}\n").unwrap();
+
+        writeln!(ts, "export interface StrategyReport {{\n    trades: Trade[];").unwrap();
+        writeln!(ts, "    /** Equity at the close of every bar */\n    equity: number[];").unwrap();
+        writeln!(ts, "    metrics: Metrics;\n}}\n").unwrap();
+        format!("    outputs: Outputs;\n{}\n    strategy: StrategyReport;", plots)
+    } else {
+        format!("    outputs: Outputs;\n{}", plots)
+    };
+    writeln!(ts, "export interface Result {{\n{}\n}}\n", result).unwrap();
+
+    writeln!(ts, "/** Bars of other symbols and timeframes for `request.security()`, `symbol` and `timeframe` name the bars").unwrap();
+    writeln!(ts, "    the script runs over, `timezone` (\"UTC\" by default) and `session` (\"24x7\") their exchange's. */").unwrap();
+    writeln!(ts, "export interface Feeds {{\n    symbol?: string;\n    timeframe?: string;\n    timezone?: string;\n    session?: string;").unwrap();
+    writeln!(ts, "    bars?: {{ symbol: string; timeframe: string; bars: Bar[] }}[];\n}}\n").unwrap();
+
+    writeln!(ts, "export interface Tick {{\n    /** Unix time in milliseconds */\n    time: number;\n    price: number;\n    volume: number;\n}}\n").unwrap();
+    writeln!(ts, "/** A script running on a live feed. Every tick runs the last bar again from the state it started with,").unwrap();
+    writeln!(ts, "    except for varip variables, until it closes with the timeframe of the feeds or `onBarClose()`. */").unwrap();
+    writeln!(ts, "export interface Stream {{\n    onTick(tick: Tick): void;\n    onBarClose(): void;").unwrap();
+    writeln!(ts, "    /** Called with every alert which fires after the history */\n    onAlert(callback: (alert: Alert) => void): void;").unwrap();
+    writeln!(ts, "    /** The history and realtime bars, the last one possibly open */\n    bars(): Bar[];").unwrap();
+    writeln!(ts, "    result(): Result;\n}}\n").unwrap();
+
+    writeln!(ts, "export interface Script {{\n    title: string;\n    inputs: InputSchema[];").unwrap();
+    writeln!(ts, "    /** Runs the script over `bars` from its initial state, throws on invalid inputs and missing feeds. */").unwrap();
+    writeln!(ts, "    run(bars: Bar[], inputs?: Inputs, feeds?: Feeds): Result;").unwrap();
+    writeln!(ts, "    /** Runs the script over the history `bars` like `run`, then on ticks. Every stream and run keeps its own state. */").unwrap();
+    writeln!(ts, "    stream(bars: Bar[], inputs?: Inputs, feeds?: Feeds): Stream;\n}}\n").unwrap();
+
+    writeln!(ts, "/** Instantiates the module, which contains the runtime. */").unwrap();
+    writeln!(ts, "export function load(wasm: BufferSource | WebAssembly.Module | Response | PromiseLike<Response>): Promise<Script>;").unwrap();
+    ts
+}
+
+/// An ES module running the WebAssembly build of a script, see `typescript` for its API.
+pub fn javascript(metadata: &ScriptMetadata, inputs: &[Input], outputs: &[Output], plots: &[Plot], requests: &[Request]) -> String {
+    let info = ScriptInfo {
+        metadata: metadata.clone(),
+        inputs: inputs.to_vec(),
+        outputs: outputs.to_vec(),
+        plots: plots.to_vec(),
+        requests: requests.to_vec(),
+    };
+    format!("const SCRIPT = {};\n\n{}", json!(info), LOADER)
+}
@@ -0,0 +1,77 @@
+/* Runs the WebAssembly build of a script, which links it with the runtime.
+ * Arguments and replies of the functions of wasm.rs are JSON. */
+
+/* Outputs which are numbers, JSON writes their na as null */
+const NUMBERS = SCRIPT.outputs.filter((o) => o.type !== "bool" && o.type !== "color").map((o) => o.name);
+
+/* Instantiates the module from its bytes, a compiled module or a (promise of a) fetch response. */
+export async function load(wasm) {
+  const imports = { env: { ninescript_now: () => performance.now() } };
+  wasm = await wasm;
+  let instance;
+  if (wasm instanceof WebAssembly.Module) {
+    instance = await WebAssembly.instantiate(wasm, imports);
+  } else {
+    const bytes = typeof Response !== "undefined" && wasm instanceof Response ? await wasm.arrayBuffer() : wasm;
+    instance = (await WebAssembly.instantiate(bytes, imports)).instance;
+  }
+  const exports = instance.exports;
+  const encoder = new TextEncoder();
+  const decoder = new TextDecoder();
+
+  /* Calls a function with numbers as they are and other arguments as JSON, returns its reply or throws its error. */
+  function call(name, ...args) {
+    const strings = [];
+    const values = args.flatMap((arg) => {
+      if (typeof arg === "number") {
+        return [arg];
+      }
+      const bytes = encoder.encode(JSON.stringify(arg));
+      const address = exports.ninescript_alloc(bytes.length);
+      new Uint8Array(exports.memory.buffer, address, bytes.length).set(bytes);
+      strings.push([address, bytes.length]);
+      return [address, bytes.length];
+    });
+    const reply = exports[name](...values);
+    strings.forEach(([address, length]) => exports.ninescript_free(address, length));
+    const memory = new Uint8Array(exports.memory.buffer);
+    const { ok, error } = JSON.parse(decoder.decode(memory.subarray(reply, memory.indexOf(0, reply))));
+    if (error !== undefined) {
+      throw new Error(error);
+    }
+    return ok;
+  }
+
+  function result(result) {
+    NUMBERS.forEach((name) => (result.outputs[name] = result.outputs[name].map((value) => value ?? NaN)));
+    return result;
+  }
+
+  /* Streams are freed along with the objects returned for them */
+  const streams = new FinalizationRegistry((stream) => exports.ninescript_stream_free(stream));
+
+  function stream(bars, inputs = {}, feeds = {}) {
+    const stream = call("ninescript_stream", { bars, inputs, feeds });
+    let callback = null;
+    const fire = (alerts) => alerts.forEach((alert) => callback?.(alert));
+    const object = {
+      onTick: (tick) => fire(call("ninescript_tick", stream, tick)),
+      onBarClose: () => fire(call("ninescript_bar_close", stream)),
+      onAlert(f) {
+        callback = f;
+      },
+      bars: () => call("ninescript_bars", stream),
+      result: () => result(call("ninescript_stream_result", stream)),
+    };
+    streams.register(object, stream);
+    return object;
+  }
+
+  call("ninescript_load", SCRIPT);
+  return {
+    title: SCRIPT.metadata.title,
+    inputs: SCRIPT.inputs,
+    run: (bars, inputs = {}, feeds = {}) => result(call("ninescript_run", { bars, inputs, feeds })),
+    stream,
+  };
+}
@@ -0,0 +1,273 @@
+use crate::{
+    ast::{CallArguments, Expr},
+    calendar,
+    drawings::{self, DrawingKind},
+    plots,
+    security,
+    types::{Constant, Signature, Type, RGBA},
+};
+
+fn sig(params: &[(&str, Type)], required: usize, returns: Type) -> Signature {
+    Signature {
+        params: params.iter().map(|(name, t)| (name.to_string(), t.clone())).collect(),
+        required,
+        returns,
+    }
+}
+
+/// Builtin constants, looked up by their full path (`strategy.long`).
+pub fn constant(name: &str) -> Option<Constant> {
+    Some(match name {
+        "strategy.long" => Constant::Int(1),
+        "strategy.short" => Constant::Int(-1),
+        "dayofweek.sunday" => Constant::Int(1),
+        "dayofweek.monday" => Constant::Int(2),
+        "dayofweek.tuesday" => Constant::Int(3),
+        "dayofweek.wednesday" => Constant::Int(4),
+        "dayofweek.thursday" => Constant::Int(5),
+        "dayofweek.friday" => Constant::Int(6),
+        "dayofweek.saturday" => Constant::Int(7),
+        _ => match name.strip_prefix("color.") {
+            Some(color) => Constant::Color(RGBA::named(color)?),
+            None => return style(name).map(|s| Constant::String(s.to_string()))
+        }
+    })
+}
+
+/// Style constants of the plotting and drawing functions, the value is the name without prefix.
+fn style(name: &str) -> Option<&str> {
+    let (namespace, value) = name.split_once('.')?;
+    let values: &[&str] = match namespace {
+        "plot" => &[
+            "style_line", "style_linebr", "style_stepline", "style_stepline_diamond", "style_histogram",
+            "style_cross", "style_area", "style_areabr", "style_columns", "style_circles",
+        ],
+        "hline" => &["style_solid", "style_dotted", "style_dashed"],
+        "shape" => &[
+            "xcross", "cross", "triangleup", "triangledown", "flag", "circle", "arrowup", "arrowdown",
+            "labelup", "labeldown", "square", "diamond",
+        ],
+        "location" => &["abovebar", "belowbar", "top", "bottom", "absolute"],
+        "label" => &[
+            "style_none", "style_xcross", "style_cross", "style_triangleup", "style_triangledown", "style_flag",
+            "style_circle", "style_arrowup", "style_arrowdown", "style_label_up", "style_label_down",
+            "style_label_left", "style_label_right", "style_label_lower_left", "style_label_lower_right",
+            "style_label_upper_left", "style_label_upper_right", "style_label_center", "style_square", "style_diamond",
+            "style_text_outline",
+        ],
+        "line" => &["style_solid", "style_dotted", "style_dashed", "style_arrow_left", "style_arrow_right", "style_arrow_both"],
+        "extend" => &["none", "left", "right", "both"],
+        "xloc" => &["bar_index", "bar_time"],
+        "yloc" => &["price", "abovebar", "belowbar"],
+        "position" => &[
+            "top_left", "top_center", "top_right", "middle_left", "middle_center", "middle_right",
+            "bottom_left", "bottom_center", "bottom_right",
+        ],
+        "text" => &["align_left", "align_center", "align_right", "align_top", "align_bottom", "wrap_auto", "wrap_none"],
+        "font" => &["family_default", "family_monospace"],
+        "size" => &["auto", "tiny", "small", "normal", "large", "huge"],
+        "display" => &["all", "none", "pane", "data_window", "price_scale", "status_line"],
+        "barmerge" => &["gaps_on", "gaps_off", "lookahead_on", "lookahead_off"],
+        "alert" => &["freq_all", "freq_once_per_bar", "freq_once_per_bar_close"],
+        _ => return None
+    };
+    values.contains(&value).then(|| value.trim_start_matches("style_"))
+}
+
+/// Builtin variables, namespaced ones are looked up by their full path (`barstate.islast`).
+///
+/// Namespaced variables which are not constants are read by calling an external
+/// function of the same name, like builtin functions without arguments.
+pub fn variable(name: &str) -> Option<Type> {
+    if let Some(c) = constant(name) {
+        return Some(c.get_type());
+    }
+    Some(match name {
+        "open" | "high" | "low" | "close" | "volume" | "hl2" | "hlc3" | "ohlc4" => Type::Float,
+        "time" | "bar_index" => Type::Int,
+        _ if calendar::FIELDS.contains(&name) => Type::Int,
+        "session.ismarket" | "session.ispremarket" | "session.ispostmarket" => Type::Bool,
+        "barstate.isnew" | "barstate.isconfirmed" | "barstate.isrealtime" | "barstate.ishistory" | "barstate.islast" => Type::Bool,
+        "na" => Type::Na,
+        "strategy.position_size" => Type::Float,
+        "syminfo.tickerid" | "timeframe.period" => Type::String,
+        "timeframe.multiplier" => Type::Int,
+        "timeframe.isticks" | "timeframe.isseconds" | "timeframe.isminutes" | "timeframe.isintraday"
+        | "timeframe.isdaily" | "timeframe.isweekly" | "timeframe.ismonthly" | "timeframe.isdwm" => Type::Bool,
+        _ => return None
+    })
+}
+
+/// Builtin functions, namespaced ones are looked up by their full path (`ta.sma`).
+pub fn function(name: &str) -> Option<Signature> {
+    use Type::*;
+
+    let tuple3 = || Tuple(vec![Float, Float, Float]);
+
+    Some(match name {
+        "indicator" => sig(&[
+            ("title", String), ("shorttitle", String), ("overlay", Bool), ("format", String),
+            ("precision", Int), ("max_bars_back", Int), ("max_lines_count", Int), ("max_labels_count", Int),
+            ("max_boxes_count", Int), ("max_polylines_count", Int),
+        ], 1, Void),
+        "strategy" | "lqstrategy" => sig(&[
+            ("title", String), ("shorttitle", String), ("overlay", Bool), ("format", String),
+            ("precision", Int), ("max_bars_back", Int), ("pyramiding", Int), ("initial_capital", Float),
+            ("default_qty_value", Float), ("commission_value", Float), ("slippage", Int),
+            ("enable_liquidity_ratio", Bool), ("enable_funding", Bool), ("max_lines_count", Int),
+            ("max_labels_count", Int), ("max_boxes_count", Int), ("max_polylines_count", Int),
+        ], 1, Void),
+        "library" => sig(&[("title", String), ("overlay", Bool)], 1, Void),
+        "input.int" | "input.float" => {
+            let t = if name == "input.int" { Int } else { Float };
+            sig(&[
+                ("defval", t.clone()), ("title", String), ("minval", t.clone()), ("maxval", t.clone()), ("step", t.clone()),
+                ("tooltip", String), ("inline", String), ("group", String), ("confirm", Bool), ("options", Array(Box::new(t.clone()))),
+            ], 1, t)
+        },
+        "input.bool" => sig(&[
+            ("defval", Bool), ("title", String), ("tooltip", String), ("inline", String), ("group", String), ("confirm", Bool),
+        ], 1, Bool),
+        "input.string" | "input.timeframe" => sig(&[
+            ("defval", String), ("title", String), ("options", Array(Box::new(String))), ("tooltip", String),
+            ("inline", String), ("group", String), ("confirm", Bool),
+        ], 1, String),
+        "input.source" => sig(&[
+            ("defval", Float), ("title", String), ("tooltip", String), ("inline", String), ("group", String),
+        ], 1, Float),
+        "input.color" => sig(&[
+            ("defval", Color), ("title", String), ("tooltip", String), ("inline", String), ("group", String), ("confirm", Bool),
+        ], 1, Color),
+        "plot" => sig(&[
+            ("series", Float), ("title", String), ("color", Color), ("linewidth", Int), ("style", String),
+            ("trackprice", Bool), ("histbase", Float), ("offset", Int), ("join", Bool), ("editable", Bool),
+            ("show_last", Int), ("display", String),
+        ], 1, plots::handle_type()),
+        "plotshape" | "plotchar" => {
+            let shape = if name == "plotshape" { ("style", String) } else { ("char", String) };
+            sig(&[
+                ("series", Bool), ("title", String), shape, ("location", String), ("color", Color), ("offset", Int),
+                ("text", String), ("textcolor", Color), ("editable", Bool), ("size", String), ("show_last", Int),
+                ("display", String),
+            ], 1, Void)
+        },
+        "hline" => sig(&[
+            ("price", Float), ("title", String), ("color", Color), ("linestyle", String), ("linewidth", Int),
+            ("editable", Bool), ("display", String),
+        ], 1, plots::handle_type()),
+        "bgcolor" | "barcolor" => sig(&[
+            ("color", Color), ("offset", Int), ("editable", Bool), ("show_last", Int), ("title", String), ("display", String),
+        ], 1, Void),
+        "fill" => sig(&[
+            ("plot1", plots::handle_type()), ("plot2", plots::handle_type()), ("color", Color), ("title", String),
+            ("editable", Bool), ("show_last", Int), ("fillgaps", Bool), ("display", String),
+        ], 2, Void),
+        "label.new" => sig(&[
+            ("x", Int), ("y", Float), ("text", String), ("xloc", String), ("yloc", String), ("color", Color),
+            ("style", String), ("textcolor", Color), ("size", String), ("textalign", String), ("tooltip", String),
+            ("text_font_family", String),
+        ], 2, drawings::handle_type(DrawingKind::Label)),
+        "line.new" => sig(&[
+            ("x1", Int), ("y1", Float), ("x2", Int), ("y2", Float), ("xloc", String), ("extend", String),
+            ("color", Color), ("style", String), ("width", Int),
+        ], 4, drawings::handle_type(DrawingKind::Line)),
+        "box.new" => sig(&[
+            ("left", Int), ("top", Float), ("right", Int), ("bottom", Float), ("border_color", Color),
+            ("border_width", Int), ("border_style", String), ("extend", String), ("xloc", String), ("bgcolor", Color),
+            ("text", String), ("text_size", String), ("text_color", Color), ("text_halign", String),
+            ("text_valign", String), ("text_wrap", String), ("text_font_family", String),
+        ], 4, drawings::handle_type(DrawingKind::Box)),
+        "table.new" => sig(&[
+            ("position", String), ("columns", Int), ("rows", Int), ("bgcolor", Color), ("frame_color", Color),
+            ("frame_width", Int), ("border_color", Color), ("border_width", Int),
+        ], 3, drawings::handle_type(DrawingKind::Table)),
+        "table.cell" => sig(&[
+            ("table_id", drawings::handle_type(DrawingKind::Table)), ("column", Int), ("row", Int), ("text", String),
+            ("width", Float), ("height", Float), ("text_color", Color), ("text_halign", String),
+            ("text_valign", String), ("text_size", String), ("bgcolor", Color), ("tooltip", String),
+            ("text_font_family", String),
+        ], 3, Void),
+        "polyline.new" => sig(&[
+            ("points", Array(Box::new(drawings::handle_type(DrawingKind::Point)))), ("curved", Bool), ("closed", Bool),
+            ("xloc", String), ("line_color", Color), ("fill_color", Color), ("line_style", String), ("line_width", Int),
+        ], 1, drawings::handle_type(DrawingKind::Polyline)),
+        "chart.point.new" => sig(&[("time", Int), ("index", Int), ("price", Float)], 3, drawings::handle_type(DrawingKind::Point)),
+        "chart.point.from_index" => sig(&[("index", Int), ("price", Float)], 2, drawings::handle_type(DrawingKind::Point)),
+        "chart.point.from_time" => sig(&[("time", Int), ("price", Float)], 2, drawings::handle_type(DrawingKind::Point)),
+        _ if name.contains(".set_") || name.contains(".get_") || name.ends_with(".delete") => return drawing_accessor(name),
+        "na" => sig(&[("x", Float)], 1, Bool),
+        "nz" => sig(&[("source", Float), ("replacement", Float)], 1, Float),
+        "math.abs" => sig(&[("number", Float)], 1, Float),
+        "math.max" | "math.min" => sig(&[("number0", Float), ("number1", Float)], 2, Float),
+        "math.sqrt" => sig(&[("number", Float)], 1, Float),
+        "color.new" => sig(&[("color", Color), ("transp", Float)], 2, Color),
+        "color.rgb" => sig(&[("red", Float), ("green", Float), ("blue", Float), ("transp", Float)], 3, Color),
+        "color.r" | "color.g" | "color.b" | "color.t" => sig(&[("color", Color)], 1, Float),
+        "color.from_gradient" => sig(&[
+            ("value", Float), ("bottom_value", Float), ("top_value", Float), ("bottom_color", Color), ("top_color", Color),
+        ], 5, Color),
+        "ta.sma" | "ta.ema" | "ta.rma" | "ta.wma" | "ta.rsi" | "ta.stdev" =>
+            sig(&[("source", Float), ("length", Int)], 2, Float),
+        "ta.atr" => sig(&[("length", Int)], 1, Float),
+        "ta.crossover" | "ta.crossunder" => sig(&[("source1", Float), ("source2", Float)], 2, Bool),
+        "ta.macd" => sig(&[("source", Float), ("fastlen", Int), ("slowlen", Int), ("siglen", Int)], 4, tuple3()),
+        "ta.bb" => sig(&[("series", Float), ("length", Int), ("mult", Float)], 3, tuple3()),
+        "ta.kc" => sig(&[("series", Float), ("length", Int), ("mult", Float)], 3, tuple3()),
+        "ta.dmi" => sig(&[("diLength", Int), ("adxSmoothing", Int)], 2, tuple3()),
+        "request.security" => security::signature(Float),
+        "timeframe.in_seconds" => sig(&[("timeframe", String)], 0, Int),
+        "time" => sig(&[("timeframe", String), ("session", String), ("timezone", String)], 1, Int),
+        _ if calendar::FIELDS.contains(&name) => sig(&[("time", Int), ("timezone", String)], 1, Int),
+        "alert" => sig(&[("message", String), ("freq", String)], 1, Void),
+        "alertcondition" => sig(&[("condition", Bool), ("title", String), ("message", String)], 1, Void),
+        "strategy.entry" => sig(&[("id", String), ("direction", Int), ("qty", Float)], 2, Void),
+        "strategy.close" => sig(&[("id", String)], 1, Void),
+        "strategy.close_all" => sig(&[], 0, Void),
+        _ => return None
+    })
+}
+
+/// The argument passed for every parameter of the builtin function `name`.
+pub(crate) fn arguments<'a>(name: &str, args: &'a CallArguments) -> Vec<Option<&'a Expr>> {
+    let params = function(name).unwrap().params;
+    let mut exprs = vec![None; params.len()];
+    for (i, (key, value)) in args.iter().enumerate() {
+        let index = match key {
+            Some(key) => params.iter().position(|p| p.0 == *key),
+            None => Some(i)
+        };
+        if let Some(expr) = index.and_then(|i| exprs.get_mut(i)) {
+            *expr = Some(value.as_ref());
+        }
+    }
+    exprs
+}
+
+/// Setters, getters and `delete` of drawing objects, setters take properties of the constructor.
+fn drawing_accessor(name: &str) -> Option<Signature> {
+    let (namespace, accessor) = name.split_once('.')?;
+    let constructor = function(&format!("{}.new", namespace))?;
+    let id = ("id".to_string(), constructor.returns.clone());
+    let property = |name: &str| constructor.params.iter().find(|p| p.0 == name).cloned();
+
+    if accessor == "delete" {
+        return Some(Signature { params: vec![id], required: 1, returns: Type::Void });
+    }
+    if let Some(name) = accessor.strip_prefix("get_") {
+        let (_, t) = property(name).filter(|p| p.1.is_numeric())?;
+        return Some(Signature { params: vec![id], required: 1, returns: t });
+    }
+    let names = match accessor.strip_prefix("set_")? {
+        "xy" => vec!["x", "y"],
+        "xy1" => vec!["x1", "y1"],
+        "xy2" => vec!["x2", "y2"],
+        "lefttop" => vec!["left", "top"],
+        "rightbottom" => vec!["right", "bottom"],
+        name => vec![name]
+    };
+    let mut params = vec![id];
+    for name in names {
+        params.push(property(name).filter(|p| !matches!(p.1, Type::Array(_)))?);
+    }
+    Some(Signature { required: params.len(), params, returns: Type::Void })
+}
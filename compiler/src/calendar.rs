@@ -0,0 +1,371 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::error::TimeframeError;
+
+pub(crate) const SECOND: i64 = 1000;
+pub(crate) const MINUTE: i64 = 60 * SECOND;
+pub(crate) const HOUR: i64 = 60 * MINUTE;
+pub(crate) const DAY: i64 = 24 * HOUR;
+
+/// Zones of the IANA tz database, the POSIX TZ rule they follow and their
+/// transitions since 1970 before it, written by timezones.py.
+const ZONES: &str = include_str!("timezones.txt");
+
+/// Offsets of a zone before its current rule took over.
+#[derive(Debug, PartialEq)]
+struct History {
+    /// Times offsets start to apply, the first one since the earliest time.
+    changes: Vec<(i64, i64)>,
+    /// When the rule takes over.
+    until: i64,
+}
+
+/// The rule and history of each zone of `ZONES`, parsed once.
+fn zones() -> &'static HashMap<&'static str, (&'static str, Option<History>)> {
+    static ZONE_TABLE: OnceLock<HashMap<&'static str, (&'static str, Option<History>)>> = OnceLock::new();
+    ZONE_TABLE.get_or_init(|| ZONES.lines().filter(|line| !line.starts_with('#')).filter_map(|line| {
+        let mut fields = line.split(' ');
+        let (name, rule) = (fields.next()?, fields.next()?);
+        let fields = fields.collect::<Vec<_>>();
+        let history = match fields.split_first() {
+            Some((first, rest)) => {
+                let (until, transitions) = rest.split_last()?;
+                let mut changes = vec![(i64::MIN, first.parse::<i64>().ok()? * SECOND)];
+                for transition in transitions {
+                    let (time, offset) = transition.split_once(':')?;
+                    changes.push((time.parse::<i64>().ok()? * SECOND, offset.parse::<i64>().ok()? * SECOND));
+                }
+                Some(History { changes, until: until.parse::<i64>().ok()? * SECOND })
+            },
+            None => None
+        };
+        Some((name, (rule, history)))
+    }).collect())
+}
+
+/// Days since 1970-01-01 to a (year, month, day) of the proleptic Gregorian calendar.
+pub(crate) fn civil(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day = days.rem_euclid(146097);
+    let year = (day - day / 1460 + day / 36524 - day / 146096) / 365;
+    let day = day - (365 * year + year / 4 - year / 100);
+    let month = (5 * day + 2) / 153;
+    let date = day - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    (year + era * 400 + (month <= 2) as i64, month, date)
+}
+
+/// Days since 1970-01-01 of a date, the inverse of `civil`.
+pub(crate) fn days(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year = year.rem_euclid(400);
+    let day = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    era * 146097 + year * 365 + year / 4 - year / 100 + day - 719468
+}
+
+/// Day of the week of a day since 1970-01-01, from 1 for Sunday to 7 for Saturday like `dayofweek`.
+pub(crate) fn weekday(days: i64) -> i64 {
+    (days + 4).rem_euclid(7) + 1
+}
+
+/// Parts of a date with builtins of the same name, `hour` of the bar and `hour(time, timezone)`.
+pub const FIELDS: [&str; 8] = ["year", "month", "weekofyear", "dayofmonth", "dayofweek", "hour", "minute", "second"];
+
+/// A date and time of day, see `Timezone::datetime`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    /// From 1 for Sunday to 7 for Saturday.
+    pub weekday: i64,
+    pub hour: i64,
+    pub minute: i64,
+    pub second: i64,
+}
+
+impl DateTime {
+    /// The part of the date named like in `FIELDS`.
+    pub fn field(&self, name: &str) -> Option<i64> {
+        Some(match name {
+            "year" => self.year,
+            "month" => self.month,
+            "weekofyear" => self.week_of_year(),
+            "dayofmonth" => self.day,
+            "dayofweek" => self.weekday,
+            "hour" => self.hour,
+            "minute" => self.minute,
+            "second" => self.second,
+            _ => return None
+        })
+    }
+
+    /// The ISO 8601 week, weeks start on Monday and the first one contains a Thursday.
+    pub fn week_of_year(&self) -> i64 {
+        let day = days(self.year, self.month, self.day);
+        let thursday = day - (self.weekday + 5) % 7 + 3;
+        let (year, _, _) = civil(thursday);
+        (thursday - days(year, 1, 1)) / 7 + 1
+    }
+}
+
+/// When daylight saving time starts or ends, POSIX `Mm.w.d/time`: day `d`
+/// (0 for Sunday) of week `w` of month `m`, week 5 being the last.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Transition {
+    month: i64,
+    week: i64,
+    weekday: i64,
+    /// Local time after midnight, which can be negative or past the day.
+    time: i64,
+}
+
+impl Transition {
+    fn parse(s: &str) -> Option<Self> {
+        let (date, time) = match s.split_once('/') {
+            Some((date, time)) => (date, offset(time)?),
+            None => (s, 2 * HOUR),
+        };
+        let mut fields = date.strip_prefix('M')?.split('.').map(|f| f.parse::<i64>().ok());
+        let (month, week, weekday) = (fields.next()??, fields.next()??, fields.next()??);
+        ((1..=12).contains(&month) && (1..=5).contains(&week) && (0..=6).contains(&weekday) && fields.next().is_none())
+            .then_some(Self { month, week, weekday, time })
+    }
+
+    /// Local time of the transition in `year`, in milliseconds since the epoch.
+    fn local(&self, year: i64) -> i64 {
+        let first = days(year, self.month, 1);
+        let length = days(year + self.month / 12, self.month % 12 + 1, 1) - first;
+        let mut day = (self.weekday + 1 - weekday(first)).rem_euclid(7) + (self.week - 1) * 7;
+        if day >= length {
+            day -= 7;
+        }
+        (first + day) * DAY + self.time
+    }
+}
+
+/// A POSIX `[+-]hh[:mm[:ss]]`, in milliseconds.
+fn offset(s: &str) -> Option<i64> {
+    let (sign, s) = match s.strip_prefix('-') {
+        Some(s) => (-1, s),
+        None => (1, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let mut total = 0;
+    for (i, field) in s.split(':').enumerate() {
+        let n = field.parse::<i64>().ok().filter(|_| i < 3 && !field.is_empty() && field.bytes().all(|b| b.is_ascii_digit()))?;
+        total += n * [HOUR, MINUTE, SECOND][i];
+    }
+    Some(sign * total)
+}
+
+/// Offsets from UTC of a zone, in milliseconds to add to UTC for local time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Rule {
+    standard: i64,
+    daylight: Option<(i64, Transition, Transition)>,
+}
+
+impl Rule {
+    /// Parses a POSIX TZ rule like `EST5EDT,M3.2.0,M11.1.0`, whose offsets are west of UTC.
+    fn parse(s: &str) -> Option<Self> {
+        fn name(s: &str) -> Option<&str> {
+            match s.strip_prefix('<') {
+                Some(s) => Some(&s[s.find('>')? + 1..]),
+                None => {
+                    let end = s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len());
+                    (end >= 3).then(|| &s[end..])
+                },
+            }
+        }
+        fn split_offset(s: &str) -> (&str, &str) {
+            let end = s.find(|c: char| !(c.is_ascii_digit() || "+-:".contains(c))).unwrap_or(s.len());
+            s.split_at(end)
+        }
+
+        let (standard, s) = split_offset(name(s)?);
+        let standard = -offset(standard)?;
+        if s.is_empty() {
+            return Some(Self { standard, daylight: None });
+        }
+        let (daylight, s) = split_offset(name(s)?);
+        let daylight = match daylight {
+            "" => standard + HOUR,
+            daylight => -offset(daylight)?,
+        };
+        let (start, end) = s.strip_prefix(',')?.split_once(',')?;
+        Some(Self { standard, daylight: Some((daylight, Transition::parse(start)?, Transition::parse(end)?)) })
+    }
+
+    fn offset(&self, time: i64) -> i64 {
+        let Some((daylight, start, end)) = self.daylight else {
+            return self.standard;
+        };
+        let (year, _, _) = civil((time + self.standard).div_euclid(DAY));
+        let start = start.local(year) - self.standard;
+        let end = end.local(year) - daylight;
+        let saving = match start < end {
+            true => start <= time && time < end,
+            false => time < end || start <= time,
+        };
+        if saving { daylight } else { self.standard }
+    }
+}
+
+/// A zone of the IANA tz database (`"America/New_York"`), following its
+/// rules for daylight saving time since 1970, or a fixed offset from UTC:
+/// `"UTC"`, `"GMT+2"`, `"UTC-03:30"` or `"+0530"`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timezone {
+    rule: Rule,
+    history: Option<&'static History>,
+}
+
+impl Timezone {
+    pub fn utc() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(s: &str) -> Result<Self, TimeframeError> {
+        let invalid = || TimeframeError::Timezone(s.to_string());
+        if let Some((rule, history)) = zones().get(s) {
+            return Rule::parse(rule).map(|rule| Self { rule, history: history.as_ref() }).ok_or_else(invalid);
+        }
+        let offset = s.strip_prefix("UTC").or_else(|| s.strip_prefix("GMT")).unwrap_or(s);
+        if offset.is_empty() && offset.len() != s.len() {
+            return Ok(Self::utc());
+        }
+        let (sign, offset) = match offset.split_at_checked(1) {
+            Some(("+", offset)) => (1, offset),
+            Some(("-", offset)) => (-1, offset),
+            _ => return Err(invalid())
+        };
+        let (hours, minutes) = match offset.split_once(':') {
+            Some(split) => split,
+            None if offset.len() > 2 => offset.split_at(offset.len() - 2),
+            None => (offset, "0"),
+        };
+        let number = |s: &str, max: i64| s.parse::<i64>().ok().filter(|n| (0..=max).contains(n) && !s.starts_with('+')).ok_or_else(invalid);
+        let standard = sign * (number(hours, 14)? * HOUR + number(minutes, 59)? * MINUTE);
+        Ok(Self { rule: Rule { standard, daylight: None }, history: None })
+    }
+
+    /// Milliseconds to add to UTC for local time at `time`, offsets before
+    /// 1970 are the ones of 1970.
+    pub fn offset(&self, time: i64) -> i64 {
+        match self.history {
+            Some(history) if time < history.until => {
+                let index = history.changes.partition_point(|c| c.0 <= time);
+                history.changes[index - 1].1
+            },
+            _ => self.rule.offset(time)
+        }
+    }
+
+    /// The time of a local time, the later one for local times which happen
+    /// twice when clocks go back.
+    pub fn from_local(&self, local: i64) -> i64 {
+        let guess = local - self.offset(local - self.rule.standard);
+        local - self.offset(guess)
+    }
+
+    pub fn datetime(&self, time: i64) -> DateTime {
+        let local = time + self.offset(time);
+        let day = local.div_euclid(DAY);
+        let (year, month, date) = civil(day);
+        let since = local - day * DAY;
+        DateTime {
+            year,
+            month,
+            day: date,
+            weekday: weekday(day),
+            hour: since / HOUR,
+            minute: since % HOUR / MINUTE,
+            second: since % MINUTE / SECOND,
+        }
+    }
+}
+
+/// Trading hours like `"0930-1600:23456"`: ranges of local time separated by
+/// commas, then the days they apply to from 1 for Sunday to 7 for Saturday, all
+/// days if omitted. `"24x7"` is always open. Ranges ending before they start
+/// are overnight sessions and belong to the day they end on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    /// Start and end in minutes after midnight, full days start where they end.
+    /// An end of 1440, "2400", is the end of the day rather than an overnight range.
+    ranges: Vec<(i64, i64)>,
+    /// Whether sessions happen on each day, by `weekday`.
+    days: [bool; 8],
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self { ranges: vec![(0, 0)], days: [true; 8] }
+    }
+}
+
+impl Session {
+    pub fn parse(s: &str) -> Result<Self, TimeframeError> {
+        let invalid = || TimeframeError::Session(s.to_string());
+        if s == "24x7" {
+            return Ok(Self::default());
+        }
+        let (ranges, days) = s.split_once(':').unwrap_or((s, "1234567"));
+        let time = |t: &str| match t.as_bytes() {
+            [h1, h2, m1, m2] if t.bytes().all(|b| b.is_ascii_digit()) => {
+                let digit = |b: &u8| (b - b'0') as i64;
+                let (hours, minutes) = (digit(h1) * 10 + digit(h2), digit(m1) * 10 + digit(m2));
+                (minutes < 60 && hours * 60 + minutes <= 1440).then_some(hours * 60 + minutes)
+            },
+            _ => None
+        };
+        let ranges = ranges.split(',').map(|range| {
+            let (start, end) = range.split_once('-')?;
+            Some((time(start)? % 1440, time(end)?))
+        }).collect::<Option<Vec<_>>>().ok_or_else(invalid)?;
+        let mut session = Self { ranges, days: [false; 8] };
+        for day in days.bytes() {
+            match day {
+                b'1'..=b'7' => session.days[(day - b'0') as usize] = true,
+                _ => return Err(invalid())
+            }
+        }
+        Ok(session)
+    }
+
+    /// When the first range starts, as (hour, minute).
+    pub fn open(&self) -> (u32, u32) {
+        let start = self.ranges[0].0;
+        ((start / 60) as u32, (start % 60) as u32)
+    }
+
+    /// Whether the session is open at `time`.
+    pub fn contains(&self, time: i64, timezone: &Timezone) -> bool {
+        let (day, minute) = self.local(time, timezone);
+        self.ranges.iter().any(|&(start, end)| match start < end {
+            true => self.days[weekday(day) as usize] && start <= minute && minute < end,
+            false => minute >= start && self.days[weekday(day + 1) as usize] || minute < end && self.days[weekday(day) as usize],
+        })
+    }
+
+    /// Whether `time` is before the session opens on a day it opens.
+    pub fn is_before(&self, time: i64, timezone: &Timezone) -> bool {
+        let (day, minute) = self.local(time, timezone);
+        self.days[weekday(day) as usize] && self.ranges.iter().all(|&(start, end)| start < end && minute < start)
+    }
+
+    /// Whether `time` is after the session closed on a day it opened.
+    pub fn is_after(&self, time: i64, timezone: &Timezone) -> bool {
+        let (day, minute) = self.local(time, timezone);
+        self.days[weekday(day) as usize] && self.ranges.iter().all(|&(start, end)| start < end && minute >= end)
+    }
+
+    /// The local day since 1970-01-01 and minute after midnight of `time`.
+    fn local(&self, time: i64, timezone: &Timezone) -> (i64, i64) {
+        let local = time + timezone.offset(time);
+        let day = local.div_euclid(DAY);
+        (day, (local - day * DAY) / MINUTE)
+    }
+}
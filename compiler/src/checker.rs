@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+
+use crate::{ast::{CallArguments, Expr, Opcode, Statement, Var, VarParam}, builtins, error::TypeError, location::Location, security, types::{Signature, Type}};
+
+/// Infers and checks types of a parsed script.
+///
+/// Function parameters without a written type are treated as `float`, the
+/// return type of a function is the type of the last statement of its body.
+pub struct TypeChecker {
+    scopes: Vec<HashMap<String, Type>>,
+    functions: HashMap<String, Signature>,
+    types: HashMap<String, Vec<(String, Type)>>,
+    enums: HashMap<String, Vec<String>>,
+    /// Value types of `if` and `switch` expressions, keyed by their address.
+    branches: HashMap<usize, Type>,
+    /// Where the innermost statement failing the check was parsed.
+    location: Option<Location>,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            types: HashMap::new(),
+            enums: HashMap::new(),
+            branches: HashMap::new(),
+            location: None,
+        }
+    }
+
+    pub fn check(&mut self, statements: &[Statement]) -> Result<(), TypeError> {
+        self.location = None;
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    /// Where the error of the last `check` was found, as far as the names of the statement tell.
+    pub fn location(&self) -> Option<Location> {
+        self.location
+    }
+
+    /// Signature of a user defined function, available after `check`.
+    pub fn function(&self, name: &str) -> Option<&Signature> {
+        self.functions.get(name)
+    }
+
+    /// Type of a top level variable, available after `check`.
+    pub fn variable(&self, name: &str) -> Option<&Type> {
+        self.scopes[0].get(name)
+    }
+
+    /// Type of the value of an `if` or `switch` expression as compiled, available
+    /// after `check`. It is `void` unless every branch, including a default one, has a value.
+    pub fn branch_type(&self, expr: &Expr) -> Option<&Type> {
+        self.branches.get(&(expr as *const Expr as usize))
+    }
+
+    /// Type of the value of a checked block, nested branches without a value have none.
+    fn block_value(&self, statements: &[Statement], t: Type) -> Type {
+        match statements.last() {
+            Some(Statement::Expression(e)) => self.branch_type(e).cloned().unwrap_or(t),
+            _ => t
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Type> {
+        self.scopes.iter().rev().find_map(|s| s.get(name)).cloned().or_else(|| builtins::variable(name))
+    }
+
+    fn declare(&mut self, name: &str, t: Type) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), t);
+    }
+
+    fn expect(expected: &Type, found: &Type) -> Result<(), TypeError> {
+        if expected.accepts(found) {
+            Ok(())
+        } else {
+            Err(TypeError::Mismatch { expected: expected.clone(), found: found.clone() })
+        }
+    }
+
+    fn unify(a: &Type, b: &Type) -> Result<Type, TypeError> {
+        a.unify(b).ok_or_else(|| TypeError::Mismatch { expected: a.clone(), found: b.clone() })
+    }
+
+    /// Checks a block in its own scope, the block value is its last statement.
+    fn block(&mut self, statements: &[Statement], locals: Vec<(String, Type)>) -> Result<Type, TypeError> {
+        self.scopes.push(locals.into_iter().collect());
+        let mut t = Ok(Type::Void);
+        for statement in statements {
+            t = self.statement(statement);
+            if t.is_err() {
+                break;
+            }
+        }
+        self.scopes.pop();
+        t
+    }
+
+    fn declaration(&mut self, var: &Var, value: &Expr) -> Result<Type, TypeError> {
+        let found = self.expr(value)?;
+        let t = match Type::from_var_type(&var.0) {
+            Some(expected) => {
+                Self::expect(&expected, &found)?;
+                expected
+            },
+            None => found
+        };
+        self.declare(&var.1, t.clone());
+        Ok(t)
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<Type, TypeError> {
+        let t = self.statement_type(statement);
+        if t.is_err() && self.location.is_none() {
+            self.location = statement.location();
+        }
+        t
+    }
+
+    fn statement_type(&mut self, statement: &Statement) -> Result<Type, TypeError> {
+        Ok(match statement {
+            Statement::Import(..) => Type::Void,
+            Statement::UnpackTuple(names, value) => {
+                let t = self.expr(value)?;
+                let items = match t {
+                    Type::Tuple(items) => items,
+                    t => return Err(TypeError::NotATuple(t))
+                };
+                if items.len() != names.len() {
+                    return Err(TypeError::TupleArity { expected: names.len(), found: items.len() });
+                }
+                for (name, t) in names.iter().zip(&items) {
+                    self.declare(name, t.clone());
+                }
+                Type::Tuple(items)
+            },
+            Statement::ConstDef(var, value)
+            | Statement::SeriesDef(var, value)
+            | Statement::VarIpDef(var, value)
+            | Statement::VarDef(var, value)
+            | Statement::VarLet(var, value) => self.declaration(var, value)?,
+            Statement::VarAssign(name, value) => {
+                let expected = self.lookup(name).ok_or_else(|| TypeError::UndefinedVariable(name.to_string()))?;
+                let found = self.expr(value)?;
+                Self::expect(&expected, &found)?;
+                expected
+            },
+            Statement::TypeDef(name, fields) => {
+                let fields = fields.iter().map(|(t, field)| (field.clone(), Type::from_name(t))).collect();
+                self.types.insert(name.clone(), fields);
+                Type::Void
+            },
+            Statement::EnumDef(name, variants) => {
+                self.enums.insert(name.clone(), variants.iter().map(|v| v.0.clone()).collect());
+                Type::Void
+            },
+            Statement::ForTo(var, start, end, body, by) => {
+                let t = Type::from_var_type(&var.0).unwrap_or(Type::Int);
+                for bound in [Some(start), Some(end), by.as_ref()].into_iter().flatten() {
+                    Self::expect(&Type::Float, &self.expr(bound)?)?;
+                }
+                self.block(body, vec![(var.1.to_string(), t)])?;
+                Type::Void
+            },
+            Statement::ForIn(var, object, body) => {
+                let t = match self.expr(object)? {
+                    Type::Array(item) => *item,
+                    t => return Err(TypeError::NotIterable(t))
+                };
+                self.block(body, vec![(var.1.to_string(), t)])?;
+                Type::Void
+            },
+            Statement::While(condition, body) => {
+                Self::expect(&Type::Bool, &self.expr(condition)?)?;
+                self.block(body, vec![])?;
+                Type::Void
+            },
+            Statement::Export(definition) => self.statement(definition)?,
+            Statement::FnDef(name, params, body) => {
+                let (params, required) = self.params(params)?;
+                let returns = self.block(body, params.clone())?;
+                self.functions.insert(name.clone(), Signature { params, required, returns });
+                Type::Void
+            },
+            Statement::Expression(e) => self.expr(e)?,
+        })
+    }
+
+    fn params(&mut self, params: &[VarParam]) -> Result<(Vec<(String, Type)>, usize), TypeError> {
+        let mut result = vec![];
+        let mut required = 0;
+        for VarParam(var, default) in params {
+            let t = match (Type::from_var_type(&var.0), default) {
+                (Some(t), Some(default)) => {
+                    Self::expect(&t, &self.expr(default)?)?;
+                    t
+                },
+                (Some(t), None) => t,
+                (None, Some(default)) => match self.expr(default)? {
+                    Type::Na => Type::Float,
+                    t => t
+                },
+                (None, None) => Type::Float
+            };
+            if default.is_none() {
+                required = result.len() + 1;
+            }
+            result.push((var.1.to_string(), t));
+        }
+        Ok((result, required))
+    }
+
+    fn call(&mut self, name: &str, signature: &Signature, args: &CallArguments) -> Result<Type, TypeError> {
+        if args.len() > signature.params.len() {
+            return Err(TypeError::ArgumentCount { function: name.to_string(), expected: signature.params.len(), found: args.len() });
+        }
+
+        let mut passed = vec![false; signature.params.len()];
+        for (i, (key, value)) in args.iter().enumerate() {
+            let index = match key {
+                Some(key) => signature.params.iter().position(|p| p.0 == *key)
+                    .ok_or_else(|| TypeError::UnknownArgument { function: name.to_string(), argument: key.to_string() })?,
+                None => i
+            };
+            Self::expect(&signature.params[index].1, &self.expr(value)?)?;
+            passed[index] = true;
+        }
+
+        if passed[..signature.required].iter().any(|p| !p) {
+            return Err(TypeError::ArgumentCount { function: name.to_string(), expected: signature.required, found: args.len() });
+        }
+        Ok(signature.returns.clone())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Type, TypeError> {
+        Ok(match expr {
+            Expr::Identifier(name) => self.lookup(name).ok_or_else(|| TypeError::UndefinedVariable(name.to_string()))?,
+            Expr::String(_) => Type::String,
+            Expr::Bool(_) => Type::Bool,
+            Expr::Int(_) => Type::Int,
+            Expr::Float(_) => Type::Float,
+            Expr::HashColor(_) => Type::Color,
+            Expr::MakeTuple(items) => Type::Tuple(items.iter().map(|e| self.expr(e)).collect::<Result<_, _>>()?),
+            Expr::Op(left, Opcode::TernaryIf, right) => {
+                Self::expect(&Type::Bool, &self.expr(left)?)?;
+                self.expr(right)?
+            },
+            Expr::Op(left, op, right) => {
+                let l = self.expr(left)?;
+                let r = self.expr(right)?;
+                match op {
+                    Opcode::TernaryElse => Self::unify(&l, &r)?,
+                    Opcode::And | Opcode::Or => {
+                        Self::expect(&Type::Bool, &l)?;
+                        Self::expect(&Type::Bool, &r)?;
+                        Type::Bool
+                    },
+                    Opcode::Equal | Opcode::NotEqual => {
+                        Self::unify(&l, &r)?;
+                        Type::Bool
+                    },
+                    Opcode::Greater | Opcode::Gte | Opcode::Less | Opcode::Lte => {
+                        Self::expect(&Type::Float, &l)?;
+                        Self::expect(&Type::Float, &r)?;
+                        Type::Bool
+                    },
+                    Opcode::Add if l == Type::String || r == Type::String => {
+                        Self::expect(&Type::String, &l)?;
+                        Self::expect(&Type::String, &r)?;
+                        Type::String
+                    },
+                    _ => {
+                        Self::expect(&Type::Float, &l)?;
+                        Self::expect(&Type::Float, &r)?;
+                        if l == Type::Int && r == Type::Int { Type::Int } else { Type::Float }
+                    }
+                }
+            },
+            Expr::If(condition, then, otherwise) => {
+                Self::expect(&Type::Bool, &self.expr(condition)?)?;
+                let t = self.block(then, vec![])?;
+                let (result, value) = match otherwise {
+                    Some(otherwise) => {
+                        let e = self.block(otherwise, vec![])?;
+                        let value = self.block_value(then, t.clone()).unify(&self.block_value(otherwise, e.clone()));
+                        (t.unify(&e).unwrap_or(Type::Void), value.unwrap_or(Type::Void))
+                    },
+                    None => (t, Type::Void)
+                };
+                self.branches.insert(expr as *const Expr as usize, value);
+                result
+            },
+            Expr::Index(name, index) => {
+                Self::expect(&Type::Int, &self.expr(index)?)?;
+                self.lookup(name).ok_or_else(|| TypeError::UndefinedVariable(name.to_string()))?
+            },
+            Expr::Switch(subject, variants) => {
+                let subject = match subject {
+                    Some(s) => Some(self.expr(s)?),
+                    None => None
+                };
+                let mut result: Option<Type> = None;
+                let mut value: Option<Type> = None;
+                for (case, body) in variants {
+                    if let Some(case) = case {
+                        let c = self.expr(case)?;
+                        match &subject {
+                            Some(s) => { Self::unify(s, &c)?; },
+                            None => Self::expect(&Type::Bool, &c)?
+                        }
+                    }
+                    let t = self.block(std::slice::from_ref(body), vec![])?;
+                    let v = self.block_value(std::slice::from_ref(body), t.clone());
+                    value = Some(match value {
+                        Some(r) => r.unify(&v).unwrap_or(Type::Void),
+                        None => v
+                    });
+                    result = Some(match result {
+                        Some(r) => r.unify(&t).unwrap_or(Type::Void),
+                        None => t
+                    });
+                }
+                let exhaustive = variants.iter().any(|v| v.0.is_none());
+                let value = value.filter(|_| exhaustive).unwrap_or(Type::Void);
+                self.branches.insert(expr as *const Expr as usize, value);
+                result.unwrap_or(Type::Void)
+            },
+            Expr::Not(e) => {
+                Self::expect(&Type::Bool, &self.expr(e)?)?;
+                Type::Bool
+            },
+            Expr::Negative(e) => {
+                let t = self.expr(e)?;
+                Self::expect(&Type::Float, &t)?;
+                t
+            },
+            Expr::FnCall(name, _, args) => {
+                let signature = self.functions.get(name.as_str()).cloned()
+                    .or_else(|| builtins::function(name))
+                    .ok_or_else(|| TypeError::UnknownFunction(name.to_string()))?;
+                self.call(name, &signature, args)?
+            },
+            Expr::MethodCall(object, name, _, args) if object == "request" && name == "security" => {
+                let expression = security::expression(args).ok_or_else(|| TypeError::ArgumentCount {
+                    function: "request.security".to_string(), expected: 3, found: args.len(),
+                })?;
+                let t = self.expr(expression)?;
+                self.call("request.security", &security::signature(t), args)?
+            },
+            Expr::MethodCall(object, name, _, args) => {
+                let path = format!("{}.{}", object, name);
+                let signature = builtins::function(&path).ok_or_else(|| TypeError::UnknownFunction(path.clone()))?;
+                self.call(&path, &signature, args)?
+            },
+            Expr::PropertyAccess(object, property) => {
+                if let Some(t) = builtins::variable(&format!("{}.{}", object, property)) {
+                    return Ok(t);
+                }
+                if let Some(variants) = self.enums.get(object.as_str()) {
+                    if variants.contains(property) {
+                        return Ok(Type::Named(object.to_string()));
+                    }
+                }
+                let t = self.lookup(object).ok_or_else(|| TypeError::UndefinedVariable(object.to_string()))?;
+                let field = match &t {
+                    Type::Named(name) => self.types.get(name).and_then(|f| f.iter().find(|f| f.0 == *property)),
+                    _ => None
+                };
+                match field {
+                    Some(field) => field.1.clone(),
+                    None => return Err(TypeError::UnknownProperty(t, property.clone()))
+                }
+            },
+        })
+    }
+}
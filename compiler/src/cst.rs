@@ -0,0 +1,1419 @@
+use std::{fmt, ops::Range, rc::Rc};
+
+use crate::{
+    ast::{Expr, Name, Opcode, Statement, Var, VarParam},
+    error::{ParseError, SyntaxError},
+    lexer::Lexer,
+    location::Location,
+    token::Tok,
+};
+
+const INDENT: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    Whitespace,
+    Newline,
+    Comment,
+    Identifier,
+    Keyword,
+    Number,
+    String,
+    Color,
+    Operator,
+    Open,
+    Close,
+    Comma,
+    Dot,
+    /// A character which is not part of the language.
+    Unknown,
+
+    Root,
+    /// The indented statements of a function, loop, branch or switch, the
+    /// fields of a type or the variants of an enum.
+    Block,
+
+    /* Statements */
+    Import,
+    /// `float x = 1`, `var x = 1`, `const int x = 1`...
+    VarDecl,
+    /// `x := 1`, `x += 1`...
+    Assignment,
+    UnpackTuple,
+    FnDef,
+    TypeDef,
+    EnumDef,
+    Export,
+    ForTo,
+    ForIn,
+    While,
+    ExprStatement,
+    /// Tokens which do not make a statement, with the nodes parsed before they were met.
+    Error,
+
+    /* Parts of statements */
+    /// The type of a declaration, `float` or `array<float>`.
+    Type,
+    ParamList,
+    Param,
+    Field,
+    Variant,
+    SwitchArm,
+
+    /* Expressions */
+    Literal,
+    Name,
+    /// `a.b`
+    Property,
+    /// `f(x)`, `a.b(x)` or `a.b<float>(x)`.
+    Call,
+    ArgList,
+    Arg,
+    /// `a[1]`
+    Index,
+    Tuple,
+    Paren,
+    Prefix,
+    Binary,
+    If,
+    Switch,
+}
+
+impl SyntaxKind {
+    /// Whether tokens of the kind are left out of the statements, like the lexer skips them.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, SyntaxKind::Whitespace | SyntaxKind::Newline | SyntaxKind::Comment | SyntaxKind::Unknown)
+    }
+
+    /// Whether nodes of the kind are expressions.
+    pub fn is_expr(self) -> bool {
+        use SyntaxKind::*;
+        matches!(self, Literal | Name | Property | Call | Index | Tuple | Paren | Prefix | Binary | If | Switch)
+    }
+}
+
+impl From<&Tok> for SyntaxKind {
+    fn from(tok: &Tok) -> Self {
+        match tok {
+            Tok::Whitespace => SyntaxKind::Whitespace,
+            Tok::NewLine | Tok::LineBreak => SyntaxKind::Newline,
+            Tok::Comment => SyntaxKind::Comment,
+            Tok::Identifier { .. } => SyntaxKind::Identifier,
+            Tok::Int { .. } | Tok::Float { .. } => SyntaxKind::Number,
+            Tok::String { .. } => SyntaxKind::String,
+            Tok::HashColor(_) => SyntaxKind::Color,
+            Tok::OpenParenthesis | Tok::OpenBrackets => SyntaxKind::Open,
+            Tok::CloseParenthesis | Tok::CloseBrackets => SyntaxKind::Close,
+            Tok::Comma => SyntaxKind::Comma,
+            Tok::Dot => SyntaxKind::Dot,
+            Tok::If | Tok::Else | Tok::For | Tok::To | Tok::In | Tok::By | Tok::While | Tok::Switch | Tok::Import
+            | Tok::As | Tok::Export | Tok::Var | Tok::VarIp | Tok::Const | Tok::Type | Tok::Enum | Tok::Series
+            | Tok::True | Tok::False | Tok::Not | Tok::And | Tok::Or => SyntaxKind::Keyword,
+            Tok::Unknown | Tok::StartOfFile | Tok::EndOfFile | Tok::Indent | Tok::Dedent => SyntaxKind::Unknown,
+            _ => SyntaxKind::Operator
+        }
+    }
+}
+
+/// A token of the green tree, the token of the lexer and its text without a position.
+#[derive(Debug, PartialEq)]
+pub struct GreenToken {
+    tok: Tok,
+    text: String,
+}
+
+impl GreenToken {
+    pub fn new(tok: Tok, text: &str) -> Self {
+        Self { tok, text: text.to_string() }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        SyntaxKind::from(&self.tok)
+    }
+
+    pub fn tok(&self) -> &Tok {
+        &self.tok
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// A node of the green tree. It is immutable and knows its width but not its
+/// position, so equal subtrees can be shared.
+#[derive(Debug, PartialEq)]
+pub struct GreenNode {
+    kind: SyntaxKind,
+    width: usize,
+    children: Vec<GreenElement>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    fn width(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.width,
+            GreenElement::Token(token) => token.text.len(),
+        }
+    }
+
+    fn write(&self, text: &mut String) {
+        match self {
+            GreenElement::Node(node) => node.children.iter().for_each(|c| c.write(text)),
+            GreenElement::Token(token) => text.push_str(&token.text),
+        }
+    }
+}
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let width = children.iter().map(GreenElement::width).sum();
+        Self { kind, width, children }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    /// Length of the text of the node in bytes.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+}
+
+/// Builds a green tree from its tokens in source order, nodes are started
+/// before their first child and finished after their last one.
+#[derive(Debug, Default)]
+pub struct GreenNodeBuilder {
+    parents: Vec<(SyntaxKind, Vec<GreenElement>)>,
+    root: Option<GreenNode>,
+}
+
+/// A position in the current node, where a node can be started once its first
+/// child is built, like a binary expression once its operator is met.
+#[derive(Clone, Copy, Debug)]
+pub struct Checkpoint(usize);
+
+impl GreenNodeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start_node(&mut self, kind: SyntaxKind) {
+        self.parents.push((kind, vec![]));
+    }
+
+    pub fn token(&mut self, tok: Tok, text: &str) {
+        let token = GreenElement::Token(Rc::new(GreenToken::new(tok, text)));
+        self.parents.last_mut().expect("token outside of a node").1.push(token);
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.parents.last().map_or(0, |p| p.1.len()))
+    }
+
+    /// Starts a node holding the children added to the current node since `checkpoint`.
+    pub fn start_node_at(&mut self, checkpoint: Checkpoint, kind: SyntaxKind) {
+        let children = self.parents.last_mut().expect("checkpoint outside of a node").1.split_off(checkpoint.0);
+        self.parents.push((kind, children));
+    }
+
+    pub fn finish_node(&mut self) {
+        let (kind, children) = self.parents.pop().expect("no node to finish");
+        let node = GreenNode::new(kind, children);
+        match self.parents.last_mut() {
+            Some(parent) => parent.1.push(GreenElement::Node(Rc::new(node))),
+            None => self.root = Some(node)
+        }
+    }
+
+    /// The root node, once every started node is finished.
+    pub fn finish(self) -> GreenNode {
+        assert!(self.parents.is_empty(), "unfinished node");
+        self.root.expect("no root node")
+    }
+}
+
+/// A node of the syntax tree: a green node with its position and parent,
+/// created on demand while walking from the root.
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+struct NodeData {
+    green: Rc<GreenNode>,
+    parent: Option<SyntaxNode>,
+    offset: usize,
+}
+
+/// A token of the syntax tree with its position and parent.
+#[derive(Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    parent: SyntaxNode,
+    offset: usize,
+}
+
+#[derive(Clone, Debug)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: GreenNode) -> Self {
+        Self(Rc::new(NodeData { green: Rc::new(green), parent: None, offset: 0 }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &GreenNode {
+        &self.0.green
+    }
+
+    pub fn parent(&self) -> Option<&SyntaxNode> {
+        self.0.parent.as_ref()
+    }
+
+    pub fn root(&self) -> SyntaxNode {
+        let mut node = self.clone();
+        while let Some(parent) = node.parent().cloned() {
+            node = parent;
+        }
+        node
+    }
+
+    /// Byte range of the node in the source.
+    pub fn text_range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.width
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity(self.0.green.width);
+        self.0.green.children.iter().for_each(|c| c.write(&mut text));
+        text
+    }
+
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.0.offset;
+        self.0.green.children.iter().map(move |child| {
+            let element = match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(), parent: Some(self.clone()), offset,
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken { green: green.clone(), parent: self.clone(), offset }),
+            };
+            offset += child.width();
+            element
+        })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children_with_tokens().filter_map(|e| match e {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None
+        })
+    }
+
+    /// Every token of the node in source order, trivia included.
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut tokens = vec![];
+        for element in self.children_with_tokens() {
+            match element {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+}
+
+impl fmt::Debug for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}@{:?}", self.kind(), self.text_range())
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text())
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind()
+    }
+
+    pub fn tok(&self) -> &Tok {
+        &self.green.tok
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn text_range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.text.len()
+    }
+
+    pub fn parent(&self) -> &SyntaxNode {
+        &self.parent
+    }
+}
+
+impl fmt::Debug for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}@{:?} {:?}", self.kind(), self.text_range(), self.text())
+    }
+}
+
+
+/// A syntax tree with the errors met while building it.
+pub struct Parse {
+    root: SyntaxNode,
+    errors: Vec<ParseError>,
+}
+
+impl Parse {
+    /// The root node, whose text is the source.
+    pub fn syntax(&self) -> SyntaxNode {
+        self.root.clone()
+    }
+
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Derives the statements of the script from its tree, failing with the
+    /// first error met, lexical errors first. Names keep where they start.
+    pub fn statements(self) -> Result<Vec<Statement>, ParseError> {
+        match self.errors.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(Lowering::new(self.root.text()).block(&self.root).expect("a tree without errors holds every part of its statements"))
+        }
+    }
+}
+
+/// Builds the syntax tree from the tokens of the lexer by recursive descent,
+/// the only grammar of the language. Every token goes to the tree: trivia go
+/// to the node open when the token after them is added, except that a comment
+/// ending a statement belongs to it and the lines before the first statement
+/// of a block to the block.
+struct Parser {
+    /// Tokens other than trivia with their text and span.
+    tokens: Vec<(Tok, String, Location, Location)>,
+    /// The trivia before every token, and after the last one.
+    trivia: Vec<Vec<(Tok, String)>>,
+    /// Trivia before the current token which are not in the tree yet.
+    pending: Vec<(Tok, String)>,
+    position: usize,
+    /// Whether the last token added to the tree is a line break.
+    line_start: bool,
+    builder: GreenNodeBuilder,
+    errors: Vec<ParseError>,
+}
+
+/// Precedence tiers of the binary operators, lowest first.
+fn tier(tok: &Tok) -> Option<usize> {
+    Some(match tok {
+        Tok::QuestionMark | Tok::Colon => 0,
+        Tok::Or => 1,
+        Tok::And => 2,
+        Tok::DoubleEqual | Tok::NotEqual => 3,
+        Tok::Less | Tok::Greater | Tok::Gte | Tok::Lte => 4,
+        Tok::Plus | Tok::Minus => 5,
+        Tok::Mod | Tok::Asterisk | Tok::Slash => 6,
+        _ => return None
+    })
+}
+
+const TIERS: usize = 7;
+
+fn is_identifier(tok: &Tok) -> bool {
+    matches!(tok, Tok::Identifier { .. })
+}
+
+impl Parser {
+    fn new(src: &str) -> Self {
+        let mut lexer = Lexer::new(src, INDENT);
+        let (mut tokens, mut trivia, mut errors) = (vec![], vec![vec![]], vec![]);
+        while let Some(token) = lexer.next() {
+            let tok = match token {
+                Ok((location, Tok::Unknown, _)) => {
+                    errors.push(ParseError(SyntaxError::InvalidToken { text: lexer.text(), location }));
+                    Tok::Unknown
+                },
+                Ok((_, tok, _)) if matches!(tok, Tok::Whitespace | Tok::Comment | Tok::LineBreak) => tok,
+                Ok((left, tok, right)) => {
+                    tokens.push((tok, lexer.text(), left, right));
+                    trivia.push(vec![]);
+                    continue;
+                },
+                /* The text of a malformed token is kept, it is not part of the language */
+                Err(error) => {
+                    errors.push(ParseError(SyntaxError::Lexical(error)));
+                    Tok::Unknown
+                }
+            };
+            trivia.last_mut().unwrap().push((tok, lexer.text()));
+        }
+        let pending = std::mem::take(&mut trivia[0]);
+        Self { tokens, trivia, pending, position: 0, line_start: true, builder: GreenNodeBuilder::new(), errors }
+    }
+
+    fn parse(mut self) -> Parse {
+        self.builder.start_node(SyntaxKind::Root);
+        while self.peek() != &Tok::EndOfFile {
+            match self.peek() {
+                Tok::NewLine => self.bump(),
+                Tok::Dedent => self.skip(),
+                _ => self.recovering(Self::statement)
+            }
+        }
+        self.flush();
+        self.builder.finish_node();
+        Parse { root: SyntaxNode::new_root(self.builder.finish()), errors: self.errors }
+    }
+
+    fn nth(&self, n: usize) -> &Tok {
+        self.tokens.get(self.position + n).map_or(&Tok::EndOfFile, |t| &t.0)
+    }
+
+    fn peek(&self) -> &Tok {
+        self.nth(0)
+    }
+
+    /// Whether no trivia separate the `n`th next token from the one before it.
+    fn joined(&self, n: usize) -> bool {
+        match n {
+            0 => self.pending.is_empty(),
+            n => self.trivia.get(self.position + n).is_some_and(Vec::is_empty)
+        }
+    }
+
+    fn add(&mut self, tok: Tok, text: &str) {
+        self.line_start = matches!(tok, Tok::NewLine | Tok::LineBreak);
+        self.builder.token(tok, text);
+    }
+
+    fn flush(&mut self) {
+        for (tok, text) in std::mem::take(&mut self.pending) {
+            self.add(tok, &text);
+        }
+    }
+
+    /// Moves past the current token without adding it, its trivia stay pending.
+    fn skip(&mut self) {
+        if self.position < self.tokens.len() {
+            self.position += 1;
+            let trivia = std::mem::take(&mut self.trivia[self.position]);
+            self.pending.extend(trivia);
+        }
+    }
+
+    /// Adds the current token with the trivia before it. Indents and dedents have no text and are left out.
+    fn bump(&mut self) {
+        self.flush();
+        if let Some((tok, text, ..)) = self.tokens.get_mut(self.position) {
+            let (tok, text) = (std::mem::replace(tok, Tok::EndOfFile), std::mem::take(text));
+            if !text.is_empty() {
+                self.add(tok, &text);
+            }
+        }
+        self.skip();
+    }
+
+    fn start(&mut self, kind: SyntaxKind) {
+        self.flush();
+        self.builder.start_node(kind);
+    }
+
+    fn checkpoint(&mut self) -> Checkpoint {
+        self.flush();
+        self.builder.checkpoint()
+    }
+
+    fn finish(&mut self) {
+        self.builder.finish_node();
+    }
+
+    /// Finishes a statement with the trivia left on its last line.
+    fn finish_line(&mut self) {
+        if !self.line_start {
+            let end = self.pending.iter().position(|t| t.0 == Tok::LineBreak).unwrap_or(self.pending.len());
+            for (tok, text) in self.pending.drain(..end).collect::<Vec<_>>() {
+                self.add(tok, &text);
+            }
+        }
+        self.finish();
+    }
+
+    fn error(&mut self, expected: &[&str]) {
+        let expected = expected.iter().map(|e| e.to_string()).collect();
+        let error = match self.tokens.get(self.position) {
+            Some((token, _, location, _)) => SyntaxError::UnexpectedToken { token: token.clone(), location: *location, expected },
+            None => {
+                let location = self.tokens.last().map_or(Location::new(1, 1), |t| t.3);
+                SyntaxError::UnexpectedEof { location, expected }
+            }
+        };
+        self.errors.push(ParseError(error));
+    }
+
+    fn expect(&mut self, tok: Tok, expected: &str) -> Option<()> {
+        if self.peek() != &tok {
+            self.error(&[expected]);
+            return None;
+        }
+        self.bump();
+        Some(())
+    }
+
+    fn identifier(&mut self) -> Option<()> {
+        if !is_identifier(self.peek()) {
+            self.error(&["identifier"]);
+            return None;
+        }
+        self.bump();
+        Some(())
+    }
+
+    /// Statements end with their line, a block or the script.
+    fn end(&mut self) -> Option<()> {
+        if !matches!(self.peek(), Tok::NewLine | Tok::Dedent | Tok::EndOfFile) {
+            self.error(&["\"\\n\""]);
+            return None;
+        }
+        Some(())
+    }
+
+    /// Adds an item, or the tokens up to the end of its line as an error node
+    /// holding what was parsed of it.
+    fn recovering(&mut self, item: fn(&mut Self) -> Option<()>) {
+        let depth = self.builder.parents.len();
+        let checkpoint = self.checkpoint();
+        if item(self).is_some() {
+            return;
+        }
+        while self.builder.parents.len() > depth {
+            self.finish();
+        }
+        self.builder.start_node_at(checkpoint, SyntaxKind::Error);
+        let mut nesting = 0;
+        loop {
+            match self.peek() {
+                Tok::EndOfFile => break,
+                Tok::NewLine | Tok::Dedent if nesting == 0 => break,
+                Tok::Indent => {
+                    nesting += 1;
+                    self.skip();
+                },
+                Tok::Dedent => {
+                    nesting -= 1;
+                    self.skip();
+                },
+                _ => self.bump()
+            }
+        }
+        self.finish_line();
+    }
+
+    /// Adds an indented block of items after the line break ending its header.
+    fn block(&mut self, item: fn(&mut Self) -> Option<()>) -> Option<()> {
+        if self.peek() != &Tok::Indent {
+            self.error(&["Indent"]);
+            return None;
+        }
+        /* Comments and blank lines before the first item belong to the block */
+        self.builder.start_node(SyntaxKind::Block);
+        self.skip();
+        loop {
+            match self.peek() {
+                Tok::Dedent | Tok::EndOfFile => break,
+                Tok::NewLine => self.bump(),
+                _ => self.recovering(item)
+            }
+        }
+        self.finish();
+        if self.peek() == &Tok::Dedent {
+            self.skip();
+        }
+        Some(())
+    }
+
+    /// Whether the brackets opening at the `n`th next token close before the
+    /// line ends and are followed by `next`.
+    fn closes_before(&self, n: usize, open: Tok, close: Tok, next: Tok) -> bool {
+        let mut nesting = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(self.position + n) {
+            match &token.0 {
+                t if t == &open => nesting += 1,
+                t if t == &close => nesting -= 1,
+                Tok::NewLine | Tok::Indent | Tok::Dedent => return false,
+                _ => {}
+            }
+            if nesting == 0 {
+                return self.tokens.get(i + 1).is_some_and(|t| t.0 == next);
+            }
+        }
+        false
+    }
+
+    /// Whether the current name starts a declaration, `x =`, `float x =`,
+    /// `lib.Side x =` or `array<float> x =`.
+    fn is_declaration(&self) -> bool {
+        let mut n = self.path_end(0);
+        if n > 1 && !is_identifier(self.nth(n)) {
+            return false;
+        }
+        if self.nth(1) == &Tok::Less {
+            if !is_identifier(self.nth(2)) || self.nth(3) != &Tok::Greater {
+                return false;
+            }
+            n = 4;
+        }
+        if is_identifier(self.nth(n)) {
+            n += 1;
+        } else if n > 1 {
+            return false;
+        }
+        self.nth(n) == &Tok::Equal
+    }
+
+    fn statement(&mut self) -> Option<()> {
+        match self.peek() {
+            Tok::Import => self.import(),
+            Tok::Type | Tok::Enum => self.definition(),
+            Tok::Export => {
+                self.start(SyntaxKind::Export);
+                self.bump();
+                self.definition()?;
+                self.finish();
+                Some(())
+            },
+            Tok::For => self.for_loop(),
+            Tok::While => {
+                self.start(SyntaxKind::While);
+                self.bump();
+                self.expr()?;
+                self.expect(Tok::NewLine, "\"\\n\"")?;
+                self.block(Self::statement)?;
+                self.finish();
+                Some(())
+            },
+            Tok::Var | Tok::VarIp | Tok::Const | Tok::Series => self.declaration(),
+            Tok::OpenBrackets if self.closes_before(0, Tok::OpenBrackets, Tok::CloseBrackets, Tok::Equal) => self.unpack_tuple(),
+            Tok::Identifier { .. } => match self.nth(1) {
+                Tok::Set | Tok::EqualAdd | Tok::EqualSub | Tok::EqualMul | Tok::EqualDiv => self.assignment(),
+                Tok::OpenParenthesis if self.closes_before(1, Tok::OpenParenthesis, Tok::CloseParenthesis, Tok::Follow) => self.fn_def(),
+                _ if self.is_declaration() => self.declaration(),
+                _ => self.expr_statement()
+            },
+            _ => self.expr_statement()
+        }
+    }
+
+    /// A function, type, enum or constant, which libraries can export.
+    fn definition(&mut self) -> Option<()> {
+        let (kind, item): (_, fn(&mut Self) -> Option<()>) = match self.peek() {
+            Tok::Type => (SyntaxKind::TypeDef, Self::field),
+            Tok::Enum => (SyntaxKind::EnumDef, Self::variant),
+            Tok::Const => return self.declaration(),
+            Tok::Identifier { .. } if self.closes_before(1, Tok::OpenParenthesis, Tok::CloseParenthesis, Tok::Follow) => return self.fn_def(),
+            _ => {
+                self.error(&["\"type\"", "\"enum\"", "\"const\"", "identifier"]);
+                return None;
+            }
+        };
+        self.start(kind);
+        self.bump();
+        self.identifier()?;
+        self.expect(Tok::NewLine, "\"\\n\"")?;
+        self.block(item)?;
+        self.finish();
+        Some(())
+    }
+
+    /// `float x` in a type.
+    fn field(&mut self) -> Option<()> {
+        self.start(SyntaxKind::Field);
+        self.type_name()?;
+        self.identifier()?;
+        self.end()?;
+        self.finish_line();
+        Some(())
+    }
+
+    /// `Buy` or `Buy = "Buy"` in an enum.
+    fn variant(&mut self) -> Option<()> {
+        self.start(SyntaxKind::Variant);
+        self.identifier()?;
+        if self.peek() == &Tok::Equal {
+            self.bump();
+            if !matches!(self.peek(), Tok::String { .. }) {
+                self.error(&["string"]);
+                return None;
+            }
+            self.bump();
+        }
+        self.end()?;
+        self.finish_line();
+        Some(())
+    }
+
+    fn import(&mut self) -> Option<()> {
+        self.start(SyntaxKind::Import);
+        self.bump();
+        while is_identifier(self.peek()) || matches!(self.peek(), Tok::Int { .. }) {
+            self.bump();
+            if self.peek() != &Tok::Slash {
+                break;
+            }
+            self.bump();
+        }
+        if self.peek() == &Tok::As {
+            self.bump();
+            self.identifier()?;
+        }
+        self.end()?;
+        self.finish_line();
+        Some(())
+    }
+
+    /// The type of a declaration, if one is written before its name.
+    /// The offset of the token after the dotted name starting at the `n`th next token.
+    fn path_end(&self, mut n: usize) -> usize {
+        n += 1;
+        while self.nth(n) == &Tok::Dot && is_identifier(self.nth(n + 1)) {
+            n += 2;
+        }
+        n
+    }
+
+    /// A type name, `float` or `lib.Side` for a type a library exports.
+    fn type_name(&mut self) -> Option<()> {
+        self.identifier()?;
+        while self.peek() == &Tok::Dot {
+            self.bump();
+            self.identifier()?;
+        }
+        Some(())
+    }
+
+    fn declared_type(&mut self) -> Option<()> {
+        let end = self.path_end(0);
+        if !is_identifier(self.nth(end)) && self.nth(1) != &Tok::Less {
+            return Some(());
+        }
+        self.start(SyntaxKind::Type);
+        self.type_name()?;
+        if self.peek() == &Tok::Less {
+            self.bump();
+            self.identifier()?;
+            self.expect(Tok::Greater, "\">\"")?;
+        }
+        self.finish();
+        Some(())
+    }
+
+    fn declaration(&mut self) -> Option<()> {
+        self.start(SyntaxKind::VarDecl);
+        if matches!(self.peek(), Tok::Var | Tok::VarIp | Tok::Const | Tok::Series) {
+            self.bump();
+        }
+        self.declared_type()?;
+        self.identifier()?;
+        self.expect(Tok::Equal, "\"=\"")?;
+        self.value()?;
+        self.finish_line();
+        Some(())
+    }
+
+    fn assignment(&mut self) -> Option<()> {
+        self.start(SyntaxKind::Assignment);
+        self.bump();
+        let set = self.peek() == &Tok::Set;
+        self.bump();
+        if set {
+            self.value()?;
+        } else {
+            self.expr()?;
+            self.end()?;
+        }
+        self.finish_line();
+        Some(())
+    }
+
+    fn unpack_tuple(&mut self) -> Option<()> {
+        self.start(SyntaxKind::UnpackTuple);
+        self.bump();
+        while self.peek() != &Tok::CloseBrackets {
+            self.identifier()?;
+            if self.peek() != &Tok::Comma {
+                break;
+            }
+            self.bump();
+        }
+        self.expect(Tok::CloseBrackets, "\"]\"")?;
+        self.expect(Tok::Equal, "\"=\"")?;
+        self.value()?;
+        self.finish_line();
+        Some(())
+    }
+
+    fn fn_def(&mut self) -> Option<()> {
+        self.start(SyntaxKind::FnDef);
+        self.bump();
+        self.start(SyntaxKind::ParamList);
+        self.bump();
+        while self.peek() != &Tok::CloseParenthesis {
+            self.param()?;
+            if self.peek() != &Tok::Comma {
+                break;
+            }
+            self.bump();
+        }
+        self.expect(Tok::CloseParenthesis, "\")\"")?;
+        self.finish();
+        self.expect(Tok::Follow, "\"=>\"")?;
+        if self.peek() == &Tok::NewLine {
+            self.bump();
+            self.block(Self::statement)?;
+        } else {
+            self.expr()?;
+            self.end()?;
+        }
+        self.finish_line();
+        Some(())
+    }
+
+    /// `x`, `float x` or `float x = 1` in the parameters of a function.
+    fn param(&mut self) -> Option<()> {
+        self.start(SyntaxKind::Param);
+        if is_identifier(self.nth(self.path_end(0))) {
+            self.start(SyntaxKind::Type);
+            self.type_name()?;
+            self.finish();
+        }
+        self.identifier()?;
+        if self.peek() == &Tok::Equal {
+            self.bump();
+            self.expr()?;
+        }
+        self.finish();
+        Some(())
+    }
+
+    fn for_loop(&mut self) -> Option<()> {
+        let checkpoint = self.checkpoint();
+        self.bump();
+        self.declared_type()?;
+        self.identifier()?;
+        match self.peek() {
+            Tok::In => {
+                self.builder.start_node_at(checkpoint, SyntaxKind::ForIn);
+                self.bump();
+                self.expr()?;
+            },
+            Tok::Equal => {
+                self.builder.start_node_at(checkpoint, SyntaxKind::ForTo);
+                self.bump();
+                self.expr()?;
+                self.expect(Tok::To, "\"to\"")?;
+                self.expr()?;
+                if self.peek() == &Tok::By {
+                    self.bump();
+                    self.expr()?;
+                }
+            },
+            _ => {
+                self.error(&["\"=\"", "\"in\""]);
+                return None;
+            }
+        }
+        self.expect(Tok::NewLine, "\"\\n\"")?;
+        self.block(Self::statement)?;
+        self.finish();
+        Some(())
+    }
+
+    fn expr_statement(&mut self) -> Option<()> {
+        self.start(SyntaxKind::ExprStatement);
+        self.value()?;
+        self.finish_line();
+        Some(())
+    }
+
+    /// The value of a statement, a branch or switch going on in blocks or an expression ending the line.
+    fn value(&mut self) -> Option<()> {
+        match self.peek() {
+            Tok::If => self.if_expr(),
+            Tok::Switch => self.switch_expr(),
+            _ => {
+                self.expr()?;
+                self.end()
+            }
+        }
+    }
+
+    fn if_expr(&mut self) -> Option<()> {
+        self.start(SyntaxKind::If);
+        self.bump();
+        self.expr()?;
+        self.expect(Tok::NewLine, "\"\\n\"")?;
+        self.block(Self::statement)?;
+        if self.peek() == &Tok::Else {
+            self.bump();
+            if self.peek() == &Tok::If {
+                self.if_expr()?;
+            } else {
+                self.expect(Tok::NewLine, "\"\\n\"")?;
+                self.block(Self::statement)?;
+            }
+        }
+        self.finish();
+        Some(())
+    }
+
+    fn switch_expr(&mut self) -> Option<()> {
+        self.start(SyntaxKind::Switch);
+        self.bump();
+        if self.peek() != &Tok::NewLine {
+            self.expr()?;
+        }
+        self.expect(Tok::NewLine, "\"\\n\"")?;
+        self.block(Self::switch_arm)?;
+        self.finish();
+        Some(())
+    }
+
+    /// `value => statement`, or `=> statement` for the default.
+    fn switch_arm(&mut self) -> Option<()> {
+        self.start(SyntaxKind::SwitchArm);
+        if self.peek() != &Tok::Follow {
+            self.expr()?;
+        }
+        self.expect(Tok::Follow, "\"=>\"")?;
+        self.statement()?;
+        self.finish();
+        Some(())
+    }
+
+    fn expr(&mut self) -> Option<()> {
+        self.binary(0)
+    }
+
+    /// Operators of a tier are left associative, `a - b - c` is `(a - b) - c`.
+    fn binary(&mut self, level: usize) -> Option<()> {
+        if level == TIERS {
+            return self.prefix();
+        }
+        let checkpoint = self.checkpoint();
+        self.binary(level + 1)?;
+        while tier(self.peek()) == Some(level) {
+            self.builder.start_node_at(checkpoint, SyntaxKind::Binary);
+            self.bump();
+            self.binary(level + 1)?;
+            self.finish();
+        }
+        Some(())
+    }
+
+    /// `not`, `-` and `+` apply to a term, not to another prefix.
+    fn prefix(&mut self) -> Option<()> {
+        if !matches!(self.peek(), Tok::Not | Tok::Minus | Tok::Plus) {
+            return self.term();
+        }
+        self.start(SyntaxKind::Prefix);
+        self.bump();
+        self.term()?;
+        self.finish();
+        Some(())
+    }
+
+    fn term(&mut self) -> Option<()> {
+        match self.peek() {
+            Tok::Int { .. } | Tok::Float { .. } | Tok::String { .. } | Tok::HashColor(_) | Tok::True | Tok::False => {
+                self.start(SyntaxKind::Literal);
+                self.bump();
+            },
+            Tok::OpenParenthesis => {
+                self.start(SyntaxKind::Paren);
+                self.bump();
+                self.expr()?;
+                self.expect(Tok::CloseParenthesis, "\")\"")?;
+            },
+            Tok::OpenBrackets => {
+                self.start(SyntaxKind::Tuple);
+                self.bump();
+                while self.peek() != &Tok::CloseBrackets {
+                    self.expr()?;
+                    if self.peek() != &Tok::Comma {
+                        break;
+                    }
+                    self.bump();
+                }
+                self.expect(Tok::CloseBrackets, "\"]\"")?;
+            },
+            Tok::Identifier { .. } => return self.name(),
+            _ => {
+                self.error(&["\"(\"", "\"[\"", "identifier", "int", "float", "string", "hash_color", "\"true\"", "\"false\""]);
+                return None;
+            }
+        }
+        self.finish();
+        Some(())
+    }
+
+    /// A name, a property, a call or an indexed name, told apart after the dotted path.
+    fn name(&mut self) -> Option<()> {
+        let checkpoint = self.checkpoint();
+        self.bump();
+        let mut segments = 1;
+        while self.peek() == &Tok::Dot {
+            self.bump();
+            self.identifier()?;
+            segments += 1;
+        }
+        /* Like the method calls of the lexer, `a.b<float>(` is written without spaces */
+        let generic = segments > 1 && self.peek() == &Tok::Less && is_identifier(self.nth(1)) && self.nth(2) == &Tok::Greater
+            && self.nth(3) == &Tok::OpenParenthesis && (0..4).all(|n| self.joined(n));
+        let kind = match self.peek() {
+            _ if generic => SyntaxKind::Call,
+            Tok::OpenParenthesis => SyntaxKind::Call,
+            Tok::OpenBrackets if segments == 1 => SyntaxKind::Index,
+            _ if segments == 1 => SyntaxKind::Name,
+            _ => SyntaxKind::Property
+        };
+        self.builder.start_node_at(checkpoint, kind);
+        match kind {
+            SyntaxKind::Call => {
+                if generic {
+                    (0..3).for_each(|_| self.bump());
+                }
+                self.arguments()?;
+            },
+            SyntaxKind::Index => {
+                self.bump();
+                self.expr()?;
+                self.expect(Tok::CloseBrackets, "\"]\"")?;
+            },
+            _ => {}
+        }
+        self.finish();
+        Some(())
+    }
+
+    fn arguments(&mut self) -> Option<()> {
+        self.start(SyntaxKind::ArgList);
+        self.bump();
+        while self.peek() != &Tok::CloseParenthesis {
+            self.start(SyntaxKind::Arg);
+            if is_identifier(self.peek()) && self.nth(1) == &Tok::Equal {
+                self.bump();
+                self.bump();
+            }
+            self.expr()?;
+            self.finish();
+            if self.peek() != &Tok::Comma {
+                break;
+            }
+            self.bump();
+        }
+        self.expect(Tok::CloseParenthesis, "\")\"")?;
+        self.finish();
+        Some(())
+    }
+}
+
+/// Parses a script into its lossless syntax tree, the text of the root is the source.
+pub fn parse(src: &str) -> Parse {
+    Parser::new(src).parse()
+}
+
+/* Lowering to `ast`, for trees without errors */
+
+/// The tokens of a node which are not trivia, those of its children aside.
+fn tokens(node: &SyntaxNode) -> Vec<SyntaxToken> {
+    node.children_with_tokens().filter_map(|e| match e {
+        SyntaxElement::Token(token) if !token.kind().is_trivia() => Some(token),
+        _ => None
+    }).collect()
+}
+
+/// The names of a node which do not need a location, like types.
+fn texts(node: &SyntaxNode) -> Vec<String> {
+    tokens(node).iter().filter_map(|t| match t.tok() {
+        Tok::Identifier { name } => Some(name.clone()),
+        _ => None
+    }).collect()
+}
+
+/// Like `texts`, with the names of a dotted type name joined.
+fn type_texts(node: &SyntaxNode) -> Vec<String> {
+    let mut texts: Vec<String> = vec![];
+    let mut dotted = false;
+    for token in tokens(node) {
+        match token.tok() {
+            Tok::Identifier { name } if dotted => {
+                texts.last_mut().unwrap().push_str(&format!(".{}", name));
+                dotted = false;
+            },
+            Tok::Identifier { name } => texts.push(name.clone()),
+            Tok::Dot => dotted = !texts.is_empty(),
+            _ => {}
+        }
+    }
+    texts
+}
+
+fn exprs(node: &SyntaxNode) -> Vec<SyntaxNode> {
+    node.children().filter(|n| n.kind().is_expr()).collect()
+}
+
+fn child(node: &SyntaxNode, kind: SyntaxKind) -> Option<SyntaxNode> {
+    node.children().find(|n| n.kind() == kind)
+}
+
+/// Lowers a tree to statements, locating their names.
+struct Lowering {
+    text: String,
+    /// Byte offsets of the lines of the source.
+    lines: Vec<usize>,
+}
+
+impl Lowering {
+    fn new(text: String) -> Self {
+        let lines = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        Self { text, lines }
+    }
+
+    fn location(&self, offset: usize) -> Location {
+        let row = self.lines.partition_point(|start| *start <= offset);
+        let start = self.lines[row - 1];
+        Location::new(row, self.text[start..offset].chars().count() + 1)
+    }
+
+    fn names(&self, node: &SyntaxNode) -> Vec<Name> {
+        tokens(node).iter().filter_map(|t| match t.tok() {
+            Tok::Identifier { name } => Some(Name::new(name.clone(), Some(self.location(t.text_range().start)))),
+            _ => None
+        }).collect()
+    }
+
+    /// The last expression of a node, like the value of a declaration.
+    fn last(&self, node: &SyntaxNode) -> Option<Box<Expr>> {
+        self.expr(exprs(node).last()?)
+    }
+
+    /// The last expression of a node which may have none, like a switch without subject.
+    fn optional(&self, node: &SyntaxNode) -> Option<Option<Box<Expr>>> {
+        match exprs(node).last() {
+            Some(e) => self.expr(e).map(Some),
+            None => Some(None)
+        }
+    }
+
+    fn block(&self, node: &SyntaxNode) -> Option<Vec<Statement>> {
+        node.children().map(|n| self.statement(&n)).collect()
+    }
+
+    /// The variable of a declaration or loop, with its type if it has one.
+    fn var(&self, node: &SyntaxNode) -> Option<Var> {
+        let name = self.names(node).pop()?;
+        let declared = match child(node, SyntaxKind::Type) {
+            Some(t) => {
+                let mut names = type_texts(&t).into_iter();
+                (names.next(), names.next())
+            },
+            None => (None, None)
+        };
+        Some(Var(declared, name))
+    }
+
+    fn statement(&self, node: &SyntaxNode) -> Option<Statement> {
+        let tokens = tokens(node);
+        Some(match node.kind() {
+            SyntaxKind::Import => {
+                let (mut parts, mut alias) = (vec![], None);
+                for (i, token) in tokens.iter().enumerate() {
+                    match token.tok() {
+                        Tok::Identifier { name } if tokens[i - 1].tok() == &Tok::As => alias = Some(name.clone()),
+                        Tok::Identifier { name } => parts.push(name.clone()),
+                        Tok::Int { value } => parts.push(value.to_string()),
+                        _ => {}
+                    }
+                }
+                Statement::Import(parts.join("/"), alias)
+            },
+            SyntaxKind::VarDecl => {
+                let value = self.last(node)?;
+                let var = self.var(node)?;
+                match tokens[0].tok() {
+                    Tok::Var => Statement::VarDef(var, value),
+                    Tok::VarIp => Statement::VarIpDef(var, value),
+                    Tok::Const => Statement::ConstDef(var, value),
+                    Tok::Series => Statement::SeriesDef(var, value),
+                    _ => Statement::VarLet(var, value)
+                }
+            },
+            SyntaxKind::Assignment => {
+                let name = self.names(node).pop()?;
+                let value = self.last(node)?;
+                let op = match tokens[1].tok() {
+                    Tok::EqualAdd => Opcode::Add,
+                    Tok::EqualSub => Opcode::Sub,
+                    Tok::EqualMul => Opcode::Mul,
+                    Tok::EqualDiv => Opcode::Div,
+                    _ => return Some(Statement::VarAssign(name, value))
+                };
+                let value = Box::new(Expr::Op(Box::new(Expr::Identifier(name.clone())), op, value));
+                Statement::VarAssign(name, value)
+            },
+            SyntaxKind::UnpackTuple => Statement::UnpackTuple(self.names(node), self.last(node)?),
+            SyntaxKind::FnDef => {
+                let params = child(node, SyntaxKind::ParamList)?.children().map(|param| {
+                    Some(VarParam(self.var(&param)?, self.optional(&param)?))
+                }).collect::<Option<_>>()?;
+                let body = match child(node, SyntaxKind::Block) {
+                    Some(body) => self.block(&body)?,
+                    None => vec![Statement::Expression(self.last(node)?)]
+                };
+                Statement::FnDef(texts(node).pop()?, params, body)
+            },
+            SyntaxKind::TypeDef => {
+                let fields = child(node, SyntaxKind::Block)?.children().map(|field| {
+                    let mut names = type_texts(&field).into_iter();
+                    Some((names.next()?, names.next()?))
+                }).collect::<Option<_>>()?;
+                Statement::TypeDef(texts(node).pop()?, fields)
+            },
+            SyntaxKind::EnumDef => {
+                let variants = child(node, SyntaxKind::Block)?.children().map(|variant| {
+                    let value = self::tokens(&variant).iter().find_map(|t| match t.tok() {
+                        Tok::String { value } => Some(value.clone()),
+                        _ => None
+                    });
+                    Some((texts(&variant).pop()?, value))
+                }).collect::<Option<_>>()?;
+                Statement::EnumDef(texts(node).pop()?, variants)
+            },
+            SyntaxKind::Export => Statement::Export(Box::new(self.statement(&node.children().next()?)?)),
+            SyntaxKind::ForTo => {
+                let exprs = exprs(node);
+                let (start, end) = (self.expr(exprs.first()?)?, self.expr(exprs.get(1)?)?);
+                let by = match exprs.get(2) {
+                    Some(by) => Some(self.expr(by)?),
+                    None => None
+                };
+                Statement::ForTo(self.var(node)?, start, end, self.block(&child(node, SyntaxKind::Block)?)?, by)
+            },
+            SyntaxKind::ForIn => Statement::ForIn(self.var(node)?, self.last(node)?, self.block(&child(node, SyntaxKind::Block)?)?),
+            SyntaxKind::While => Statement::While(self.last(node)?, self.block(&child(node, SyntaxKind::Block)?)?),
+            SyntaxKind::ExprStatement => Statement::Expression(self.last(node)?),
+            _ => return None
+        })
+    }
+
+    fn expr(&self, node: &SyntaxNode) -> Option<Box<Expr>> {
+        let tokens = tokens(node);
+        Some(Box::new(match node.kind() {
+            SyntaxKind::Literal => match tokens.first()?.tok() {
+                Tok::Int { value } => Expr::Int(*value),
+                Tok::Float { value } => Expr::Float(*value),
+                Tok::String { value } => Expr::String(value.clone()),
+                Tok::HashColor(color) => Expr::HashColor(color.clone()),
+                Tok::True => Expr::Bool(true),
+                Tok::False => Expr::Bool(false),
+                _ => return None
+            },
+            SyntaxKind::Name => Expr::Identifier(self.names(node).pop()?),
+            SyntaxKind::Property => {
+                /* `lib.Side.long` is a variant of the enum `lib.Side` */
+                let mut names = self.names(node);
+                let property = names.pop()?;
+                let object = Name::new(names.iter().map(|n| n.as_str()).collect::<Vec<_>>().join("."), names.first()?.location());
+                Expr::PropertyAccess(object, property.into())
+            },
+            SyntaxKind::Call => {
+                let mut path = self.names(node);
+                /* The name in `<>` is the generic parameter of a method */
+                let generic = tokens.iter().any(|t| t.tok() == &Tok::Less).then(|| path.pop()).flatten().map(String::from);
+                let arguments = child(node, SyntaxKind::ArgList)?.children().map(|argument| {
+                    Some((self.names(&argument).pop(), self.last(&argument)?))
+                }).collect::<Option<_>>()?;
+                let method = path.pop()?;
+                match path.is_empty() {
+                    true => Expr::FnCall(method, None, arguments),
+                    false => Expr::MethodCall(path.iter().map(|n| n.as_str()).collect::<Vec<_>>().join("."), method.into(), generic, arguments)
+                }
+            },
+            SyntaxKind::Index => Expr::Index(self.names(node).pop()?, self.last(node)?),
+            SyntaxKind::Tuple => Expr::MakeTuple(exprs(node).iter().map(|e| self.expr(e)).collect::<Option<_>>()?),
+            SyntaxKind::Paren => return self.last(node),
+            SyntaxKind::Prefix => {
+                let operand = self.last(node)?;
+                match tokens.first()?.tok() {
+                    Tok::Not => Expr::Not(operand),
+                    Tok::Minus => Expr::Negative(operand),
+                    _ => return Some(operand)
+                }
+            },
+            SyntaxKind::Binary => {
+                let operands = exprs(node);
+                let op = match tokens.first()?.tok() {
+                    Tok::QuestionMark => Opcode::TernaryIf,
+                    Tok::Colon => Opcode::TernaryElse,
+                    Tok::Or => Opcode::Or,
+                    Tok::And => Opcode::And,
+                    Tok::DoubleEqual => Opcode::Equal,
+                    Tok::NotEqual => Opcode::NotEqual,
+                    Tok::Less => Opcode::Less,
+                    Tok::Greater => Opcode::Greater,
+                    Tok::Gte => Opcode::Gte,
+                    Tok::Lte => Opcode::Lte,
+                    Tok::Plus => Opcode::Add,
+                    Tok::Minus => Opcode::Sub,
+                    Tok::Mod => Opcode::Mod,
+                    Tok::Asterisk => Opcode::Mul,
+                    Tok::Slash => Opcode::Div,
+                    _ => return None
+                };
+                Expr::Op(self.expr(operands.first()?)?, op, self.expr(operands.get(1)?)?)
+            },
+            SyntaxKind::If => {
+                let mut children = node.children();
+                let condition = self.expr(&children.next()?)?;
+                let then = self.block(&children.next()?)?;
+                let otherwise = match children.next() {
+                    Some(branch) if branch.kind() == SyntaxKind::Block => Some(self.block(&branch)?),
+                    Some(branch) => Some(vec![Statement::Expression(self.expr(&branch)?)]),
+                    None => None
+                };
+                Expr::If(condition, then, otherwise)
+            },
+            SyntaxKind::Switch => {
+                let subject = self.optional(node)?;
+                let arms = child(node, SyntaxKind::Block)?.children().map(|arm| {
+                    let case = self.optional(&arm)?;
+                    let body = arm.children().find(|n| !n.kind().is_expr())?;
+                    Some((case, Box::new(self.statement(&body)?)))
+                }).collect::<Option<_>>()?;
+                Expr::Switch(subject, arms)
+            },
+            _ => return None
+        }))
+    }
+}
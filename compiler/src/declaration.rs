@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{ast::{CallArguments, Expr, Statement}, builtins, error::{CodegenError, DeclarationError}, history::MAX_BARS_BACK, types::Constant};
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum ScriptKind {
+    Indicator,
+    Strategy,
+    /// `lqstrategy()`, a strategy providing liquidity to a `liq_pool` symbol.
+    LiquidityStrategy,
+    Library,
+}
+
+/// Settings of the `indicator()`, `strategy()` or `library()` call a script starts with.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ScriptMetadata {
+    pub kind: ScriptKind,
+    pub title: String,
+    pub shorttitle: Option<String>,
+    pub overlay: bool,
+    /// Decimal places the outputs and plots are rounded to.
+    pub precision: Option<i64>,
+    /// Values kept of every series with a history, see `bars_back`.
+    pub max_bars_back: Option<i64>,
+    pub pyramiding: i64,
+    pub initial_capital: f64,
+    pub default_qty_value: f64,
+    pub commission: f64,
+    pub slippage: i64,
+    pub enable_liquidity_ratio: bool,
+    pub enable_funding: bool,
+    /// How many objects of each kind are kept, the oldest are deleted past it.
+    pub max_lines_count: i64,
+    pub max_labels_count: i64,
+    pub max_boxes_count: i64,
+    pub max_polylines_count: i64,
+}
+
+/// Whether a statement is a script declaration call.
+pub fn is_declaration(statement: &Statement) -> bool {
+    declaration_call(statement).is_some()
+}
+
+fn declaration_call(statement: &Statement) -> Option<(ScriptKind, &CallArguments)> {
+    let (name, args) = match statement {
+        Statement::Expression(e) => match e.as_ref() {
+            Expr::FnCall(name, None, args) => (name, args),
+            _ => return None
+        },
+        _ => return None
+    };
+    let kind = match name.as_str() {
+        "indicator" => ScriptKind::Indicator,
+        "strategy" => ScriptKind::Strategy,
+        "lqstrategy" => ScriptKind::LiquidityStrategy,
+        "library" => ScriptKind::Library,
+        _ => return None
+    };
+    Some((kind, args))
+}
+
+impl ScriptMetadata {
+    fn new(kind: ScriptKind) -> Self {
+        Self {
+            kind,
+            title: String::new(),
+            shorttitle: None,
+            overlay: false,
+            precision: None,
+            max_bars_back: None,
+            pyramiding: 0,
+            initial_capital: 1_000_000.0,
+            default_qty_value: 1.0,
+            commission: 0.0,
+            slippage: 0,
+            enable_liquidity_ratio: true,
+            enable_funding: true,
+            max_lines_count: 50,
+            max_labels_count: 50,
+            max_boxes_count: 50,
+            max_polylines_count: 50,
+        }
+    }
+
+    pub fn is_strategy(&self) -> bool {
+        matches!(self.kind, ScriptKind::Strategy | ScriptKind::LiquidityStrategy)
+    }
+
+    /// How many bars a history reference `x[n]` reaches back, `MAX_BARS_BACK` unless declared.
+    pub fn bars_back(&self) -> u64 {
+        self.max_bars_back.map_or(MAX_BARS_BACK, |n| n as u64)
+    }
+
+    /// Reads the declaration like `from_statements`, locating errors at the
+    /// declaration, the second one if it is duplicated.
+    pub(crate) fn located(statements: &[Statement]) -> Result<Self, CodegenError> {
+        Self::from_statements(statements).map_err(|e| {
+            let mut declarations = statements.iter().filter(|s| is_declaration(s));
+            let declaration = if e == DeclarationError::Duplicate { declarations.nth(1) } else { declarations.next() };
+            CodegenError::from(e).at(declaration.and_then(Statement::location))
+        })
+    }
+
+    /// Finds the single top level declaration of a script and reads its arguments,
+    /// which have to be literals.
+    pub fn from_statements(statements: &[Statement]) -> Result<Self, DeclarationError> {
+        let mut declarations = statements.iter().filter_map(declaration_call);
+        let (kind, args) = declarations.next().ok_or(DeclarationError::Missing)?;
+        if declarations.next().is_some() {
+            return Err(DeclarationError::Duplicate);
+        }
+
+        let function = match kind {
+            ScriptKind::Indicator => "indicator",
+            ScriptKind::Strategy => "strategy",
+            ScriptKind::LiquidityStrategy => "lqstrategy",
+            ScriptKind::Library => "library",
+        };
+        let params = builtins::function(function).unwrap().params;
+
+        let mut metadata = Self::new(kind);
+        let mut has_title = false;
+        for (i, (key, value)) in args.iter().enumerate() {
+            let (name, t) = match key {
+                Some(key) => params.iter().find(|p| p.0 == *key),
+                None => params.get(i)
+            }.ok_or_else(|| DeclarationError::UnknownArgument(key.as_ref().map_or_else(|| i.to_string(), |k| k.to_string())))?;
+
+            let value = Constant::from_expr(value).and_then(|c| c.cast(t))
+                .ok_or_else(|| DeclarationError::InvalidArgument { argument: name.clone(), expected: t.clone() })?;
+
+            match (name.as_str(), value) {
+                ("title", Constant::String(s)) => {
+                    metadata.title = s;
+                    has_title = true;
+                },
+                ("shorttitle", Constant::String(s)) => metadata.shorttitle = Some(s),
+                ("overlay", Constant::Bool(b)) => metadata.overlay = b,
+                ("pyramiding", Constant::Int(i)) => metadata.pyramiding = i,
+                ("initial_capital", Constant::Float(f)) => metadata.initial_capital = f,
+                ("default_qty_value", Constant::Float(f)) => metadata.default_qty_value = f,
+                ("commission_value", Constant::Float(f)) => metadata.commission = f,
+                ("slippage", Constant::Int(i)) => metadata.slippage = i,
+                ("enable_liquidity_ratio", Constant::Bool(b)) => metadata.enable_liquidity_ratio = b,
+                ("enable_funding", Constant::Bool(b)) => metadata.enable_funding = b,
+                /* The most Pine allows */
+                ("precision", Constant::Int(i)) => metadata.precision = Some(i.clamp(0, 16)),
+                ("max_bars_back", Constant::Int(i)) => metadata.max_bars_back = Some(i.clamp(1, MAX_BARS_BACK as i64)),
+                ("max_lines_count", Constant::Int(i)) => metadata.max_lines_count = i.clamp(1, 500),
+                ("max_labels_count", Constant::Int(i)) => metadata.max_labels_count = i.clamp(1, 500),
+                ("max_boxes_count", Constant::Int(i)) => metadata.max_boxes_count = i.clamp(1, 500),
+                ("max_polylines_count", Constant::Int(i)) => metadata.max_polylines_count = i.clamp(1, 100),
+                _ => {}
+            }
+        }
+
+        if !has_title {
+            return Err(DeclarationError::MissingTitle);
+        }
+        Ok(metadata)
+    }
+}
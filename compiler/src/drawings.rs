@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use crate::{
+    builtins,
+    declaration::ScriptMetadata,
+    types::{Constant, Type, RGBA},
+};
+#[cfg(feature = "llvm")]
+use crate::types::Signature;
+
+/* Builtins the drawing functions are compiled to. Their first argument, in
+ * place of a call site, is the kind for `NEW` and the index of the property in
+ * `properties()` for `SET`, `SET_STRING` and `GET`. Ids start at 1, 0 is `na`. */
+
+/// Creates an object of a kind and returns its id.
+#[cfg(feature = "llvm")]
+pub const NEW: &str = "drawing.new";
+/// Sets a property of an object, numbers, bools and packed colors are passed as `double`.
+#[cfg(feature = "llvm")]
+pub const SET: &str = "drawing.set";
+#[cfg(feature = "llvm")]
+pub const SET_STRING: &str = "drawing.set_string";
+/// Reads a numeric property of an object, `NaN` if it is not set.
+#[cfg(feature = "llvm")]
+pub const GET: &str = "drawing.get";
+#[cfg(feature = "llvm")]
+pub const DELETE: &str = "drawing.delete";
+/// Returns the id of a table cell, creating it or clearing its properties.
+#[cfg(feature = "llvm")]
+pub const CELL: &str = "drawing.cell";
+/// Appends a copy of a `chart.point` to a polyline.
+#[cfg(feature = "llvm")]
+pub const POINT: &str = "drawing.point";
+
+/// Signatures of the builtins above, without the leading `int32_t`.
+#[cfg(feature = "llvm")]
+pub fn import(name: &str) -> Option<Signature> {
+    let sig = |params: &[(&str, Type)], returns: Type| Signature {
+        params: params.iter().map(|(name, t)| (name.to_string(), t.clone())).collect(),
+        required: params.len(),
+        returns,
+    };
+    Some(match name {
+        NEW => sig(&[], Type::Int),
+        SET => sig(&[("id", Type::Int), ("value", Type::Float)], Type::Void),
+        SET_STRING => sig(&[("id", Type::Int), ("value", Type::String)], Type::Void),
+        GET => sig(&[("id", Type::Int)], Type::Float),
+        DELETE => sig(&[("id", Type::Int)], Type::Void),
+        CELL => sig(&[("table", Type::Int), ("column", Type::Int), ("row", Type::Int)], Type::Int),
+        POINT => sig(&[("polyline", Type::Int), ("point", Type::Int)], Type::Void),
+        _ => return None
+    })
+}
+
+/// `chart.point` objects kept, the oldest are deleted past it like other drawings
+/// as points are created on every bar and only live on copied into polylines.
+const MAX_POINTS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DrawingKind {
+    Label,
+    Line,
+    Box,
+    Table,
+    Cell,
+    Polyline,
+    Point,
+}
+
+const KINDS: [DrawingKind; 7] = [
+    DrawingKind::Label, DrawingKind::Line, DrawingKind::Box, DrawingKind::Table,
+    DrawingKind::Cell, DrawingKind::Polyline, DrawingKind::Point,
+];
+
+impl DrawingKind {
+    pub fn from_index(index: u32) -> Option<Self> {
+        KINDS.get(index as usize).copied()
+    }
+
+    pub fn index(&self) -> u32 {
+        KINDS.iter().position(|k| k == self).unwrap() as u32
+    }
+
+    fn from_namespace(namespace: &str) -> Option<Self> {
+        Some(match namespace {
+            "label" => DrawingKind::Label,
+            "line" => DrawingKind::Line,
+            "box" => DrawingKind::Box,
+            "table" => DrawingKind::Table,
+            "polyline" => DrawingKind::Polyline,
+            "chart.point" => DrawingKind::Point,
+            _ => return None
+        })
+    }
+
+    /// The function creating objects of the kind, its parameters are the properties.
+    fn constructor(&self) -> &'static str {
+        match self {
+            DrawingKind::Label => "label.new",
+            DrawingKind::Line => "line.new",
+            DrawingKind::Box => "box.new",
+            DrawingKind::Table => "table.new",
+            DrawingKind::Cell => "table.cell",
+            DrawingKind::Polyline => "polyline.new",
+            DrawingKind::Point => "chart.point.new",
+        }
+    }
+
+    /// How many objects of the kind a script keeps, the oldest ones are deleted past it.
+    fn limit(&self, metadata: &ScriptMetadata) -> Option<usize> {
+        match self {
+            DrawingKind::Label => Some(metadata.max_labels_count as usize),
+            DrawingKind::Line => Some(metadata.max_lines_count as usize),
+            DrawingKind::Box => Some(metadata.max_boxes_count as usize),
+            DrawingKind::Polyline => Some(metadata.max_polylines_count as usize),
+            DrawingKind::Point => Some(MAX_POINTS),
+            DrawingKind::Table | DrawingKind::Cell => None,
+        }
+    }
+}
+
+/// Type of the ids of a kind of object.
+pub fn handle_type(kind: DrawingKind) -> Type {
+    Type::Named(kind.constructor().rsplit_once('.').unwrap().0.to_string())
+}
+
+/// What a call of a drawing function does.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Call {
+    /// `label.new()`, `chart.point.from_index()`, the arguments are properties.
+    New(DrawingKind),
+    /// `label.set_xy()`, the parameters after the id are properties.
+    Set(DrawingKind),
+    /// `label.get_x()`.
+    Get(DrawingKind, String),
+    Delete,
+    /// `table.cell()`, the parameters after the table, column and row are properties.
+    Cell,
+}
+
+/// The drawing function `name` is, if it is one.
+pub fn call(name: &str) -> Option<Call> {
+    builtins::function(name)?;
+    if name == "table.cell" {
+        return Some(Call::Cell);
+    }
+    let (namespace, function) = name.rsplit_once('.')?;
+    let kind = DrawingKind::from_namespace(namespace)?;
+    Some(match function {
+        "new" | "from_index" | "from_time" => Call::New(kind),
+        "delete" => Call::Delete,
+        f if f.starts_with("set_") => Call::Set(kind),
+        f => Call::Get(kind, f.strip_prefix("get_")?.to_string())
+    })
+}
+
+/// Properties of all kinds, the parameters of their constructors which are not objects.
+pub fn properties() -> Vec<(DrawingKind, String, Type)> {
+    KINDS.iter().flat_map(|&kind| {
+        builtins::function(kind.constructor()).unwrap().params.into_iter()
+            .filter(|(_, t)| !matches!(t, Type::Named(_) | Type::Array(_)))
+            .map(move |(name, t)| (kind, name, t))
+    }).collect()
+}
+
+/// Index of a property in `properties()`.
+pub fn property(kind: DrawingKind, name: &str) -> Option<u32> {
+    properties().iter().position(|p| p.0 == kind && p.1 == name).map(|i| i as u32)
+}
+
+
+/// An object as the script left it at the end of the run.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Drawing {
+    pub id: i64,
+    pub kind: DrawingKind,
+    /// Properties which were set and are not `na`, by the name of the parameter of `new`.
+    #[serde(flatten)]
+    pub properties: BTreeMap<String, Constant>,
+    /// Cells of a table, by row and column.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cells: Vec<Drawing>,
+    /// Points of a polyline, with the properties of `chart.point`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub points: Vec<BTreeMap<String, Constant>>,
+}
+
+impl Drawing {
+    fn new(id: i64, kind: DrawingKind) -> Self {
+        Drawing { id, kind, properties: BTreeMap::new(), cells: vec![], points: vec![] }
+    }
+}
+
+/// The objects alive during a run, the builtins of this module operate on it.
+///
+/// Calls with the id of a deleted object or `na` do nothing.
+#[derive(Clone, Debug, Default)]
+pub struct Drawings {
+    properties: Vec<(DrawingKind, String, Type)>,
+    limits: HashMap<DrawingKind, usize>,
+    /// In creation order, ids grow.
+    objects: BTreeMap<i64, Drawing>,
+    /// Ids of the objects of every kind with a limit, the oldest first.
+    alive: HashMap<DrawingKind, BTreeSet<i64>>,
+    /// Cells by table, column and row.
+    cells: HashMap<(i64, i64, i64), i64>,
+    next: i64,
+    /// What changed since `save`, for `restore`.
+    saved: Option<Saved>,
+}
+
+/// Objects and cells as they were at `Drawings::save`, recorded on their first change since.
+#[derive(Clone, Debug, Default)]
+struct Saved {
+    objects: HashMap<i64, Option<Drawing>>,
+    cells: HashMap<(i64, i64, i64), Option<i64>>,
+    next: i64,
+}
+
+impl Drawings {
+    pub fn new(metadata: &ScriptMetadata) -> Self {
+        Self {
+            properties: properties(),
+            limits: KINDS.iter().filter_map(|k| Some((*k, k.limit(metadata)?.max(1)))).collect(),
+            next: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Starts recording changes, which `restore` undoes.
+    pub fn save(&mut self) {
+        self.saved = Some(Saved { next: self.next, ..Saved::default() });
+    }
+
+    /// Goes back to the objects at the last `save`.
+    pub fn restore(&mut self) {
+        let Some(saved) = &mut self.saved else { return };
+        for (id, object) in saved.objects.drain() {
+            if let Some(alive) = self.objects.remove(&id).and_then(|old| self.alive.get_mut(&old.kind)) {
+                alive.remove(&id);
+            }
+            if let Some(object) = object {
+                if self.limits.contains_key(&object.kind) {
+                    self.alive.entry(object.kind).or_default().insert(id);
+                }
+                self.objects.insert(id, object);
+            }
+        }
+        for (key, cell) in saved.cells.drain() {
+            match cell {
+                Some(cell) => self.cells.insert(key, cell),
+                None => self.cells.remove(&key)
+            };
+        }
+        self.next = saved.next;
+    }
+
+    /// Records the object `id` before its first change since `save`.
+    fn change(&mut self, id: i64) {
+        if let Some(saved) = &mut self.saved {
+            saved.objects.entry(id).or_insert_with(|| self.objects.get(&id).cloned());
+        }
+    }
+
+    fn change_cell(&mut self, key: (i64, i64, i64)) {
+        if let Some(saved) = &mut self.saved {
+            saved.cells.entry(key).or_insert_with(|| self.cells.get(&key).copied());
+        }
+    }
+
+    pub fn create(&mut self, kind: DrawingKind) -> i64 {
+        let id = self.next;
+        self.next += 1;
+        self.change(id);
+        self.objects.insert(id, Drawing::new(id, kind));
+        if let Some(&limit) = self.limits.get(&kind) {
+            let alive = self.alive.entry(kind).or_default();
+            alive.insert(id);
+            let oldest = (alive.len() > limit).then(|| alive.pop_first()).flatten();
+            if let Some(oldest) = oldest {
+                self.delete(oldest);
+            }
+        }
+        id
+    }
+
+    /// The object `id` if it has the property, and the name and type of the property.
+    fn property(&mut self, property: u32, id: i64) -> Option<(&mut Drawing, &str, &Type)> {
+        let (kind, name, t) = self.properties.get(property as usize)?;
+        let object = self.objects.get_mut(&id).filter(|o| o.kind == *kind)?;
+        Some((object, name, t))
+    }
+
+    pub fn set(&mut self, property: u32, id: i64, value: f64) {
+        self.change(id);
+        if let Some((object, name, t)) = self.property(property, id) {
+            let value = match t {
+                _ if value.is_nan() => None,
+                Type::Int => Some(Constant::Int(value as i64)),
+                Type::Bool => Some(Constant::Bool(value != 0.0)),
+                Type::Color if value == 0.0 => None,
+                Type::Color => Some(Constant::Color(RGBA::from_packed(value as u32))),
+                _ => Some(Constant::Float(value))
+            };
+            match value {
+                Some(value) => object.properties.insert(name.to_string(), value),
+                None => object.properties.remove(name)
+            };
+        }
+    }
+
+    pub fn set_string(&mut self, property: u32, id: i64, value: &str) {
+        self.change(id);
+        if let Some((object, name, _)) = self.property(property, id) {
+            object.properties.insert(name.to_string(), Constant::String(value.to_string()));
+        }
+    }
+
+    pub fn get(&mut self, property: u32, id: i64) -> f64 {
+        match self.property(property, id) {
+            Some((object, name, _)) => match object.properties.get(name) {
+                Some(Constant::Color(c)) => c.packed() as f64,
+                Some(Constant::Bool(b)) => *b as u8 as f64,
+                Some(c) => c.as_f64().unwrap_or(f64::NAN),
+                None => f64::NAN
+            },
+            None => f64::NAN
+        }
+    }
+
+    /// Deletes an object, with the cells of a table.
+    pub fn delete(&mut self, id: i64) {
+        self.change(id);
+        let Some(object) = self.objects.remove(&id) else { return };
+        if let Some(alive) = self.alive.get_mut(&object.kind) {
+            alive.remove(&id);
+        }
+        if object.kind == DrawingKind::Table {
+            let cells = self.cells.iter().filter(|(key, _)| key.0 == id).map(|(key, cell)| (*key, *cell)).collect::<Vec<_>>();
+            for (key, cell) in cells {
+                self.change_cell(key);
+                self.change(cell);
+                self.cells.remove(&key);
+                self.objects.remove(&cell);
+            }
+        }
+    }
+
+    pub fn cell(&mut self, table: i64, column: i64, row: i64) -> i64 {
+        if !self.objects.get(&table).is_some_and(|o| o.kind == DrawingKind::Table) {
+            return 0;
+        }
+        let id = match self.cells.get(&(table, column, row)) {
+            Some(&id) => id,
+            None => {
+                let id = self.next;
+                self.next += 1;
+                self.change_cell((table, column, row));
+                self.cells.insert((table, column, row), id);
+                id
+            }
+        };
+        self.change(id);
+        let mut cell = Drawing::new(id, DrawingKind::Cell);
+        cell.properties.insert("column".to_string(), Constant::Int(column));
+        cell.properties.insert("row".to_string(), Constant::Int(row));
+        self.objects.insert(id, cell);
+        id
+    }
+
+    pub fn point(&mut self, polyline: i64, point: i64) {
+        let point = match self.objects.get(&point).filter(|o| o.kind == DrawingKind::Point) {
+            Some(point) => point.properties.clone(),
+            None => return
+        };
+        self.change(polyline);
+        if let Some(polyline) = self.objects.get_mut(&polyline).filter(|o| o.kind == DrawingKind::Polyline) {
+            polyline.points.push(point);
+        }
+    }
+
+    /// The objects left at the end of the run in creation order, cells inside
+    /// their tables and without points, which only exist as arguments.
+    pub fn into_output(mut self) -> Vec<Drawing> {
+        let mut cells = self.cells.into_iter().collect::<Vec<_>>();
+        cells.sort_by_key(|((table, column, row), _)| (*table, *row, *column));
+        for ((table, _, _), id) in cells {
+            let cell = self.objects.remove(&id).unwrap();
+            self.objects.get_mut(&table).unwrap().cells.push(cell);
+        }
+        self.objects.into_values().filter(|o| o.kind != DrawingKind::Point).collect()
+    }
+}
@@ -1,4 +1,9 @@
-use crate::location::Location;
+use core::{fmt, time::Duration};
+
+#[cfg(feature = "llvm")]
+use inkwell::builder::BuilderError;
+
+use crate::{location::Location, optimizer::MAX_COMBINATIONS, token::Tok, types::Type};
 
 /// Represents an error during lexical scanning.
 #[derive(Debug, PartialEq)]
@@ -10,9 +15,626 @@ pub struct LexicalError {
 #[derive(Debug, PartialEq)]
 pub enum LexicalErrorType {
     NumberError,
-    StringError,
-    IndentationError,
     HashColorError,
-    Eof,
-    OtherError(String),
+}
+
+/// Represents an error met building the syntax tree of a script.
+#[derive(Debug, PartialEq)]
+pub(crate) enum SyntaxError {
+    Lexical(LexicalError),
+    /// Text which is not part of the language.
+    InvalidToken { text: String, location: Location },
+    /// A token the parser does not allow there, with what it expects instead.
+    UnexpectedToken { token: Tok, location: Location, expected: Vec<String> },
+    UnexpectedEof { location: Location, expected: Vec<String> },
+}
+
+impl SyntaxError {
+    pub(crate) fn location(&self) -> Location {
+        match self {
+            SyntaxError::Lexical(e) => e.location,
+            SyntaxError::InvalidToken { location, .. }
+            | SyntaxError::UnexpectedToken { location, .. }
+            | SyntaxError::UnexpectedEof { location, .. } => *location,
+        }
+    }
+}
+
+/// Represents an error parsing the statements of a script, see `parse`.
+#[derive(Debug, PartialEq)]
+pub struct ParseError(pub(crate) SyntaxError);
+
+impl ParseError {
+    pub fn location(&self) -> Location {
+        self.0.location()
+    }
+}
+
+/// Represents an invalid compiler annotation (`//@version=5`).
+#[derive(Debug, PartialEq)]
+pub struct AnnotationError {
+    pub error: AnnotationErrorType,
+    pub location: Location,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AnnotationErrorType {
+    InvalidVersion(String),
+    UnsupportedVersion(i32),
+    DuplicateVersion,
+}
+
+/// Represents a problem which does not stop compilation.
+#[derive(Debug, PartialEq)]
+pub enum Warning {
+    NewerVersion(i32),
+    UnknownAnnotation(String, Location),
+    DetachedAnnotation(String, Location),
+    /// A declaration hiding a variable of an outer scope or a builtin one.
+    Shadowing(String, Option<Location>),
+}
+
+/// Represents an error during type checking.
+#[derive(Debug, PartialEq)]
+pub enum TypeError {
+    Mismatch { expected: Type, found: Type },
+    TupleArity { expected: usize, found: usize },
+    NotATuple(Type),
+    NotIterable(Type),
+    UndefinedVariable(String),
+    UnknownFunction(String),
+    UnknownProperty(Type, String),
+    ArgumentCount { function: String, expected: usize, found: usize },
+    UnknownArgument { function: String, argument: String },
+}
+
+/// Represents a name which cannot be resolved, see `scopes::NameResolver`.
+#[derive(Debug, PartialEq)]
+pub enum NameError {
+    Undefined(String, Option<Location>),
+    UndefinedFunction(String, Option<Location>),
+    /// `:=` to a name which is not declared yet.
+    UndeclaredAssignment(String, Option<Location>),
+    BuiltinAssignment(String, Option<Location>),
+    /// A second declaration of a name in the same scope.
+    Redeclaration(String, Option<Location>),
+}
+
+/// Represents an invalid `indicator()`, `strategy()` or `library()` declaration.
+#[derive(Debug, PartialEq)]
+pub enum DeclarationError {
+    Missing,
+    Duplicate,
+    MissingTitle,
+    UnknownArgument(String),
+    InvalidArgument { argument: String, expected: Type },
+}
+
+/// Represents an invalid `input.*()` call or an invalid value supplied for an input.
+#[derive(Debug, PartialEq)]
+pub enum InputError {
+    UnknownFunction(String),
+    UnknownArgument { input: String, argument: String },
+    InvalidArgument { input: String, argument: String },
+    MissingDefault(String),
+    DuplicateName(String),
+    DuplicateTitle(String),
+    UnknownInput(String),
+    InvalidValue { input: String, value: String },
+    OutOfRange { input: String, value: f64 },
+    NotAnOption { input: String, value: String },
+    /// A range of values to optimize an input over which ends before it starts.
+    EmptyRange { input: String, start: f64, end: f64 },
+    /// More runs than `optimizer::MAX_COMBINATIONS`.
+    TooManyCombinations(usize),
+    Json(String),
+}
+
+/// Represents a plotting call with arguments that cannot be drawn.
+#[derive(Debug, PartialEq)]
+pub enum PlotError {
+    UnknownArgument { plot: String, argument: String },
+    /// Style arguments have to be known at compile time.
+    InvalidArgument { plot: String, argument: String },
+    /// `fill()` between something which is not a plot or hline.
+    UnknownPlot(String),
+}
+
+/// Represents a `request.security()` call whose data cannot be found.
+#[derive(Debug, PartialEq)]
+pub enum RequestError {
+    /// Symbols and timeframes have to be literals, inputs or the ones of the
+    /// chart, `gaps` and `lookahead` `barmerge` constants.
+    InvalidArgument(String),
+    /// No bars were provided for the symbol and timeframe.
+    MissingFeed { symbol: String, timeframe: String },
+}
+
+/// Represents a timeframe, timezone or session string which cannot be parsed.
+#[derive(Debug, PartialEq)]
+pub enum TimeframeError {
+    Invalid(String),
+    Timezone(String),
+    Session(String),
+}
+
+/// Represents an `import` which cannot be resolved to a library, see `modules::Resolver`.
+#[derive(Debug, PartialEq)]
+pub enum ModuleError {
+    /// Import paths are `publisher/library/version`.
+    InvalidPath(String),
+    NotFound(String),
+    Io { module: String, message: String },
+    Parse { module: String, diagnostic: Diagnostic },
+    NotALibrary(String),
+    /// A library source which is not at `publisher/library/version/library.ns`.
+    Misplaced(String),
+    /// A compiled library which cannot be read.
+    Artifact { module: String, message: String },
+    ExportOutsideLibrary(String),
+    /// Parameters of exported functions need a type.
+    UntypedExport { function: String, param: String },
+    /// The chain of imports, starting and ending with the same library.
+    Cycle(Vec<String>),
+    DuplicateAlias(String),
+    /// An alias which is also the name of a variable, function or type of the script.
+    AliasCollision(String),
+    NotExported { module: String, name: String },
+    Type { module: String, error: TypeError },
+}
+
+/// Represents a script which cannot be formatted, see `format::format`.
+#[derive(Debug, PartialEq)]
+pub enum FormatError {
+    /// Only scripts which parse are formatted.
+    Parse(Diagnostic),
+    /// The formatted script would not parse to the same statements.
+    Changed,
+}
+
+/// Represents an error during LLVM code generation.
+#[derive(Debug, PartialEq)]
+pub enum CodegenError {
+    Type(TypeError),
+    Declaration(DeclarationError),
+    Input(InputError),
+    Plot(PlotError),
+    Request(RequestError),
+    Unsupported(String),
+    /// A loop ran longer than the loop limit on a bar.
+    LoopLimit(Duration),
+    Llvm(String),
+    /// The external linker failed, with its output.
+    Link(String),
+    /// An error in the statement at a location, see `CodegenError::at`.
+    At(Box<CodegenError>, Location),
+}
+
+impl CodegenError {
+    /// Locates the error at `location`, unless it is located already.
+    pub fn at(self, location: Option<Location>) -> Self {
+        match (self, location) {
+            (e, Some(location)) if !matches!(e, CodegenError::At(..)) => CodegenError::At(Box::new(e), location),
+            (e, _) => e
+        }
+    }
+
+    /// The error without its location.
+    pub fn kind(&self) -> &CodegenError {
+        match self {
+            CodegenError::At(e, _) => e.kind(),
+            e => e
+        }
+    }
+}
+
+impl From<TypeError> for CodegenError {
+    fn from(e: TypeError) -> Self {
+        CodegenError::Type(e)
+    }
+}
+
+impl From<DeclarationError> for CodegenError {
+    fn from(e: DeclarationError) -> Self {
+        CodegenError::Declaration(e)
+    }
+}
+
+impl From<InputError> for CodegenError {
+    fn from(e: InputError) -> Self {
+        CodegenError::Input(e)
+    }
+}
+
+impl From<PlotError> for CodegenError {
+    fn from(e: PlotError) -> Self {
+        CodegenError::Plot(e)
+    }
+}
+
+impl From<RequestError> for CodegenError {
+    fn from(e: RequestError) -> Self {
+        CodegenError::Request(e)
+    }
+}
+
+#[cfg(feature = "llvm")]
+impl From<BuilderError> for CodegenError {
+    fn from(e: BuilderError) -> Self {
+        CodegenError::Llvm(e.to_string())
+    }
+}
+
+/// Represents an error compiling a script in the JIT.
+#[derive(Debug)]
+pub enum JitError {
+    Parse(ParseError),
+    Codegen(CodegenError),
+}
+
+impl From<CodegenError> for JitError {
+    fn from(e: CodegenError) -> Self {
+        JitError::Codegen(e)
+    }
+}
+
+/// `a`, `a or b`, `a, b or c` of the tokens the parser expected, keywords and
+/// punctuation in backticks like `Tok` prints them.
+fn alternatives(expected: &[String]) -> String {
+    let items = expected.iter().map(|e| match e.as_str() {
+        "\"\\n\"" => "end of line".to_string(),
+        "Indent" => "an indented block".to_string(),
+        e if e.len() > 1 && e.starts_with('"') && e.ends_with('"') => format!("`{}`", &e[1..e.len() - 1]),
+        e => e.to_string(),
+    }).collect::<Vec<_>>();
+    match items.as_slice() {
+        [] => String::new(),
+        [item] => item.clone(),
+        [init @ .., last] => format!("{} or {}", init.join(", "), last),
+    }
+}
+
+impl fmt::Display for LexicalErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexicalErrorType::NumberError => write!(f, "invalid number"),
+            LexicalErrorType::HashColorError => write!(f, "invalid color, expected #RRGGBB or #RRGGBBAA"),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntaxError::Lexical(e) => write!(f, "{}", e.error),
+            SyntaxError::InvalidToken { text, .. } => write!(f, "invalid token `{}`", text),
+            SyntaxError::UnexpectedToken { token, expected, .. } if expected.is_empty() => write!(f, "unexpected {}", token),
+            SyntaxError::UnexpectedToken { token, expected, .. } => write!(f, "unexpected {}, expected {}", token, alternatives(expected)),
+            SyntaxError::UnexpectedEof { expected, .. } if expected.is_empty() => write!(f, "unexpected end of input"),
+            SyntaxError::UnexpectedEof { expected, .. } => write!(f, "unexpected end of input, expected {}", alternatives(expected)),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.0, self.location())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for AnnotationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.error {
+            AnnotationErrorType::InvalidVersion(version) => write!(f, "invalid version {}", version),
+            AnnotationErrorType::UnsupportedVersion(version) => write!(f, "version {} is not supported", version),
+            AnnotationErrorType::DuplicateVersion => write!(f, "the version is set more than once"),
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeError::Mismatch { expected, found } => write!(f, "expected {}, found {}", expected, found),
+            TypeError::TupleArity { expected, found } => write!(f, "expected {} values, the tuple has {}", expected, found),
+            TypeError::NotATuple(t) => write!(f, "{} is not a tuple", t),
+            TypeError::NotIterable(t) => write!(f, "{} cannot be iterated", t),
+            TypeError::UndefinedVariable(name) => write!(f, "undefined variable {}", name),
+            TypeError::UnknownFunction(name) => write!(f, "unknown function {}", name),
+            TypeError::UnknownProperty(t, property) => write!(f, "{} has no property {}", t, property),
+            TypeError::ArgumentCount { function, expected, found } =>
+                write!(f, "{} takes {} arguments, {} are given", function, expected, found),
+            TypeError::UnknownArgument { function, argument } => write!(f, "{} has no argument {}", function, argument),
+        }
+    }
+}
+
+impl fmt::Display for DeclarationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeclarationError::Missing => write!(f, "the script has no indicator(), strategy() or library() declaration"),
+            DeclarationError::Duplicate => write!(f, "the script is declared more than once"),
+            DeclarationError::MissingTitle => write!(f, "the declaration has no title"),
+            DeclarationError::UnknownArgument(argument) => write!(f, "the declaration has no argument {}", argument),
+            DeclarationError::InvalidArgument { argument, expected } =>
+                write!(f, "{} of the declaration has to be a constant {}", argument, expected),
+        }
+    }
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::UnknownFunction(function) => write!(f, "unknown input function {}", function),
+            InputError::UnknownArgument { input, argument } => write!(f, "{} has no argument {}", input, argument),
+            InputError::InvalidArgument { input, argument } => write!(f, "{} of {} has to be a constant of its type", argument, input),
+            InputError::MissingDefault(function) => write!(f, "{} has no default value", function),
+            InputError::DuplicateName(name) => write!(f, "input {} is declared more than once", name),
+            InputError::DuplicateTitle(title) => write!(f, "more than one input is titled {}", title),
+            InputError::UnknownInput(name) => write!(f, "unknown input {}", name),
+            InputError::InvalidValue { input, value } => write!(f, "invalid value {} of input {}", value, input),
+            InputError::OutOfRange { input, value } => write!(f, "{} is out of the range of input {}", value, input),
+            InputError::NotAnOption { input, value } => write!(f, "{} is not an option of input {}", value, input),
+            InputError::EmptyRange { input, start, end } => write!(f, "the range of input {} ends at {} before it starts at {}", input, end, start),
+            InputError::TooManyCombinations(n) =>
+                write!(f, "{} combinations of input values are more than the {} an optimization runs", n, MAX_COMBINATIONS),
+            InputError::Json(e) => write!(f, "invalid JSON, {}", e),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+impl fmt::Display for PlotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlotError::UnknownArgument { plot, argument } => write!(f, "{} has no argument {}", plot, argument),
+            PlotError::InvalidArgument { plot, argument } => write!(f, "{} of {} has to be known at compile time", argument, plot),
+            PlotError::UnknownPlot(name) => write!(f, "{} is not a plot or hline", name),
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::InvalidArgument(argument) => write!(f, "invalid {} of request.security()", argument),
+            RequestError::MissingFeed { symbol, timeframe } => write!(f, "no bars of {} at timeframe {}", symbol, timeframe),
+        }
+    }
+}
+
+impl fmt::Display for TimeframeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeframeError::Invalid(timeframe) => write!(f, "invalid timeframe {}", timeframe),
+            TimeframeError::Timezone(timezone) => write!(f, "invalid timezone {}", timezone),
+            TimeframeError::Session(session) => write!(f, "invalid session {}", session),
+        }
+    }
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodegenError::Type(e) => write!(f, "{}", e),
+            CodegenError::Declaration(e) => write!(f, "{}", e),
+            CodegenError::Input(e) => write!(f, "{}", e),
+            CodegenError::Plot(e) => write!(f, "{}", e),
+            CodegenError::Request(e) => write!(f, "{}", e),
+            CodegenError::Unsupported(what) => write!(f, "unsupported {}", what),
+            CodegenError::LoopLimit(limit) => write!(f, "loop ran longer than {} ms", limit.as_millis()),
+            CodegenError::Llvm(e) | CodegenError::Link(e) => write!(f, "{}", e),
+            CodegenError::At(e, _) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JitError::Parse(e) => write!(f, "{}", e),
+            JitError::Codegen(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a script, located when it can be traced to a line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, location: Option<Location>) -> Self {
+        Self { severity: Severity::Error, message: message.into(), location }
+    }
+
+    pub fn warning(message: impl Into<String>, location: Option<Location>) -> Self {
+        Self { severity: Severity::Warning, message: message.into(), location }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", severity, self.message)?;
+        match self.location {
+            Some(location) => write!(f, " at {}", location),
+            None => Ok(())
+        }
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Self {
+        Diagnostic::error(e.0.to_string(), Some(e.location()))
+    }
+}
+
+impl From<AnnotationError> for Diagnostic {
+    fn from(e: AnnotationError) -> Self {
+        Diagnostic::error(e.to_string(), Some(e.location))
+    }
+}
+
+impl From<Warning> for Diagnostic {
+    fn from(w: Warning) -> Self {
+        match w {
+            Warning::NewerVersion(v) => Diagnostic::warning(format!("version {} is newer than the compiler", v), None),
+            Warning::UnknownAnnotation(key, location) => Diagnostic::warning(format!("unknown annotation @{}", key), Some(location)),
+            Warning::DetachedAnnotation(key, location) =>
+                Diagnostic::warning(format!("@{} annotation is not followed by a declaration", key), Some(location)),
+            Warning::Shadowing(name, location) => Diagnostic::warning(format!("{} shadows a variable of an outer scope", name), location),
+        }
+    }
+}
+
+impl From<NameError> for Diagnostic {
+    fn from(e: NameError) -> Self {
+        let (message, location) = match e {
+            NameError::Undefined(name, location) => (format!("undefined variable {}", name), location),
+            NameError::UndefinedFunction(name, location) => (format!("undefined function {}", name), location),
+            NameError::UndeclaredAssignment(name, location) => (format!("{} is assigned with := before being declared", name), location),
+            NameError::BuiltinAssignment(name, location) => (format!("builtin variable {} cannot be assigned", name), location),
+            NameError::Redeclaration(name, location) => (format!("{} is already declared in this scope", name), location),
+        };
+        Diagnostic::error(message, location)
+    }
+}
+
+impl From<CodegenError> for Diagnostic {
+    fn from(e: CodegenError) -> Self {
+        let location = match &e {
+            CodegenError::At(_, location) => Some(*location),
+            _ => None
+        };
+        Diagnostic::error(e.to_string(), location)
+    }
+}
+
+impl From<ModuleError> for Diagnostic {
+    fn from(e: ModuleError) -> Self {
+        let message = match e {
+            ModuleError::InvalidPath(path) => format!("invalid import {}, expected publisher/library/version", path),
+            ModuleError::NotFound(path) => format!("library {} not found", path),
+            ModuleError::Io { module, message } => format!("{}: {}", module, message),
+            ModuleError::Parse { module, diagnostic } => format!("{}: {}", module, diagnostic),
+            ModuleError::NotALibrary(path) => format!("{} is not a library()", path),
+            ModuleError::Misplaced(file) => format!("{}: libraries are compiled from publisher/library/version/library.ns", file),
+            ModuleError::Artifact { module, message } => format!("{}: invalid compiled library, {}", module, message),
+            ModuleError::ExportOutsideLibrary(name) => format!("{} is exported outside of a library()", name),
+            ModuleError::UntypedExport { function, param } => format!("parameter {} of exported {} has no type", param, function),
+            ModuleError::Cycle(chain) => format!("import cycle {}", chain.join(" -> ")),
+            ModuleError::DuplicateAlias(alias) => format!("{} is imported twice", alias),
+            ModuleError::AliasCollision(alias) => format!("{} is both an import alias and a name declared by the script", alias),
+            ModuleError::NotExported { module, name } => format!("{} does not export {}", module, name),
+            ModuleError::Type { module, error } => format!("{}: {}", module, error),
+        };
+        Diagnostic::error(message, None)
+    }
+}
+
+impl From<FormatError> for Diagnostic {
+    fn from(e: FormatError) -> Self {
+        match e {
+            FormatError::Parse(diagnostic) => diagnostic,
+            FormatError::Changed => Diagnostic::error("formatting would change the meaning of the script", None),
+        }
+    }
+}
+
+impl From<InputError> for Diagnostic {
+    fn from(e: InputError) -> Self {
+        Diagnostic::error(e.to_string(), None)
+    }
+}
+
+impl From<JitError> for Diagnostic {
+    fn from(e: JitError) -> Self {
+        match e {
+            JitError::Parse(e) => e.into(),
+            JitError::Codegen(e) => e.into(),
+        }
+    }
+}
+
+/// Diagnostics of one source, displayed with the lines they point at.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diagnostics {
+    source: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new(source: &str) -> Self {
+        Self { source: source.to_string(), diagnostics: vec![] }
+    }
+
+    pub fn push(&mut self, diagnostic: impl Into<Diagnostic>) {
+        self.diagnostics.push(diagnostic.into());
+    }
+
+    /// Diagnostics holding a single error.
+    pub fn error(source: &str, diagnostic: impl Into<Diagnostic>) -> Self {
+        let mut diagnostics = Self::new(source);
+        diagnostics.push(diagnostic);
+        diagnostics
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.severity == Severity::Error)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}", diagnostic)?;
+            if let Some(location) = diagnostic.location {
+                let line = self.source.split('\n').nth(location.row().saturating_sub(1)).unwrap_or("");
+                writeln!(f, "{}", location.visualize(line))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+/// Input values have no location in the source, see `Optimizer::run`.
+impl From<InputError> for Diagnostics {
+    fn from(e: InputError) -> Self {
+        Diagnostics::error("", e)
+    }
 }
@@ -0,0 +1,125 @@
+use serde_json::{json, Value};
+#[cfg(any(all(test, feature = "llvm"), target_arch = "wasm32"))]
+use serde_json::Map;
+
+#[cfg(any(all(test, feature = "llvm"), target_arch = "wasm32"))]
+use crate::{runtime::Output, types::{Type, RGBA}};
+use crate::runtime::{Bar, RunResult};
+
+/// One row per bar with the time, the outputs, then a value and a color column per plot.
+///
+/// Plots which only have a color (`bgcolor`, `barcolor`, `fill`) get no value column,
+/// colors are `#RRGGBBAA` and left empty for `na`. Names repeated in the header get
+/// a number, ` 2` for the second, and cells are quoted as in RFC 4180.
+pub fn csv(bars: &[Bar], result: &RunResult) -> String {
+    let mut header = vec!["time".to_string()];
+    for series in &result.outputs {
+        header.push(unique(&header, &series.name));
+    }
+    for (i, series) in result.plots.iter().enumerate() {
+        let mut name = series.plot.name(i);
+        if series.plot.kind.value_param().is_some() {
+            name = unique(&header, &name);
+            header.push(name.clone());
+        }
+        header.push(unique(&header, &format!("{}.color", name)));
+    }
+
+    let mut csv = header.iter().map(|h| quote(h)).collect::<Vec<_>>().join(",") + "\n";
+    for (i, bar) in bars.iter().enumerate() {
+        let mut row = vec![bar.time.to_string()];
+        row.extend(result.outputs.iter().map(|s| s.values[i].to_string()));
+        for series in &result.plots {
+            if series.plot.kind.value_param().is_some() {
+                row.push(series.values[i].to_string());
+            }
+            row.push(series.colors[i].as_ref().map(|c| c.to_string()).unwrap_or_default());
+        }
+        csv += &row.join(",");
+        csv.push('\n');
+    }
+    csv
+}
+
+/// `name`, numbered from 2 if `header` already has it.
+fn unique(header: &[String], name: &str) -> String {
+    let mut unique = name.to_string();
+    let mut n = 1;
+    while header.contains(&unique) {
+        n += 1;
+        unique = format!("{} {}", name, n);
+    }
+    unique
+}
+
+/// A CSV cell, in double quotes with theirs doubled if it has a separator, quote or line break.
+fn quote(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// The plots of `result` with their names, without values if they only have a color.
+pub fn plots(result: &RunResult) -> Vec<Value> {
+    result.plots.iter().enumerate().map(|(i, series)| {
+        let mut plot = json!(series);
+        plot["name"] = json!(series.plot.name(i));
+        if series.plot.kind.value_param().is_none() {
+            plot.as_object_mut().unwrap().remove("values");
+        }
+        plot
+    }).collect()
+}
+
+/// The bar times, outputs, plots with their style, the drawing objects left at
+/// the end, the strategy report and the alerts as a JSON object.
+///
+/// Values are `null` where nothing was drawn.
+pub fn json(bars: &[Bar], result: &RunResult) -> String {
+    json!({
+        "time": bars.iter().map(|b| b.time).collect::<Vec<_>>(),
+        "outputs": result.outputs,
+        "plots": plots(result),
+        "drawings": result.drawings,
+        "strategy": result.strategy,
+        "alerts": result.alerts,
+    }).to_string()
+}
+
+/// The run as the `Result` of `bindings::typescript`: outputs by name,
+/// booleans and colors as such, and the plots like `json`.
+#[cfg(any(all(test, feature = "llvm"), target_arch = "wasm32"))]
+pub fn typed(outputs: &[Output], result: &RunResult) -> Value {
+    let values = outputs.iter().zip(&result.outputs).map(|(output, series)| {
+        let values = series.values.iter().map(|&v| match output.t {
+            Type::Bool => json!(v != 0.0),
+            Type::Color => json!(RGBA::from_packed(v as u32)),
+            _ => json!(v)
+        }).collect::<Vec<_>>();
+        (series.name.clone(), Value::from(values))
+    }).collect::<Map<_, _>>();
+    let mut json = json!({
+        "outputs": values,
+        "plots": plots(result),
+        "drawings": result.drawings,
+        "alerts": result.alerts,
+    });
+    if let Some(strategy) = &result.strategy {
+        json["strategy"] = json!(strategy);
+    }
+    json
+}
+
+impl RunResult {
+    /// The run over `bars` as CSV, see `csv`.
+    pub fn to_csv(&self, bars: &[Bar]) -> String {
+        csv(bars, self)
+    }
+
+    /// The run over `bars` as JSON, see `json`.
+    pub fn to_json(&self, bars: &[Bar]) -> String {
+        json(bars, self)
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashSet;
+
+use crate::{error::FormatError, lexer::Lexer, parse, token::Tok};
+
+/// Lines longer than this are wrapped after the commas of argument lists.
+pub const WIDTH: usize = 100;
+
+const INDENT: usize = 4;
+
+/// Indentation of continuation lines past the one of their statement, a
+/// continuation has to be deeper than a block and not a multiple of `INDENT`.
+const CONTINUATION: usize = INDENT + 2;
+
+/// A token with its text as written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub tok: Tok,
+    pub text: String,
+    /// Whether whitespace precedes the token in the source.
+    pub spaced: bool,
+    /// Whether the token starts a line, a comment then has a continuation line of its own.
+    pub line_start: bool,
+}
+
+/// A line of a script, blocks are nested by depth.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+    /// A statement with its continuation lines and their comments.
+    Code { depth: usize, tokens: Vec<Token> },
+    Comment { depth: usize, text: String },
+    Blank,
+}
+
+/// Collects the lines of a script from the tokens of the lexer.
+#[derive(Default)]
+struct Lines {
+    lines: Vec<Line>,
+    /// The statement whose tokens are being collected, with its depth.
+    code: Option<(usize, Vec<Token>)>,
+    /// Depth of the block the tokens are in, from the indents and dedents of the lexer.
+    depth: usize,
+    newlines: usize,
+    indent: usize,
+    spaced: bool,
+    line_start: bool,
+}
+
+impl Lines {
+    fn end(&mut self) {
+        if let Some((depth, tokens)) = self.code.take() {
+            self.lines.push(Line::Code { depth, tokens });
+        }
+    }
+
+    fn token(&mut self, tok: Tok, text: String) {
+        match tok {
+            Tok::NewLine | Tok::LineBreak => {
+                /* Only the line breaks which are not trivia end a statement */
+                if tok == Tok::NewLine {
+                    self.end();
+                }
+                self.newlines += 1;
+                self.indent = 0;
+                self.spaced = false;
+                self.line_start = true;
+                return;
+            },
+            Tok::Whitespace => {
+                if self.line_start {
+                    self.indent = text.chars().map(|c| if c == '\t' { INDENT } else { 1 }).sum();
+                }
+                self.spaced = true;
+                return;
+            },
+            Tok::Indent => {
+                self.depth += 1;
+                return;
+            },
+            Tok::Dedent => {
+                self.depth = self.depth.saturating_sub(1);
+                return;
+            },
+            _ => {}
+        }
+
+        /* Blank lines within a statement are dropped, runs of them collapsed */
+        if self.code.is_none() && self.newlines > 1 && !self.lines.is_empty() {
+            self.lines.push(Line::Blank);
+        }
+        let text = text.trim_end().to_string();
+        let line_start = std::mem::take(&mut self.line_start);
+        match (&mut self.code, tok) {
+            (None, Tok::Comment) => self.lines.push(Line::Comment { depth: self.indent / INDENT, text }),
+            (code, tok) => {
+                let token = Token { tok, text, spaced: self.spaced, line_start };
+                code.get_or_insert_with(|| (self.depth, vec![])).1.push(token);
+            }
+        }
+        self.newlines = 0;
+        self.spaced = false;
+    }
+}
+
+/// Collects the lines of a script, a statement continued on deeper lines is one line.
+pub fn lines(src: &str) -> Vec<Line> {
+    let mut lines = Lines { line_start: true, ..Lines::default() };
+    let mut lexer = Lexer::new(src, INDENT);
+    while let Some(token) = lexer.next() {
+        /* A malformed token is kept as written */
+        let tok = token.map_or(Tok::Unknown, |(_, tok, _)| tok);
+        lines.token(tok, lexer.text());
+    }
+    lines.end();
+    lines.lines
+}
+
+fn is_open(tok: &Tok) -> bool {
+    matches!(tok, Tok::OpenParenthesis | Tok::OpenBrackets)
+}
+
+fn is_close(tok: &Tok) -> bool {
+    matches!(tok, Tok::CloseParenthesis | Tok::CloseBrackets)
+}
+
+/// Indices of the `<` and `>` of generic types like `array.new<float>`, written
+/// right after the type name and enclosing only names.
+fn generics(tokens: &[Token]) -> HashSet<usize> {
+    let mut result = HashSet::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let name = i > 0 && matches!(tokens[i - 1].tok, Tok::Identifier { .. });
+        if token.text != "<" || !name || token.spaced || result.contains(&i) {
+            continue;
+        }
+        let mut nesting = 0;
+        for (j, t) in tokens.iter().enumerate().skip(i) {
+            match t.tok {
+                Tok::Less => nesting += 1,
+                Tok::Greater => nesting -= 1,
+                Tok::Identifier { .. } | Tok::Dot | Tok::Comma => {},
+                _ => break
+            }
+            if nesting == 0 {
+                result.extend((i..=j).filter(|k| matches!(tokens[*k].text.as_str(), "<" | ">")));
+                break;
+            }
+        }
+    }
+    result
+}
+
+/// Whether a space separates `token` from the one before it.
+fn spaced(tokens: &[Token], i: usize, generics: &HashSet<usize>, unary: &HashSet<usize>) -> bool {
+    let (previous, token) = (&tokens[i - 1], &tokens[i]);
+    let call = is_close(&previous.tok) || matches!(previous.tok, Tok::Identifier { .. });
+    match token.tok {
+        Tok::Comment => return true,
+        Tok::Comma | Tok::Dot => return false,
+        ref tok if is_close(tok) => return false,
+        ref tok if is_open(tok) && (call || generics.contains(&(i - 1))) => return false,
+        _ => {}
+    }
+    if generics.contains(&i) || (tokens[0].text == "import" && (token.text == "/" || previous.text == "/")) {
+        return false;
+    }
+    !(is_open(&previous.tok) || previous.tok == Tok::Dot
+        || (generics.contains(&(i - 1)) && previous.text == "<") || unary.contains(&(i - 1)))
+}
+
+/// Indices of prefix `-`, `+` and `!` operators.
+fn unary(tokens: &[Token], generics: &HashSet<usize>) -> HashSet<usize> {
+    (0..tokens.len()).filter(|&i| {
+        matches!(tokens[i].text.as_str(), "-" | "+" | "!") && (i == 0 || match &tokens[i - 1].tok {
+            Tok::Less | Tok::Greater => !generics.contains(&(i - 1)),
+            /* After anything but an operand, a keyword like `and` included */
+            tok => !is_close(tok) && !matches!(tok, Tok::Identifier { .. } | Tok::Int { .. } | Tok::Float { .. }
+                | Tok::String { .. } | Tok::HashColor(_) | Tok::True | Tok::False | Tok::Dot | Tok::Comment | Tok::Unknown)
+        })
+    }).collect()
+}
+
+/// Prints a statement, wrapping it after the commas of argument lists if it is too long.
+fn statement(depth: usize, tokens: &[Token], output: &mut String) {
+    let generics = generics(tokens);
+    let unary = unary(tokens, &generics);
+
+    /* Lines are broken after the outermost commas of brackets, except in the
+     * names of a tuple unpacking and in a function header which has to stay on
+     * the line of its `=>` */
+    let start = match tokens.iter().position(|t| t.text == "=>") {
+        Some(i) => i,
+        None if tokens[0].text == "[" => tokens.iter().position(|t| t.text == "=").unwrap_or(0),
+        None => 0
+    };
+    let mut nesting = 0;
+    let commas = tokens.iter().enumerate().map(|(i, token)| {
+        match token.tok {
+            Tok::Comma if i > start && nesting > 0 => return Some(nesting),
+            ref tok if is_open(tok) => nesting += 1,
+            ref tok if is_close(tok) => nesting -= 1,
+            _ => {}
+        }
+        None
+    }).collect::<Vec<_>>();
+    let outermost = commas.iter().flatten().min().copied();
+
+    /* Chunks of the statement and whether a line break has to follow them */
+    let mut chunks = vec![(String::new(), false)];
+    for (i, token) in tokens.iter().enumerate() {
+        let comment = token.tok == Tok::Comment;
+        if comment && token.line_start && !chunks.last().unwrap().0.is_empty() {
+            chunks.last_mut().unwrap().1 = true;
+            chunks.push((String::new(), false));
+        }
+        let chunk = chunks.last_mut().unwrap();
+        if !chunk.0.is_empty() && spaced(tokens, i, &generics, &unary) {
+            chunk.0.push(' ');
+        }
+        chunk.0.push_str(&token.text);
+        if comment {
+            chunk.1 = true;
+            chunks.push((String::new(), false));
+        } else if commas[i].is_some() && commas[i] == outermost {
+            chunks.push((String::new(), false));
+        }
+    }
+
+    let indent = depth * INDENT;
+    output.push_str(&" ".repeat(indent));
+    let mut column = indent;
+    let mut broken = false;
+    for (chunk, breaks) in chunks.iter().filter(|c| !c.0.is_empty()) {
+        if column > indent && (broken || column + 1 + chunk.len() > WIDTH) {
+            output.push('\n');
+            output.push_str(&" ".repeat(indent + CONTINUATION));
+            column = indent + CONTINUATION;
+        } else if column > indent {
+            output.push(' ');
+            column += 1;
+        }
+        output.push_str(chunk);
+        column += chunk.len();
+        broken = *breaks;
+    }
+    output.push('\n');
+}
+
+/// Formats a script: 4 spaces indentation, single spaces around operators, no
+/// spaces inside brackets, long argument lists wrapped and runs of blank lines
+/// collapsed to one. Comments are kept where they are.
+pub fn format(src: &str) -> Result<String, FormatError> {
+    let statements = parse(src).map_err(|e| FormatError::Parse(e.into()))?;
+
+    let mut output = String::new();
+    for line in lines(src) {
+        match line {
+            Line::Code { depth, tokens } => statement(depth, &tokens, &mut output),
+            Line::Comment { depth, text } => {
+                output.push_str(&" ".repeat(depth * INDENT));
+                output.push_str(&text);
+                output.push('\n');
+            },
+            Line::Blank => output.push('\n'),
+        }
+    }
+
+    /* The layout must not change what the script means */
+    let formatted = parse(&output).map_err(|_| FormatError::Changed)?;
+    if formatted != statements {
+        return Err(FormatError::Changed);
+    }
+    Ok(output)
+}
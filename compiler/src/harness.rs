@@ -0,0 +1,104 @@
+use wasmtime::{Engine, Instance, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::{
+    inputs::Input,
+    runtime::{Bar, Output},
+    types::Constant,
+};
+
+const PAGE: usize = 65536;
+
+/// Runs a module built by `target::wasm` through its host ABI.
+///
+/// Builtins fail without a run of the runtime, which the harness does not start.
+pub struct WasmScript {
+    store: Store<()>,
+    memory: Memory,
+    init: TypedFunc<(u32, u32), ()>,
+    on_bar: TypedFunc<(u32, u32), ()>,
+    get_outputs: TypedFunc<u32, u32>,
+    /// Start of a page the harness owns, bars and parameters are written there.
+    scratch: usize,
+    /// Start of the state block of the run.
+    state: usize,
+    schema: Vec<Input>,
+    outputs: Vec<Output>,
+}
+
+impl WasmScript {
+    pub fn new(wasm: &[u8], schema: Vec<Input>, outputs: Vec<Output>) -> wasmtime::Result<Self> {
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm)?;
+        let mut linker = Linker::new(&engine);
+        linker.define_unknown_imports_as_traps(&module)?;
+
+        let mut store = Store::new(&engine, ());
+        let instance: Instance = linker.instantiate(&mut store, &module)?;
+        let memory = instance.get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("the module does not export its memory"))?;
+        let scratch = memory.grow(&mut store, 1)? as usize * PAGE;
+        let state_size = instance.get_typed_func::<(), u64>(&mut store, "state_size")?.call(&mut store, ())? as usize;
+        let state = memory.grow(&mut store, state_size.div_ceil(PAGE) as u64)? as usize * PAGE;
+
+        Ok(Self {
+            init: instance.get_typed_func(&mut store, "init")?,
+            on_bar: instance.get_typed_func(&mut store, "on_bar")?,
+            get_outputs: instance.get_typed_func(&mut store, "get_outputs")?,
+            store,
+            memory,
+            scratch,
+            state,
+            schema,
+            outputs,
+        })
+    }
+
+    /// Calls `init` with input values in schema order, as from `inputs::resolve`.
+    pub fn init(&mut self, values: &[Constant]) -> wasmtime::Result<()> {
+        let strings = values.iter().map(|v| match v {
+            Constant::String(s) => s.len() + 1,
+            _ => 0
+        }).sum::<usize>();
+        let size = values.len() * 8 + strings;
+        /* Parameters follow the bar in the scratch page if they fit */
+        let params = if size <= PAGE - 64 {
+            self.scratch + 64
+        } else {
+            self.memory.grow(&mut self.store, size.div_ceil(PAGE) as u64)? as usize * PAGE
+        };
+
+        let mut slots = vec![];
+        let mut strings = vec![];
+        for (input, value) in self.schema.iter().zip(values) {
+            let slot = match (input.slot(value), value) {
+                (Some(slot), _) => slot,
+                (None, Constant::String(s)) => {
+                    let address = params + values.len() * 8 + strings.len();
+                    strings.extend_from_slice(s.as_bytes());
+                    strings.push(0);
+                    address as u64
+                },
+                (None, value) => return Err(wasmtime::Error::msg(format!("invalid value {:?} of {}", value, input.name)))
+            };
+            slots.extend(slot.to_le_bytes());
+        }
+        slots.extend(strings);
+        self.memory.write(&mut self.store, params, &slots)?;
+        self.init.call(&mut self.store, (self.state as u32, params as u32))
+    }
+
+    /// Runs one bar and returns the outputs after it.
+    pub fn on_bar(&mut self, bar: &Bar) -> wasmtime::Result<Vec<f64>> {
+        let mut bytes = bar.time.to_le_bytes().to_vec();
+        for field in [bar.open, bar.high, bar.low, bar.close, bar.volume] {
+            bytes.extend(field.to_le_bytes());
+        }
+        self.memory.write(&mut self.store, self.scratch, &bytes)?;
+        self.on_bar.call(&mut self.store, (self.state as u32, self.scratch as u32))?;
+
+        let outputs = self.get_outputs.call(&mut self.store, self.state as u32)? as usize;
+        let mut bytes = vec![0u8; self.outputs.len() * 8];
+        self.memory.read(&self.store, outputs, &mut bytes)?;
+        Ok(bytes.chunks(8).map(|b| f64::from_le_bytes(b.try_into().unwrap())).collect())
+    }
+}
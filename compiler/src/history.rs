@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Expr, Node, Statement},
+    declaration,
+    error::CodegenError,
+};
+
+/// The most bars a history reference `x[n]` reaches back, and how far it does
+/// unless the declaration sets `max_bars_back`. References further back are `na`.
+pub const MAX_BARS_BACK: u64 = 5000;
+
+/// Bar values with a history, along with top level variables.
+const BAR_VALUES: [&str; 9] = ["time", "open", "high", "low", "close", "volume", "hl2", "hlc3", "ohlc4"];
+
+/// Names of the series a script references the history of, in source order.
+///
+/// Backends keep the last `ScriptMetadata::bars_back` values of each at the end of every bar,
+/// which only works for bar values and variables declared once at the top level.
+pub(crate) fn collect(statements: &[Statement]) -> Result<Vec<String>, CodegenError> {
+    let mut top_level = HashMap::new();
+    for statement in statements.iter().filter(|s| !declaration::is_declaration(s)) {
+        for name in declared(statement) {
+            *top_level.entry(name).or_insert(0) += 1;
+        }
+    }
+
+    let mut everywhere = HashMap::new();
+    let mut references = vec![];
+    for statement in statements {
+        statement.walk(&mut |node| match node {
+            Node::Statement(s) => {
+                for name in declared(s) {
+                    *everywhere.entry(name).or_insert(0) += 1;
+                }
+            },
+            Node::Expr(Expr::Index(name, _)) if !references.iter().any(|(r, _)| r == name) => references.push((name.to_string(), name.location())),
+            _ => {}
+        });
+    }
+
+    for (name, location) in &references {
+        let valid = match top_level.get(name.as_str()) {
+            Some(count) => everywhere[name.as_str()] == *count,
+            None => !everywhere.contains_key(name.as_str()) && BAR_VALUES.contains(&name.as_str())
+        };
+        if !valid {
+            return Err(CodegenError::Unsupported(format!("history of {}", name)).at(*location));
+        }
+    }
+    Ok(references.into_iter().map(|(name, _)| name).collect())
+}
+
+/// Variables and parameters a statement declares, not counting nested statements.
+fn declared(statement: &Statement) -> Vec<&str> {
+    match statement {
+        Statement::ConstDef(var, _)
+        | Statement::SeriesDef(var, _)
+        | Statement::VarIpDef(var, _)
+        | Statement::VarDef(var, _)
+        | Statement::VarLet(var, _)
+        | Statement::ForTo(var, ..)
+        | Statement::ForIn(var, ..) => vec![var.1.as_str()],
+        Statement::UnpackTuple(names, _) => names.iter().map(|n| n.as_str()).collect(),
+        Statement::FnDef(_, params, _) => params.iter().map(|p| p.0.1.as_str()).collect(),
+        _ => vec![]
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ast::{CallArguments, Expr, Node, Statement}, builtins, error::{CodegenError, InputError}, types::{Constant, Type, RGBA}};
+
+/// Series an `input.source()` can select, the compiled value is an index into this list.
+pub const SOURCES: [&str; 8] = ["open", "high", "low", "close", "volume", "hl2", "hlc3", "ohlc4"];
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputKind {
+    Int,
+    Float,
+    Bool,
+    String,
+    Source,
+    Timeframe,
+    Color,
+}
+
+/// A script parameter declared with one of the `input.*()` functions.
+///
+/// Inputs are named after the variable they are assigned to, falling back to
+/// their title, so hosts can override them by name.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Input {
+    pub name: String,
+    pub title: Option<String>,
+    pub kind: InputKind,
+    pub default: Constant,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub step: Option<f64>,
+    pub options: Vec<Constant>,
+    pub group: Option<String>,
+    pub tooltip: Option<String>,
+}
+
+impl Input {
+    fn new(kind: InputKind, default: Constant) -> Self {
+        Self {
+            name: String::new(),
+            title: None,
+            kind,
+            default,
+            min: None,
+            max: None,
+            step: None,
+            options: vec![],
+            group: None,
+            tooltip: None,
+        }
+    }
+
+    pub fn get_type(&self) -> Type {
+        match self.kind {
+            InputKind::Int => Type::Int,
+            InputKind::Float | InputKind::Source => Type::Float,
+            InputKind::Bool => Type::Bool,
+            InputKind::String | InputKind::Timeframe => Type::String,
+            InputKind::Color => Type::Color,
+        }
+    }
+
+    /// Checks a value supplied by the host against the type, bounds and options of the input.
+    pub fn validate(&self, value: Constant) -> Result<Constant, InputError> {
+        let invalid = |value: &Constant| InputError::InvalidValue { input: self.name.clone(), value: format!("{:?}", value) };
+        let value = match (&self.kind, value) {
+            (InputKind::Source, Constant::String(s)) if SOURCES.contains(&s.as_str()) => Constant::String(s),
+            (InputKind::Source, v) => return Err(invalid(&v)),
+            (_, v) => v.clone().cast(&self.get_type()).ok_or_else(|| invalid(&v))?
+        };
+
+        if let Some(v) = value.as_f64() {
+            if self.min.is_some_and(|min| v < min) || self.max.is_some_and(|max| v > max) {
+                return Err(InputError::OutOfRange { input: self.name.clone(), value: v });
+            }
+        }
+        if !self.options.is_empty() && !self.options.contains(&value) {
+            return Err(InputError::NotAnOption { input: self.name.clone(), value: format!("{:?}", value) });
+        }
+        Ok(value)
+    }
+
+    /// The 8 byte parameter slot `init` reads this input from, see `Processor::compile`.
+    /// `None` for strings, which are passed as pointers to NUL terminated bytes.
+    pub fn slot(&self, value: &Constant) -> Option<u64> {
+        Some(match value {
+            Constant::String(s) if self.kind == InputKind::Source => SOURCES.iter().position(|x| x == s)? as u64,
+            Constant::String(_) => return None,
+            Constant::Bool(b) => *b as u64,
+            Constant::Int(i) if self.kind == InputKind::Float => (*i as f64).to_bits(),
+            Constant::Int(i) => *i as u64,
+            Constant::Float(f) => f.to_bits(),
+            Constant::Color(c) => c.packed() as u64,
+        })
+    }
+
+    /// Reads a value of this input from JSON, colors are written as `#RRGGBB[AA]` strings.
+    pub fn value_from_json(&self, json: &serde_json::Value) -> Result<Constant, InputError> {
+        let value = match (&self.kind, json) {
+            (InputKind::Color, serde_json::Value::String(s)) => RGBA::from_hex(s).map(Constant::Color),
+            (_, serde_json::Value::String(s)) => Some(Constant::String(s.clone())),
+            (_, serde_json::Value::Bool(b)) => Some(Constant::Bool(*b)),
+            (_, serde_json::Value::Number(n)) => n.as_i64().map(Constant::Int).or_else(|| n.as_f64().map(Constant::Float)),
+            _ => None
+        };
+        let value = value.ok_or_else(|| InputError::InvalidValue { input: self.name.clone(), value: json.to_string() })?;
+        self.validate(value)
+    }
+}
+
+/// Finds the input calls of a script, keyed by the address of the call expression.
+pub(crate) fn collect(statements: &[Statement]) -> Result<Vec<(*const Expr, Input)>, CodegenError> {
+    let mut names = HashMap::new();
+    let mut calls = vec![];
+    for statement in statements {
+        let location = statement.location();
+        statement.walk(&mut |node| match node {
+            Node::Statement(Statement::VarLet(var, e))
+            | Node::Statement(Statement::VarDef(var, e))
+            | Node::Statement(Statement::VarIpDef(var, e))
+            | Node::Statement(Statement::ConstDef(var, e))
+            | Node::Statement(Statement::SeriesDef(var, e)) => {
+                names.insert(e.as_ref() as *const Expr, var.1.to_string());
+            },
+            Node::Expr(e @ Expr::MethodCall(object, name, _, args)) if object == "input" => calls.push((e, name, args, e.location().or(location))),
+            _ => {}
+        });
+    }
+
+    let mut inputs: Vec<(*const Expr, Input)> = vec![];
+    for (i, (e, name, args, location)) in calls.into_iter().enumerate() {
+        let located = |error: InputError| CodegenError::from(error).at(location);
+        let mut input = input(name, args).map_err(located)?;
+        input.name = names.get(&(e as *const Expr)).cloned()
+            .or_else(|| input.title.clone())
+            .unwrap_or_else(|| format!("input{}", i));
+        /* Hosts address inputs by name and users by title, both have to be unique */
+        if let Some(other) = inputs.iter().map(|i| &i.1).find(|i| i.name == input.name) {
+            return Err(located(InputError::DuplicateName(other.name.clone())));
+        }
+        if let Some(other) = inputs.iter().map(|i| &i.1).find(|i| i.title.is_some() && i.title == input.title) {
+            return Err(located(InputError::DuplicateTitle(other.title.clone().unwrap())));
+        }
+        inputs.push((e as *const Expr, input));
+    }
+    Ok(inputs)
+}
+
+/// The parameter schema of a script, in source order.
+#[cfg(any(test, feature = "llvm"))]
+pub fn extract(statements: &[Statement]) -> Result<Vec<Input>, CodegenError> {
+    Ok(collect(statements)?.into_iter().map(|i| i.1).collect())
+}
+
+fn input(name: &str, args: &CallArguments) -> Result<Input, InputError> {
+    let function = format!("input.{}", name);
+    let kind = match name {
+        "int" => InputKind::Int,
+        "float" => InputKind::Float,
+        "bool" => InputKind::Bool,
+        "string" => InputKind::String,
+        "source" => InputKind::Source,
+        "timeframe" => InputKind::Timeframe,
+        "color" => InputKind::Color,
+        _ => return Err(InputError::UnknownFunction(function))
+    };
+    let params = builtins::function(&function).unwrap().params;
+
+    let args = args.iter().enumerate().map(|(i, (key, value))| match key {
+        Some(key) => params.iter().find(|p| p.0 == *key),
+        None => params.get(i)
+    }.map(|(param, t)| (param, t, value.as_ref()))
+        .ok_or_else(|| InputError::UnknownArgument { input: function.clone(), argument: key.as_ref().map_or_else(|| i.to_string(), |k| k.to_string()) })
+    ).collect::<Result<Vec<_>, _>>()?;
+    let invalid = |param: &String| InputError::InvalidArgument { input: function.clone(), argument: param.clone() };
+
+    let default = match args.iter().find(|a| a.0 == "defval") {
+        Some((_, _, Expr::Identifier(source))) if kind == InputKind::Source && SOURCES.contains(&source.as_str()) =>
+            Constant::String(source.to_string()),
+        Some((param, t, value)) => Constant::from_expr(value).and_then(|c| c.cast(t)).ok_or_else(|| invalid(param))?,
+        None => return Err(InputError::MissingDefault(function))
+    };
+
+    let mut input = Input::new(kind, default);
+    for (param, t, value) in args {
+        match (param.as_str(), value) {
+            ("defval", _) => {},
+            ("options", Expr::MakeTuple(items)) => {
+                let item_type = match t {
+                    Type::Array(item) => item.as_ref(),
+                    t => t
+                };
+                input.options = items.iter()
+                    .map(|e| Constant::from_expr(e).and_then(|c| c.cast(item_type)))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| invalid(param))?;
+            },
+            (name, value) => {
+                let value = Constant::from_expr(value).and_then(|c| c.cast(t)).ok_or_else(|| invalid(param))?;
+                match (name, value) {
+                    ("title", Constant::String(s)) => input.title = Some(s),
+                    ("group", Constant::String(s)) => input.group = Some(s),
+                    ("tooltip", Constant::String(s)) => input.tooltip = Some(s),
+                    ("minval", v) => input.min = v.as_f64(),
+                    ("maxval", v) => input.max = v.as_f64(),
+                    ("step", v) => input.step = v.as_f64(),
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(input)
+}
+
+/// Values of all inputs in schema order, overridden ones are validated.
+pub fn resolve(inputs: &[Input], overrides: &HashMap<String, Constant>) -> Result<Vec<Constant>, InputError> {
+    if let Some(unknown) = overrides.keys().find(|k| !inputs.iter().any(|i| i.name == **k)) {
+        return Err(InputError::UnknownInput(unknown.clone()));
+    }
+    inputs.iter().map(|input| match overrides.get(&input.name) {
+        Some(value) => input.validate(value.clone()),
+        None => Ok(input.default.clone())
+    }).collect()
+}
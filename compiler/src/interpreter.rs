@@ -0,0 +1,1043 @@
+use std::{collections::{HashMap, HashSet}, mem, time::Duration};
+
+use crate::{
+    ast::{CallArguments, Expr, Opcode, Statement, Var, VarParam},
+    builtins,
+    calendar,
+    checker::TypeChecker,
+    declaration::{self, ScriptMetadata},
+    drawings::{self, Call, DrawingKind},
+    error::{CodegenError, TypeError},
+    history,
+    inputs::{self, Input, InputKind, SOURCES},
+    modules,
+    plots::{self, Plot, PlotKind},
+    runtime::{Bar, BarState, Output, RunResult, Runtime, Series, LOOP_LIMIT, NA_INT},
+    security::{self, Feeds, Request},
+    timeframe::Timeframe,
+    types::{Constant, Type},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// `0xRRGGBBAA`, like compiled code.
+    Color(u32),
+    String(String),
+    Tuple(Vec<Value>),
+}
+
+type Typed = (Value, Type);
+
+impl Value {
+    /// What `na` becomes when stored as anything but a float. Fails for types
+    /// compiled code cannot hold either.
+    fn zero(t: &Type) -> Result<Self, CodegenError> {
+        Ok(match t {
+            Type::Int => Value::Int(0),
+            Type::Float | Type::Na => Value::Float(0.0),
+            Type::Bool => Value::Bool(false),
+            Type::Color => Value::Color(0),
+            Type::String => Value::String(String::new()),
+            Type::Tuple(items) => Value::Tuple(items.iter().map(Value::zero).collect::<Result<_, _>>()?),
+            t if t.is_handle() => Value::Int(0),
+            t => return Err(CodegenError::Unsupported(format!("values of type {}", t)))
+        })
+    }
+
+    fn constant(c: &Constant) -> Self {
+        match c {
+            Constant::String(s) => Value::String(s.clone()),
+            Constant::Bool(b) => Value::Bool(*b),
+            Constant::Int(i) => Value::Int(*i),
+            Constant::Float(f) => Value::Float(*f),
+            Constant::Color(c) => Value::Color(c.packed()),
+        }
+    }
+
+    fn floats(values: [f64; 3]) -> Self {
+        Value::Tuple(values.into_iter().map(Value::Float).collect())
+    }
+
+    /// The value as a `double`, the way outputs are exposed.
+    fn float(&self) -> f64 {
+        match self {
+            Value::Int(NA_INT) => f64::NAN,
+            Value::Int(i) => *i as f64,
+            Value::Float(f) => *f,
+            Value::Bool(b) => *b as u8 as f64,
+            Value::Color(c) => *c as f64,
+            _ => f64::NAN
+        }
+    }
+
+    fn int(&self) -> i64 {
+        match self {
+            Value::Int(i) => *i,
+            Value::Bool(b) => *b as i64,
+            Value::Color(c) => *c as i64,
+            _ => 0
+        }
+    }
+
+    fn bool(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+
+    fn string(&self) -> &str {
+        match self {
+            Value::String(s) => s,
+            _ => ""
+        }
+    }
+}
+
+fn cast(value: Value, from: &Type, to: &Type) -> Result<Value, CodegenError> {
+    Ok(match (from, to, value) {
+        (Type::Int, Type::Float, v) => Value::Float(v.float()),
+        (Type::Na, Type::Na | Type::Float, v) => v,
+        (Type::Na, Type::Int, _) => Value::Int(NA_INT),
+        (Type::Na, t, _) => Value::zero(t)?,
+        (Type::Tuple(from), Type::Tuple(to), Value::Tuple(items)) => Value::Tuple(
+            items.into_iter().zip(from.iter().zip(to)).map(|(v, (f, t))| cast(v, f, t)).collect::<Result<_, _>>()?
+        ),
+        (_, _, v) => v
+    })
+}
+
+fn order<T: PartialOrd>(op: &Opcode, a: T, b: T) -> bool {
+    match op {
+        Opcode::Equal => a == b,
+        Opcode::NotEqual => a != b,
+        Opcode::Greater => a > b,
+        Opcode::Gte => a >= b,
+        Opcode::Less => a < b,
+        _ => a <= b,
+    }
+}
+
+fn compare(op: &Opcode, l: Typed, r: Typed) -> Result<Value, CodegenError> {
+    if matches!((&l.1, &r.1), (Type::Int, Type::Int) | (Type::Bool, Type::Bool) | (Type::Color, Type::Color)) {
+        return Ok(Value::Bool(order(op, l.0.int(), r.0.int())));
+    }
+
+    if !l.1.is_numeric() && l.1 != Type::Na || !r.1.is_numeric() && r.1 != Type::Na {
+        return Err(CodegenError::Unsupported(format!("comparison of {} and {}", l.1, r.1)));
+    }
+    let a = cast(l.0, &l.1, &Type::Float)?.float();
+    let b = cast(r.0, &r.1, &Type::Float)?.float();
+    Ok(Value::Bool(order(op, a, b)))
+}
+
+fn arithmetic(op: &Opcode, l: Typed, r: Typed) -> Result<Typed, CodegenError> {
+    if l.1 == Type::Int && r.1 == Type::Int {
+        let (a, b) = (l.0.int(), r.0.int());
+        /* `na` operands and zero divisors give `na`, like the compiled code */
+        let v = match op {
+            _ if a == NA_INT || b == NA_INT => None,
+            Opcode::Add => Some(a.wrapping_add(b)),
+            Opcode::Sub => Some(a.wrapping_sub(b)),
+            Opcode::Mul => Some(a.wrapping_mul(b)),
+            Opcode::Div => a.checked_div(b),
+            _ => a.checked_rem(b),
+        };
+        return Ok((Value::Int(v.unwrap_or(NA_INT)), Type::Int));
+    }
+
+    if !l.1.is_numeric() && l.1 != Type::Na || !r.1.is_numeric() && r.1 != Type::Na {
+        return Err(CodegenError::Unsupported(format!("arithmetic on {} and {}", l.1, r.1)));
+    }
+    let a = cast(l.0, &l.1, &Type::Float)?.float();
+    let b = cast(r.0, &r.1, &Type::Float)?.float();
+    let v = match op {
+        Opcode::Add => a + b,
+        Opcode::Sub => a - b,
+        Opcode::Mul => a * b,
+        Opcode::Div => a / b,
+        _ => a % b,
+    };
+    Ok((Value::Float(v), Type::Float))
+}
+
+/// Storage of a top level or `var` variable: the call context it belongs to,
+/// the address of its declaration and the index of the name in it.
+type Key = (usize, usize, usize);
+
+/// The call context of the top level code, see `State::contexts`.
+const TOP_LEVEL: usize = 0;
+
+/// Runs scripts by walking their syntax tree, with the semantics of compiled code.
+///
+/// Needs no LLVM, so it stands in for the `jit` where LLVM is unavailable and
+/// serves as a reference for it. Constructs the code generator rejects are
+/// reported once a run reaches them.
+pub struct Interpreter {
+    statements: Vec<Statement>,
+    checker: TypeChecker,
+    metadata: ScriptMetadata,
+    schema: Vec<Input>,
+    /// Schema indices of the inputs, keyed by the address of their call.
+    inputs: HashMap<usize, usize>,
+    outputs: Vec<Output>,
+    /// Where the outputs are stored, in order.
+    keys: Vec<Key>,
+    plots: Vec<Plot>,
+    /// Indices of the plots, keyed by the address of their call.
+    plot_calls: HashMap<usize, (usize, PlotKind)>,
+    requests: Vec<Request>,
+    /// Indices of the requests, keyed by the address of their call.
+    request_calls: HashMap<usize, usize>,
+    /// Series with a history, see `history::collect`.
+    histories: Vec<String>,
+    loop_limit: Duration,
+}
+
+impl Interpreter {
+    pub fn new(statements: Vec<Statement>) -> Result<Self, CodegenError> {
+        let metadata = ScriptMetadata::located(&statements)?;
+        let mut checker = TypeChecker::new();
+        checker.check(&statements).map_err(|e| CodegenError::from(e).at(checker.location()))?;
+        let inputs = inputs::collect(&statements)?;
+        let plots = plots::collect(&statements)?;
+        let requests = security::collect(&statements)?;
+        let histories = history::collect(&statements)?;
+
+        /* Top level declarations of scalars, see `Processor::outputs` */
+        let mut outputs = vec![];
+        let mut keys = vec![];
+        for statement in statements.iter().filter(|s| !declaration::is_declaration(s)) {
+            let declared = match statement {
+                Statement::ConstDef(var, _)
+                | Statement::SeriesDef(var, _)
+                | Statement::VarIpDef(var, _)
+                | Statement::VarDef(var, _)
+                | Statement::VarLet(var, _) => vec![(&var.1, Type::from_var_type(&var.0))],
+                Statement::UnpackTuple(names, _) => names.iter().map(|n| (n, None)).collect(),
+                _ => vec![]
+            };
+            for (i, (name, t)) in declared.into_iter().enumerate().filter(|(_, (name, _))| !modules::is_linked(name)) {
+                if let Some(t @ (Type::Int | Type::Float | Type::Bool | Type::Color)) = t.or_else(|| checker.variable(name).cloned()) {
+                    outputs.push(Output { name: name.to_string(), t });
+                    keys.push((TOP_LEVEL, statement as *const Statement as usize, i));
+                }
+            }
+        }
+
+        Ok(Self {
+            checker,
+            metadata,
+            inputs: inputs.iter().enumerate().map(|(i, (e, _))| (*e as usize, i)).collect(),
+            schema: inputs.into_iter().map(|i| i.1).collect(),
+            outputs,
+            keys,
+            plot_calls: plots.iter().enumerate().map(|(i, (e, plot))| (*e as usize, (i, plot.kind))).collect(),
+            plots: plots.into_iter().map(|p| p.1).collect(),
+            request_calls: requests.iter().enumerate().map(|(i, (e, _))| (*e as usize, i)).collect(),
+            requests: requests.into_iter().map(|r| r.1).collect(),
+            histories,
+            loop_limit: LOOP_LIMIT,
+            statements,
+        })
+    }
+
+    /// How long a loop may run on a bar before the run fails, `runtime::LOOP_LIMIT` by default.
+    pub fn with_loop_limit(mut self, limit: Duration) -> Self {
+        self.loop_limit = limit;
+        self
+    }
+
+    pub fn metadata(&self) -> &ScriptMetadata {
+        &self.metadata
+    }
+
+    pub fn inputs(&self) -> &[Input] {
+        &self.schema
+    }
+
+    pub fn outputs(&self) -> &[Output] {
+        &self.outputs
+    }
+
+    pub fn plots(&self) -> &[Plot] {
+        &self.plots
+    }
+
+    #[cfg(feature = "llvm")]
+    pub fn requests(&self) -> &[Request] {
+        &self.requests
+    }
+
+    /// Runs the script from its first bar like `Executable::run_with`.
+    pub fn run_with(&self, bars: &[Bar], values: &[Constant], feeds: &Feeds) -> Result<RunResult, CodegenError> {
+        let mut state = self.start(bars, values, feeds)?;
+        let outputs = state.execute(bars)?;
+        Ok(state.runtime.into_result(outputs))
+    }
+
+    /// The state of a run over `bars` before its first bar, like `Executable::start`.
+    pub fn start(&self, bars: &[Bar], values: &[Constant], feeds: &Feeds) -> Result<State<'_>, CodegenError> {
+        let values = self.schema.iter().enumerate().map(|(i, input)| match values.get(i) {
+            Some(value) => input.validate(value.clone()),
+            None => Ok(input.default.clone())
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        let requested = security::prefetch(&self.requests, &values, feeds, bars, |feed, timeframe| {
+            let runtime = Runtime::new(&self.metadata)
+                .with_loop_limit(self.loop_limit)
+                .with_timeframe(timeframe)
+                .with_calendar(feeds.timezone, feeds.session.clone())
+                .recording(self.requests.len());
+            let mut state = self.state(&values, runtime);
+            state.execute(feed)?;
+            Ok::<_, CodegenError>(state.runtime.into_recorded())
+        })?;
+        let runtime = Runtime::new(&self.metadata)
+            .with_loop_limit(self.loop_limit)
+            .with_symbol(&feeds.symbol)
+            .with_timeframe(&feeds.timeframe)
+            .with_calendar(feeds.timezone, feeds.session.clone())
+            .with_plots(&self.plots)
+            .with_requested(requested);
+        Ok(self.state(&values, runtime))
+    }
+
+    /// A run with the input `values` and builtins of `runtime`.
+    fn state(&self, values: &[Constant], runtime: Runtime) -> State<'_> {
+        State {
+            interpreter: self,
+            values: values.to_vec(),
+            runtime,
+            bar: Bar::default(),
+            bar_index: 0,
+            scopes: vec![],
+            statics: HashMap::new(),
+            varip: HashSet::new(),
+            saved: None,
+            locals: vec![],
+            functions: HashMap::new(),
+            sites: HashMap::new(),
+            contexts: HashMap::new(),
+            context: TOP_LEVEL,
+            histories: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Binding {
+    Static(Key),
+    /// Index into the locals of the running function.
+    Local(usize),
+}
+
+type Scope = HashMap<String, (Binding, Type)>;
+
+struct Function<'a> {
+    params: &'a [VarParam],
+    body: &'a [Statement],
+    /// The globals declared before the function.
+    globals: Scope,
+}
+
+/// State of one run of an `Interpreter`, which goes through the bars one at a time.
+pub struct State<'a> {
+    interpreter: &'a Interpreter,
+    values: Vec<Constant>,
+    runtime: Runtime,
+    bar: Bar,
+    bar_index: i64,
+    /// The first scope holds globals, the rest are locals of the current function.
+    scopes: Vec<Scope>,
+    /// Top level and `var` variables, kept between bars.
+    statics: HashMap<Key, Typed>,
+    /// Keys of the `varip` variables, which `restore` leaves alone.
+    varip: HashSet<Key>,
+    /// What `restore` returns to.
+    saved: Option<(HashMap<Key, Typed>, Runtime, i64)>,
+    locals: Vec<Value>,
+    functions: HashMap<String, Function<'a>>,
+    /// Call site ids of builtins, keyed by the call context and the address of the call.
+    sites: HashMap<(usize, usize), u32>,
+    /// Ids of the call contexts of user functions, keyed by the context of the
+    /// caller and the address of the call, so every chain of calls keeps its
+    /// own `var` variables and builtin call sites like compiled code.
+    contexts: HashMap<(usize, usize), usize>,
+    context: usize,
+    /// The last values of the series with a history, at `bar_index % bars_back`
+    /// like `Processor::record_histories`.
+    histories: HashMap<&'a str, Vec<Value>>,
+}
+
+impl<'a> State<'a> {
+    /// Runs the statements over the history `bars`, returning the outputs on every bar.
+    fn execute(&mut self, bars: &[Bar]) -> Result<Vec<Series>, CodegenError> {
+        let mut outputs = self.interpreter.outputs.iter()
+            .map(|o| Series { name: o.name.clone(), values: Vec::with_capacity(bars.len()) })
+            .collect::<Vec<_>>();
+        for (i, bar) in bars.iter().enumerate() {
+            let values = self.bar(bar, BarState::history(i + 1 == bars.len()))?;
+            for (series, value) in outputs.iter_mut().zip(values) {
+                series.values.push(value);
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Runs the statements once on `bar`, returning the outputs.
+    pub fn bar(&mut self, bar: &Bar, barstate: BarState) -> Result<Vec<f64>, CodegenError> {
+        self.runtime.start_bar(bar, barstate);
+        self.bar = *bar;
+        self.scopes = vec![HashMap::new()];
+        for statement in self.interpreter.statements.iter().filter(|s| !declaration::is_declaration(s)) {
+            self.statement(statement)?;
+        }
+        let slot = (self.bar_index as u64 % self.interpreter.metadata.bars_back()) as usize;
+        let interpreter = self.interpreter;
+        for name in &interpreter.histories {
+            let value = self.identifier(name)?.0;
+            let ring = self.histories.entry(name).or_default();
+            if slot < ring.len() {
+                ring[slot] = value;
+            } else {
+                ring.push(value);
+            }
+        }
+        let outputs = self.interpreter.keys.iter()
+            .map(|key| self.statics.get(key).map_or(f64::NAN, |v| v.0.float()))
+            .collect();
+        self.runtime.end_bar();
+        self.bar_index += 1;
+        Ok(outputs)
+    }
+
+    /// Remembers the state between two bars.
+    pub fn save(&mut self) {
+        self.saved = Some((self.statics.clone(), self.runtime.save(), self.bar_index));
+    }
+
+    /// Goes back to the state last saved, except for `varip` variables.
+    pub fn restore(&mut self) {
+        let Some((statics, runtime, bar_index)) = &self.saved else { return };
+        let varip = self.varip.iter().filter_map(|key| Some((*key, self.statics.get(key)?.clone()))).collect::<Vec<_>>();
+        self.statics = statics.clone();
+        self.statics.extend(varip);
+        self.runtime.restore(runtime);
+        self.bar_index = *bar_index;
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
+
+    fn lookup(&self, name: &str) -> Option<(Binding, Type)> {
+        self.scopes.iter().rev().find_map(|s| s.get(name)).cloned()
+    }
+
+    fn load(&self, binding: Binding) -> Value {
+        match binding {
+            Binding::Static(key) => self.statics[&key].0.clone(),
+            Binding::Local(i) => self.locals[i].clone(),
+        }
+    }
+
+    fn store(&mut self, binding: Binding, value: Value) {
+        match binding {
+            Binding::Static(key) => self.statics.get_mut(&key).unwrap().0 = value,
+            Binding::Local(i) => self.locals[i] = value,
+        }
+    }
+
+    /// Storage for a new variable: static at the top level, where `key` identifies
+    /// the declaration, a local otherwise.
+    fn declare(&mut self, name: &str, t: &Type, value: Value, key: Option<Key>) -> Result<Binding, CodegenError> {
+        Value::zero(t)?;
+        let binding = match key.filter(|_| self.scopes.len() == 1) {
+            Some(key) => {
+                self.statics.insert(key, (value, t.clone()));
+                Binding::Static(key)
+            },
+            None => {
+                self.locals.push(value);
+                Binding::Local(self.locals.len() - 1)
+            }
+        };
+        self.scopes.last_mut().unwrap().insert(name.to_string(), (binding, t.clone()));
+        Ok(binding)
+    }
+
+    fn block(&mut self, statements: &'a [Statement]) -> Result<Option<Typed>, CodegenError> {
+        self.scopes.push(HashMap::new());
+        let depth = self.locals.len();
+        let mut result = None;
+        for statement in statements {
+            result = self.statement(statement)?;
+        }
+        self.scopes.pop();
+        self.locals.truncate(depth);
+        Ok(result)
+    }
+
+    fn declaration(&mut self, key: Key, var: &Var, value: &'a Expr) -> Result<Option<Typed>, CodegenError> {
+        let (v, vt) = self.value(value)?;
+        let t = Type::from_var_type(&var.0).unwrap_or_else(|| vt.clone());
+        let v = cast(v, &vt, &t)?;
+        self.declare(&var.1, &t, v.clone(), Some(key))?;
+        Ok(Some((v, t)))
+    }
+
+    /// `var` declarations keep their value between bars, the initializer runs once per run.
+    fn persistent_declaration(&mut self, key: Key, var: &Var, value: &'a Expr) -> Result<Option<Typed>, CodegenError> {
+        if !self.statics.contains_key(&key) {
+            let (v, vt) = self.value(value)?;
+            let t = Type::from_var_type(&var.0).unwrap_or_else(|| vt.clone());
+            Value::zero(&t)?;
+            let v = cast(v, &vt, &t)?;
+            self.statics.insert(key, (v, t));
+        }
+        let (v, t) = self.statics[&key].clone();
+        self.scopes.last_mut().unwrap().insert(var.1.to_string(), (Binding::Static(key), t.clone()));
+        Ok(Some((v, t)))
+    }
+
+    /// Runs a statement, locating its errors at it.
+    fn statement(&mut self, statement: &'a Statement) -> Result<Option<Typed>, CodegenError> {
+        self.statement_value(statement).map_err(|e| e.at(statement.location()))
+    }
+
+    fn statement_value(&mut self, statement: &'a Statement) -> Result<Option<Typed>, CodegenError> {
+        let context = self.context;
+        let key = |i| (context, statement as *const Statement as usize, i);
+        Ok(match statement {
+            Statement::Import(..) | Statement::TypeDef(..) | Statement::EnumDef(..) => None,
+            Statement::UnpackTuple(names, value) => {
+                let (v, t) = self.value(value)?;
+                let (items, types) = match (&v, &t) {
+                    (Value::Tuple(items), Type::Tuple(types)) => (items.clone(), types.clone()),
+                    _ => return Err(CodegenError::Type(TypeError::NotATuple(t)))
+                };
+                for (i, (name, (item, t))) in names.iter().zip(items.into_iter().zip(&types)).enumerate() {
+                    self.declare(name, t, item, Some(key(i)))?;
+                }
+                Some((v, t))
+            },
+            Statement::ConstDef(var, value)
+            | Statement::SeriesDef(var, value)
+            | Statement::VarLet(var, value) => self.declaration(key(0), var, value)?,
+            Statement::VarDef(var, value) => self.persistent_declaration(key(0), var, value)?,
+            Statement::VarIpDef(var, value) => {
+                self.varip.insert(key(0));
+                self.persistent_declaration(key(0), var, value)?
+            },
+            Statement::VarAssign(name, value) => {
+                let (binding, t) = self.lookup(name)
+                    .ok_or_else(|| CodegenError::Unsupported(format!("assignment to {}", name)))?;
+                let (v, vt) = self.value(value)?;
+                let v = cast(v, &vt, &t)?;
+                self.store(binding, v.clone());
+                Some((v, t))
+            },
+            Statement::ForTo(var, start, end, body, by) => {
+                let site = self.site(statement as *const Statement as usize);
+                self.for_to(site, var, start, end, body, by.as_deref())?;
+                None
+            },
+            Statement::ForIn(..) => return Err(CodegenError::Unsupported("for ... in loops".to_string())),
+            Statement::While(condition, body) => {
+                let site = self.site(statement as *const Statement as usize);
+                self.runtime.loop_start(site);
+                while self.value(condition)?.0.bool() {
+                    self.runtime.loop_next(site)?;
+                    self.block(body)?;
+                }
+                None
+            },
+            Statement::Export(definition) => self.statement(definition)?,
+            Statement::FnDef(name, params, body) => {
+                let globals = self.scopes[0].clone();
+                self.functions.insert(name.clone(), Function { params, body, globals });
+                None
+            },
+            Statement::Expression(e) => self.expr(e)?,
+        })
+    }
+
+    /// A `for` loop, timed against the loop limit at call site `site`.
+    fn for_to(&mut self, site: u32, var: &Var, start: &'a Expr, end: &'a Expr, body: &'a [Statement], by: Option<&'a Expr>) -> Result<(), CodegenError> {
+        let t = Type::from_var_type(&var.0).unwrap_or(Type::Int);
+
+        self.scopes.push(HashMap::new());
+        let depth = self.locals.len();
+        let (v, vt) = self.value(start)?;
+        let counter = self.declare(&var.1, &t, cast(v, &vt, &t)?, None)?;
+        let (v, vt) = self.value(end)?;
+        let end = cast(v, &vt, &t)?;
+        let step = match by {
+            Some(by) => {
+                let (v, vt) = self.value(by)?;
+                cast(v, &vt, &t)?
+            },
+            None => cast(Value::Int(1), &Type::Int, &t)?
+        };
+
+        self.runtime.loop_start(site);
+        while compare(&Opcode::Lte, (self.load(counter), t.clone()), (end.clone(), t.clone()))?.bool() {
+            self.runtime.loop_next(site)?;
+            self.block(body)?;
+            let next = arithmetic(&Opcode::Add, (self.load(counter), t.clone()), (step.clone(), t.clone()))?.0;
+            self.store(counter, next);
+        }
+
+        self.scopes.pop();
+        self.locals.truncate(depth);
+        Ok(())
+    }
+
+    fn value(&mut self, expr: &'a Expr) -> Result<Typed, CodegenError> {
+        self.expr(expr)?.ok_or_else(|| CodegenError::Unsupported("use of an expression without a value".to_string()))
+    }
+
+    fn expr(&mut self, expr: &'a Expr) -> Result<Option<Typed>, CodegenError> {
+        Ok(Some(match expr {
+            Expr::Identifier(name) => self.identifier(name)?,
+            Expr::String(s) => (Value::String(s.clone()), Type::String),
+            Expr::Bool(b) => (Value::Bool(*b), Type::Bool),
+            Expr::Int(i) => (Value::Int(*i), Type::Int),
+            Expr::Float(f) => (Value::Float(*f), Type::Float),
+            Expr::HashColor(c) => (Value::Color(c.packed()), Type::Color),
+            Expr::MakeTuple(items) => {
+                let (values, types) = items.iter().map(|e| self.value(e)).collect::<Result<Vec<_>, _>>()?.into_iter().unzip();
+                (Value::Tuple(values), Type::Tuple(types))
+            },
+            Expr::Op(left, Opcode::TernaryElse, otherwise) => {
+                let (condition, then) = match left.as_ref() {
+                    Expr::Op(condition, Opcode::TernaryIf, then) => (condition, then),
+                    _ => return Err(CodegenError::Unsupported("`:` without `?`".to_string()))
+                };
+                /* Both sides run, like the `select` of compiled code */
+                let c = self.value(condition)?.0.bool();
+                let (a, at) = self.value(then)?;
+                let (b, bt) = self.value(otherwise)?;
+                let t = at.unify(&bt).ok_or(CodegenError::Type(TypeError::Mismatch { expected: at.clone(), found: bt.clone() }))?;
+                (if c { cast(a, &at, &t)? } else { cast(b, &bt, &t)? }, t)
+            },
+            Expr::Op(_, Opcode::TernaryIf, _) => return Err(CodegenError::Unsupported("`?` without `:`".to_string())),
+            Expr::Op(left, op, right) => {
+                let l = self.value(left)?;
+                let r = self.value(right)?;
+                match op {
+                    Opcode::And => (Value::Bool(l.0.bool() & r.0.bool()), Type::Bool),
+                    Opcode::Or => (Value::Bool(l.0.bool() | r.0.bool()), Type::Bool),
+                    Opcode::Equal | Opcode::NotEqual | Opcode::Greater | Opcode::Gte | Opcode::Less | Opcode::Lte =>
+                        (compare(op, l, r)?, Type::Bool),
+                    _ => arithmetic(op, l, r)?
+                }
+            },
+            Expr::If(condition, then, otherwise) => {
+                let value = if self.value(condition)?.0.bool() {
+                    self.block(then)?
+                } else {
+                    match otherwise {
+                        Some(otherwise) => self.block(otherwise)?,
+                        None => None
+                    }
+                };
+                return self.merge(expr, value);
+            },
+            Expr::Switch(subject, variants) => return self.switch(expr, subject.as_deref(), variants),
+            Expr::Not(e) => (Value::Bool(!self.value(e)?.0.bool()), Type::Bool),
+            Expr::Negative(e) => match self.value(e)? {
+                (v, Type::Int) => (Value::Int(v.int().wrapping_neg()), Type::Int),
+                (v, t) => (Value::Float(-v.float()), t)
+            },
+            Expr::FnCall(_, _, args) if self.interpreter.plot_calls.contains_key(&(expr as *const Expr as usize)) => return self.plot(expr, args),
+            Expr::FnCall(name, _, args) => return self.call(expr, name, args),
+            Expr::MethodCall(object, _, _, _) if object == "input" => self.input(expr),
+            Expr::MethodCall(_, _, _, args) if self.interpreter.request_calls.contains_key(&(expr as *const Expr as usize)) =>
+                return self.security(expr, args),
+            Expr::MethodCall(object, name, _, args) => {
+                let path = format!("{}.{}", object, name);
+                return match drawings::call(&path) {
+                    Some(call) => self.drawing(call, &path, args),
+                    None => self.builtin_call(expr, &path, args)
+                };
+            },
+            Expr::Index(name, index) => self.history(name, index)?,
+            Expr::PropertyAccess(object, property) => return self.property(expr, object, property),
+        }))
+    }
+
+    /// Records the value and color of a plot on the current bar, like `Processor::plot`.
+    fn plot(&mut self, expr: &Expr, args: &'a CallArguments) -> Result<Option<Typed>, CodegenError> {
+        let (id, kind) = self.interpreter.plot_calls[&(expr as *const Expr as usize)];
+        let value = match kind.value_param().and_then(|p| plots::argument(kind, args, p)) {
+            Some(e) => self.value(e)?.0.float(),
+            None => f64::NAN
+        };
+        let color = match plots::argument(kind, args, "color") {
+            Some(e) => match self.value(e)? {
+                (Value::Color(c), _) => c,
+                _ => 0
+            },
+            None => 0
+        };
+        self.runtime.plot(id as u32, value, color);
+        let returns = builtins::function(kind.function()).unwrap().returns;
+        Ok((returns != Type::Void).then_some((Value::Int(id as i64), returns)))
+    }
+
+    /// Evaluates the expression of a `request.security()` call and returns the value requested for the bar.
+    fn security(&mut self, expr: &Expr, args: &'a CallArguments) -> Result<Option<Typed>, CodegenError> {
+        let request = self.interpreter.request_calls[&(expr as *const Expr as usize)];
+        let (value, t) = self.value(security::expression(args).unwrap())?;
+        if !matches!(t, Type::Int | Type::Float | Type::Bool | Type::Color | Type::Na) {
+            return Err(CodegenError::Unsupported(format!("request.security() of {}", t)));
+        }
+        let requested = self.runtime.security(request as u32, value.float());
+        let value = match t {
+            Type::Int => Value::Int(if requested.is_nan() { NA_INT } else { requested as i64 }),
+            Type::Bool => Value::Bool(!requested.is_nan() && requested != 0.0),
+            Type::Color => Value::Color(if requested.is_nan() { 0 } else { requested as u32 }),
+            _ => Value::Float(requested)
+        };
+        Ok(Some((value, t)))
+    }
+
+    /// Evaluates the id of a drawing object, `na` is 0.
+    fn drawing_id(&mut self, kind: DrawingKind, expr: Option<&'a Expr>) -> Result<i64, CodegenError> {
+        let (v, t) = self.value(expr.unwrap())?;
+        Ok(cast(v, &t, &drawings::handle_type(kind))?.int())
+    }
+
+    /// Sets a property of a drawing object to the value of `expr`, like `Processor::drawing_property`.
+    fn drawing_property(&mut self, kind: DrawingKind, name: &str, id: i64, expr: &'a Expr) -> Result<(), CodegenError> {
+        let property = drawings::property(kind, name).unwrap();
+        let t = drawings::properties().swap_remove(property as usize).2;
+        let (v, vt) = self.value(expr)?;
+        match cast(v, &vt, &t)? {
+            Value::String(s) => self.runtime.drawings().set_string(property, id, &s),
+            Value::Int(i) => self.runtime.drawings().set(property, id, i as f64),
+            v => self.runtime.drawings().set(property, id, v.float())
+        }
+        Ok(())
+    }
+
+    /// Runs a drawing function on the objects of the runtime, like `Processor::drawing`.
+    fn drawing(&mut self, call: Call, name: &str, args: &'a CallArguments) -> Result<Option<Typed>, CodegenError> {
+        let signature = builtins::function(name).unwrap();
+        let exprs = builtins::arguments(name, args);
+        let properties = signature.params.iter().map(|p| p.0.as_str()).zip(exprs.iter().copied());
+        match call {
+            Call::New(kind) => {
+                let id = self.runtime.drawings().create(kind);
+                for (param, expr) in properties {
+                    let Some(expr) = expr else { continue };
+                    match expr {
+                        Expr::MakeTuple(points) if param == "points" => for point in points {
+                            let point = self.drawing_id(DrawingKind::Point, Some(point))?;
+                            self.runtime.drawings().point(id, point);
+                        },
+                        _ if param == "points" => return Err(CodegenError::Unsupported("points of polyline.new other than an array literal".to_string())),
+                        _ => self.drawing_property(kind, param, id, expr)?
+                    }
+                }
+                Ok(Some((Value::Int(id), signature.returns)))
+            },
+            Call::Set(kind) => {
+                let id = self.drawing_id(kind, exprs[0])?;
+                for (param, expr) in properties.skip(1) {
+                    self.drawing_property(kind, param, id, expr.unwrap())?;
+                }
+                Ok(None)
+            },
+            Call::Get(kind, property) => {
+                let id = self.drawing_id(kind, exprs[0])?;
+                let value = self.runtime.drawings().get(drawings::property(kind, &property).unwrap(), id);
+                let value = match signature.returns {
+                    Type::Int => Value::Int(value as i64),
+                    _ => Value::Float(value)
+                };
+                Ok(Some((value, signature.returns)))
+            },
+            Call::Delete => {
+                let (v, t) = self.value(exprs[0].unwrap())?;
+                let id = cast(v, &t, &signature.params[0].1)?.int();
+                self.runtime.drawings().delete(id);
+                Ok(None)
+            },
+            Call::Cell => {
+                let table = self.drawing_id(DrawingKind::Table, exprs[0])?;
+                let mut position = vec![];
+                for expr in &exprs[1..3] {
+                    let (v, t) = self.value(expr.unwrap())?;
+                    position.push(cast(v, &t, &Type::Int)?.int());
+                }
+                let cell = self.runtime.drawings().cell(table, position[0], position[1]);
+                for (param, expr) in properties.skip(3) {
+                    if let Some(expr) = expr {
+                        self.drawing_property(DrawingKind::Cell, param, cell, expr)?;
+                    }
+                }
+                Ok(None)
+            },
+        }
+    }
+
+    /// The value of the branch an `if` or `switch` took, cast to the type of the expression.
+    fn merge(&self, expr: &Expr, value: Option<Typed>) -> Result<Option<Typed>, CodegenError> {
+        match (self.interpreter.checker.branch_type(expr), value) {
+            (Some(t), Some((v, vt))) if *t != Type::Void => Ok(Some((cast(v, &vt, t)?, t.clone()))),
+            _ => Ok(None)
+        }
+    }
+
+    fn switch(&mut self, expr: &Expr, subject: Option<&'a Expr>, variants: &'a [(Option<Box<Expr>>, Box<Statement>)]) -> Result<Option<Typed>, CodegenError> {
+        let subject = match subject {
+            Some(s) => Some(self.value(s)?),
+            None => None
+        };
+
+        for (case, body) in variants {
+            let matched = match (case, &subject) {
+                (None, _) => true,
+                (Some(case), Some(s)) => {
+                    let v = self.value(case)?;
+                    compare(&Opcode::Equal, s.clone(), v)?.bool()
+                },
+                (Some(case), None) => self.value(case)?.0.bool()
+            };
+            if matched {
+                let value = self.block(std::slice::from_ref(body))?;
+                return self.merge(expr, value);
+            }
+        }
+        Ok(None)
+    }
+
+    fn input(&self, expr: &Expr) -> Typed {
+        let id = self.interpreter.inputs[&(expr as *const Expr as usize)];
+        let input = &self.interpreter.schema[id];
+        match (&input.kind, &self.values[id]) {
+            (InputKind::Source, Constant::String(s)) => {
+                let source = SOURCES.iter().find(|x| **x == s.as_str()).unwrap_or(&SOURCES[3]);
+                self.bar_value(source).unwrap()
+            },
+            (_, value) => (Value::constant(value), input.get_type())
+        }
+    }
+
+    /// A field of the current bar (`close`) or an average of fields (`hl2`).
+    fn bar_value(&self, name: &str) -> Option<Typed> {
+        let bar = &self.bar;
+        let value = match name {
+            "time" => return Some((Value::Int(bar.time), Type::Int)),
+            "open" => bar.open,
+            "high" => bar.high,
+            "low" => bar.low,
+            "close" => bar.close,
+            "volume" => bar.volume,
+            "hl2" => (bar.high + bar.low) / 2.0,
+            "hlc3" => (bar.high + bar.low + bar.close) / 3.0,
+            "ohlc4" => (bar.open + bar.high + bar.low + bar.close) / 4.0,
+            _ => return None
+        };
+        Some((Value::Float(value), Type::Float))
+    }
+
+    /// `name[index]`, like `Processor::history`.
+    fn history(&mut self, name: &str, index: &'a Expr) -> Result<Typed, CodegenError> {
+        let (current, t) = self.identifier(name)?;
+        let n = self.value(index)?.0.int();
+        let position = self.bar_index - n;
+        let kept = self.interpreter.metadata.bars_back();
+        Ok(match n {
+            0 => (current, t),
+            n if n > 0 && n < kept as i64 && position >= 0 => {
+                let ring = &self.histories[name];
+                (ring[(position as u64 % kept) as usize].clone(), t)
+            },
+            _ => (cast(Value::Float(f64::NAN), &Type::Na, &t)?, t)
+        })
+    }
+
+    fn identifier(&self, name: &str) -> Result<Typed, CodegenError> {
+        if let Some((binding, t)) = self.lookup(name) {
+            return Ok((self.load(binding), t));
+        }
+
+        if let Some(v) = self.bar_value(name) {
+            return Ok(v);
+        }
+
+        match name {
+            "bar_index" => Ok((Value::Int(self.bar_index), Type::Int)),
+            "na" => Ok((Value::Float(f64::NAN), Type::Na)),
+            _ if calendar::FIELDS.contains(&name) => Ok((Value::Int(self.runtime.calendar(name, self.bar.time, "")), Type::Int)),
+            _ => Err(CodegenError::Type(TypeError::UndefinedVariable(name.to_string())))
+        }
+    }
+
+    fn arguments(&mut self, name: &str, params: &[(String, Type)], args: &'a CallArguments, defaults: Option<&'a [VarParam]>) -> Result<Vec<Value>, CodegenError> {
+        let mut exprs: Vec<Option<&'a Expr>> = vec![None; params.len()];
+        for (i, (key, value)) in args.iter().enumerate() {
+            let index = match key {
+                Some(key) => params.iter().position(|p| p.0 == *key)
+                    .ok_or_else(|| CodegenError::Type(TypeError::UnknownArgument { function: name.to_string(), argument: key.to_string() }))?,
+                None => i
+            };
+            exprs[index] = Some(value);
+        }
+
+        let mut values = vec![];
+        for (i, (_, t)) in params.iter().enumerate() {
+            let expr = exprs[i].or_else(|| defaults.and_then(|d| d[i].1.as_deref()));
+            let (v, vt) = match expr {
+                Some(expr) => self.value(expr)?,
+                None => (Value::Float(f64::NAN), Type::Na)
+            };
+            values.push(cast(v, &vt, t)?);
+        }
+        Ok(values)
+    }
+
+    fn call(&mut self, expr: &Expr, name: &str, args: &'a CallArguments) -> Result<Option<Typed>, CodegenError> {
+        let (params, body, globals) = match self.functions.get(name) {
+            Some(f) => (f.params, f.body, f.globals.clone()),
+            None => return self.builtin_call(expr, name, args)
+        };
+        let signature = self.interpreter.checker.function(name).cloned()
+            .ok_or_else(|| CodegenError::Unsupported(format!("function {}", name)))?;
+        let values = self.arguments(name, &signature.params, args, Some(params))?;
+
+        let next = self.contexts.len() + 1;
+        let context = *self.contexts.entry((self.context, expr as *const Expr as usize)).or_insert(next);
+        let caller = mem::replace(&mut self.context, context);
+        let scopes = mem::replace(&mut self.scopes, vec![globals, HashMap::new()]);
+        let depth = self.locals.len();
+        for ((param, t), v) in signature.params.iter().zip(values) {
+            self.declare(param, t, v, None)?;
+        }
+        let result = self.block(body)?;
+        self.scopes = scopes;
+        self.locals.truncate(depth);
+        self.context = caller;
+
+        match (&signature.returns, result) {
+            (Type::Void, _) => Ok(None),
+            (t, Some((v, vt))) => Ok(Some((cast(v, &vt, t)?, t.clone()))),
+            (t, None) => Err(CodegenError::Unsupported(format!("function {} returning {} without a value", name, t)))
+        }
+    }
+
+    fn builtin_call(&mut self, expr: &Expr, name: &str, args: &'a CallArguments) -> Result<Option<Typed>, CodegenError> {
+        let signature = builtins::function(name)
+            .ok_or_else(|| CodegenError::Type(TypeError::UnknownFunction(name.to_string())))?;
+        let values = self.arguments(name, &signature.params, args, None)?;
+        self.builtin(expr, name, &values, signature.returns)
+    }
+
+    /// The call site id of a builtin call or a loop, by its address.
+    fn site(&mut self, address: usize) -> u32 {
+        let next = self.sites.len() as u32;
+        *self.sites.entry((self.context, address)).or_insert(next)
+    }
+
+    /// Calls the implementation of a builtin in `Runtime`, each call expression is a call site.
+    fn builtin(&mut self, expr: &Expr, name: &str, args: &[Value], returns: Type) -> Result<Option<Typed>, CodegenError> {
+        let site = self.site(expr as *const Expr as usize);
+        let float = |i: usize| args[i].float();
+        let int = |i: usize| args[i].int();
+        let color = |i: usize| args[i].int() as u32;
+        let r = &mut self.runtime;
+
+        let value = match name {
+            "na" => Some(Value::Bool(Runtime::na(float(0)))),
+            "nz" => Some(Value::Float(Runtime::nz(float(0), float(1)))),
+            "math.abs" => Some(Value::Float(float(0).abs())),
+            "math.max" => Some(Value::Float(float(0).max(float(1)))),
+            "math.min" => Some(Value::Float(float(0).min(float(1)))),
+            "math.sqrt" => Some(Value::Float(float(0).sqrt())),
+            "color.new" => Some(Value::Color(Runtime::color_new(color(0), float(1)))),
+            "color.rgb" => Some(Value::Color(Runtime::color_rgb(float(0), float(1), float(2), float(3)))),
+            "color.r" => Some(Value::Float(Runtime::color_component(color(0), 0))),
+            "color.g" => Some(Value::Float(Runtime::color_component(color(0), 1))),
+            "color.b" => Some(Value::Float(Runtime::color_component(color(0), 2))),
+            "color.t" => Some(Value::Float(Runtime::color_component(color(0), 3))),
+            "color.from_gradient" => Some(Value::Color(Runtime::color_from_gradient(float(0), float(1), float(2), color(3), color(4)))),
+            "ta.sma" => Some(Value::Float(r.sma(site, float(0), int(1)))),
+            "ta.ema" => Some(Value::Float(r.ema(site, float(0), int(1)))),
+            "ta.rma" => Some(Value::Float(r.rma(site, float(0), int(1)))),
+            "ta.wma" => Some(Value::Float(r.wma(site, float(0), int(1)))),
+            "ta.rsi" => Some(Value::Float(r.rsi(site, float(0), int(1)))),
+            "ta.stdev" => Some(Value::Float(r.stdev(site, float(0), int(1)))),
+            "ta.atr" => Some(Value::Float(r.atr(site, int(0)))),
+            "ta.crossover" => Some(Value::Bool(r.crossover(site, float(0), float(1)))),
+            "ta.crossunder" => Some(Value::Bool(r.crossunder(site, float(0), float(1)))),
+            "ta.macd" => Some(Value::floats(r.macd(site, float(0), int(1), int(2), int(3)))),
+            "ta.bb" => Some(Value::floats(r.bb(site, float(0), int(1), float(2)))),
+            "ta.kc" => Some(Value::floats(r.kc(site, float(0), int(1), float(2)))),
+            "ta.dmi" => Some(Value::floats(r.dmi(site, int(0), int(1)))),
+            "strategy.entry" => {
+                r.entry(args[0].string(), int(1), float(2));
+                None
+            },
+            "strategy.close" => {
+                r.close(args[0].string());
+                None
+            },
+            "strategy.close_all" => {
+                r.close_all();
+                None
+            },
+            "strategy.position_size" => Some(Value::Float(r.position_size())),
+            "alert" => {
+                r.alert(site, args[0].string(), args[1].string());
+                None
+            },
+            "alertcondition" => {
+                r.alertcondition(args[0].bool(), args[1].string(), args[2].string());
+                None
+            },
+            "timeframe.multiplier" => Some(Value::Int(r.timeframe().map_or(0, |t| t.multiplier() as i64))),
+            "timeframe.in_seconds" => Some(Value::Int(r.in_seconds(args[0].string()))),
+            "time" => Some(Value::Int(r.time(args[0].string(), args[1].string(), args[2].string()))),
+            _ if calendar::FIELDS.contains(&name) => Some(Value::Int(r.calendar(name, int(0), args[1].string()))),
+            "session.ismarket" => Some(Value::Bool(r.is_market())),
+            "session.ispremarket" => Some(Value::Bool(r.is_premarket())),
+            "session.ispostmarket" => Some(Value::Bool(r.is_postmarket())),
+            "barstate.isnew" => Some(Value::Bool(r.barstate().new)),
+            "barstate.isconfirmed" => Some(Value::Bool(r.barstate().confirmed)),
+            "barstate.isrealtime" => Some(Value::Bool(r.barstate().realtime)),
+            "barstate.ishistory" => Some(Value::Bool(!r.barstate().realtime)),
+            "barstate.islast" => Some(Value::Bool(r.barstate().last)),
+            _ if name.starts_with("timeframe.is") => {
+                let is = match name {
+                    "timeframe.isticks" => Timeframe::is_ticks,
+                    "timeframe.isseconds" => Timeframe::is_seconds,
+                    "timeframe.isminutes" => Timeframe::is_minutes,
+                    "timeframe.isintraday" => Timeframe::is_intraday,
+                    "timeframe.isdaily" => Timeframe::is_daily,
+                    "timeframe.isweekly" => Timeframe::is_weekly,
+                    "timeframe.ismonthly" => Timeframe::is_monthly,
+                    _ => Timeframe::is_dwm,
+                };
+                Some(Value::Bool(r.timeframe().is_some_and(is)))
+            },
+            _ => return Err(CodegenError::Unsupported(format!("{} in the interpreter", name)))
+        };
+        Ok(value.map(|v| (v, returns)))
+    }
+
+    /// Builtin constants (`strategy.long`) and variables read from the runtime (`strategy.position_size`).
+    fn property(&mut self, expr: &Expr, object: &str, property: &str) -> Result<Option<Typed>, CodegenError> {
+        let path = format!("{}.{}", object, property);
+        if let Some(c) = builtins::constant(&path) {
+            return Ok(Some((Value::constant(&c), c.get_type())));
+        }
+        match builtins::variable(&path) {
+            Some(t) => self.builtin(expr, &path, &[], t),
+            None => Err(CodegenError::Unsupported(path))
+        }
+    }
+}
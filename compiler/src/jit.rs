@@ -0,0 +1,94 @@
+use std::{
+    ops::Deref,
+    time::Duration,
+};
+
+use inkwell::{
+    context::Context,
+    execution_engine::ExecutionEngine,
+    targets::{InitializationConfig, Target},
+    OptimizationLevel,
+};
+
+use crate::{
+    abi::{self, Executable, Exports, GetOutputsFn, InitFn, OnBarFn, RollbackFn, ScriptInfo, StateSizeFn},
+    ast::Statement,
+    declaration::ScriptMetadata,
+    error::{CodegenError, JitError},
+    processor::Processor,
+    runtime::LOOP_LIMIT,
+};
+
+/// A script compiled to machine code in memory, see `Jit`. Runs go through
+/// the `Executable` it dereferences to.
+pub struct JitScript<'ctx> {
+    executable: Executable,
+    /* Owns the code `executable` calls */
+    _engine: ExecutionEngine<'ctx>,
+}
+
+impl Deref for JitScript<'_> {
+    type Target = Executable;
+
+    fn deref(&self) -> &Executable {
+        &self.executable
+    }
+}
+
+/// Compiles scripts in memory with LLVM's MCJIT, one module per script in the context.
+///
+/// Input values are passed to every run, so changing them does not recompile.
+/// `Script` keeps the scripts it compiled cached, see `script::Compiled`.
+pub struct Jit<'ctx> {
+    context: &'ctx Context,
+    loop_limit: Duration,
+}
+
+impl<'ctx> Jit<'ctx> {
+    pub fn new(context: &'ctx Context) -> Self {
+        Self {
+            context,
+            loop_limit: LOOP_LIMIT,
+        }
+    }
+
+    /// How long a loop of the scripts compiled next may run on a bar, `runtime::LOOP_LIMIT` by default.
+    pub fn set_loop_limit(&mut self, limit: Duration) {
+        self.loop_limit = limit;
+    }
+
+    /// Compiles parsed statements, like scripts linked with their libraries.
+    pub fn compile(&mut self, statements: Vec<Statement>) -> Result<JitScript<'ctx>, JitError> {
+        let metadata = ScriptMetadata::located(&statements)?;
+        let processor = Processor::new(statements);
+        let inputs = processor.inputs()?;
+        let outputs = processor.outputs()?;
+        let plots = processor.plots()?;
+        let requests = processor.requests()?;
+        let module = processor.compile(self.context)?;
+
+        Target::initialize_native(&InitializationConfig::default()).map_err(CodegenError::Llvm)?;
+        let engine = module.create_jit_execution_engine(OptimizationLevel::Default)
+            .map_err(|e| CodegenError::Llvm(e.to_string()))?;
+        for function in module.get_functions().filter(|f| f.count_basic_blocks() == 0) {
+            let name = function.get_name().to_string_lossy();
+            let address = abi::symbol(&name).ok_or_else(|| CodegenError::Unsupported(format!("{} in the JIT", name)))?;
+            engine.add_global_mapping(&function, address);
+        }
+
+        let missing = |e| CodegenError::Llvm(format!("{:?}", e));
+        let script = unsafe {
+            let exports = Exports {
+                state_size: engine.get_function::<StateSizeFn>("state_size").map_err(missing)?.as_raw(),
+                init: engine.get_function::<InitFn>("init").map_err(missing)?.as_raw(),
+                on_bar: engine.get_function::<OnBarFn>("on_bar").map_err(missing)?.as_raw(),
+                get_outputs: engine.get_function::<GetOutputsFn>("get_outputs").map_err(missing)?.as_raw(),
+                save: engine.get_function::<RollbackFn>("save").map_err(missing)?.as_raw(),
+                restore: engine.get_function::<RollbackFn>("restore").map_err(missing)?.as_raw(),
+            };
+            let info = ScriptInfo { metadata, inputs, outputs, plots, requests };
+            JitScript { executable: Executable::new(exports, info, self.loop_limit), _engine: engine }
+        };
+        Ok(script)
+    }
+}
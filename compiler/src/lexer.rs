@@ -1,5 +1,3 @@
-use regex::Regex;
-
 use crate::{error::{LexicalError, LexicalErrorType}, location::Location, token::Tok, types::RGBA};
 
 pub type Spanned = (Location, Tok, Location);
@@ -13,42 +11,23 @@ pub struct Lexer {
     indention_now: usize,
     dedent_required: isize,
     prev_new_line: bool,
-    new_line: bool
+    new_line: bool,
+    /// Where the token returned last starts.
+    start: usize,
 }
 
 impl Lexer {
+    /// Creates a lexer which loses nothing: whitespace, comments, unknown
+    /// characters and the line breaks which do not end a statement are tokens
+    /// too, so the texts of the tokens put together are the source. Indents
+    /// and dedents have no text.
     pub fn new(src: &str, indention_level: usize) -> Self {
-        /* Before processing apply some regex barebones, to resolve ambigiousy problems. */
-        // FIXME: IDK, maybe it can be fixed easily with some magic, maybe it
-        // requires a lot of lookaheads.
-        
-        /* Replace tabs with spaces */
-        let src = src.replace("\t", &String::from(" ").repeat(indention_level));
-
-        let line_start = r"((?:^||\n)\s*)"; // capture group (usually $1)
-        let ident = "[_a-zA-Z]+[_a-zA-Z0-9]*";
-        let generic_params = ["(?:<", ident, ">)?"].concat();
-        let generic_subject = [ident, &generic_params].concat();
-
-        /* Function definition: ident((Type(<Generic>)?)? name, ...) => statements/expressions */
-        let func_def_rules = [line_start, "(", ident, "\\(.*\\)\\s*=>)"].concat();
-        let src = Regex::new(&func_def_rules).unwrap().replace_all(&src, "$1\0function $2");
-        
-        /* Tuple unpacking [ident, ...] = ident */
-        let tuple_unpack_rules = [line_start, "(\\[(?:", ident , ")(?:,\\s*", ident, ")*\\]\\s*=)"].concat();
-        let src = Regex::new(&tuple_unpack_rules).unwrap().replace_all(&src, "$1\0unpack_tuple $2");
-
-        /* Method calls: a.b<int>(), a.b(), a.b<int, string>() */
-        let method_calls_rules = [ident, "\\.", &generic_subject, "\\(.*\\)"].concat();
-        let src = Regex::new(&method_calls_rules).unwrap().replace_all(&src, "\0method_call $0");
-
-        /* Generic types (for var/fields definitions only) `(var|const)? array<int> x = na`, int x = 3 */
-        let decl_type_specifier = "(?:(?:var|varip|series|const) )?";
-        let var_decl_rules = [line_start, "(", decl_type_specifier, &generic_subject, " ", ident, "\\s*=)"].concat();
-        let src = Regex::new(&var_decl_rules).unwrap().replace_all(&src, "$1\0var_decl $2"); 
-
-        let chars = src.chars().collect();
-        Self { chars, position: 0, location: Location::new(1, 1), indention_level, new_line: true, indention_now: 0, dedent_required: 0, prev_new_line: true }
+        Self { chars: src.chars().collect(), position: 0, location: Location::new(1, 1), indention_level, new_line: true, indention_now: 0, dedent_required: 0, prev_new_line: true, start: 0 }
+    }
+
+    /// The text of the token returned last.
+    pub fn text(&self) -> String {
+        self.chars[self.start..self.position.min(self.chars.len())].iter().collect()
     }
 
     fn has_more_tokens(&self) -> bool {
@@ -71,14 +50,47 @@ impl Lexer {
         self.location.go_left();
     }
 
-    fn get_marker(&self, id: &str) -> Option<Tok> {
-        Some(match id {
-            "function" => Tok::FunctionMarker,
-            "unpack_tuple" => Tok::UnpackTupleMarker,
-            "method_call" => Tok::MethodCallMarker,
-            "var_decl" => Tok::VarDeclarationMarker,
-            _ => return None
-        })
+    /// Length of the line starting at `position`, without its line break, if
+    /// it is blank or only holds a comment.
+    fn trivia_line(&self, position: usize) -> Option<usize> {
+        let line = &self.chars[position..];
+        let line = &line[..line.iter().position(|c| *c == '\n').unwrap_or(line.len())];
+        let code = line.iter().position(|c| !matches!(c, ' ' | '\t' | '\r')).unwrap_or(line.len());
+        match &line[code..] {
+            [] => (position + line.len() < self.chars.len()).then_some(line.len()),
+            ['/', '/', ..] => Some(line.len()),
+            _ => None
+        }
+    }
+
+    /// Width of the whitespace at `position`, or the length of the source if
+    /// nothing else follows.
+    fn indentation(&self, position: usize) -> usize {
+        let rest = &self.chars[position..];
+        match rest.iter().position(|c| *c != ' ' && *c != '\t') {
+            Some(end) => rest[..end].iter().map(|c| if *c == '\t' { self.indention_level } else { 1 }).sum(),
+            None => self.chars.len()
+        }
+    }
+
+    /// Whitespace and comments, the line breaks aside.
+    fn trivia(&mut self, char: char) -> Option<Tok> {
+        let run = |lexer: &mut Self, f: fn(char) -> bool| {
+            while lexer.chars.get(lexer.position).is_some_and(|c| f(*c)) {
+                lexer.go_right();
+            }
+        };
+        match (char, self.chars.get(self.position)) {
+            (' ' | '\t' | '\r', _) => {
+                run(self, |c| matches!(c, ' ' | '\t' | '\r'));
+                Some(Tok::Whitespace)
+            },
+            ('/', Some('/')) => {
+                run(self, |c| c != '\n');
+                Some(Tok::Comment)
+            },
+            _ => None
+        }
     }
 
     fn get_keyword(&self, id: &str) -> Option<Tok> {
@@ -98,6 +110,8 @@ impl Lexer {
             "while" => Tok::While,
             "switch" => Tok::Switch,
             "import" => Tok::Import,
+            "as" => Tok::As,
+            "export" => Tok::Export,
             "not" => Tok::Not,
             "and" => Tok::And,
             "or" => Tok::Or,
@@ -119,6 +133,7 @@ impl Lexer {
     }
 
     fn inner_next(&mut self) -> Result<Spanned, LexicalError> {
+        self.start = self.position;
         if !self.has_more_tokens() {
             return Ok(self.make_eof());
         };
@@ -126,25 +141,11 @@ impl Lexer {
         let loc_left = self.location.clone();
         let mut char = self.set_next_char().unwrap();
 
-        // comment
-        if char == '/' {
-            if let Some('/') = self.set_next_char() {
-                loop {
-                    char = match self.set_next_char() {
-                        Some(v) => v,
-                        None => return Ok(self.make_eof())
-                    };
-
-                    if char == '\n' {
-                        self.go_left();
-                        break;
-                    }
-                };
-            } else {
-                self.go_left();
-            }
+        if let Some(t) = self.trivia(char) {
+            return Ok((loc_left, t, self.location));
         }
-        
+
+
         let count_for_indent = (self.indention_now + 1) * self.indention_level;
         let count_for_indent2 = (self.indention_now + 2) * self.indention_level;
 
@@ -154,18 +155,24 @@ impl Lexer {
              * exact as it must be */
             self.location.newline();
 
-            let count = self.chars[self.position .. self.chars.len()].into_iter().position(|x| *x != ' ').unwrap_or(self.chars.len());
+            /* Blank and comment-only lines are trivia, they neither end a statement nor change the indentation */
+            let mut next = self.position;
+            while let Some(length) = self.trivia_line(next) {
+                next = (next + length + 1).min(self.chars.len());
+            }
+
+            let count = self.indentation(next);
             if count > count_for_indent && count < count_for_indent2 {
-                return self.inner_next();
+                return Ok((loc_left, Tok::LineBreak, self.location));
             }
 
             self.new_line = true;
             if self.prev_new_line {
-                return self.inner_next();
+                return Ok((loc_left, Tok::LineBreak, self.location));
             }
 
             self.prev_new_line = true;
-            return Ok((self.location, Tok::NewLine, self.location));
+            return Ok((loc_left, Tok::NewLine, self.location));
         }
         self.prev_new_line = false;
 
@@ -177,14 +184,14 @@ impl Lexer {
             return Ok((loc_left, Tok::Dedent, self.location));
         }
         if self.new_line { 
-            let count = self.chars[self.position - 1 .. self.chars.len()].into_iter().position(|x| *x != ' ').unwrap_or(self.chars.len());
-            if count > 0 {
-                self.position += count - 1;
-            }
+            /* The indentation is a token of its own already */
+            let line_start = self.chars[..self.position - 1].iter().rposition(|c| *c == '\n').map_or(0, |i| i + 1);
+            let count = self.indentation(line_start);
 
             if count_for_indent == count {
                 self.indention_now += 1;
                 self.new_line = false;
+                self.go_left();
                 return Ok((loc_left, Tok::Indent, self.location));
             }
 
@@ -336,8 +343,7 @@ impl Lexer {
         }
 
         // idents:
-        if char.is_alphabetic() || char == '_' || char == '\0' {
-            let s = char;
+        if char.is_alphabetic() || char == '_' {
             let mut stack = String::new();
             stack.push(char);
 
@@ -355,14 +361,6 @@ impl Lexer {
                 stack.push(char);
             }
 
-            if s == '\0' {
-                stack.remove(0);
-                let marker = self.get_marker(&stack);
-                if let Some(v) = marker {
-                    return Ok((loc_left, v, self.location));
-                }
-            }
-
             if let Some(v) = self.get_keyword(&stack) {
                 return Ok((loc_left, v, self.location))
             }
@@ -468,7 +466,7 @@ impl Lexer {
                 t
             },
             ';' => Tok::Semicolon,
-            _ => return self.inner_next()
+            _ => Tok::Unknown
         };
 
         return Ok((loc_left, symbol, self.location));
@@ -1,35 +1,1546 @@
-use lalrpop_util::lalrpop_mod;
+//! Compiles and runs NineScript, a dialect of Pine Script.
+//!
+//! Hosts compile a `Script` with `Script::compile` or a `Compiler` and run it
+//! over `Bar`s, or stream it on a live feed. Problems are reported as
+//! `Diagnostics`. The syntax tree of `cst` is for tools working on the source.
 
-pub mod ast;
-pub mod lexer;
-pub mod location;
-pub mod error;
-pub mod token;
-pub mod types;
-pub mod processor;
-lalrpop_mod!(ninescript);
+pub(crate) mod abi;
+pub(crate) mod annotation;
+pub(crate) mod ast;
+#[cfg(feature = "llvm")]
+pub(crate) mod bindings;
+pub(crate) mod builtins;
+pub(crate) mod calendar;
+pub(crate) mod checker;
+pub mod cst;
+pub(crate) mod declaration;
+pub(crate) mod drawings;
+pub(crate) mod lexer;
+pub(crate) mod location;
+pub(crate) mod error;
+pub(crate) mod export;
+pub(crate) mod format;
+#[cfg(all(test, feature = "llvm", feature = "wasmtime"))]
+mod harness;
+pub(crate) mod history;
+pub(crate) mod inputs;
+pub(crate) mod interpreter;
+#[cfg(feature = "llvm")]
+pub(crate) mod jit;
+pub(crate) mod modules;
+pub(crate) mod optimizer;
+pub(crate) mod plots;
+pub(crate) mod token;
+pub(crate) mod types;
+#[cfg(feature = "llvm")]
+pub(crate) mod processor;
+pub(crate) mod runtime;
+pub(crate) mod scopes;
+pub(crate) mod script;
+pub(crate) mod security;
+#[cfg(feature = "llvm")]
+pub(crate) mod target;
+pub(crate) mod timeframe;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+pub use crate::{
+    ast::FunctionDoc,
+    calendar::{Session, Timezone},
+    declaration::{ScriptKind, ScriptMetadata},
+    drawings::{Drawing, DrawingKind},
+    error::{Diagnostic, Diagnostics, FormatError, InputError, ModuleError, ParseError, Severity, TimeframeError},
+    format::format,
+    inputs::{Input, InputKind},
+    location::Location,
+    modules::{Resolver, ARTIFACT_EXTENSION as LIBRARY_EXTENSION},
+    optimizer::{default_space, space_from_json, Metric, Metrics, Optimizer, Search, Space, Trial},
+    plots::{Plot, PlotKind},
+    runtime::{Alert, Bar, Output, PlotSeries, RunResult, Series, StrategyReport, Tick, Trade},
+    script::{Backend, Compiler, Params, Script, Stream},
+    security::Feeds,
+    timeframe::{Resampler, Timeframe},
+    types::{Constant, Type, RGBA},
+};
+#[cfg(feature = "llvm")]
+pub use crate::target::Artifact;
+
+/// Parses a script with 4 spaces indentation, lowering its syntax tree to statements.
+pub(crate) fn parse(src: &str) -> Result<Vec<ast::Statement>, ParseError> {
+    cst::parse(src).statements()
+}
+
+/// Builds the runtime library for the target `triple`, the host by default, for
+/// tests linking artifacts. `None` if the standard library of `triple` is not installed.
+#[cfg(all(test, feature = "llvm"))]
+fn runtime(triple: Option<&str>) -> Option<std::path::PathBuf> {
+    use std::{path::Path, process::Command};
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/runtime");
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo.args(["build", "-p", "ninescript-runtime", "--target-dir"]).arg(&dir);
+    let dir = match triple {
+        Some(triple) => {
+            let sysroot = Command::new("rustc").args(["--print", "sysroot"]).output().ok()?.stdout;
+            if !Path::new(String::from_utf8_lossy(&sysroot).trim()).join("lib/rustlib").join(triple).is_dir() {
+                return None;
+            }
+            cargo.args(["--target", triple]);
+            dir.join(triple)
+        },
+        None => dir
+    };
+    assert!(cargo.status().unwrap().success());
+    Some(dir.join("debug").join(target::RUNTIME_LIBRARY))
+}
 
 #[test]
 fn calculator1() {
     let src = r#"
 varip int<int> x = 2
 "#.trim_start();
-    let tokens = lexer::Lexer::new(src, 4).collect::<Vec<_>>();
-    let tokens = tokens.into_iter().map(|x| x.unwrap()).collect::<Vec<_>>();
-    let result = ninescript::StatementsParser::new().parse(tokens);
+    let result = parse(src);
     println!("{:?}", result);
     let result = match result {
         Ok(x) => x,
         Err(e) => {
-            let mut y = false;
-            e.map_location(|x| {
-                if y == true {return;}
-                y = true;
-                let vis = x.visualize(src.split('\n').nth(x.row()-1).unwrap_or(""));
-                println!("\n\nError at {}:{}\n{}\n\n", x.row(), x.column(), vis);
-            });
+            println!("{}", Diagnostics::error(src, e));
             return;
         }
     };
     println!("{:#?}", result);
 }
+
+#[test]
+fn tuples() {
+    use crate::{checker::TypeChecker, error::TypeError, types::Type};
+
+    let src = r#"
+f(x) => [x, x * 2]
+[a, b] = f(1.5)
+[macd, signal, hist] = ta.macd(close, 12, 26, 9)
+"#.trim_start();
+    let mut checker = TypeChecker::new();
+    checker.check(&parse(src).unwrap()).unwrap();
+    assert_eq!(checker.function("f").unwrap().returns, Type::Tuple(vec![Type::Float, Type::Float]));
+    assert_eq!(checker.variable("hist"), Some(&Type::Float));
+
+    let src = "[macd, signal] = ta.macd(close, 12, 26, 9)\n";
+    assert_eq!(TypeChecker::new().check(&parse(src).unwrap()), Err(TypeError::TupleArity { expected: 2, found: 3 }));
+
+    #[cfg(feature = "llvm")]
+    {
+        let src = "indicator(\"Tuples\")\nf(x) => [x, x * 2]\n[a, b] = f(1.5)\n";
+        let ir = processor::Processor::new(parse(src).unwrap()).ir().unwrap();
+        assert!(ir.contains("{ double, double }"));
+    }
+}
+
+#[test]
+fn declarations() {
+    use crate::{declaration::{ScriptKind, ScriptMetadata}, error::DeclarationError};
+
+    let src = r#"
+strategy("Crossover", overlay = true, initial_capital = 5000, pyramiding = 2, commission_value = 0.1)
+float fast = ta.sma(close, 9)
+"#.trim_start();
+    let metadata = ScriptMetadata::from_statements(&parse(src).unwrap()).unwrap();
+    assert_eq!(metadata.kind, ScriptKind::Strategy);
+    assert_eq!(metadata.title, "Crossover");
+    assert!(metadata.overlay);
+    assert_eq!(metadata.initial_capital, 5000.0);
+    assert_eq!(metadata.pyramiding, 2);
+    assert_eq!(metadata.commission, 0.1);
+
+    let src = "indicator(\"A\")\nindicator(\"B\")\n";
+    assert_eq!(ScriptMetadata::from_statements(&parse(src).unwrap()), Err(DeclarationError::Duplicate));
+    let src = "float x = close\n";
+    assert_eq!(ScriptMetadata::from_statements(&parse(src).unwrap()), Err(DeclarationError::Missing));
+    let src = "indicator(\"A\", overlay = 1)\n";
+    assert!(matches!(ScriptMetadata::from_statements(&parse(src).unwrap()), Err(DeclarationError::InvalidArgument { .. })));
+}
+
+#[test]
+fn annotations() {
+    use crate::{ast::Program, error::{AnnotationErrorType, Warning}};
+
+    let src = r#"
+//@version=5
+//@description Moving average helpers.
+indicator("MA")
+
+//@function Averages two series.
+//@param a First series.
+//@param b Second series.
+//@returns The mean.
+mean(a, b) => (a + b) / 2
+"#.trim_start();
+    let program = Program::new(src, parse(src).unwrap()).unwrap();
+    assert_eq!(program.version, 5);
+    assert_eq!(program.description.as_deref(), Some("Moving average helpers."));
+    assert_eq!(program.functions[0].name, "mean");
+    assert_eq!(program.functions[0].params[1], ("b".to_string(), "Second series.".to_string()));
+    assert_eq!(program.functions[0].returns.as_deref(), Some("The mean."));
+
+    let error = Program::new("//@version=4\n", vec![]).unwrap_err();
+    assert_eq!(error.error, AnnotationErrorType::UnsupportedVersion(4));
+    let program = Program::new("//@version=6\n", vec![]).unwrap();
+    assert_eq!(program.warnings, vec![Warning::NewerVersion(6)]);
+}
+
+#[test]
+fn inputs() {
+    use std::collections::HashMap;
+    use crate::{error::{CodegenError, InputError}, inputs::InputKind, types::Constant};
+
+    let src = r#"
+indicator("Inputs")
+int length = input.int(14, "Length", minval = 1, maxval = 200, group = "MA")
+float src = input.source(close, "Source")
+string mode = input.string("fast", "Mode", options = ["fast", "slow"])
+float ma = ta.sma(src, length)
+"#.trim_start();
+    let statements = parse(src).unwrap();
+    let schema = inputs::extract(&statements).unwrap();
+    assert_eq!(schema.len(), 3);
+    assert_eq!(schema[0].name, "length");
+    assert_eq!(schema[0].default, Constant::Int(14));
+    assert_eq!(schema[0].max, Some(200.0));
+    assert_eq!(schema[0].group.as_deref(), Some("MA"));
+    assert_eq!(schema[1].kind, InputKind::Source);
+    assert_eq!(schema[2].options.len(), 2);
+
+    let mut params = Script::compile(src).unwrap().params();
+    params.set_json(r#"{"length": 50, "src": "hl2"}"#).unwrap();
+    assert_eq!(params.values()[0], Constant::Int(50));
+    assert_eq!(params.values()[1], Constant::String("hl2".to_string()));
+    let overrides = HashMap::from([("length".to_string(), Constant::Int(500))]);
+    assert_eq!(inputs::resolve(&schema, &overrides), Err(InputError::OutOfRange { input: "length".to_string(), value: 500.0 }));
+    assert!(params.set_json(r#"{"mode": "medium"}"#).is_err());
+    let invalid = |src: &str| inputs::extract(&parse(src).unwrap()).unwrap_err();
+    assert_eq!(invalid("int length = input.int(title = \"Length\")\n").kind(), &CodegenError::Input(InputError::MissingDefault("input.int".to_string())));
+    assert_eq!(invalid("int length = input.int(1)\nf() =>\n    int length = input.int(2)\n    length\n").kind(), &CodegenError::Input(InputError::DuplicateName("length".to_string())));
+    assert_eq!(invalid("int fast = input.int(1, \"Length\")\nint slow = input.int(2, \"Length\")\n").kind(), &CodegenError::Input(InputError::DuplicateTitle("Length".to_string())));
+
+    #[cfg(feature = "llvm")]
+    {
+        let ir = processor::Processor::new(statements).with_inputs(params.values().to_vec()).ir().unwrap();
+        assert!(ir.contains("store i64 50, "));
+    }
+}
+
+#[test]
+fn optimizer() {
+    use crate::{error::InputError, optimizer::{Metric, Metrics, Optimizer, Search, Space}, types::Constant};
+
+    let src = r#"
+strategy("Sweep")
+int fast = input.int(10, "Fast", minval = 2, maxval = 20, step = 2)
+int slow = input.int(30, "Slow")
+bool long_only = input.bool(true, "Long only")
+"#.trim_start();
+    let schema = inputs::extract(&parse(src).unwrap()).unwrap();
+    let space = optimizer::space_from_json(&schema, r#"{"fast": {"start": 2, "end": 10, "step": 4}, "slow": [20, 40]}"#).unwrap();
+    let optimizer = Optimizer::new(&schema, space, Metric::NetProfit).threads(3);
+    assert_eq!(optimizer.combinations().unwrap().len(), 6);
+
+    let trials = optimizer.run(|chunk| -> Result<Vec<Metrics>, InputError> {
+        Ok(chunk.iter().map(|values| {
+            let (Constant::Int(fast), Constant::Int(slow)) = (&values[0], &values[1]) else { unreachable!() };
+            Metrics::from_equity(&[100.0, 100.0 + (*slow - *fast) as f64])
+        }).collect())
+    }).unwrap();
+    assert_eq!(trials[0].values, vec![Constant::Int(2), Constant::Int(40), Constant::Bool(true)]);
+    assert_eq!(trials[0].metrics.net_profit, 38.0);
+    assert!(optimizer.table(&trials).lines().nth(1).unwrap().trim_start().starts_with("1     2    40"));
+
+    let sampled = Optimizer::new(&schema, optimizer::default_space(&schema).unwrap(), Metric::Sharpe)
+        .search(Search::Random { samples: 5, seed: 7 });
+    let combinations = sampled.combinations().unwrap();
+    assert_eq!(combinations.len(), 5);
+    assert_eq!(combinations, sampled.combinations().unwrap());
+
+    let backwards = optimizer::space_from_json(&schema, r#"{"fast": {"start": 10, "end": 2}}"#);
+    assert_eq!(backwards, Err(InputError::EmptyRange { input: "fast".to_string(), start: 10.0, end: 2.0 }));
+    let huge = optimizer::space_from_json(&schema, r#"{"slow": {"start": 1, "end": 1e12}}"#);
+    assert_eq!(huge, Err(InputError::TooManyCombinations(1_000_000_000_000)));
+    let axis = |n: i64| (0..n).map(Constant::Int).collect::<Vec<_>>();
+    let product = Space::from([("fast".to_string(), axis(2000)), ("slow".to_string(), axis(1000))]);
+    let grid = Optimizer::new(&schema, product.clone(), Metric::Sharpe);
+    assert_eq!(grid.combinations(), Err(InputError::TooManyCombinations(2_000_000)));
+    let sampled = Optimizer::new(&schema, product, Metric::Sharpe).search(Search::Random { samples: 3, seed: 1 });
+    assert_eq!(sampled.combinations().unwrap().len(), 3);
+    let unbounded = "strategy(\"Wide\")\nint n = input.int(1, \"N\", minval = 0, maxval = 2000000000)\n";
+    let schema = inputs::extract(&parse(unbounded).unwrap()).unwrap();
+    assert!(matches!(optimizer::default_space(&schema), Err(InputError::TooManyCombinations(_))));
+
+    let metrics = Metrics::from_equity(&[100.0, 120.0, 90.0, 110.0]);
+    assert_eq!(metrics.max_drawdown, 30.0);
+}
+
+#[cfg(all(feature = "llvm", feature = "wasmtime"))]
+#[test]
+fn wasm() {
+    use crate::{harness::WasmScript, processor::Processor, runtime::Bar, types::{Constant, Type}};
+
+    let src = r#"
+indicator("Wasm")
+int factor = input.int(2, "Factor")
+var float total = 0.0
+total := total + close
+float average = total / (bar_index + 1)
+bool above = close * factor > average
+"#.trim_start();
+    let statements = parse(src).unwrap();
+    let schema = inputs::extract(&statements).unwrap();
+    let processor = Processor::new(statements);
+    let outputs = processor.outputs().unwrap();
+    assert_eq!(outputs.iter().map(|o| o.name.as_str()).collect::<Vec<_>>(), ["factor", "total", "average", "above"]);
+    assert_eq!(outputs[0].t, Type::Int);
+
+    let Some(runtime) = runtime(Some(target::WASM32)) else {
+        return eprintln!("skipping the wasm test: {} is not installed", target::WASM32);
+    };
+    std::env::set_var("NINESCRIPT_WASM_RUNTIME", runtime);
+    let wasm = target::wasm(&processor).unwrap();
+    let mut script = WasmScript::new(&wasm, schema, outputs).unwrap();
+    let bar = |close| Bar { close, ..Bar::default() };
+    assert_eq!(script.on_bar(&bar(2.0)).unwrap(), [2.0, 2.0, 2.0, 1.0]);
+    assert_eq!(script.on_bar(&bar(4.0)).unwrap(), [2.0, 6.0, 3.0, 1.0]);
+
+    script.init(&[Constant::Int(0)]).unwrap();
+    assert_eq!(script.on_bar(&bar(1.0)).unwrap(), [0.0, 1.0, 1.0, 0.0]);
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn native() {
+    use std::{env, fs, process::Command};
+    use crate::{processor::Processor, target::{Artifact, X86_64_LINUX}};
+
+    let src = r#"
+strategy("Native")
+int length = input.int(14, "Length")
+float src = input.source(close, "Source")
+float ma = ta.sma(src, length)
+bool rising = ma > ma[1]
+if rising
+    strategy.entry("Long", strategy.long)
+"#.trim_start();
+    let processor = Processor::new(parse(src).unwrap());
+    let object = target::native(&processor, X86_64_LINUX, Artifact::Object, Some("ma")).unwrap();
+    assert!(object.starts_with(b"\x7fELF"));
+
+    let header = target::header(&processor, Some("ma")).unwrap();
+    assert!(header.contains("void ma_on_bar(void *state, const struct ninescript_bar *bar);"));
+    assert!(header.contains(" * [1] src (int64_t, index of open"));
+    assert!(header.contains("#define MA_OUTPUT_RISING 3"));
+    assert!(header.contains("#define MA_SCRIPT \"{\\\"metadata\\\":{"));
+    assert!(header.contains("struct ninescript_run *ninescript_run_new("));
+
+    /* The libraries bundle the runtime, built without LLVM */
+    env::set_var("NINESCRIPT_RUNTIME", runtime(None).unwrap());
+    let archive = target::native(&processor, X86_64_LINUX, Artifact::StaticLibrary, Some("ma")).unwrap();
+    assert!(archive.starts_with(b"!<arch>\n"));
+    let library = target::native(&processor, X86_64_LINUX, Artifact::SharedLibrary, Some("ma")).unwrap();
+
+    let host = r#"
+#include <stdio.h>
+#include <stdlib.h>
+#include "ma.h"
+
+int main(void) {
+    struct ninescript_run *run = ninescript_run_new(MA_SCRIPT, "TEST", "1D");
+    void *state = aligned_alloc(8, (ma_state_size() + 7) / 8 * 8);
+    ma_init(state, NULL);
+    for (int i = 0; i < 40; i++) {
+        double close = 100.0 + (i % 7) * (i % 3);
+        struct ninescript_bar bar = { i * 86400000LL, close, close + 1, close - 1, close, 10 };
+        ninescript_start_bar(run, &bar, i == 39);
+        ma_on_bar(state, &bar);
+        const char *error = ninescript_end_bar(run);
+        if (error) {
+            puts(error);
+            return 1;
+        }
+        printf("%.6f\n", ma_get_outputs(state)[MA_OUTPUT_MA]);
+    }
+    puts(ninescript_result(run));
+    ninescript_run_free(run);
+    free(state);
+    return 0;
+}
+"#;
+    let dir = env::temp_dir().join(format!("ninescript-native-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("ma.h"), header).unwrap();
+    fs::write(dir.join("host.c"), host).unwrap();
+    fs::write(dir.join("libma.so"), library).unwrap();
+    let status = Command::new("cc").current_dir(&dir).args(["host.c", "-o", "host", "-L.", "-lma", "-Wl,-rpath,$ORIGIN"]).status().unwrap();
+    assert!(status.success());
+    let output = Command::new(dir.join("host")).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+
+    let bars = (0..40).map(|i| {
+        let close = 100.0 + ((i % 7) * (i % 3)) as f64;
+        Bar { time: i * 86_400_000, open: close, high: close + 1.0, low: close - 1.0, close, volume: 10.0 }
+    }).collect::<Vec<_>>();
+    let script = Compiler::new().compile(src).unwrap();
+    let expected = script.run(&bars, &script.params()).unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    let ma = &expected.outputs.iter().find(|o| o.name == "ma").unwrap().values;
+    assert_eq!(lines[..40], ma.iter().map(|v| format!("{:.6}", v).to_lowercase()).collect::<Vec<_>>());
+    let result: serde_json::Value = serde_json::from_str(lines[40]).unwrap();
+    assert_eq!(result["strategy"]["trades"], serde_json::to_value(&expected.strategy.unwrap().trades).unwrap());
+}
+
+#[test]
+fn script() {
+    use crate::{error::InputError, types::Constant};
+
+    let errors = Script::compile("indicator(\"Broken\")\nint x = = 1\n").err().unwrap();
+    let error = errors.iter().next().unwrap();
+    assert_eq!((error.severity, error.location.map(|l| l.row())), (Severity::Error, Some(2)));
+    assert!(errors.to_string().starts_with("error: unexpected `=`, expected `(`, `[`, identifier, int"));
+    let errors = Script::compile("indicator(\"Broken\")\nfloat x = 1 $ 2\n").err().unwrap();
+    assert!(errors.to_string().starts_with("error: invalid token `$` at line 2 column 13"));
+
+    let src = r#"
+//@version=5
+//@colour red
+indicator("Embedded")
+int length = input.int(2, "Length", minval = 1)
+float ma = ta.sma(close, length)
+"#.trim_start();
+    let bars = [1.0, 2.0, 3.0, 5.0].map(|close| Bar { close, ..Bar::default() });
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).compile(src).unwrap();
+        assert_eq!(script.warnings().iter().map(|w| w.severity).collect::<Vec<_>>(), [Severity::Warning]);
+
+        let mut params = script.params();
+        assert_eq!(params.set("length", 0), Err(InputError::OutOfRange { input: "length".to_string(), value: 0.0 }));
+        params.set("length", 3).unwrap();
+        assert_eq!(params.get("length"), Some(&Constant::Int(3)));
+        assert!(params.set_json(r#"{"length": 2, "unknown": 1}"#).is_err());
+        assert_eq!(params.values(), [Constant::Int(3)]);
+
+        let result = script.run(&bars, &params).unwrap();
+        assert_eq!(result.outputs[1].values[3], 10.0 / 3.0);
+        assert!(result.strategy.is_none());
+    }
+}
+
+#[test]
+fn plots() {
+    use crate::{plots::PlotKind, types::{Constant, RGBA}};
+
+    let src = r#"
+indicator("Plots")
+float ma = ta.sma(close, 2)
+plot fast = plot(ma, "MA", color = close > open ? #4caf50 : #f23645, linewidth = 2)
+plot level = hline(2.5, "Level", linestyle = hline.style_dashed)
+plotshape(close > ma, style = shape.triangleup, location = location.belowbar)
+fill(fast, level, color = #2196f380)
+bgcolor(close > 3 ? #ff000040 : na)
+"#.trim_start();
+    let bars = [1.0, 2.0, 3.0, 5.0].map(|close| Bar { open: 2.0, close, ..Bar::default() });
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).compile(src).unwrap();
+        let kinds = script.plots().iter().map(|p| p.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [PlotKind::Plot, PlotKind::Hline, PlotKind::PlotShape, PlotKind::Fill, PlotKind::Bgcolor]);
+        assert_eq!(script.plots()[0].style.get("linewidth"), Some(&Constant::Int(2)));
+        assert_eq!(script.plots()[1].style.get("linestyle"), Some(&Constant::String("dashed".to_string())));
+        assert_eq!(script.plots()[3].between, Some((0, 1)));
+
+        let result = script.run(&bars, &script.params()).unwrap();
+        assert!(result.plots[0].values[0].is_nan());
+        assert_eq!(result.plots[0].values[1..], [1.5, 2.5, 4.0]);
+        assert_eq!(result.plots[0].colors[1], RGBA::from_hex("#F23645"));
+        assert_eq!(result.plots[1].values, [2.5; 4]);
+        assert_eq!(result.plots[2].values, [0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(result.plots[4].colors, [None, None, None, RGBA::from_hex("#ff000040")]);
+
+        let csv = export::csv(&bars, &result);
+        assert!(csv.starts_with("time,ma,MA,MA.color,Level,Level.color,plotshape2,plotshape2.color,fill3.color,bgcolor4.color\n"));
+        let json: serde_json::Value = serde_json::from_str(&export::json(&bars, &result)).unwrap();
+        assert_eq!(json["plots"][0]["values"][0], serde_json::Value::Null);
+        assert_eq!(json["plots"][3]["between"], serde_json::json!([0, 1]));
+        assert!(json["plots"][4].get("values").is_none());
+    }
+}
+
+#[test]
+fn csv() {
+    let src = r#"
+indicator("Columns")
+float ma = ta.sma(close, 2)
+plot(ma, "ma")
+plot(close, "Fast, \"slow\"")
+plot(close, "ma")
+"#.trim_start();
+    let bars = [1.0, 2.0].map(|close| Bar { time: close as i64, close, ..Bar::default() });
+    let script = Compiler::new().with_backend(Backend::Interpreter).compile(src).unwrap();
+    let csv = script.run(&bars, &script.params()).unwrap().to_csv(&bars);
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some(r#"time,ma,ma 2,ma 2.color,"Fast, ""slow""","Fast, ""slow"".color",ma 3,ma 3.color"#));
+    assert_eq!(lines.next(), Some("1,NaN,NaN,,1,,1,"));
+}
+
+#[test]
+fn colors() {
+    use crate::types::RGBA;
+
+    let src = r#"
+indicator("Colors")
+color faded = color.new(color.red, 50)
+color mixed = color.rgb(300, 128.4, -1)
+float transp = color.t(faded)
+float red = color.r(faded)
+color heat = color.from_gradient(close, 1, 3, #000000, #ffffff80)
+color missing = color.new(na, 20)
+"#.trim_start();
+    let bars = [1.0, 2.0, 5.0].map(|close| Bar { close, ..Bar::default() });
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).compile(src).unwrap();
+        let result = script.run(&bars, &script.params()).unwrap();
+        let color = |output: usize, bar: usize| RGBA::from_packed(result.outputs[output].values[bar] as u32);
+        assert_eq!(color(0, 0), RGBA(0xFF, 0x52, 0x52, 128));
+        assert_eq!(color(1, 0), RGBA(255, 128, 0, 255));
+        assert_eq!(result.outputs[2].values[0], 50.0);
+        assert_eq!(result.outputs[3].values[0], 255.0);
+        assert_eq!([color(4, 0), color(4, 1), color(4, 2)], [RGBA(0, 0, 0, 255), RGBA(128, 128, 128, 191), RGBA(255, 255, 255, 128)]);
+        assert_eq!(result.outputs[5].values[0], 0.0);
+    }
+}
+
+#[test]
+fn drawings() {
+    use crate::{declaration::ScriptMetadata, drawings::DrawingKind, types::{Constant, RGBA}};
+
+    let src = r#"
+indicator("Drawings", max_labels_count = 2)
+label tag = label.new(bar_index, close, "bar", color = #ff0000)
+label.set_xy(tag, bar_index, high)
+float x = label.get_x(tag)
+var label first = label.new(0, close)
+label.delete(first)
+var table t = table.new(position.top_right, 2, 2)
+table.cell(t, 1, 0, "Close")
+polyline p = polyline.new([chart.point.from_index(bar_index, low), chart.point.from_index(bar_index, high)])
+"#.trim_start();
+    let bars = [1.0, 2.0, 5.0].map(|close| Bar { close, high: close + 1.0, ..Bar::default() });
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).compile(src).unwrap();
+        let result = script.run(&bars, &script.params()).unwrap();
+        assert_eq!(result.outputs[0].values, [0.0, 1.0, 2.0]);
+        let labels = result.drawings.iter().filter(|d| d.kind == DrawingKind::Label).collect::<Vec<_>>();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[1].properties["y"], Constant::Float(6.0));
+        assert_eq!(labels[1].properties["color"], Constant::Color(RGBA(255, 0, 0, 255)));
+        let table = result.drawings.iter().find(|d| d.kind == DrawingKind::Table).unwrap();
+        assert_eq!(table.cells[0].properties["text"], Constant::from("Close"));
+        assert_eq!(result.drawings.iter().filter(|d| d.kind == DrawingKind::Polyline && d.points.len() == 2).count(), 3);
+        assert!(export::json(&bars, &result).contains("\"drawings\":[{"));
+    }
+
+    let mut drawings = drawings::Drawings::new(&ScriptMetadata::from_statements(&parse(src).unwrap()).unwrap());
+    let index = drawings::property(DrawingKind::Point, "index").unwrap();
+    let first = drawings.create(DrawingKind::Point);
+    drawings.set(index, first, 1.0);
+    for _ in 0..10_000 {
+        drawings.create(DrawingKind::Point);
+    }
+    assert!(drawings.get(index, first).is_nan());
+
+    let y = drawings::property(DrawingKind::Label, "y").unwrap();
+    let label = drawings.create(DrawingKind::Label);
+    drawings.set(y, label, 0.5);
+    drawings.save();
+    for _ in 0..2 {
+        drawings.set(y, label, 1.0);
+        let created = [drawings.create(DrawingKind::Label), drawings.create(DrawingKind::Label)];
+        assert!(drawings.get(y, label).is_nan());
+        drawings.restore();
+        assert_eq!(created[0], label + 1);
+    }
+    assert_eq!(drawings.get(y, label), 0.5);
+    assert_eq!(drawings.into_output().iter().map(|d| d.id).collect::<Vec<_>>(), [label]);
+}
+
+#[test]
+fn security() {
+    use crate::security::{self, Feeds};
+
+    let src = r#"
+indicator("Security")
+string tf = input.timeframe("D", "Timeframe")
+float daily = request.security(syminfo.tickerid, tf, close)
+float ahead = request.security(syminfo.tickerid, tf, close, lookahead = barmerge.lookahead_on)
+float gaps = request.security("", tf, close, gaps = barmerge.gaps_on)
+int index = request.security("", tf, bar_index)
+float same = request.security("", "", close)
+"#.trim_start();
+    let bar = |time: i64, close: f64| Bar { time, close, ..Bar::default() };
+    let chart = (0..6).map(|i| bar(i, i as f64)).collect::<Vec<_>>();
+    let daily = vec![bar(0, 10.0), bar(3, 20.0)];
+    assert_eq!(security::align(&chart, &daily, false, false), [None, None, Some(0), Some(0), Some(0), Some(1)]);
+
+    let feeds = Feeds::new("X", "60").with("X", "D", daily);
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).compile(src).unwrap();
+        let result = script.run_with(&chart, &script.params(), &feeds).unwrap();
+        let series = |i: usize| result.outputs[i].values.iter().map(|v| if v.is_nan() { -1.0 } else { *v }).collect::<Vec<_>>();
+        assert_eq!(series(0), [-1.0, -1.0, 10.0, 10.0, 10.0, 20.0]);
+        assert_eq!(series(1), [10.0, 10.0, 10.0, 20.0, 20.0, 20.0]);
+        assert_eq!(series(2), [-1.0, -1.0, 10.0, -1.0, -1.0, 20.0]);
+        assert_eq!(series(3), [-1.0, -1.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(result.outputs[4].values, [0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert!(script.run(&chart, &script.params()).is_err());
+    }
+}
+
+#[test]
+fn timeframes() {
+    for s in ["5S", "60", "D", "3D", "W", "3M", "100T", "1000V", "2.5R"] {
+        assert_eq!(Timeframe::parse(s).unwrap().to_string(), s);
+    }
+    assert_eq!(Timeframe::parse("4H"), Ok(Timeframe::Minutes(240)));
+    assert!(["", "0", "-1D", "1.5", "2Y"].iter().all(|s| Timeframe::parse(s).is_err()));
+    assert_eq!(Timeframe::parse("M").unwrap().in_seconds(), Some(2628003));
+    assert_eq!(Timezone::parse("UTC-03:30").unwrap().offset(0), -210 * 60_000);
+
+    let hour = 3_600_000;
+    let monday = 1_704_067_200_000;
+    let bars = (0..24 * 9).map(|i| {
+        let x = i as f64;
+        Bar { time: monday + i * hour, open: x, high: x + 1.0, low: x, close: x + 0.5, volume: 1.0 }
+    }).collect::<Vec<_>>();
+    let resample = |timeframe: &str| Resampler::new(Timeframe::parse(timeframe).unwrap()).resample(&bars);
+    let daily = resample("D");
+    assert_eq!(daily.len(), 9);
+    assert_eq!(daily[1], Bar { time: monday + 24 * hour, open: 24.0, high: 48.0, low: 24.0, close: 47.5, volume: 24.0 });
+    assert_eq!(resample("W").iter().map(|b| b.time).collect::<Vec<_>>(), [monday, monday + 7 * 24 * hour]);
+    assert_eq!(resample("M").len(), 1);
+    assert_eq!(resample("240").len(), 54);
+    assert_eq!(resample("5T").len(), 44);
+    assert_eq!(resample("10V").len(), 22);
+    assert_eq!(resample("3R").len(), 72);
+    let futures = Resampler::new(Timeframe::Days(1))
+        .with_timezone(Timezone::parse("UTC-5").unwrap())
+        .with_session_start(17, 0)
+        .resample(&bars);
+    assert_eq!((futures[0].time, futures[1].time), (monday - 2 * hour, monday + 22 * hour));
+
+    let src = r#"
+indicator("Timeframes")
+float daily = request.security("", "D", close)
+bool dwm = request.security("", "D", timeframe.isdwm)
+int seconds = timeframe.in_seconds()
+int day = timeframe.in_seconds("D")
+int multiplier = timeframe.multiplier
+bool intraday = timeframe.isintraday
+"#.trim_start();
+    let feeds = Feeds::new("X", "60");
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).compile(src).unwrap();
+        let result = script.run_with(&bars, &script.params(), &feeds).unwrap();
+        let value = |output: usize, bar: usize| result.outputs[output].values[bar];
+        assert!(value(0, 22).is_nan());
+        assert_eq!((value(0, 23), value(0, 47), value(1, 22), value(1, 23)), (23.5, 47.5, 0.0, 1.0));
+        assert_eq!((value(2, 0), value(3, 0), value(4, 0), value(5, 0)), (3600.0, 86400.0, 60.0, 1.0));
+    }
+}
+
+#[test]
+fn calendar() {
+    let hour = 3_600_000;
+    let new_york = Timezone::parse("America/New_York").unwrap();
+    let dst = 1_710_054_000_000;
+    assert_eq!((new_york.offset(dst - 1), new_york.offset(dst)), (-5 * hour, -4 * hour));
+    assert_eq!(Timezone::parse("Australia/Sydney").unwrap().offset(dst), 11 * hour);
+    assert_eq!(Timezone::parse("Asia/Kolkata").unwrap().offset(dst), 11 * hour / 2);
+    assert!(Timezone::parse("Mars/Olympus_Mons").is_err());
+    let sao_paulo = Timezone::parse("America/Sao_Paulo").unwrap();
+    assert_eq!(new_york.offset(1_162_382_400_000), -5 * hour);
+    assert_eq!((sao_paulo.offset(1_543_665_600_000), sao_paulo.offset(1_575_201_600_000)), (-2 * hour, -3 * hour));
+    let date = new_york.datetime(1_704_067_200_000);
+    assert_eq!((date.year, date.month, date.day, date.weekday, date.hour, date.week_of_year()), (2023, 12, 31, 1, 19, 52));
+
+    let monday = 1_704_690_000_000;
+    let regular = Session::parse("0930-1600:23456").unwrap();
+    assert!(regular.contains(monday + 19 * hour / 2, &new_york) && !regular.contains(monday + 16 * hour, &new_york));
+    assert!(!regular.contains(monday - 38 * hour, &new_york));
+    let overnight = Session::parse("1700-1600:23456").unwrap();
+    assert!(overnight.contains(monday - 6 * hour, &new_york) && !overnight.contains(monday + 114 * hour, &new_york));
+    assert!(["0930", "2500-1000", "0930-1600:8"].iter().all(|s| Session::parse(s).is_err()));
+    let midnight = Session::parse("0930-2400:2").unwrap();
+    assert!(midnight.contains(monday + 47 * hour / 2, &new_york) && !midnight.contains(monday - hour, &new_york));
+    assert!(midnight.is_before(monday + 9 * hour, &new_york) && !midnight.is_after(monday + 47 * hour / 2, &new_york));
+
+    let src = r#"
+indicator("Calendar")
+int h = hour
+int m = minute
+bool monday = dayofweek == dayofweek.monday
+bool market = session.ismarket
+bool premarket = session.ispremarket
+bool postmarket = session.ispostmarket
+int day = time("D")
+int morning = time("60", "1000-1100")
+int utc = hour(time, "UTC")
+bool in_morning = not na(time("60", "1000-1100"))
+"#.trim_start();
+    let bars = (0..48).map(|i| Bar { time: monday + i * hour / 2, ..Bar::default() }).collect::<Vec<_>>();
+    let mut feeds = Feeds::new("X", "30");
+    feeds.timezone = new_york;
+    feeds.session = regular;
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).compile(src).unwrap();
+        let result = script.run_with(&bars, &script.params(), &feeds).unwrap();
+        let bar = |i: usize| result.outputs.iter().map(|o| o.values[i]).collect::<Vec<_>>();
+        let time = |i: usize| bars[i].time as f64;
+        assert_eq!(bar(18)[..7], [9.0, 0.0, 1.0, 0.0, 1.0, 0.0, time(19) - 24.0 * hour as f64]);
+        assert_eq!(bar(18)[8..], [14.0, 0.0]);
+        assert_eq!(bar(19)[..7], [9.0, 30.0, 1.0, 1.0, 0.0, 0.0, time(19)]);
+        assert!(bar(18)[7].is_nan() && bar(19)[7].is_nan());
+        assert_eq!(bar(20), [10.0, 0.0, 1.0, 1.0, 0.0, 0.0, time(19), time(19), 15.0, 1.0]);
+        assert_eq!(bar(21)[7], time(21));
+        assert_eq!(bar(32)[3..6], [0.0, 0.0, 1.0]);
+    }
+}
+
+#[test]
+fn streaming() {
+    let src = r#"
+indicator("Streaming")
+var int closes = 0
+varip int ticks = 0
+ticks := ticks + 1
+if barstate.isconfirmed
+    closes := closes + 1
+bool new = barstate.isnew
+bool realtime = barstate.isrealtime
+bool history = barstate.ishistory
+bool last = barstate.islast
+float sma = ta.sma(close, 2)
+"#.trim_start();
+    let minute = 60_000;
+    let history = (0..3).map(|i| Bar { time: i * minute, close: (i + 1) as f64, ..Bar::default() }).collect::<Vec<_>>();
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).compile(src).unwrap();
+        let mut stream = script.stream(&history, &script.params(), &Feeds::new("X", "1")).unwrap();
+        let last = |stream: &Stream| stream.result().outputs.iter().map(|o| *o.values.last().unwrap()).collect::<Vec<_>>();
+        assert_eq!(last(&stream), [3.0, 3.0, 1.0, 0.0, 1.0, 1.0, 2.5]);
+
+        stream.on_tick(Tick { time: 3 * minute, price: 4.0, volume: 1.0 }).unwrap();
+        assert_eq!(last(&stream), [3.0, 4.0, 1.0, 1.0, 0.0, 1.0, 3.5]);
+        stream.on_tick(Tick { time: 3 * minute + 30_000, price: 6.0, volume: 2.0 }).unwrap();
+        assert_eq!(last(&stream), [3.0, 5.0, 0.0, 1.0, 0.0, 1.0, 4.5]);
+        stream.on_tick(Tick { time: 4 * minute + 10_000, price: 5.0, volume: 1.0 }).unwrap();
+        let result = stream.result();
+        assert_eq!(result.outputs.iter().map(|o| o.values[3]).collect::<Vec<_>>(), [4.0, 6.0, 0.0, 1.0, 0.0, 1.0, 4.5]);
+        assert_eq!(last(&stream), [4.0, 7.0, 1.0, 1.0, 0.0, 1.0, 5.5]);
+        stream.on_bar_close().unwrap();
+        assert_eq!(last(&stream), [5.0, 8.0, 0.0, 1.0, 0.0, 1.0, 5.5]);
+
+        assert_eq!(stream.bars().len(), 5);
+        assert_eq!(stream.bars()[3], Bar { time: 3 * minute, open: 4.0, high: 6.0, low: 4.0, close: 6.0, volume: 3.0 });
+        assert_eq!(stream.result().outputs[0].values, [1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        /* A tick inside the period of the last bar of the history updates it */
+        let mut stream = script.stream(&history, &script.params(), &Feeds::new("X", "1")).unwrap();
+        stream.on_tick(Tick { time: 2 * minute + 30_000, price: 10.0, volume: 1.0 }).unwrap();
+        assert_eq!(last(&stream), [2.0, 4.0, 0.0, 1.0, 0.0, 1.0, 6.0]);
+        stream.on_bar_close().unwrap();
+        assert_eq!(last(&stream), [3.0, 5.0, 0.0, 1.0, 0.0, 1.0, 6.0]);
+        assert_eq!(stream.bars().len(), 3);
+        assert_eq!(stream.bars()[2], Bar { time: 2 * minute, high: 10.0, close: 10.0, volume: 1.0, ..Bar::default() });
+
+        let invalid = script.stream(&history, &script.params(), &Feeds::new("X", "1x")).err().unwrap();
+        assert_eq!(invalid.to_string(), "error: invalid timeframe 1x\n");
+    }
+}
+
+#[test]
+fn alerts() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let src = r#"
+indicator("Alerts")
+plot(close * 2, "Double")
+alert("all {{close}}", alert.freq_all)
+alert("once")
+alert('close {{plot_0}} {{ plot("Double") }} {{ticker}} {{interval}} {{time}} {{unknown}}', alert.freq_once_per_bar_close)
+alertcondition(close > 2, "Up", "{{close}} on {{ticker}}")
+"#.trim_start();
+    let minute = 60_000;
+    let history = (0..3).map(|i| Bar { time: i * minute, close: (i + 1) as f64, ..Bar::default() }).collect::<Vec<_>>();
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).compile(src).unwrap();
+        let mut stream = script.stream(&history, &script.params(), &Feeds::new("X", "1")).unwrap();
+        let alerts = stream.result().alerts;
+        assert_eq!(alerts.len(), 10);
+        assert_eq!(alerts[6].message, "all 3");
+        assert_eq!(alerts[8], Alert { bar_index: 2, time: 2 * minute, title: None, message: "close 6 6 X 1 1970-01-01T00:02:00Z {{unknown}}".to_string() });
+        assert_eq!(alerts[9], Alert { bar_index: 2, time: 2 * minute, title: Some("Up".to_string()), message: "3 on X".to_string() });
+
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        let sink = fired.clone();
+        stream.on_alert(move |alert| sink.borrow_mut().push(alert.message.clone()));
+        stream.on_tick(Tick { time: 3 * minute, price: 4.0, volume: 1.0 }).unwrap();
+        stream.on_tick(Tick { time: 3 * minute + 30_000, price: 6.0, volume: 1.0 }).unwrap();
+        stream.on_bar_close().unwrap();
+        assert_eq!(*fired.borrow(), ["all 4", "once", "all 6", "all 6", "close 12 12 X 1 1970-01-01T00:03:00Z {{unknown}}", "6 on X"]);
+        assert_eq!(stream.result().alerts.len(), 16);
+    }
+}
+
+#[test]
+fn modules() {
+    use std::fs;
+    use crate::error::ModuleError;
+
+    let root = std::env::temp_dir().join(format!("ninescript-modules-{}", std::process::id()));
+    let libraries = [
+        ("acme/math/1/math.ns", "library(\"Math\")\nexport square(float x) => x * x\nexport twice(float x) => square(x) * 2\n"),
+        ("acme/util/2/util.ns", "library(\"Util\")\nimport acme/math/1\nexport quad(float x) => math.twice(math.square(x))\n"),
+        ("acme/a/1/a.ns", "library(\"A\")\nimport acme/b/1\n"),
+        ("acme/b/1/b.ns", "library(\"B\")\nimport acme/a/1\n"),
+    ];
+    for (path, src) in libraries {
+        fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+        fs::write(root.join(path), src).unwrap();
+    }
+
+    let src = r#"
+indicator("Modules")
+import acme/util/2
+import acme/math/1
+float q = util.quad(close)
+float s = math.square(close)
+"#.trim_start();
+    let bars = [Bar { close: 2.0, ..Bar::default() }];
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).with_resolver(Resolver::new().with_path(&root)).compile(src).unwrap();
+        let result = script.run(&bars, &script.params()).unwrap();
+        assert_eq!(result.outputs.iter().map(|o| o.values[0]).collect::<Vec<_>>(), [32.0, 4.0]);
+    }
+
+    let link = |src: &str| Resolver::new().with_path(&root).link(parse(src).unwrap());
+    assert_eq!(link("import acme/math/1\nfloat x = math.cube(1)\n").unwrap_err(),
+        ModuleError::NotExported { module: "acme/math/1".to_string(), name: "cube".to_string() });
+    assert_eq!(link("import acme/a/1\n").unwrap_err(),
+        ModuleError::Cycle(vec!["acme/a/1".to_string(), "acme/b/1".to_string(), "acme/a/1".to_string()]));
+    assert_eq!(link("import acme/none/1\n").unwrap_err(), ModuleError::NotFound("acme/none/1".to_string()));
+    assert_eq!(link("import acme/math\n").unwrap_err(), ModuleError::InvalidPath("acme/math".to_string()));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn libraries() {
+    use std::fs;
+    use crate::{checker::TypeChecker, error::ModuleError, modules::{self, Resolver}};
+
+    let root = std::env::temp_dir().join(format!("ninescript-libraries-{}", std::process::id()));
+    let file = root.join("src/acme/stats/3/stats.ns");
+    fs::create_dir_all(file.parent().unwrap()).unwrap();
+    fs::write(&file, r#"
+library("Stats")
+const float PERCENT = 100.0
+export const float WEIGHT = 0.5
+half(float x) => x / 2
+export mid(float a, float b) => half(a + b)
+export share(float x, float total) => x / total * PERCENT * WEIGHT
+export enum Side
+    long
+    short
+"#.trim_start()).unwrap();
+    assert_eq!(modules::locate(&file), Some((root.join("src"), "acme/stats/3".to_string())));
+
+    /* Scripts importing the compiled library do not need its source */
+    let module = Resolver::new().with_path(root.join("src")).load("acme/stats/3").unwrap();
+    let mut exports = module.exports().collect::<Vec<_>>();
+    exports.sort();
+    assert_eq!(exports, ["Side", "WEIGHT", "mid", "share"]);
+    let artifact = root.join("lib/acme/stats/3/stats.nslib");
+    fs::create_dir_all(artifact.parent().unwrap()).unwrap();
+    fs::write(&artifact, module.artifact()).unwrap();
+
+    let src = "indicator(\"Mid\")\nimport acme/stats/3\nfloat m = stats.mid(high, low)\nfloat s = stats.share(low, high) + stats.WEIGHT\n";
+    let bars = [Bar { high: 4.0, low: 2.0, ..Bar::default() }];
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).with_resolver(Resolver::new().with_path(root.join("lib"))).compile(src).unwrap();
+        let result = script.run(&bars, &script.params()).unwrap();
+        assert_eq!(result.outputs.iter().map(|o| (o.name.as_str(), o.values[0])).collect::<Vec<_>>(), [("m", 3.0), ("s", 25.5)]);
+    }
+
+    let link = |src: &str| Resolver::new().with_path(root.join("lib")).link(parse(src).unwrap());
+    let check = |src: &str| TypeChecker::new().check(&link(src).unwrap());
+    assert!(check("indicator(\"E\")\nimport acme/stats/3\nstats.Side side = stats.Side.short\nbool long = side == stats.Side.long\n").is_ok());
+    assert!(check("indicator(\"E\")\nimport acme/stats/3\nstats.Side side = stats.Side.flat\n").is_err());
+    assert_eq!(link("import acme/stats/3\nfloat h = stats.half(1)\n").unwrap_err(),
+        ModuleError::NotExported { module: "acme/stats/3".to_string(), name: "half".to_string() });
+    assert_eq!(link("import acme/stats/3\nfloat p = stats.PERCENT\n").unwrap_err(),
+        ModuleError::NotExported { module: "acme/stats/3".to_string(), name: "PERCENT".to_string() });
+    assert_eq!(link("library(\"L\")\nexport f(x) => x\n").unwrap_err(),
+        ModuleError::UntypedExport { function: "f".to_string(), param: "x".to_string() });
+    assert_eq!(link("indicator(\"I\")\nexport f(float x) => x\n").unwrap_err(), ModuleError::ExportOutsideLibrary("f".to_string()));
+    assert_eq!(link("indicator(\"I\")\nexport enum Side\n    long\n").unwrap_err(), ModuleError::ExportOutsideLibrary("Side".to_string()));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn import_aliases() {
+    use std::fs;
+    use crate::{ast::Statement, error::ModuleError};
+
+    assert!(matches!(&parse("import acme/math/1 as m\n").unwrap()[..], [Statement::Import(path, Some(alias))] if path == "acme/math/1" && alias == "m"));
+
+    let root = std::env::temp_dir().join(format!("ninescript-aliases-{}", std::process::id()));
+    fs::create_dir_all(root.join("acme/math/1")).unwrap();
+    fs::write(root.join("acme/math/1/math.ns"), "library(\"Math\")\nexport square(float x) => x * x\n").unwrap();
+
+    let src = "indicator(\"Aliases\")\nimport acme/math/1 as m\nfloat s = m.square(close)\n";
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).with_resolver(Resolver::new().with_path(&root)).compile(src).unwrap();
+        assert_eq!(script.run(&[Bar { close: 3.0, ..Bar::default() }], &script.params()).unwrap().outputs[0].values, [9.0]);
+    }
+
+    let link = |src: &str| Resolver::new().with_path(&root).link(parse(src).unwrap());
+    assert_eq!(link("import acme/math/1 as m\nfloat m = 1\n").unwrap_err(), ModuleError::AliasCollision("m".to_string()));
+    assert_eq!(link("import acme/math/1 as m\nf(m) => m\n").unwrap_err(), ModuleError::AliasCollision("m".to_string()));
+    assert_eq!(link("import acme/math/1\nimport acme/math/1 as math\n").unwrap_err(), ModuleError::DuplicateAlias("math".to_string()));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn name_resolution() {
+    use crate::{ast::{Expr, Statement}, error::{NameError, Warning}, location::Location, scopes::{NameResolver, Symbol}};
+
+    /* Names keep their location in copies of the statements */
+    let resolve = |src: &str| {
+        let statements = parse(src).unwrap().clone();
+        let mut names = NameResolver::new();
+        names.resolve(&statements).map(|_| names.into_warnings())
+    };
+    let at = |row, column| Some(Location::new(row, column));
+    assert_eq!(resolve("float x = 1\nf(float y) =>\n    float z = x + y\n    z\nfloat w = f(x)\n"), Ok(vec![]));
+    assert_eq!(resolve("float x = 1\nif close > 1\n    float x = 2\n"), Ok(vec![Warning::Shadowing("x".to_string(), at(3, 11))]));
+    assert_eq!(resolve("float high = 1\n"), Ok(vec![Warning::Shadowing("high".to_string(), at(1, 7))]));
+
+    assert_eq!(resolve("if close > 1\n    float t = 1\nfloat u = t\n"), Err(NameError::Undefined("t".to_string(), at(3, 11))));
+    assert_eq!(resolve("for i = 0 to 3\n    float s = i\nfloat u = i\n"), Err(NameError::Undefined("i".to_string(), at(3, 11))));
+    assert_eq!(resolve("total := 1\n"), Err(NameError::UndeclaredAssignment("total".to_string(), at(1, 1))));
+    assert_eq!(resolve("close += 1\n"), Err(NameError::BuiltinAssignment("close".to_string(), at(1, 1))));
+    assert_eq!(resolve("float x = 1\nfloat x = 2\n"), Err(NameError::Redeclaration("x".to_string(), at(2, 7))));
+    assert_eq!(resolve("f(float a, float a) => a\n"), Err(NameError::Redeclaration("a".to_string(), at(1, 18))));
+    assert_eq!(resolve("float x = g(1)\n"), Err(NameError::UndefinedFunction("g".to_string(), at(1, 11))));
+
+    let statements = parse("f(float a) => a + close\n").unwrap();
+    let mut names = NameResolver::new();
+    names.resolve(&statements).unwrap();
+    match &statements[0] {
+        Statement::FnDef(_, _, body) => match &body[0] {
+            Statement::Expression(e) => match e.as_ref() {
+                Expr::Op(a, _, b) => assert_eq!((names.symbol(a), names.symbol(b)), (Some(Symbol::Parameter), Some(Symbol::Builtin))),
+                e => panic!("{:?}", e)
+            },
+            s => panic!("{:?}", s)
+        },
+        s => panic!("{:?}", s)
+    }
+
+    let script = Compiler::new().compile("indicator(\"S\")\nfloat x = 1\nif close > 1\n    float x = 2\n").unwrap();
+    assert!(script.warnings().to_string().contains("x shadows a variable of an outer scope"));
+    assert_eq!(script.warnings().iter().next().unwrap().location, at(4, 11));
+}
+
+#[test]
+fn formatter() {
+    use crate::error::FormatError;
+
+    let src = r#"
+//@version=5
+indicator("Fmt",overlay=true)
+
+
+int length=input.int(14,"Length",minval=1)   // the length
+float d = -close+open*2-(-1)
+array<float> xs = array.new<float>(0)
+f(float x,int n)=>
+    float y=x*n
+    y
+if close>open and not(close<d)
+    d:=d+f(d,2)
+plot(d,title="A moving average of the close price",color=color.new(color.blue,20),linewidth=2,display=display.all)
+"#.trim_start();
+    let formatted = r#"
+//@version=5
+indicator("Fmt", overlay = true)
+
+int length = input.int(14, "Length", minval = 1) // the length
+float d = -close + open * 2 - (-1)
+array<float> xs = array.new<float>(0)
+f(float x, int n) =>
+    float y = x * n
+    y
+if close > open and not (close < d)
+    d := d + f(d, 2)
+plot(d, title = "A moving average of the close price", color = color.new(color.blue, 20),
+      linewidth = 2, display = display.all)
+"#.trim_start();
+    assert_eq!(format::format(src).unwrap(), formatted);
+    assert_eq!(format::format(formatted).unwrap(), formatted);
+    assert_eq!(format::format("import acme/math/1 as m\n").unwrap(), "import acme/math/1 as m\n");
+    assert!(matches!(format::format("float x = (\n"), Err(FormatError::Parse(_))));
+
+    let block = "float d = 0\nif close>open\n    // note\n\n    d:=1\n";
+    assert_eq!(format::format(block).unwrap(), "float d = 0\nif close > open\n    // note\n\n    d := 1\n");
+}
+
+#[test]
+fn syntax_tree() {
+    use crate::{ast::{Expr, Opcode, Statement, Var}, cst::SyntaxKind, token::Tok};
+
+    let src = r#"
+//@version=5
+indicator("Tree") // the title
+
+// Doubles a series.
+f(float x) =>
+    x * 2
+float z = math.max(f(close),
+      open)
+if close > open
+    z := 1
+else
+    z := 2
+"#.trim_start();
+    let mut lexer = lexer::Lexer::new(src, 4);
+    let mut tokens = vec![];
+    while let Some(token) = lexer.next() {
+        tokens.push((token.unwrap().1, lexer.text()));
+    }
+    assert_eq!(tokens.iter().map(|t| t.1.as_str()).collect::<String>(), src);
+    assert_eq!(tokens.iter().filter(|t| t.0 == Tok::Comment).count(), 3);
+    assert_eq!(tokens.iter().filter(|t| t.0 == Tok::Indent).count(), 3);
+
+    let root = cst::parse(src).syntax();
+    assert_eq!(root.text(), src);
+    let statements = root.children().collect::<Vec<_>>();
+    let kinds = statements.iter().map(|n| n.kind()).collect::<Vec<_>>();
+    assert_eq!(kinds, [SyntaxKind::ExprStatement, SyntaxKind::FnDef, SyntaxKind::VarDecl, SyntaxKind::ExprStatement]);
+    assert_eq!(statements[1].children().filter(|n| n.kind() == SyntaxKind::Block).count(), 1);
+    assert_eq!(statements[2].text(), "float z = math.max(f(close),\n      open)");
+    let call = statements[2].children().find(|n| n.kind() == SyntaxKind::Call).unwrap();
+    assert_eq!(call.children().next().unwrap().children().count(), 2);
+    let branch = statements[3].children().next().unwrap();
+    assert_eq!(branch.children().filter(|n| n.kind() == SyntaxKind::Block).count(), 2);
+
+    let title = statements[0].tokens().into_iter().find(|t| t.kind() == SyntaxKind::Comment).unwrap();
+    assert_eq!(&src[title.text_range()], "// the title");
+    assert_eq!(title.parent().kind(), SyntaxKind::ExprStatement);
+
+    let name = |name: &str| Box::new(Expr::Identifier(name.into()));
+    let src = "if c\n    // note\n    y := 1\nx = a - b - c * d\n";
+    assert_eq!(parse(src).unwrap(), [
+        Statement::Expression(Box::new(Expr::If(name("c"), vec![Statement::VarAssign("y".into(), Box::new(Expr::Int(1)))], None))),
+        Statement::VarLet(Var((None, None), "x".into()),
+            Box::new(Expr::Op(Box::new(Expr::Op(name("a"), Opcode::Sub, name("b"))), Opcode::Sub, Box::new(Expr::Op(name("c"), Opcode::Mul, name("d")))))),
+    ]);
+
+    let src = "float a = 1\nif close > open\n    float b = (\n";
+    assert_eq!(Diagnostic::from(parse(src).unwrap_err()).location, Some(location::Location::new(3, 16)));
+    assert_eq!(cst::parse(src).syntax().text(), src);
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn bindings() {
+    use crate::interpreter::Interpreter;
+
+    let src = r#"
+strategy("Bindings")
+int length = input.int(14, "Length", minval = 1)
+string mode = input.string("fast", "Mode", options = ["fast", "slow"])
+float ma = ta.sma(close, length)
+bool rising = ma > ma[1]
+color tint = #ff0000
+"#.trim_start();
+    let interpreter = Interpreter::new(parse(src).unwrap()).unwrap();
+    let (metadata, inputs, outputs) = (interpreter.metadata(), interpreter.inputs(), interpreter.outputs());
+
+    let ts = bindings::typescript(metadata, inputs, outputs, interpreter.plots());
+    assert!(ts.contains("    /** Length. Default 14, at least 1, an integer. */\n    length?: number;"));
+    assert!(ts.contains("    mode?: \"fast\" | \"slow\";"));
+    assert!(ts.contains("    rising: boolean[];\n    tint: string[];"));
+    assert!(ts.contains("    strategy: StrategyReport;"));
+
+    let js = bindings::javascript(metadata, inputs, outputs, interpreter.plots(), interpreter.requests());
+    assert!(js.starts_with("const SCRIPT = {"));
+    assert!(js.contains(r#"{"name":"rising","type":"bool"}"#));
+    assert!(js.contains("export async function load(wasm)"));
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn node() {
+    use std::{env, fs, process::{self, Command}};
+    use serde_json::Value;
+
+    let src = r#"
+strategy("Node")
+int length = input.int(5, "Length", minval = 1)
+float ma = ta.sma(close, length)
+var float total = 0.0
+total := total + close
+float change = close - close[1]
+bool up = close > open
+color heat = color.from_gradient(close, 90, 110, #000000, #ffffff80)
+label tag = label.new(bar_index, close, up ? "up" : "down", color = heat)
+plot(ma, "MA", color = heat)
+alert("close {{close}}", alert.freq_all)
+alertcondition(close > ma, "Above", "{{close}} on {{ticker}}")
+if ta.crossover(close, ma)
+    strategy.entry("Long", strategy.long)
+if ta.crossunder(close, ma)
+    strategy.close("Long")
+"#.trim_start();
+    let minute = 60_000;
+    let bars = (0..60).map(|i| {
+        let close = 100.0 + (i as f64 / 4.0).sin() * 10.0;
+        Bar { time: i * minute, open: close - 1.0, high: close + 2.0, low: close - 2.0, close, volume: 100.0 }
+    }).collect::<Vec<_>>();
+    let ticks = [Tick { time: 60 * minute, price: 120.0, volume: 1.0 }, Tick { time: 60 * minute + 30_000, price: 80.0, volume: 2.0 }];
+    let script = Script::compile(src).unwrap();
+    let mut params = script.params();
+    params.set("length", 3).unwrap();
+    let feeds = Feeds::new("X", "1");
+    let run = export::typed(script.outputs(), &script.run_with(&bars, &params, &feeds).unwrap());
+    let mut stream = script.stream(&bars, &params, &feeds).unwrap();
+    for tick in ticks {
+        stream.on_tick(tick).unwrap();
+    }
+    stream.on_bar_close().unwrap();
+    let streamed = export::typed(script.outputs(), &stream.result());
+    let closes = stream.bars().iter().map(|b| b.close).collect::<Vec<_>>();
+    assert!(run["strategy"]["trades"].as_array().unwrap().len() > 1);
+
+    let statements = parse(src).unwrap();
+    let processor = processor::Processor::new(statements.clone());
+    let interpreter = interpreter::Interpreter::new(statements).unwrap();
+    let js = bindings::javascript(interpreter.metadata(), interpreter.inputs(), interpreter.outputs(), interpreter.plots(), interpreter.requests());
+    let Some(runtime) = runtime(Some(target::WASM32)) else {
+        return eprintln!("skipping the node test: {} is not installed", target::WASM32);
+    };
+    env::set_var("NINESCRIPT_WASM_RUNTIME", runtime);
+    let dir = env::temp_dir().join(format!("ninescript-node-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("script.wasm"), target::wasm(&processor).unwrap()).unwrap();
+    fs::write(dir.join("script.mjs"), js).unwrap();
+    fs::write(dir.join("bars.json"), serde_json::to_string(&bars).unwrap()).unwrap();
+    fs::write(dir.join("ticks.json"), serde_json::to_string(&serde_json::json!(ticks.map(|t| serde_json::json!({
+        "time": t.time, "price": t.price, "volume": t.volume,
+    })))).unwrap()).unwrap();
+    fs::write(dir.join("main.mjs"), r#"
+import { readFileSync } from "fs";
+import { load } from "./script.mjs";
+const read = (name) => JSON.parse(readFileSync(new URL(name, import.meta.url)));
+const script = await load(readFileSync(new URL("script.wasm", import.meta.url)));
+const feeds = { symbol: "X", timeframe: "1" };
+const run = script.run(read("bars.json"), { length: 3 }, feeds);
+const stream = script.stream(read("bars.json"), { length: 3 }, feeds);
+const fired = [];
+stream.onAlert((alert) => fired.push(alert));
+read("ticks.json").forEach((tick) => stream.onTick(tick));
+stream.onBarClose();
+let error = null;
+try {
+  script.run([], { length: 0 });
+} catch (e) {
+  error = e.message;
+}
+console.log(JSON.stringify({ title: script.title, run, stream: stream.result(), closes: stream.bars().map((b) => b.close), fired, error }));
+"#).unwrap();
+    let output = Command::new("node").arg(dir.join("main.mjs")).output();
+    fs::remove_dir_all(&dir).unwrap();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => return eprintln!("skipping the node test: {}", e)
+    };
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    /* JSON has no NaN, both sides write it as null. Node writes 1 where serde_json writes 1.0 */
+    fn same(a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+            (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b)),
+            (Value::Object(a), Value::Object(b)) => a.len() == b.len() && a.iter().all(|(k, v)| b.get(k).is_some_and(|w| same(v, w))),
+            _ => a == b
+        }
+    }
+    let loaded: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(loaded["title"], "Node");
+    assert!(same(&loaded["run"], &run), "{}\n{}", loaded["run"], run);
+    assert!(same(&loaded["stream"], &streamed), "{}\n{}", loaded["stream"], streamed);
+    assert!(same(&loaded["closes"], &serde_json::json!(closes)));
+    let alerts = streamed["alerts"].as_array().unwrap();
+    assert!(same(&loaded["fired"], &Value::Array(alerts[alerts.len() - loaded["fired"].as_array().unwrap().len()..].to_vec())));
+    assert!(loaded["fired"].as_array().unwrap().len() >= ticks.len());
+    assert_eq!(loaded["error"], "0 is out of the range of input length");
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn jit() {
+    use std::rc::Rc;
+    use std::time::Duration;
+    use crate::{runtime::{Bar, BarState, LOOP_LIMIT}, script::Compiled, security::Feeds, types::Constant};
+
+    let src = r#"
+strategy("Cross", initial_capital = 1000.0)
+int fast = input.int(2, "Fast")
+float fast_ma = ta.sma(close, fast)
+float slow_ma = ta.sma(close, 4)
+if ta.crossover(fast_ma, slow_ma)
+    strategy.entry("long", strategy.long, 2)
+if ta.crossunder(fast_ma, slow_ma)
+    strategy.close("long")
+float position = strategy.position_size
+[macd, signal, histogram] = ta.macd(close, 2, 3, 2)
+"#.trim_start();
+    let compiled = Compiled::cached(src, parse(src).unwrap(), LOOP_LIMIT).unwrap();
+    assert!(Rc::ptr_eq(&compiled, &Compiled::cached(src, parse(src).unwrap(), LOOP_LIMIT).unwrap()));
+    let limited = Compiled::cached(src, parse(src).unwrap(), Duration::from_secs(1)).unwrap();
+    assert!(!Rc::ptr_eq(&compiled, &limited));
+    let script = compiled.borrow_dependent();
+
+    let closes = [5.0, 4.0, 3.0, 2.0, 3.0, 5.0, 6.0, 4.0, 2.0, 1.0];
+    let bars = closes.iter().map(|c| Bar { open: *c, close: *c, ..Bar::default() }).collect::<Vec<_>>();
+    let result = script.run_with(&bars, &[], &Feeds::default()).unwrap();
+    assert_eq!(result.outputs[1].name, "fast_ma");
+    assert_eq!(result.outputs[1].values[1], 4.5);
+    assert!(result.outputs[2].values[2].is_nan());
+
+    let report = result.strategy.unwrap();
+    assert_eq!(report.trades.len(), 1);
+    let trade = &report.trades[0];
+    assert_eq!((trade.entry_bar, trade.entry_price), (6, 6.0));
+    assert_eq!((trade.exit_bar, trade.exit_price, trade.profit), (Some(9), Some(1.0), Some(-10.0)));
+    assert_eq!(result.outputs[3].values[6..], [2.0, 2.0, 2.0, 0.0]);
+    assert_eq!(report.metrics.net_profit, -10.0);
+    let (macd, signal, histogram) = (&result.outputs[4].values, &result.outputs[5].values, &result.outputs[6].values);
+    assert_eq!(histogram[9], macd[9] - signal[9]);
+    assert!(!histogram[9].is_nan());
+
+    let slower = script.run_with(&bars, &[Constant::Int(3)], &Feeds::default()).unwrap();
+    assert_eq!(slower.outputs[0].values[0], 3.0);
+
+    /* Runs keep their variables apart */
+    let mut first = script.start(&bars, &[], &Feeds::default()).unwrap();
+    let mut second = script.start(&bars, &[Constant::Int(3)], &Feeds::default()).unwrap();
+    for (i, bar) in bars.iter().enumerate() {
+        let barstate = BarState::history(i + 1 == bars.len());
+        let (a, b) = (first.bar(bar, barstate).unwrap(), second.bar(bar, barstate).unwrap());
+        assert_eq!(format!("{:?}", &a[..4]), format!("{:?}", result.outputs[..4].iter().map(|o| o.values[i]).collect::<Vec<_>>()));
+        assert_eq!(format!("{:?}", &b[..4]), format!("{:?}", slower.outputs[..4].iter().map(|o| o.values[i]).collect::<Vec<_>>()));
+    }
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn int_arithmetic() {
+    use inkwell::context::Context;
+    use crate::jit::Jit;
+
+    let src = r#"
+indicator("Arithmetic")
+int a = 7
+int zero = 0
+int quotient = a / zero
+int remainder = a % zero
+int missing = na
+int sum = missing + 1
+int product = missing * 2
+int lowest = missing / -1
+int kept = a / 2 + a % 2
+"#.trim_start();
+    let context = Context::create();
+    let result = Jit::new(&context).compile(parse(src).unwrap()).unwrap().run_with(&[Bar::default()], &[], &Feeds::default()).unwrap();
+    let value = |name: &str| result.outputs.iter().find(|o| o.name == name).unwrap().values[0];
+    for name in ["quotient", "remainder", "sum", "product", "lowest"] {
+        assert!(value(name).is_nan(), "{} is not na", name);
+    }
+    assert_eq!(value("kept"), 4.0);
+}
+
+#[test]
+fn history() {
+    use crate::{error::CodegenError, interpreter::Interpreter, runtime::Bar};
+
+    let src = r#"
+indicator("History")
+float change = close - close[1]
+float total = 0
+total := nz(total[1]) + close
+int count = bar_index
+int previous = count[1]
+int back = bar_index % 3
+float far = close[back]
+float gone = close[6000]
+"#.trim_start();
+    let interpreter = Interpreter::new(parse(src).unwrap()).unwrap();
+    let bars = [1.0, 2.0, 4.0, 7.0, 11.0].map(|c| Bar { close: c, ..Bar::default() });
+    let result = interpreter.run_with(&bars, &[], &Feeds::default()).unwrap();
+    let series = |name: &str| format!("{:?}", result.outputs.iter().find(|s| s.name == name).unwrap().values);
+    assert_eq!(series("change"), "[NaN, 1.0, 2.0, 3.0, 4.0]");
+    assert_eq!(series("total"), "[1.0, 3.0, 7.0, 14.0, 25.0]");
+    assert_eq!(series("previous"), "[NaN, 0.0, 1.0, 2.0, 3.0]");
+    assert_eq!(series("far"), "[1.0, 1.0, 1.0, 7.0, 7.0]");
+    assert_eq!(series("gone"), "[NaN, NaN, NaN, NaN, NaN]");
+
+    let local = "indicator(\"Local\")\nf() =>\n    float level = close\n    level[1]\nfloat x = f()\n";
+    let unsupported = || CodegenError::Unsupported("history of level".to_string());
+    let error = Interpreter::new(parse(local).unwrap()).err().unwrap();
+    assert_eq!(error.kind(), &unsupported());
+    assert_eq!(Diagnostic::from(error).location.map(|l| (l.row(), l.column())), Some((4, 5)));
+
+    #[cfg(feature = "llvm")]
+    {
+        let context = inkwell::context::Context::create();
+        let mut jit = jit::Jit::new(&context);
+        let compiled = jit.compile(parse(src).unwrap()).unwrap().run_with(&bars, &[], &Feeds::default()).unwrap();
+        assert_eq!(format!("{:?}", compiled), format!("{:?}", result));
+        assert!(matches!(jit.compile(parse(local).unwrap()), Err(error::JitError::Codegen(e)) if e.kind() == &unsupported()));
+    }
+}
+
+#[test]
+fn max_bars_back() {
+    use crate::{interpreter::Interpreter, runtime::Bar};
+
+    let src = r#"
+indicator("Kept", max_bars_back = 2)
+float previous = close[1]
+float before = close[2]
+"#.trim_start();
+    let interpreter = Interpreter::new(parse(src).unwrap()).unwrap();
+    assert_eq!(interpreter.metadata().bars_back(), 2);
+    let bars = [1.0, 2.0, 4.0, 7.0].map(|c| Bar { close: c, ..Bar::default() });
+    let result = interpreter.run_with(&bars, &[], &Feeds::default()).unwrap();
+    assert_eq!(format!("{:?}", result.outputs[0].values), "[NaN, 1.0, 2.0, 4.0]");
+    assert_eq!(format!("{:?}", result.outputs[1].values), "[NaN, NaN, NaN, NaN]");
+
+    #[cfg(feature = "llvm")]
+    {
+        /* One ring of `close`, 8 bytes a bar */
+        let state_size = |src: &str| {
+            let ir = processor::Processor::new(parse(src).unwrap()).ir().unwrap();
+            let body = &ir[ir.find("@state_size").unwrap()..];
+            body[body.find("ret i64 ").unwrap() + 8..].split_whitespace().next().unwrap().parse::<u64>().unwrap()
+        };
+        let unbounded = src.replace(", max_bars_back = 2", "");
+        assert_eq!(state_size(&unbounded) - state_size(src), 8 * (history::MAX_BARS_BACK - 2));
+
+        let context = inkwell::context::Context::create();
+        let compiled = jit::Jit::new(&context).compile(parse(src).unwrap()).unwrap().run_with(&bars, &[], &Feeds::default()).unwrap();
+        assert_eq!(format!("{:?}", compiled), format!("{:?}", result));
+    }
+}
+
+#[test]
+fn precision() {
+    use crate::{interpreter::Interpreter, runtime::Bar};
+
+    let src = r#"
+indicator("Rounded", precision = 2)
+float third = close / 3
+plot(close * 2 / 3)
+"#.trim_start();
+    let interpreter = Interpreter::new(parse(src).unwrap()).unwrap();
+    let bars = [1.0, 2.0, f64::NAN].map(|c| Bar { close: c, ..Bar::default() });
+    let result = interpreter.run_with(&bars, &[], &Feeds::default()).unwrap();
+    assert_eq!(format!("{:?}", result.outputs[0].values), "[0.33, 0.67, NaN]");
+    assert_eq!(format!("{:?}", result.plots[0].values), "[0.67, 1.33, NaN]");
+
+    #[cfg(feature = "llvm")]
+    {
+        let context = inkwell::context::Context::create();
+        let compiled = jit::Jit::new(&context).compile(parse(src).unwrap()).unwrap().run_with(&bars, &[], &Feeds::default()).unwrap();
+        assert_eq!(format!("{:?}", compiled), format!("{:?}", result));
+    }
+}
+
+#[test]
+fn call_sites() {
+    use crate::{interpreter::Interpreter, runtime::Bar};
+
+    let src = r#"
+indicator("Sites")
+f(x) =>
+    var int calls = 0
+    calls := calls + 1
+    ta.sma(x, 2) + calls
+float a = f(close)
+float b = f(close * 10)
+"#.trim_start();
+    let interpreter = Interpreter::new(parse(src).unwrap()).unwrap();
+    let bars = [1.0, 2.0, 3.0].map(|c| Bar { close: c, ..Bar::default() });
+    let result = interpreter.run_with(&bars, &[], &Feeds::default()).unwrap();
+    assert_eq!(format!("{:?}", result.outputs[0].values), "[NaN, 3.5, 5.5]");
+    assert_eq!(format!("{:?}", result.outputs[1].values), "[NaN, 17.0, 28.0]");
+
+    #[cfg(feature = "llvm")]
+    {
+        let context = inkwell::context::Context::create();
+        let compiled = jit::Jit::new(&context).compile(parse(src).unwrap()).unwrap().run_with(&bars, &[], &Feeds::default()).unwrap();
+        assert_eq!(format!("{:?}", compiled), format!("{:?}", result));
+    }
+}
+
+#[test]
+fn loop_limit() {
+    use std::time::Duration;
+
+    let src = "indicator(\"Loop\")\nint count = 0\nwhile true\n    count := count + 1\n";
+    let bars = [Bar::default()];
+    for &backend in Backend::available() {
+        let script = Compiler::new().with_backend(backend).with_loop_limit(Duration::from_millis(10)).compile(src).unwrap();
+        let error = script.run(&bars, &script.params()).unwrap_err();
+        assert!(error.to_string().contains("loop ran longer than 10 ms"), "{}", error);
+    }
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn backends() {
+    /* every script runs on both backends, which must agree on every result */
+    let scripts = [
+        r#"
+indicator("Arithmetic")
+int a = bar_index - 2
+int quotient = 7 / a
+int remainder = 7 % a
+int missing = a > 0 ? a : na
+int sum = missing + 1
+int lowest = missing / -1
+float ratio = close / a
+float half = nz(missing, -1) / 2
+bool odd = a % 2 != 0
+"#,
+        r#"
+indicator("History")
+var float total = 0.0
+total := total + close
+float change = close - close[1]
+float older = nz(close[3], -1)
+int up = 0
+for i = 0 to 2
+    up := up + (close[i] > nz(close[i + 1]) ? 1 : 0)
+float ma = ta.sma(close, 3)
+float spread = ta.stdev(close, 3) + ta.rsi(close, 2)
+plot(ma, "MA", color = close > ma ? color.green : color.red)
+plotshape(ta.crossover(close, ma), "Cross")
+"#,
+        r#"
+indicator("Drawings", max_labels_count = 2)
+label tag = label.new(bar_index, close, close > 3 ? "high" : "low")
+var line l = line.new(0, close, 1, close)
+line.set_x2(l, bar_index)
+float x = line.get_x2(l)
+"#,
+        r#"
+strategy("Strategy", initial_capital = 1000.0)
+float fast = ta.sma(close, 2)
+float slow = ta.sma(close, 4)
+if ta.crossover(fast, slow)
+    strategy.entry("long", strategy.long, 2)
+if ta.crossunder(fast, slow)
+    strategy.close("long")
+float position = strategy.position_size
+alertcondition(fast > slow, "Up", "{{close}}")
+"#,
+    ];
+    let closes = [5.0, 4.0, 3.0, 2.0, 3.0, 5.0, 6.0, 4.0, 2.0, 1.0, 3.0, 4.0];
+    let bars = closes.iter().enumerate()
+        .map(|(i, c)| Bar { time: i as i64 * 60_000, open: *c, high: c + 1.0, low: c - 1.0, close: *c, volume: 10.0 })
+        .collect::<Vec<_>>();
+    for src in scripts {
+        let results = [Backend::Jit, Backend::Interpreter].map(|backend| {
+            let script = Compiler::new().with_backend(backend).compile(src.trim_start()).unwrap();
+            format!("{:?}", script.run(&bars, &script.params()).unwrap())
+        });
+        assert_eq!(results[0], results[1], "{}", src);
+    }
+}
+
+#[test]
+fn interpreter() {
+    use crate::{interpreter::Interpreter, runtime::Bar, types::Constant};
+
+    let src = r#"
+strategy("Reference", initial_capital = 1000.0)
+float src = input.source(close, "Source")
+int length = input.int(2, "Length")
+count(x, step = 1) =>
+    var int calls = 0
+    calls := calls + step
+    [x * 2, calls]
+float fast = ta.sma(src, length)
+float slow = ta.ema(src, 3)
+[doubled, calls] = count(close)
+int total = 0
+for i = 1 to 3
+    total := total + i
+int steps = 0
+while steps < bar_index
+    steps := steps + 1
+float trend = if fast > slow
+    1
+else
+    -1.5
+int grade = switch
+    close > 4 => 2
+    close > 2 => 1
+    => 0
+bool rising = ta.crossover(fast, slow)
+if rising
+    strategy.entry("long", strategy.long, 1)
+if ta.crossunder(fast, slow)
+    strategy.close_all()
+float position = strategy.position_size
+"#.trim_start();
+    let interpreter = Interpreter::new(parse(src).unwrap()).unwrap();
+    let closes = [5.0, 4.0, 3.0, 2.0, 3.0, 5.0, 6.0, 4.0, 2.0, 1.0];
+    let bars = closes.iter().enumerate()
+        .map(|(i, c)| Bar { time: i as i64, open: *c, high: c + 1.0, low: c - 1.0, close: *c, volume: 10.0 })
+        .collect::<Vec<_>>();
+    let result = interpreter.run_with(&bars, &[], &Feeds::default()).unwrap();
+    let series = |name: &str| &result.outputs.iter().find(|s| s.name == name).unwrap().values;
+    assert_eq!(series("fast")[1], 4.5);
+    assert_eq!(series("calls")[9], 10.0);
+    assert_eq!(series("doubled")[0], 10.0);
+    assert_eq!(series("total")[0], 6.0);
+    assert_eq!(series("steps")[4], 4.0);
+    assert_eq!(series("trend")[0], -1.5);
+    assert_eq!(series("grade")[..4], [2.0, 1.0, 1.0, 0.0]);
+    let report = result.strategy.as_ref().unwrap();
+    assert_eq!((report.trades[0].entry_bar, report.trades[0].entry_price), (7, 4.0));
+
+    let high = interpreter.run_with(&bars, &[Constant::String("high".to_string())], &Feeds::default()).unwrap();
+    assert_eq!(high.outputs[0].values[0], 6.0);
+    assert!(series("position")[0].is_sign_positive());
+
+    let pyramiding = Interpreter::new(parse("strategy(\"Pyramid\", pyramiding = 1)\nstrategy.entry(\"long\", strategy.long, 1)\n").unwrap()).unwrap();
+    assert_eq!(pyramiding.run_with(&bars, &[], &Feeds::default()).unwrap().strategy.unwrap().trades.len(), 1);
+
+    #[cfg(feature = "llvm")]
+    {
+        let context = inkwell::context::Context::create();
+        let script = jit::Jit::new(&context).compile(parse(src).unwrap()).unwrap();
+        let compiled = script.run_with(&bars, &[], &Feeds::default()).unwrap();
+        assert_eq!(format!("{:?}", compiled), format!("{:?}", result));
+    }
+}
@@ -0,0 +1,294 @@
+use std::{env, fs, path::Path, process::ExitCode};
+
+use ninescript_compiler::{
+    default_space, format, space_from_json, Backend, Bar, Compiler, Diagnostic, Diagnostics, Feeds, Metric, Metrics, Optimizer, Resolver,
+    RunResult, Search, Session, Tick, Timezone, LIBRARY_EXTENSION,
+};
+#[cfg(feature = "llvm")]
+use ninescript_compiler::Artifact;
+
+const USAGE: &str = "usage:
+    ninescript fmt <script>... [--check]       format scripts in place, with --check list the ones which
+                                               are not formatted and fail instead
+    ninescript inputs <script>                 print the input schema as JSON
+    ninescript library <script> [-o <file>]    compile a library at publisher/library/version/library.ns
+                                               to library.nslib, which imports read without its source
+    ninescript ir <script> [--params <file>]   print LLVM IR, inputs overridden by a JSON file
+    ninescript wasm <script> [--params <file>] [-o <file>]
+                                               compile to a WebAssembly module, with a JS loader and its
+                                               TypeScript declarations next to the output. Modules contain
+                                               the runtime, libninescript_runtime.a from cargo build -p
+                                               ninescript-runtime --target wasm32-unknown-unknown, found in
+                                               the target directory or at NINESCRIPT_WASM_RUNTIME
+    ninescript native <script> [--params <file>] [-o <file>] [--artifact object|shared|static] [--prefix <name>]
+                                               compile for x86-64 Linux, with a C header next to the output.
+                                               Libraries contain the runtime, libninescript_runtime.a from
+                                               cargo build -p ninescript-runtime, found next to the executable
+                                               or at NINESCRIPT_RUNTIME. Objects are linked with it
+    ninescript run <script> <bars> [--params <file>] [--interpret] [--format csv|json]
+                   [--symbol <symbol>] [--timeframe <timeframe>] [--timezone <timezone>] [--session <session>]
+                   [--feed <symbol>:<timeframe>=<bars>]... [--ticks <ticks>] [--alerts <file>]
+                                               run over CSV bars (time,open,high,low,close,volume) and print
+                                               the outputs and plots, with the interpreter if LLVM is unavailable.
+                                               Bars are in the exchange timezone and session, UTC and 24x7 by default.
+                                               request.security() reads other symbols and timeframes from feeds,
+                                               longer timeframes of the symbol are resampled from the bars.
+                                               CSV ticks (time,price,volume) are replayed after the bars as
+                                               realtime bars of the timeframe. Alerts are written as JSON lines
+                                               to the --alerts file, or stderr, with either format
+    ninescript optimize <script> <bars> [--space <file>] [--metric net_profit|sharpe|max_drawdown]
+                   [--samples <n> [--seed <n>]] [--threads <n>] [--interpret]
+                                               run a strategy over every combination of input values, or a
+                                               random sample of them, and print the runs ranked by the metric,
+                                               net profit by default. The space is a JSON file of values or
+                                               {start, end, step} ranges by input, inputs with options,
+                                               bools and bounded ints by default. Every thread compiles the
+                                               linked script once and runs its share of the combinations
+
+Imports of publisher/library/version are read from publisher/library/version/library.ns next to the
+script, then in the directories of --lib <dir> options.";
+
+/// Reads bars from CSV with a header line, columns are `time,open,high,low,close,volume`.
+fn read_bars(path: &str) -> Result<Vec<Bar>, String> {
+    let csv = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    csv.lines().enumerate().skip(1).filter(|(_, line)| !line.trim().is_empty()).map(|(i, line)| {
+        let invalid = || format!("{}:{}: expected time,open,high,low,close,volume", path, i + 1);
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        if fields.len() != 6 {
+            return Err(invalid());
+        }
+        let number = |i: usize| fields[i].parse::<f64>().map_err(|_| invalid());
+        Ok(Bar {
+            time: fields[0].parse().map_err(|_| invalid())?,
+            open: number(1)?,
+            high: number(2)?,
+            low: number(3)?,
+            close: number(4)?,
+            volume: number(5)?,
+        })
+    }).collect()
+}
+
+/// Reads ticks from CSV with a header line, columns are `time,price,volume`.
+fn read_ticks(path: &str) -> Result<Vec<Tick>, String> {
+    let csv = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    csv.lines().enumerate().skip(1).filter(|(_, line)| !line.trim().is_empty()).map(|(i, line)| {
+        let invalid = || format!("{}:{}: expected time,price,volume", path, i + 1);
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        if fields.len() != 3 {
+            return Err(invalid());
+        }
+        Ok(Tick {
+            time: fields[0].parse().map_err(|_| invalid())?,
+            price: fields[1].parse().map_err(|_| invalid())?,
+            volume: fields[2].parse().map_err(|_| invalid())?,
+        })
+    }).collect()
+}
+
+/// Formats scripts in place, or lists the ones which are not formatted with `--check`.
+fn fmt(args: &[String]) -> Result<(), String> {
+    let check = args.iter().any(|a| a == "--check");
+    let paths = args.iter().filter(|a| *a != "--check").collect::<Vec<_>>();
+    if paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    let mut unformatted = 0;
+    for path in &paths {
+        let src = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let formatted = match format(&src) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprint!("{}", Diagnostics::error(&src, e));
+                return Err(format!("{}: could not format the script", path));
+            }
+        };
+        if formatted == src {
+            continue;
+        }
+        if check {
+            println!("{}", path);
+            unformatted += 1;
+        } else {
+            fs::write(path, formatted).map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+    match unformatted {
+        0 => Ok(()),
+        n => Err(format!("{} of {} scripts are not formatted", n, paths.len()))
+    }
+}
+
+/// Searches imports next to the script at `path`, then in the `--lib` directories.
+fn resolver(path: &str, args: &[String]) -> Resolver {
+    let mut resolver = Resolver::new().with_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+    for dir in args.windows(2).filter(|w| w[0] == "--lib").map(|w| &w[1]) {
+        resolver = resolver.with_path(dir);
+    }
+    resolver
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    if let [command, args @ ..] = args {
+        if command == "fmt" {
+            return fmt(args);
+        }
+    }
+    let (command, path) = match args {
+        [command, path, ..] => (command.as_str(), path),
+        _ => return Err(USAGE.to_string())
+    };
+    let option = |name: &str| match args.iter().position(|a| a == name) {
+        Some(i) => args.get(i + 1).map(Some).ok_or(USAGE),
+        None => Ok(None)
+    };
+    if command == "library" {
+        let artifact = resolver(path, args).compile(Path::new(path)).map_err(|e| Diagnostic::from(e).to_string())?;
+        let output = option("-o")?.cloned().unwrap_or_else(|| Path::new(path).with_extension(LIBRARY_EXTENSION).display().to_string());
+        return fs::write(&output, artifact).map_err(|e| format!("{}: {}", output, e));
+    }
+
+    let src = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    /* Only runs need the JIT, the other commands read what any backend compiles */
+    let backend = if args.iter().any(|a| a == "--interpret") { Backend::Interpreter } else { Backend::default() };
+    let compile = |backend: Backend| Compiler::new().with_backend(backend).with_resolver(resolver(path, args)).compile(&src);
+    let script = compile(if command == "run" { backend } else { Backend::Interpreter }).map_err(|e| e.to_string())?;
+    eprint!("{}", script.warnings());
+    let mut params = script.params();
+    if let Some(file) = option("--params")? {
+        let json = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+        params.set_json(&json).map_err(|e| format!("{}: {}", file, e))?;
+    }
+
+    match command {
+        "inputs" => {
+            println!("{}", serde_json::to_string_pretty(script.inputs()).unwrap());
+        },
+        "run" => {
+            let bars = read_bars(args.get(2).ok_or(USAGE)?)?;
+            let format: fn(&RunResult, &[Bar]) -> String = match option("--format")?.map(String::as_str) {
+                None | Some("csv") => RunResult::to_csv,
+                Some("json") => RunResult::to_json,
+                Some(_) => return Err(USAGE.to_string())
+            };
+            let symbol = option("--symbol")?.map_or("", String::as_str);
+            let timeframe = option("--timeframe")?.map_or("", String::as_str);
+            let mut feeds = Feeds::new(symbol, timeframe);
+            if let Some(timezone) = option("--timezone")? {
+                feeds.timezone = Timezone::parse(timezone).map_err(|e| e.to_string())?;
+            }
+            if let Some(session) = option("--session")? {
+                feeds.session = Session::parse(session).map_err(|e| e.to_string())?;
+            }
+            for feed in args.windows(2).filter(|w| w[0] == "--feed").map(|w| &w[1]) {
+                let (name, file) = feed.split_once('=').ok_or(USAGE)?;
+                let (symbol, timeframe) = name.rsplit_once(':').ok_or(USAGE)?;
+                feeds.insert(symbol, timeframe, read_bars(file)?);
+            }
+            let (bars, result) = match option("--ticks")? {
+                Some(file) => {
+                    let mut stream = script.stream(&bars, &params, &feeds).map_err(|e| e.to_string())?;
+                    for tick in read_ticks(file)? {
+                        stream.on_tick(tick).map_err(|e| e.to_string())?;
+                    }
+                    (stream.bars().to_vec(), stream.result())
+                },
+                None => {
+                    let result = script.run_with(&bars, &params, &feeds).map_err(|e| e.to_string())?;
+                    (bars, result)
+                }
+            };
+            print!("{}", format(&result, &bars));
+            let alerts = result.alerts.iter().map(|a| serde_json::to_string(a).unwrap() + "\n").collect::<String>();
+            match option("--alerts")? {
+                Some(file) => fs::write(file, alerts).map_err(|e| format!("{}: {}", file, e))?,
+                None => eprint!("{}", alerts)
+            }
+            if let Some(report) = result.strategy {
+                eprintln!("{} trades, net profit {}", report.trades.len(), report.metrics.net_profit);
+            }
+        },
+        "optimize" => {
+            let bars = read_bars(args.get(2).ok_or(USAGE)?)?;
+            if !script.metadata().is_strategy() {
+                return Err(format!("{}: only strategies can be optimized", path));
+            }
+            let schema = script.inputs();
+            let space = match option("--space")? {
+                Some(file) => {
+                    let json = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+                    space_from_json(schema, &json).map_err(|e| format!("{}: {}", file, e))?
+                },
+                None => default_space(schema).map_err(|e| format!("{}: {}", path, e))?
+            };
+            let metric = match option("--metric")? {
+                Some(name) => Metric::from_name(name).ok_or(USAGE)?,
+                None => Metric::NetProfit
+            };
+            let number = |name: &str| option(name)?.map(|n| n.parse::<u64>().map_err(|_| USAGE.to_string())).transpose();
+            let mut optimizer = Optimizer::new(schema, space, metric);
+            if let Some(samples) = number("--samples")? {
+                optimizer = optimizer.search(Search::Random { samples: samples as usize, seed: number("--seed")?.unwrap_or(1) });
+            }
+            if let Some(threads) = number("--threads")? {
+                optimizer = optimizer.threads(threads as usize);
+            }
+
+            let metrics = |result: RunResult| result.strategy.map_or_else(|| Metrics::from_equity(&[]), |report| report.metrics);
+            let trials = optimizer.run(|chunk| -> Result<Vec<Metrics>, Diagnostics> {
+                /* Scripts compiled by the JIT stay on the thread which compiled them */
+                let script = compile(backend)?;
+                chunk.iter().map(|values| {
+                    let mut params = script.params();
+                    for (input, value) in script.inputs().iter().zip(values) {
+                        params.set(&input.name, value.clone())?;
+                    }
+                    Ok(metrics(script.run(&bars, &params)?))
+                }).collect()
+            }).map_err(|e| e.to_string())?;
+            print!("{}", optimizer.table(&trials));
+        },
+        #[cfg(feature = "llvm")]
+        "ir" => {
+            print!("{}", script.ir(&params).map_err(|e| e.to_string())?);
+        },
+        #[cfg(feature = "llvm")]
+        "wasm" => {
+            let output = option("-o")?.cloned().unwrap_or_else(|| Path::new(path).with_extension("wasm").display().to_string());
+            fs::write(&output, script.wasm(&params).map_err(|e| e.to_string())?).map_err(|e| format!("{}: {}", output, e))?;
+            for (extension, bindings) in [("d.ts", script.typescript()), ("js", script.javascript())] {
+                let path = Path::new(&output).with_extension(extension);
+                fs::write(&path, bindings).map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+        },
+        #[cfg(feature = "llvm")]
+        "native" => {
+            let (artifact, extension) = match option("--artifact")?.map(String::as_str) {
+                None | Some("object") => (Artifact::Object, "o"),
+                Some("shared") => (Artifact::SharedLibrary, "so"),
+                Some("static") => (Artifact::StaticLibrary, "a"),
+                Some(_) => return Err(USAGE.to_string())
+            };
+            let prefix = option("--prefix")?.map(String::as_str);
+            let output = option("-o")?.cloned().unwrap_or_else(|| Path::new(path).with_extension(extension).display().to_string());
+            let binary = script.native(&params, artifact, prefix).map_err(|e| e.to_string())?;
+            fs::write(&output, binary).map_err(|e| format!("{}: {}", output, e))?;
+            let header = Path::new(&output).with_extension("h");
+            fs::write(&header, script.header(&params, prefix).map_err(|e| e.to_string())?)
+                .map_err(|e| format!("{}: {}", header.display(), e))?;
+        },
+        _ => return Err(USAGE.to_string())
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+    slice,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ast::{Expr, Name, Node, NodeMut, Statement, VarType},
+    checker::TypeChecker,
+    declaration::{self, ScriptKind, ScriptMetadata},
+    error::ModuleError,
+};
+
+/// Extension of library sources.
+pub const EXTENSION: &str = "ns";
+
+/// Extension of compiled libraries, see `Module::artifact`.
+pub const ARTIFACT_EXTENSION: &str = "nslib";
+
+/// A library loaded by `Resolver`, parsed and type checked once.
+#[derive(Debug)]
+pub struct Module {
+    /// The import path, `publisher/library/version`.
+    pub path: String,
+    /// Its `library()` call.
+    declaration: Statement,
+    /// Functions, types, enums and constants scripts importing the library can use.
+    exports: HashSet<String>,
+    /// Libraries it imports, in order.
+    imports: Vec<Rc<Module>>,
+    /// Its functions, types, enums and constants, named by `qualified`.
+    definitions: Vec<Statement>,
+}
+
+/// A compiled library, its definitions checked and qualified.
+#[derive(Deserialize, Serialize)]
+struct Artifact {
+    /// Version of the compiler which wrote it, others do not read it.
+    compiler: String,
+    path: String,
+    declaration: Statement,
+    exports: Vec<String>,
+    imports: Vec<String>,
+    definitions: Vec<Statement>,
+}
+
+impl Module {
+    /// Name of a definition of the library once linked into a script, `publisher/library/version.name`.
+    pub fn qualified(&self, name: &str) -> String {
+        format!("{}.{}", self.path, name)
+    }
+
+    #[cfg(test)]
+    pub fn exports(&self) -> impl Iterator<Item = &str> {
+        self.exports.iter().map(String::as_str)
+    }
+
+    /// The definition of an exported name.
+    fn export(&self, name: &str) -> Option<&Statement> {
+        let qualified = self.qualified(name);
+        self.definitions.iter().filter_map(definition)
+            .find(|d| self.exports.contains(name) && defined_name(d) == Some(qualified.as_str()))
+    }
+
+    /// The library compiled to JSON, which `Resolver` reads from
+    /// `library.nslib` in place of a missing `library.ns`. Its imports are
+    /// resolved again when it is loaded.
+    pub fn artifact(&self) -> String {
+        let mut exports = self.exports.iter().cloned().collect::<Vec<_>>();
+        exports.sort();
+        serde_json::to_string(&Artifact {
+            compiler: env!("CARGO_PKG_VERSION").to_string(),
+            path: self.path.clone(),
+            declaration: self.declaration.clone(),
+            exports,
+            imports: self.imports.iter().map(|m| m.path.clone()).collect(),
+            definitions: self.definitions.clone(),
+        }).unwrap()
+    }
+
+    /// Adds the definitions of the library after those of its imports, every library once.
+    fn collect(&self, definitions: &mut Vec<Statement>, linked: &mut HashSet<String>) {
+        if !linked.insert(self.path.clone()) {
+            return;
+        }
+        for module in &self.imports {
+            module.collect(definitions, linked);
+        }
+        definitions.extend(self.definitions.iter().cloned());
+    }
+}
+
+/// Finds the libraries scripts import and links them into the scripts.
+///
+/// `import publisher/library/version` loads `publisher/library/version/library.ns`,
+/// or the compiled `library.nslib`, from the first search path which has
+/// either. Every library is loaded once and may import other libraries, but
+/// not itself through a cycle. A library is known by its alias in the script
+/// importing it, its name unless it is imported `as` another one, and
+/// `alias.function()` calls one of its exported functions. Its exported
+/// types and enums are `alias.Type` and its constants `alias.CONSTANT`.
+#[derive(Clone, Debug, Default)]
+pub struct Resolver {
+    paths: Vec<PathBuf>,
+    modules: HashMap<String, Rc<Module>>,
+    /// Libraries being loaded, innermost last.
+    loading: Vec<String>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Searches libraries in `path` after the paths added before.
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Links the libraries a script imports into it, after checking its own
+    /// exports. Their definitions come first and calls through their aliases
+    /// become calls of the definitions.
+    pub(crate) fn link(&mut self, mut statements: Vec<Statement>) -> Result<Vec<Statement>, ModuleError> {
+        check_exports(&statements)?;
+        let imports = self.imports(&statements)?;
+        if imports.is_empty() {
+            return Ok(statements);
+        }
+        let names = Names { module: None, functions: HashSet::new(), types: HashSet::new(), constants: HashSet::new(), imports: &imports };
+        names.qualify(&mut statements)?;
+
+        let (mut linked, mut seen) = (vec![], HashSet::new());
+        for (_, module) in &imports {
+            module.collect(&mut linked, &mut seen);
+        }
+        linked.append(&mut statements);
+        Ok(linked)
+    }
+
+    /// Compiles the library source `file` to the artifact imports read without
+    /// its source, searching its imports next to it after the other paths.
+    pub fn compile(mut self, file: &Path) -> Result<String, ModuleError> {
+        let (root, path) = locate(file).ok_or_else(|| ModuleError::Misplaced(file.display().to_string()))?;
+        self.paths.push(root);
+        Ok(self.load(&path)?.artifact())
+    }
+
+    /// Loads the library imported as `path`, unless it already was.
+    pub(crate) fn load(&mut self, path: &str) -> Result<Rc<Module>, ModuleError> {
+        if let Some(module) = self.modules.get(path) {
+            return Ok(module.clone());
+        }
+        let name = library_name(path).ok_or_else(|| ModuleError::InvalidPath(path.to_string()))?;
+        if let Some(i) = self.loading.iter().position(|p| p == path) {
+            let mut chain = self.loading[i..].to_vec();
+            chain.push(path.to_string());
+            return Err(ModuleError::Cycle(chain));
+        }
+
+        let file = self.paths.iter()
+            .flat_map(|p| [EXTENSION, ARTIFACT_EXTENSION].map(|e| p.join(path).join(name).with_extension(e)))
+            .find(|f| f.is_file())
+            .ok_or_else(|| ModuleError::NotFound(path.to_string()))?;
+        let src = fs::read_to_string(&file).map_err(|e| ModuleError::Io { module: path.to_string(), message: e.to_string() })?;
+        self.loading.push(path.to_string());
+        let module = if file.extension().is_some_and(|e| e == ARTIFACT_EXTENSION) {
+            self.artifact(path, &src)
+        } else {
+            self.source(path, &src)
+        };
+        self.loading.pop();
+
+        let module = Rc::new(module?);
+        self.modules.insert(path.to_string(), module.clone());
+        Ok(module)
+    }
+
+    fn source(&mut self, path: &str, src: &str) -> Result<Module, ModuleError> {
+        let statements = crate::parse(src).map_err(|e| ModuleError::Parse { module: path.to_string(), diagnostic: e.into() })?;
+        if !ScriptMetadata::from_statements(&statements).is_ok_and(|m| m.kind == ScriptKind::Library) {
+            return Err(ModuleError::NotALibrary(path.to_string()));
+        }
+        check_exports(&statements)?;
+        let imports = self.imports(&statements)?;
+        library(path, statements, &imports)
+    }
+
+    fn artifact(&mut self, path: &str, src: &str) -> Result<Module, ModuleError> {
+        let invalid = |message: String| ModuleError::Artifact { module: path.to_string(), message };
+        let artifact = serde_json::from_str::<Artifact>(src).map_err(|e| invalid(e.to_string()))?;
+        if artifact.compiler != env!("CARGO_PKG_VERSION") {
+            return Err(invalid(format!("compiled by version {}", artifact.compiler)));
+        }
+        if artifact.path != path {
+            return Err(invalid(format!("compiled as {}", artifact.path)));
+        }
+        ScriptMetadata::from_statements(slice::from_ref(&artifact.declaration))
+            .map_err(|_| ModuleError::NotALibrary(path.to_string()))?;
+        Ok(Module {
+            path: artifact.path,
+            declaration: artifact.declaration,
+            exports: artifact.exports.into_iter().collect(),
+            imports: artifact.imports.iter().map(|p| self.load(p)).collect::<Result<_, _>>()?,
+            definitions: artifact.definitions,
+        })
+    }
+
+    /// Loads the top level imports of a script or library, with their aliases.
+    fn imports(&mut self, statements: &[Statement]) -> Result<Vec<(String, Rc<Module>)>, ModuleError> {
+        let mut imports: Vec<(String, Rc<Module>)> = vec![];
+        let mut declared = None;
+        for statement in statements {
+            if let Statement::Import(path, alias) = statement {
+                let module = self.load(path)?;
+                let alias = alias.clone().unwrap_or_else(|| library_name(path).unwrap_or_default().to_string());
+                if imports.iter().any(|(a, _)| *a == alias) {
+                    return Err(ModuleError::DuplicateAlias(alias));
+                }
+                if declared.get_or_insert_with(|| declarations(statements)).contains(alias.as_str()) {
+                    return Err(ModuleError::AliasCollision(alias));
+                }
+                imports.push((alias, module));
+            }
+        }
+        Ok(imports)
+    }
+}
+
+/// The import path of the library source `file`, laid out like `Resolver`
+/// expects it, with the directory to search it in.
+pub fn locate(file: &Path) -> Option<(PathBuf, String)> {
+    let mut dirs = file.ancestors().skip(1);
+    let (version, library, publisher) = (dirs.next()?, dirs.next()?, dirs.next()?);
+    let name = |dir: &Path| dir.file_name()?.to_str().map(str::to_string);
+    let path = format!("{}/{}/{}", name(publisher)?, name(library)?, name(version)?);
+    let matches = library_name(&path) == file.file_stem()?.to_str() && file.extension()? == EXTENSION;
+    matches.then(|| (dirs.next().unwrap_or(Path::new("")).to_path_buf(), path))
+}
+
+/// Checks that only libraries export and that their exported functions have
+/// a type written for every parameter.
+pub fn check_exports(statements: &[Statement]) -> Result<(), ModuleError> {
+    let library = ScriptMetadata::from_statements(statements).is_ok_and(|m| m.kind == ScriptKind::Library);
+    for statement in statements {
+        let Statement::Export(definition) = statement else { continue };
+        match definition.as_ref() {
+            d if !library => return Err(ModuleError::ExportOutsideLibrary(defined_name(d).unwrap_or_default().to_string())),
+            Statement::FnDef(name, params, _) => {
+                if let Some(param) = params.iter().find(|p| (p.0).0.0.is_none()) {
+                    return Err(ModuleError::UntypedExport { function: name.clone(), param: (param.0).1.to_string() });
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` is that of a definition of a library linked into a script, see `Module::qualified`.
+pub fn is_linked(name: &str) -> bool {
+    name.contains('/')
+}
+
+/// The library of a `publisher/library/version` import path.
+fn library_name(path: &str) -> Option<&str> {
+    match path.split('/').collect::<Vec<_>>()[..] {
+        [_, name, version] if version.parse::<u32>().is_ok() => Some(name),
+        _ => None
+    }
+}
+
+/// Names a script or library declares, in any scope.
+fn declarations(statements: &[Statement]) -> HashSet<&str> {
+    let mut names = HashSet::new();
+    for statement in statements {
+        statement.walk(&mut |node| match node {
+            Node::Statement(Statement::UnpackTuple(items, _)) => names.extend(items.iter().map(|n| n.as_str())),
+            Node::Statement(
+                Statement::ConstDef(var, _)
+                | Statement::SeriesDef(var, _)
+                | Statement::VarIpDef(var, _)
+                | Statement::VarDef(var, _)
+                | Statement::VarLet(var, _)
+                | Statement::ForTo(var, ..)
+                | Statement::ForIn(var, ..)
+            ) => {
+                names.insert(var.1.as_str());
+            },
+            Node::Statement(Statement::FnDef(name, params, _)) => {
+                names.insert(name.as_str());
+                names.extend(params.iter().map(|p| (p.0).1.as_str()));
+            },
+            Node::Statement(Statement::TypeDef(name, _) | Statement::EnumDef(name, _)) => {
+                names.insert(name.as_str());
+            },
+            _ => {}
+        });
+    }
+    names
+}
+
+/// The function, type, enum or constant a statement defines, exported or not.
+fn definition(statement: &Statement) -> Option<&Statement> {
+    match statement {
+        Statement::Export(definition) => Some(definition),
+        Statement::FnDef(..) | Statement::TypeDef(..) | Statement::EnumDef(..) | Statement::ConstDef(..) => Some(statement),
+        _ => None
+    }
+}
+
+fn defined_name(definition: &Statement) -> Option<&str> {
+    match definition {
+        Statement::FnDef(name, ..) | Statement::TypeDef(name, _) | Statement::EnumDef(name, _) => Some(name),
+        Statement::ConstDef(var, _) => Some(&var.1),
+        _ => None
+    }
+}
+
+/// Qualifies the definitions of a library and type checks it with those of its imports.
+fn library(path: &str, mut statements: Vec<Statement>, imports: &[(String, Rc<Module>)]) -> Result<Module, ModuleError> {
+    let mut names = Names { module: Some(path), functions: HashSet::new(), types: HashSet::new(), constants: HashSet::new(), imports };
+    let mut exports = HashSet::new();
+    for statement in &statements {
+        let Some(definition) = definition(statement) else { continue };
+        let name = defined_name(definition).unwrap_or_default().to_string();
+        if matches!(statement, Statement::Export(_)) {
+            exports.insert(name.clone());
+        }
+        match definition {
+            Statement::FnDef(..) => names.functions.insert(name),
+            Statement::ConstDef(..) => names.constants.insert(name),
+            _ => names.types.insert(name)
+        };
+    }
+    names.qualify(&mut statements)?;
+
+    let declaration = statements.iter().find(|s| declaration::is_declaration(s)).cloned()
+        .ok_or_else(|| ModuleError::NotALibrary(path.to_string()))?;
+    let mut module = Module {
+        path: path.to_string(),
+        declaration,
+        exports,
+        imports: imports.iter().map(|(_, m)| m.clone()).collect(),
+        definitions: vec![],
+    };
+    let (mut checked, mut seen) = (vec![], HashSet::new());
+    for import in &module.imports {
+        import.collect(&mut checked, &mut seen);
+    }
+    let start = checked.len();
+    checked.append(&mut statements);
+    TypeChecker::new().check(&checked).map_err(|error| ModuleError::Type { module: path.to_string(), error })?;
+    module.definitions = checked.split_off(start).into_iter().filter(|s| definition(s).is_some()).collect();
+    Ok(module)
+}
+
+/// Renames what a script or library refers to once libraries are linked.
+struct Names<'a> {
+    /// Path of the library whose own definitions are renamed.
+    module: Option<&'a str>,
+    functions: HashSet<String>,
+    /// Types and enums.
+    types: HashSet<String>,
+    constants: HashSet<String>,
+    imports: &'a [(String, Rc<Module>)],
+}
+
+impl Names<'_> {
+    fn qualify(&self, statements: &mut [Statement]) -> Result<(), ModuleError> {
+        let mut error = None;
+        for statement in statements.iter_mut() {
+            /* The names a function declares hide the constants of its library */
+            let shadowed = match definition(statement) {
+                Some(function @ Statement::FnDef(..)) => declarations(slice::from_ref(function)).into_iter().map(str::to_string).collect(),
+                _ => HashSet::new()
+            };
+            statement.walk_mut(&mut |node| {
+                let result = match node {
+                    NodeMut::Statement(statement) => self.statement(statement, &shadowed),
+                    NodeMut::Expr(expr) => self.expr(expr, &shadowed),
+                };
+                if let Err(e) = result {
+                    error.get_or_insert(e);
+                }
+            });
+        }
+        error.map_or(Ok(()), Err)
+    }
+
+    /// The definition `alias.name` refers to, if `alias` is that of an import.
+    fn import(&self, alias: &str, name: &str) -> Result<Option<(&Module, &Statement)>, ModuleError> {
+        let Some((_, module)) = self.imports.iter().find(|(a, _)| a == alias) else {
+            return Ok(None);
+        };
+        match module.export(name) {
+            Some(definition) => Ok(Some((module, definition))),
+            None => Err(ModuleError::NotExported { module: module.path.clone(), name: name.to_string() })
+        }
+    }
+
+    /// Renames `alias.Type` to the type or enum of the library imported as `alias`.
+    fn imported_type(&self, name: &mut String) -> Result<(), ModuleError> {
+        let Some((alias, t)) = name.split_once('.') else { return Ok(()) };
+        match self.import(alias, t)? {
+            Some((module, Statement::TypeDef(..) | Statement::EnumDef(..))) => *name = module.qualified(t),
+            Some((module, _)) => return Err(ModuleError::NotExported { module: module.path.clone(), name: t.to_string() }),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn rename(&self, name: &mut String, own: &HashSet<String>) {
+        if let Some(path) = self.module.filter(|_| own.contains(name)) {
+            *name = format!("{}.{}", path, name);
+        }
+    }
+
+    fn type_name(&self, name: &mut String) -> Result<(), ModuleError> {
+        self.rename(name, &self.types);
+        self.imported_type(name)
+    }
+
+    fn var_type(&self, t: &mut VarType) -> Result<(), ModuleError> {
+        [&mut t.0, &mut t.1].into_iter().flatten().try_for_each(|name| self.type_name(name))
+    }
+
+    fn statement(&self, statement: &mut Statement, shadowed: &HashSet<String>) -> Result<(), ModuleError> {
+        match statement {
+            Statement::FnDef(name, params, _) => {
+                self.rename(name, &self.functions);
+                params.iter_mut().try_for_each(|p| self.var_type(&mut (p.0).0))?;
+            },
+            Statement::TypeDef(name, fields) => {
+                self.rename(name, &self.types);
+                fields.iter_mut().try_for_each(|(t, _)| self.type_name(t))?;
+            },
+            Statement::EnumDef(name, _) => self.rename(name, &self.types),
+            Statement::ConstDef(var, _) => {
+                if !shadowed.contains(var.1.as_str()) {
+                    self.rename(&mut var.1, &self.constants);
+                }
+                self.var_type(&mut var.0)?;
+            },
+            Statement::SeriesDef(var, _)
+            | Statement::VarIpDef(var, _)
+            | Statement::VarDef(var, _)
+            | Statement::VarLet(var, _)
+            | Statement::ForTo(var, ..)
+            | Statement::ForIn(var, ..) => self.var_type(&mut var.0)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn expr(&self, expr: &mut Expr, shadowed: &HashSet<String>) -> Result<(), ModuleError> {
+        match expr {
+            Expr::Identifier(name) if !shadowed.contains(name.as_str()) => self.rename(name, &self.constants),
+            Expr::FnCall(name, ..) => self.rename(name, &self.functions),
+            Expr::PropertyAccess(object, property) => match self.import(object, property)? {
+                Some((module, Statement::ConstDef(..))) => *expr = Expr::Identifier(Name::new(module.qualified(property), object.location())),
+                Some((module, _)) => return Err(ModuleError::NotExported { module: module.path.clone(), name: property.clone() }),
+                None => self.type_name(object)?
+            },
+            Expr::MethodCall(object, name, _, args) => match self.import(object, name)? {
+                Some((module, Statement::FnDef(..))) => *expr = Expr::FnCall(module.qualified(name).into(), None, mem::take(args)),
+                Some((module, _)) => return Err(ModuleError::NotExported { module: module.path.clone(), name: name.clone() }),
+                None => self.type_name(object)?
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+use std::{collections::{HashMap, HashSet}, fmt::Write, thread};
+
+use serde::Serialize;
+
+use crate::{error::InputError, inputs::{self, Input, InputKind}, types::Constant};
+
+/// Performance figures of a single strategy run.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Metrics {
+    pub net_profit: f64,
+    /// Mean over standard deviation of per bar returns, not annualized.
+    pub sharpe: f64,
+    /// Largest peak to trough fall of equity, as a positive amount.
+    pub max_drawdown: f64,
+}
+
+impl Metrics {
+    pub fn from_equity(equity: &[f64]) -> Self {
+        let net_profit = match (equity.first(), equity.last()) {
+            (Some(first), Some(last)) => last - first,
+            _ => 0.0
+        };
+
+        let mut peak = f64::MIN;
+        let mut max_drawdown: f64 = 0.0;
+        for e in equity {
+            peak = peak.max(*e);
+            max_drawdown = max_drawdown.max(peak - e);
+        }
+
+        let returns = equity.windows(2)
+            .filter(|w| w[0] != 0.0)
+            .map(|w| w[1] / w[0] - 1.0)
+            .collect::<Vec<_>>();
+        let n = returns.len() as f64;
+        let mean = returns.iter().sum::<f64>() / n;
+        let std = (returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n).sqrt();
+        let sharpe = if returns.is_empty() || std == 0.0 { 0.0 } else { mean / std };
+
+        Self { net_profit, sharpe, max_drawdown }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    NetProfit,
+    Sharpe,
+    MaxDrawdown,
+}
+
+impl Metric {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "net_profit" => Metric::NetProfit,
+            "sharpe" => Metric::Sharpe,
+            "max_drawdown" => Metric::MaxDrawdown,
+            _ => return None
+        })
+    }
+
+    /// Score where higher is better.
+    fn score(&self, metrics: &Metrics) -> f64 {
+        match self {
+            Metric::NetProfit => metrics.net_profit,
+            Metric::Sharpe => metrics.sharpe,
+            Metric::MaxDrawdown => -metrics.max_drawdown,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Search {
+    /// Every combination of the values of the space.
+    Grid,
+    /// A reproducible random sample of combinations.
+    Random { samples: usize, seed: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trial {
+    pub values: Vec<Constant>,
+    pub metrics: Metrics,
+}
+
+/// Values tried for each input, inputs missing from the space keep their default.
+pub type Space = HashMap<String, Vec<Constant>>;
+
+/// The most runs an optimization makes, and values a range of an input has.
+pub const MAX_COMBINATIONS: usize = 1_000_000;
+
+/// Values an input can take on its own: options, booleans or a bounded int range.
+pub fn default_range(input: &Input) -> Result<Option<Vec<Constant>>, InputError> {
+    if !input.options.is_empty() {
+        return Ok(Some(input.options.clone()));
+    }
+    match (&input.kind, input.min, input.max) {
+        (InputKind::Bool, ..) => Ok(Some(vec![Constant::Bool(false), Constant::Bool(true)])),
+        (InputKind::Int, Some(min), Some(max)) => {
+            let step = input.step.map_or(1.0, |s| s.round()).max(1.0);
+            let values = range(&input.name, min.round(), max.round(), step)?;
+            Ok(Some(values.map(|v| Constant::Int(v as i64)).collect()))
+        },
+        _ => Ok(None)
+    }
+}
+
+/// `start` and every `step` up to `end`, or an error if it ends before it starts or has too many values.
+fn range(input: &str, start: f64, end: f64, step: f64) -> Result<impl Iterator<Item = f64>, InputError> {
+    if end < start {
+        return Err(InputError::EmptyRange { input: input.to_string(), start, end });
+    }
+    let count = ((end - start) / step + 1e-9).floor() + 1.0;
+    if count > MAX_COMBINATIONS as f64 {
+        return Err(InputError::TooManyCombinations(count as usize));
+    }
+    Ok((0..count as usize).map(move |i| start + step * i as f64))
+}
+
+/// Space over every input with a natural range, used when none is given.
+pub fn default_space(schema: &[Input]) -> Result<Space, InputError> {
+    let mut space = Space::new();
+    for input in schema {
+        if let Some(values) = default_range(input)? {
+            space.insert(input.name.clone(), values);
+        }
+    }
+    Ok(space)
+}
+
+/// Reads a space from JSON: either a list of values or `{"start", "end", "step"}` per input.
+pub fn space_from_json(schema: &[Input], json: &str) -> Result<Space, InputError> {
+    let object: HashMap<String, serde_json::Value> = serde_json::from_str(json).map_err(|e| InputError::Json(e.to_string()))?;
+    let mut space = Space::new();
+    for (name, value) in object {
+        let input = schema.iter().find(|i| i.name == name).ok_or_else(|| InputError::UnknownInput(name.clone()))?;
+        let values = match &value {
+            serde_json::Value::Array(items) => items.iter().map(|v| input.value_from_json(v)).collect::<Result<Vec<_>, _>>()?,
+            serde_json::Value::Object(range) => {
+                let bound = |key: &str| range.get(key).and_then(|v| v.as_f64())
+                    .ok_or_else(|| InputError::InvalidValue { input: name.clone(), value: value.to_string() });
+                let (start, end) = (bound("start")?, bound("end")?);
+                let step = range.get("step").and_then(|v| v.as_f64()).unwrap_or(1.0);
+                if step <= 0.0 {
+                    return Err(InputError::InvalidValue { input: name.clone(), value: value.to_string() });
+                }
+                self::range(&name, start, end, step)?.map(|v| {
+                    let v = if input.kind == InputKind::Int { Constant::Int(v.round() as i64) } else { Constant::Float(v) };
+                    input.validate(v)
+                }).collect::<Result<Vec<_>, _>>()?
+            },
+            _ => vec![input.value_from_json(&value)?]
+        };
+        space.insert(name, values);
+    }
+    Ok(space)
+}
+
+/// A small xorshift generator, sampling has to be reproducible across runs.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// Runs a script over combinations of input values and ranks the results.
+///
+/// Every worker thread sets up a module of its own once, as scripts compiled
+/// by the JIT stay on their thread, and reuses it for its runs with other
+/// input values.
+pub struct Optimizer<'a> {
+    schema: &'a [Input],
+    space: Space,
+    metric: Metric,
+    search: Search,
+    threads: usize,
+}
+
+impl<'a> Optimizer<'a> {
+    pub fn new(schema: &'a [Input], space: Space, metric: Metric) -> Self {
+        Self {
+            schema,
+            space,
+            metric,
+            search: Search::Grid,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    pub fn search(mut self, search: Search) -> Self {
+        self.search = search;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Input values of every run, in schema order.
+    pub fn combinations(&self) -> Result<Vec<Vec<Constant>>, InputError> {
+        let defaults = inputs::resolve(self.schema, &HashMap::new())?;
+        let axes = self.schema.iter().enumerate()
+            .filter_map(|(i, input)| self.space.get(&input.name).map(|values| (i, values)))
+            .collect::<Vec<_>>();
+        if let Some(unknown) = self.space.keys().find(|k| !self.schema.iter().any(|i| i.name == **k)) {
+            return Err(InputError::UnknownInput(unknown.clone()));
+        }
+
+        let total = axes.iter().fold(1usize, |total, a| total.saturating_mul(a.1.len()));
+        let runs = match self.search {
+            Search::Grid => total,
+            Search::Random { samples, .. } => samples.min(total),
+        };
+        if runs > MAX_COMBINATIONS {
+            return Err(InputError::TooManyCombinations(runs));
+        }
+        let combination = |mut n: usize| {
+            let mut values = defaults.clone();
+            for (i, axis) in &axes {
+                values[*i] = axis[n % axis.len()].clone();
+                n /= axis.len();
+            }
+            values
+        };
+
+        Ok(match self.search {
+            Search::Grid => (0..total).map(combination).collect(),
+            Search::Random { samples, .. } if samples >= total => (0..total).map(combination).collect(),
+            Search::Random { samples, seed } => {
+                let mut rng = Rng(seed.max(1));
+                let mut picked = HashSet::new();
+                while picked.len() < samples {
+                    picked.insert(rng.below(total));
+                }
+                let mut picked = picked.into_iter().collect::<Vec<_>>();
+                picked.sort();
+                picked.into_iter().map(combination).collect()
+            }
+        })
+    }
+
+    /// Runs every combination across the worker threads, best results first.
+    ///
+    /// `worker` is called on every thread with its share of the combinations
+    /// and returns their metrics in the same order.
+    pub fn run<E, F>(&self, worker: F) -> Result<Vec<Trial>, E>
+    where
+        E: Send + From<InputError>,
+        F: Fn(&[Vec<Constant>]) -> Result<Vec<Metrics>, E> + Sync,
+    {
+        let combinations = self.combinations()?;
+        let chunk = combinations.len().div_ceil(self.threads).max(1);
+
+        let results = thread::scope(|scope| {
+            let workers = combinations.chunks(chunk)
+                .map(|chunk| scope.spawn(|| worker(chunk).map(|metrics| {
+                    chunk.iter().zip(metrics).map(|(values, metrics)| Trial { values: values.clone(), metrics }).collect::<Vec<_>>()
+                })))
+                .collect::<Vec<_>>();
+            workers.into_iter().map(|w| w.join().unwrap()).collect::<Result<Vec<_>, E>>()
+        })?;
+
+        let mut trials = results.into_iter().flatten().collect::<Vec<_>>();
+        trials.sort_by(|a, b| self.metric.score(&b.metrics).total_cmp(&self.metric.score(&a.metrics)));
+        Ok(trials)
+    }
+
+    /// Formats ranked trials as a plain text table.
+    pub fn table(&self, trials: &[Trial]) -> String {
+        let mut headers = vec!["rank".to_string()];
+        headers.extend(self.schema.iter().filter(|i| self.space.contains_key(&i.name)).map(|i| i.name.clone()));
+        headers.extend(["net_profit", "sharpe", "max_drawdown"].map(String::from));
+
+        let rows = trials.iter().enumerate().map(|(rank, trial)| {
+            let mut row = vec![(rank + 1).to_string()];
+            for (input, value) in self.schema.iter().zip(&trial.values) {
+                if self.space.contains_key(&input.name) {
+                    row.push(serde_json::to_string(value).unwrap());
+                }
+            }
+            row.push(format!("{:.2}", trial.metrics.net_profit));
+            row.push(format!("{:.4}", trial.metrics.sharpe));
+            row.push(format!("{:.2}", trial.metrics.max_drawdown));
+            row
+        }).collect::<Vec<_>>();
+
+        let widths = (0..headers.len())
+            .map(|i| rows.iter().map(|r| r[i].len()).chain([headers[i].len()]).max().unwrap())
+            .collect::<Vec<_>>();
+        let mut table = String::new();
+        for row in [headers].iter().chain(&rows) {
+            let cells = row.iter().zip(&widths).map(|(c, w)| format!("{:>w$}", c, w = w)).collect::<Vec<_>>();
+            writeln!(table, "{}", cells.join("  ").trim_end()).unwrap();
+        }
+        table
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ast::{CallArguments, Expr, Node, Statement},
+    builtins,
+    error::{CodegenError, PlotError},
+    types::{Constant, Type},
+};
+#[cfg(feature = "llvm")]
+use crate::types::Signature;
+
+/// Builtin that compiled plot calls call with the index of the plot in place of
+/// a call site, the value drawn (`NaN` for none) and its color (0 for `na`).
+#[cfg(feature = "llvm")]
+pub const RECORD: &str = "plot.record";
+
+#[cfg(feature = "llvm")]
+pub fn record_signature() -> Signature {
+    Signature {
+        params: vec![("value".to_string(), Type::Float), ("color".to_string(), Type::Color)],
+        required: 2,
+        returns: Type::Void,
+    }
+}
+
+/// Type of the values `plot()` and `hline()` return, which `fill()` takes.
+pub fn handle_type() -> Type {
+    Type::Named("plot".to_string())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlotKind {
+    Plot,
+    PlotShape,
+    PlotChar,
+    Hline,
+    Bgcolor,
+    Barcolor,
+    Fill,
+}
+
+impl PlotKind {
+    pub fn from_function(name: &str) -> Option<Self> {
+        Some(match name {
+            "plot" => PlotKind::Plot,
+            "plotshape" => PlotKind::PlotShape,
+            "plotchar" => PlotKind::PlotChar,
+            "hline" => PlotKind::Hline,
+            "bgcolor" => PlotKind::Bgcolor,
+            "barcolor" => PlotKind::Barcolor,
+            "fill" => PlotKind::Fill,
+            _ => return None
+        })
+    }
+
+    pub fn function(&self) -> &'static str {
+        match self {
+            PlotKind::Plot => "plot",
+            PlotKind::PlotShape => "plotshape",
+            PlotKind::PlotChar => "plotchar",
+            PlotKind::Hline => "hline",
+            PlotKind::Bgcolor => "bgcolor",
+            PlotKind::Barcolor => "barcolor",
+            PlotKind::Fill => "fill",
+        }
+    }
+
+    /// The parameter holding the value drawn on each bar, `None` for plots which only have a color.
+    pub fn value_param(&self) -> Option<&'static str> {
+        match self {
+            PlotKind::Plot | PlotKind::PlotShape | PlotKind::PlotChar => Some("series"),
+            PlotKind::Hline => Some("price"),
+            PlotKind::Bgcolor | PlotKind::Barcolor | PlotKind::Fill => None,
+        }
+    }
+}
+
+/// A visual output of a script, declared with `plot()`, `plotshape()`, `plotchar()`,
+/// `hline()`, `bgcolor()`, `barcolor()` or `fill()`.
+///
+/// The value and color are evaluated on every bar, other arguments have to be
+/// known at compile time and are kept as style for the frontend.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Plot {
+    pub kind: PlotKind,
+    /// The `title` argument, falling back to the variable the plot is assigned to.
+    pub title: Option<String>,
+    /// Arguments like `linewidth`, `style` or `location`, by parameter name.
+    pub style: BTreeMap<String, Constant>,
+    /// Indices of the plots or hlines a `fill()` is drawn between.
+    pub between: Option<(usize, usize)>,
+}
+
+impl Plot {
+    /// The title, or the function and index of the plot (`plot2`).
+    pub fn name(&self, index: usize) -> String {
+        self.title.clone().unwrap_or_else(|| format!("{}{}", self.kind.function(), index))
+    }
+}
+
+/// The argument passed for `param` of a plotting function.
+pub(crate) fn argument<'a>(kind: PlotKind, args: &'a CallArguments, param: &str) -> Option<&'a Expr> {
+    let params = builtins::function(kind.function()).unwrap().params;
+    args.iter().enumerate().find_map(|(i, (key, value))| {
+        let name = match key {
+            Some(key) => key.as_str(),
+            None => params.get(i)?.0.as_str()
+        };
+        (name == param).then_some(value.as_ref())
+    })
+}
+
+/// Value of a style argument, literals or builtin constants like `shape.triangleup`.
+fn constant(expr: &Expr) -> Option<Constant> {
+    match expr {
+        Expr::PropertyAccess(object, property) => builtins::constant(&format!("{}.{}", object, property)),
+        e => Constant::from_expr(e)
+    }
+}
+
+/// Finds the plotting calls of a script, keyed by the address of the call expression.
+pub(crate) fn collect(statements: &[Statement]) -> Result<Vec<(*const Expr, Plot)>, CodegenError> {
+    let mut names = HashMap::new();
+    let mut calls = vec![];
+    for statement in statements {
+        let location = statement.location();
+        statement.walk(&mut |node| match node {
+            Node::Statement(Statement::VarLet(var, e))
+            | Node::Statement(Statement::VarDef(var, e))
+            | Node::Statement(Statement::VarIpDef(var, e))
+            | Node::Statement(Statement::ConstDef(var, e))
+            | Node::Statement(Statement::SeriesDef(var, e)) => {
+                names.insert(e.as_ref() as *const Expr, var.1.to_string());
+            },
+            Node::Expr(e @ Expr::FnCall(name, _, args)) => {
+                if let Some(kind) = PlotKind::from_function(name) {
+                    calls.push((e, kind, args, e.location().or(location)));
+                }
+            },
+            _ => {}
+        });
+    }
+
+    /* Variables holding plots, for `fill()` */
+    let handles = calls.iter().enumerate()
+        .filter_map(|(i, (e, ..))| Some((names.get(&(*e as *const Expr))?.clone(), i)))
+        .collect::<HashMap<_, _>>();
+
+    let mut plots = vec![];
+    for (e, kind, args, location) in calls {
+        let plot = read(kind, args, &handles).map_err(|error| CodegenError::from(error).at(location))?;
+        plots.push((e as *const Expr, Plot { title: plot.title.or_else(|| names.get(&(e as *const Expr)).cloned()), ..plot }));
+    }
+    Ok(plots)
+}
+
+/// Reads the arguments of a plotting call, `handles` are the plots held by variables.
+fn read(kind: PlotKind, args: &CallArguments, handles: &HashMap<String, usize>) -> Result<Plot, PlotError> {
+    let function = kind.function();
+    let params = builtins::function(function).unwrap().params;
+    let mut plot = Plot { kind, title: None, style: BTreeMap::new(), between: None };
+    let mut between = (None, None);
+    for (i, (key, value)) in args.iter().enumerate() {
+        let param = match key {
+            Some(key) => params.iter().find(|p| p.0 == *key),
+            None => params.get(i)
+        }.ok_or_else(|| PlotError::UnknownArgument { plot: function.to_string(), argument: key.as_ref().map_or_else(|| i.to_string(), |k| k.to_string()) })?;
+        let invalid = || PlotError::InvalidArgument { plot: function.to_string(), argument: param.0.clone() };
+
+        match param.0.as_str() {
+            p if Some(p) == kind.value_param() || p == "color" => {},
+            p @ ("plot1" | "plot2") => {
+                let plot = match value.as_ref() {
+                    Expr::Identifier(name) => handles.get(name.as_str()).copied().ok_or_else(|| PlotError::UnknownPlot(name.to_string()))?,
+                    _ => return Err(invalid())
+                };
+                if p == "plot1" { between.0 = Some(plot) } else { between.1 = Some(plot) }
+            },
+            p => match (p, constant(value).and_then(|c| c.cast(&param.1)).ok_or_else(invalid)?) {
+                ("title", Constant::String(s)) => plot.title = Some(s),
+                (p, c) => { plot.style.insert(p.to_string(), c); }
+            }
+        }
+    }
+    if let (Some(a), Some(b)) = between {
+        plot.between = Some((a, b));
+    }
+    Ok(plot)
+}
+
+/// The plots of a script, in source order.
+#[cfg(feature = "llvm")]
+pub fn extract(statements: &[Statement]) -> Result<Vec<Plot>, CodegenError> {
+    Ok(collect(statements)?.into_iter().map(|p| p.1).collect())
+}
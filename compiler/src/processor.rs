@@ -1,19 +1,1400 @@
-use crate::ast::Statement;
+use std::{collections::HashMap, mem};
+
+use inkwell::{
+    basic_block::BasicBlock,
+    builder::Builder,
+    context::Context,
+    module::Module,
+    types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum, StructType},
+    values::{BasicMetadataValueEnum, BasicValue, BasicValueEnum, FunctionValue, IntValue, PointerValue},
+    AddressSpace, FloatPredicate, IntPredicate,
+};
+
+use crate::{
+    ast::{CallArguments, Expr, Opcode, Statement, Var, VarParam},
+    builtins,
+    calendar,
+    checker::TypeChecker,
+    declaration::{self, ScriptMetadata},
+    drawings::{self, Call, DrawingKind},
+    history,
+    error::{CodegenError, TypeError},
+    inputs::{self, Input, InputKind, SOURCES},
+    modules,
+    plots::{self, Plot, PlotKind},
+    runtime::{self, Output, NA_INT},
+    security::{self, Request},
+    types::{Constant, Type, RGBA},
+};
 
 pub struct Processor {
     source: Vec<Statement>,
-    position: usize
+    inputs: Option<Vec<Constant>>,
 }
 
 impl Processor {
     pub fn new(source: Vec<Statement>) -> Self {
         Self {
             source,
-            position: 0
+            inputs: None,
+        }
+    }
+
+    /// Compiles with the given input values instead of the defaults, see `inputs::resolve`.
+    pub fn with_inputs(mut self, values: Vec<Constant>) -> Self {
+        self.inputs = Some(values);
+        self
+    }
+
+    /// Type checks the script and lowers it into an LLVM module.
+    ///
+    /// The script has to start with exactly one `indicator()`, `strategy()` or
+    /// `library()` declaration, see `ScriptMetadata`.
+    ///
+    /// Variables of a run live in a state block the host allocates, of
+    /// `state_size()` bytes aligned to 8, which every export takes first. Runs
+    /// with their own blocks are independent of each other.
+    ///
+    /// The module exports `on_bar(ptr state, ptr bar)`, which runs the top level code once per bar.
+    /// Builtin functions are declared as external symbols named after their path
+    /// (`ta.sma`), taking a call site id followed by their arguments and, if they
+    /// return a tuple, a pointer to store it at. `input.source()` holds an index
+    /// into `inputs::SOURCES`.
+    /// Plotting calls call `plots::RECORD` with the index of the plot, drawing
+    /// functions call the builtins of `drawings` and `request.security()`
+    /// calls `security::REQUEST` with the index of the request.
+    ///
+    /// `init(ptr state, ptr params)` resets the state to the first bar and reads
+    /// every input from an 8 byte slot of `params` in schema order (see
+    /// `Input::slot`), or takes the values compiled in if it is null.
+    /// `get_outputs(ptr state)` returns the `double` values of `outputs()` as of
+    /// the last bar.
+    ///
+    /// `save(ptr state)` remembers the state between two bars and `restore(ptr state)`
+    /// returns to it, keeping `varip` variables, so a bar can run again on every tick.
+    pub fn compile<'ctx>(&self, context: &'ctx Context) -> Result<Module<'ctx>, CodegenError> {
+        Ok(self.codegen(context)?.0)
+    }
+
+    /// The declaration of the script, see `ScriptMetadata::from_statements`.
+    pub fn metadata(&self) -> Result<ScriptMetadata, CodegenError> {
+        ScriptMetadata::located(&self.source)
+    }
+
+    /// The input schema of the script, see `inputs::extract`.
+    pub fn inputs(&self) -> Result<Vec<Input>, CodegenError> {
+        inputs::extract(&self.source)
+    }
+
+    /// The plots of the script, see `plots::extract`.
+    pub fn plots(&self) -> Result<Vec<Plot>, CodegenError> {
+        plots::extract(&self.source)
+    }
+
+    /// The `request.security()` calls of the script, see `security::extract`.
+    pub fn requests(&self) -> Result<Vec<Request>, CodegenError> {
+        security::extract(&self.source)
+    }
+
+    /// Values exposed through `get_outputs()`, in order.
+    pub fn outputs(&self) -> Result<Vec<Output>, CodegenError> {
+        let context = Context::create();
+        let (_, outputs) = self.codegen(&context)?;
+        Ok(outputs)
+    }
+
+    fn codegen<'ctx>(&self, context: &'ctx Context) -> Result<(Module<'ctx>, Vec<Output>), CodegenError> {
+        let metadata = ScriptMetadata::located(&self.source)?;
+        let mut checker = TypeChecker::new();
+        checker.check(&self.source).map_err(|e| CodegenError::from(e).at(checker.location()))?;
+
+        let inputs = inputs::collect(&self.source)?;
+        let values = match &self.inputs {
+            Some(values) => values.clone(),
+            None => inputs.iter().map(|i| i.1.default.clone()).collect()
+        };
+
+        let mut codegen = Codegen::new(context, &checker);
+        codegen.inputs = inputs.into_iter().enumerate().map(|(i, (e, input))| (e, (i, input))).collect();
+        codegen.input_values = values;
+        codegen.plots = plots::collect(&self.source)?.into_iter().enumerate().map(|(i, (e, plot))| (e, (i, plot.kind))).collect();
+        codegen.requests = security::collect(&self.source)?.into_iter().enumerate().map(|(i, (e, _))| (e, i)).collect();
+        codegen.bars_back = metadata.bars_back();
+        for name in history::collect(&self.source)? {
+            let ring = codegen.state_size;
+            codegen.state_size += 8 * codegen.bars_back;
+            codegen.histories.push((name, ring));
+        }
+        codegen.program(&self.source)?;
+        codegen.module.verify().map_err(|e| CodegenError::Llvm(e.to_string()))?;
+        let outputs = codegen.outputs.into_iter().map(|(name, t, _)| Output { name, t }).collect();
+        Ok((codegen.module, outputs))
+    }
+
+    pub fn ir(&self) -> Result<String, CodegenError> {
+        let context = Context::create();
+        let module = self.compile(&context)?;
+        Ok(module.print_to_string().to_string())
+    }
+}
+
+type Value<'ctx> = (BasicValueEnum<'ctx>, Type);
+
+/// Field order of `runtime::Bar`.
+const BAR_FIELDS: [&str; 6] = ["time", "open", "high", "low", "close", "volume"];
+
+/// Offsets of the state block holding the pointer to the current bar and `bar_index`.
+const BAR: u64 = 0;
+const BAR_INDEX: u64 = 8;
+
+/// Where a variable lives: at a byte offset of the state block, or on the
+/// stack of the function using it.
+#[derive(Clone, Copy, Debug)]
+enum Storage<'ctx> {
+    State(u64),
+    Local(PointerValue<'ctx>),
+}
+
+/// Bytes a value takes in the state block, 8 per scalar so it fits any target.
+fn size(t: &Type) -> u64 {
+    match t {
+        Type::Tuple(items) => items.iter().map(size).sum(),
+        _ => 8
+    }
+}
+
+type Scope<'ctx> = HashMap<String, (Storage<'ctx>, Type)>;
+
+/// A user function, compiled once per call site so every call keeps its own
+/// `var` variables and builtin call sites.
+struct Function<'a, 'ctx> {
+    params: &'a [VarParam],
+    body: &'a [Statement],
+    /// The globals declared before the function.
+    globals: Scope<'ctx>,
+}
+
+struct Codegen<'a, 'ctx> {
+    context: &'ctx Context,
+    module: Module<'ctx>,
+    builder: Builder<'ctx>,
+    checker: &'a TypeChecker,
+    /// The first scope holds globals, the rest are locals of the current function.
+    scopes: Vec<Scope<'ctx>>,
+    functions: HashMap<String, Function<'a, 'ctx>>,
+    function_name: Option<String>,
+    callsites: u32,
+    inputs: HashMap<*const Expr, (usize, Input)>,
+    input_values: Vec<Constant>,
+    /// Offsets of the inputs the script reads, by schema index.
+    input_slots: HashMap<usize, u64>,
+    plots: HashMap<*const Expr, (usize, PlotKind)>,
+    /// Indices of the `request.security()` calls.
+    requests: HashMap<*const Expr, usize>,
+    /// Offsets of the rings of the last values of the series with a history, see `history::collect`.
+    histories: Vec<(String, u64)>,
+    /// Values every ring keeps, see `ScriptMetadata::bars_back`.
+    bars_back: u64,
+    outputs: Vec<(String, Type, Storage<'ctx>)>,
+    /// Variables kept between bars which `restore` rolls back, all but `varip` variables.
+    saved: Vec<(u64, Type)>,
+    /// Bytes of the state block allocated so far.
+    state_size: u64,
+}
+
+impl<'a, 'ctx> Codegen<'a, 'ctx> {
+    fn new(context: &'ctx Context, checker: &'a TypeChecker) -> Self {
+        Self {
+            context,
+            module: context.create_module("ninescript"),
+            builder: context.create_builder(),
+            checker,
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            function_name: None,
+            callsites: 0,
+            inputs: HashMap::new(),
+            input_values: vec![],
+            input_slots: HashMap::new(),
+            plots: HashMap::new(),
+            requests: HashMap::new(),
+            histories: vec![],
+            bars_back: history::MAX_BARS_BACK,
+            outputs: vec![],
+            saved: vec![],
+            state_size: BAR_INDEX + 8,
+        }
+    }
+
+    fn bar_type(&self) -> StructType<'ctx> {
+        let f64_type = self.context.f64_type();
+        self.context.struct_type(&[
+            self.context.i64_type().into(),
+            f64_type.into(),
+            f64_type.into(),
+            f64_type.into(),
+            f64_type.into(),
+            f64_type.into(),
+        ], false)
+    }
+
+    fn llvm_type(&self, t: &Type) -> Result<BasicTypeEnum<'ctx>, CodegenError> {
+        Ok(match t {
+            Type::Int => self.context.i64_type().into(),
+            Type::Float | Type::Na => self.context.f64_type().into(),
+            Type::Bool => self.context.bool_type().into(),
+            Type::Color => self.context.i32_type().into(),
+            Type::String => self.context.ptr_type(AddressSpace::default()).into(),
+            /* Ids of plots and drawing objects */
+            t if t.is_handle() => self.context.i64_type().into(),
+            Type::Tuple(items) => {
+                let fields = items.iter().map(|t| self.llvm_type(t)).collect::<Result<Vec<_>, _>>()?;
+                self.context.struct_type(&fields, false).into()
+            },
+            t => return Err(CodegenError::Unsupported(format!("values of type {}", t)))
+        })
+    }
+
+    /// Reserves bytes of the state block for a value of type `t`.
+    fn allocate(&mut self, t: &Type) -> u64 {
+        let offset = self.state_size;
+        self.state_size += size(t);
+        offset
+    }
+
+    /// The state block, the first parameter of every function with a body.
+    fn state(&self) -> PointerValue<'ctx> {
+        self.current_function().get_first_param().unwrap().into_pointer_value()
+    }
+
+    fn pointer(&self, storage: Storage<'ctx>, name: &str) -> Result<PointerValue<'ctx>, CodegenError> {
+        Ok(match storage {
+            Storage::State(offset) => {
+                let (i8_type, state) = (self.context.i8_type(), self.state());
+                unsafe { self.builder.build_in_bounds_gep(i8_type, state, &[self.context.i64_type().const_int(offset, false)], name)? }
+            },
+            Storage::Local(ptr) => ptr
+        })
+    }
+
+    fn load(&self, storage: Storage<'ctx>, t: BasicTypeEnum<'ctx>, name: &str) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        let ptr = self.pointer(storage, name)?;
+        Ok(self.builder.build_load(t, ptr, name)?)
+    }
+
+    fn store(&self, storage: Storage<'ctx>, value: BasicValueEnum<'ctx>) -> Result<(), CodegenError> {
+        let ptr = self.pointer(storage, "slot")?;
+        self.builder.build_store(ptr, value)?;
+        Ok(())
+    }
+
+    fn current_function(&self) -> FunctionValue<'ctx> {
+        self.builder.get_insert_block().and_then(|b| b.get_parent()).unwrap()
+    }
+
+    /// Allocates a local in the entry block, so loops do not grow the stack.
+    fn alloca(&self, name: &str, t: BasicTypeEnum<'ctx>) -> Result<PointerValue<'ctx>, CodegenError> {
+        let entry = self.current_function().get_first_basic_block().unwrap();
+        let builder = self.context.create_builder();
+        match entry.get_first_instruction() {
+            Some(i) => builder.position_before(&i),
+            None => builder.position_at_end(entry)
+        }
+        Ok(builder.build_alloca(t, name)?)
+    }
+
+    /// Storage for a new variable: in the state block at the top level, a local otherwise.
+    fn declare(&mut self, name: &str, t: &Type) -> Result<Storage<'ctx>, CodegenError> {
+        let llvm_type = self.llvm_type(t)?;
+        let storage = if self.scopes.len() == 1 {
+            Storage::State(self.allocate(t))
+        } else {
+            Storage::Local(self.alloca(name, llvm_type)?)
+        };
+        self.scopes.last_mut().unwrap().insert(name.to_string(), (storage, t.clone()));
+        self.output(name, t, storage);
+        Ok(storage)
+    }
+
+    /// Makes a top level variable of a scalar type an output, unless a library declares it.
+    fn output(&mut self, name: &str, t: &Type, storage: Storage<'ctx>) {
+        let scalar = matches!(t, Type::Int | Type::Float | Type::Bool | Type::Color);
+        if scalar && self.scopes.len() == 1 && self.function_name.is_none() && !modules::is_linked(name) {
+            self.outputs.push((name.to_string(), t.clone(), storage));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<(Storage<'ctx>, Type)> {
+        self.scopes.iter().rev().find_map(|s| s.get(name)).cloned()
+    }
+
+    fn program(&mut self, statements: &'a [Statement]) -> Result<(), CodegenError> {
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let i64_type = self.context.i64_type();
+        let void_type = self.context.void_type();
+
+        let on_bar = self.module.add_function("on_bar", void_type.fn_type(&[ptr_type.into(), ptr_type.into()], false), None);
+        self.builder.position_at_end(self.context.append_basic_block(on_bar, "entry"));
+        self.store(Storage::State(BAR), on_bar.get_nth_param(1).unwrap())?;
+
+        for statement in statements.iter().filter(|s| !declaration::is_declaration(s)) {
+            self.statement(statement)?;
+        }
+
+        self.record_histories()?;
+        let outputs = self.store_outputs()?;
+
+        let index = self.load(Storage::State(BAR_INDEX), i64_type.into(), "bar_index")?.into_int_value();
+        let next = self.builder.build_int_add(index, i64_type.const_int(1, false), "bar_index")?;
+        self.store(Storage::State(BAR_INDEX), next.into())?;
+        self.builder.build_return(None)?;
+
+        let get_outputs = self.module.add_function("get_outputs", ptr_type.fn_type(&[ptr_type.into()], false), None);
+        self.builder.position_at_end(self.context.append_basic_block(get_outputs, "entry"));
+        let outputs = self.pointer(Storage::State(outputs), "outputs")?;
+        self.builder.build_return(Some(&outputs))?;
+
+        self.saved.push((BAR_INDEX, Type::Int));
+        self.rollback()?;
+        self.init()?;
+
+        /* Last, once everything is allocated */
+        let state_size = self.module.add_function("state_size", i64_type.fn_type(&[], false), None);
+        self.builder.position_at_end(self.context.append_basic_block(state_size, "entry"));
+        self.builder.build_return(Some(&i64_type.const_int(self.state_size, false)))?;
+        Ok(())
+    }
+
+    /// Stores the values of the series with a history at the end of the bar,
+    /// in the slot `bar_index % bars_back` of their rings.
+    fn record_histories(&mut self) -> Result<(), CodegenError> {
+        let i64_type = self.context.i64_type();
+        let index = self.load(Storage::State(BAR_INDEX), i64_type.into(), "bar_index")?.into_int_value();
+        let slot = self.builder.build_int_unsigned_rem(index, i64_type.const_int(self.bars_back, false), "slot")?;
+        for (name, ring) in self.histories.clone() {
+            let (v, _) = self.identifier(&name)?;
+            let ptr = self.ring_slot(ring, slot)?;
+            self.store(Storage::Local(ptr), v)?;
+        }
+        Ok(())
+    }
+
+    fn ring_slot(&self, ring: u64, slot: IntValue<'ctx>) -> Result<PointerValue<'ctx>, CodegenError> {
+        let (i8_type, i64_type) = (self.context.i8_type(), self.context.i64_type());
+        let base = self.pointer(Storage::State(ring), "history")?;
+        let offset = self.builder.build_int_mul(slot, i64_type.const_int(8, false), "offset")?;
+        Ok(unsafe { self.builder.build_in_bounds_gep(i8_type, base, &[offset], "slot")? })
+    }
+
+    /// `name[index]`, the value of a series `index` bars ago or `na` if the ring does not reach back that far.
+    fn history(&mut self, name: &str, index: &'a Expr) -> Result<Value<'ctx>, CodegenError> {
+        let ring = self.histories.iter().find(|h| h.0 == name).unwrap().1;
+        let (current, t) = self.identifier(name)?;
+        let (n, nt) = self.value(index)?;
+        let n = self.cast(n, &nt, &Type::Int)?.into_int_value();
+
+        let i64_type = self.context.i64_type();
+        let zero = i64_type.const_zero();
+        let max = i64_type.const_int(self.bars_back, false);
+        let bar_index = self.load(Storage::State(BAR_INDEX), i64_type.into(), "bar_index")?.into_int_value();
+        let position = self.builder.build_int_sub(bar_index, n, "position")?;
+        let slot = self.builder.build_int_unsigned_rem(position, max, "slot")?;
+        let llvm_type = self.llvm_type(&t)?;
+        let past = self.load(Storage::Local(self.ring_slot(ring, slot)?), llvm_type, name)?;
+
+        let conditions = [
+            self.builder.build_int_compare(IntPredicate::SGT, n, zero, "back")?,
+            self.builder.build_int_compare(IntPredicate::SLT, n, max, "kept")?,
+            self.builder.build_int_compare(IntPredicate::SGE, position, zero, "started")?,
+        ];
+        let mut reachable = conditions[0];
+        for c in &conditions[1..] {
+            reachable = self.builder.build_and(reachable, *c, "reachable")?;
+        }
+        let na = self.cast(self.context.f64_type().const_float(f64::NAN).into(), &Type::Na, &t)?;
+        let v = self.builder.build_select(reachable, past, na, name)?;
+        let now = self.builder.build_int_compare(IntPredicate::EQ, n, zero, "now")?;
+        Ok((self.builder.build_select(now, current, v, name)?, t))
+    }
+
+    /// Copies the outputs of the current bar into the `double`s `get_outputs` points to, returns their offset.
+    fn store_outputs(&mut self) -> Result<u64, CodegenError> {
+        let f64_type = self.context.f64_type();
+        let start = self.allocate(&Type::Tuple(vec![Type::Float; self.outputs.len()]));
+
+        for (i, (name, t, storage)) in self.outputs.iter().enumerate() {
+            let llvm_type = self.llvm_type(t)?;
+            let v = self.load(*storage, llvm_type, name)?;
+            let v = match t {
+                Type::Bool | Type::Color => self.builder.build_unsigned_int_to_float(v.into_int_value(), f64_type, name)?.into(),
+                t => self.cast(v, t, &Type::Float)?
+            };
+            self.store(Storage::State(start + 8 * i as u64), v)?;
+        }
+        Ok(start)
+    }
+
+    /// `save()` copies the variables of `saved` aside, `restore()` copies them back.
+    fn rollback(&mut self) -> Result<(), CodegenError> {
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let void_fn = self.context.void_type().fn_type(&[ptr_type.into()], false);
+        let save = self.module.add_function("save", void_fn, None);
+        let restore = self.module.add_function("restore", void_fn, None);
+        let save_block = self.context.append_basic_block(save, "entry");
+        let restore_block = self.context.append_basic_block(restore, "entry");
+        for (offset, t) in self.saved.clone() {
+            let llvm_type = self.llvm_type(&t)?;
+            let (slot, copy) = (Storage::State(offset), Storage::State(self.allocate(&t)));
+            self.builder.position_at_end(save_block);
+            let v = self.load(slot, llvm_type, "saved")?;
+            self.store(copy, v)?;
+            self.builder.position_at_end(restore_block);
+            let v = self.load(copy, llvm_type, "saved")?;
+            self.store(slot, v)?;
+        }
+        for block in [save_block, restore_block] {
+            self.builder.position_at_end(block);
+            self.builder.build_return(None)?;
+        }
+        Ok(())
+    }
+
+    /// `init(ptr state, ptr params)`, see `Processor::compile`.
+    fn init(&mut self) -> Result<(), CodegenError> {
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let i64_type = self.context.i64_type();
+        let init = self.module.add_function("init", self.context.void_type().fn_type(&[ptr_type.into(), ptr_type.into()], false), None);
+        let entry = self.context.append_basic_block(init, "entry");
+        let zero = self.context.append_basic_block(init, "zero");
+        let inputs = self.context.append_basic_block(init, "inputs");
+        self.builder.position_at_end(entry);
+        self.builder.build_unconditional_branch(zero)?;
+
+        /* Clears the state a word at a time with volatile stores, which LLVM
+         * does not turn into a call of `memset` that wasm modules lack */
+        self.builder.position_at_end(zero);
+        let state = self.state();
+        let word = self.builder.build_phi(i64_type, "word")?;
+        let index = word.as_basic_value().into_int_value();
+        let ptr = unsafe { self.builder.build_in_bounds_gep(i64_type, state, &[index], "word")? };
+        self.builder.build_store(ptr, i64_type.const_zero())?
+            .set_volatile(true).map_err(|e| CodegenError::Llvm(e.to_string()))?;
+        let next = self.builder.build_int_add(index, i64_type.const_int(1, false), "next")?;
+        word.add_incoming(&[(&i64_type.const_zero(), entry), (&next, zero)]);
+        let words = i64_type.const_int(self.state_size.div_ceil(8), false);
+        let more = self.builder.build_int_compare(IntPredicate::ULT, next, words, "more")?;
+        self.builder.build_conditional_branch(more, zero, inputs)?;
+
+        self.builder.position_at_end(inputs);
+        let params = init.get_nth_param(1).unwrap().into_pointer_value();
+        let read = self.context.append_basic_block(init, "params");
+        let defaults = self.context.append_basic_block(init, "defaults");
+        let end = self.context.append_basic_block(init, "end");
+        let null = self.builder.build_is_null(params, "null")?;
+        self.builder.build_conditional_branch(null, defaults, read)?;
+
+        let mut slots = self.input_slots.iter().map(|(id, offset)| (*id, *offset)).collect::<Vec<_>>();
+        slots.sort();
+        let kinds = self.inputs.values().map(|(id, input)| (*id, input.kind.clone())).collect::<HashMap<_, _>>();
+
+        self.builder.position_at_end(defaults);
+        for &(id, offset) in &slots {
+            let (_, value) = self.input_value(id)?;
+            self.store(Storage::State(offset), value)?;
+        }
+        self.builder.build_unconditional_branch(end)?;
+
+        self.builder.position_at_end(read);
+        for (id, offset) in slots {
+            let name = format!("input.{}", id);
+            let slot = unsafe { self.builder.build_in_bounds_gep(i64_type, params, &[i64_type.const_int(id as u64, false)], &name)? };
+            let value: BasicValueEnum = match kinds[&id] {
+                InputKind::Int | InputKind::Source => self.builder.build_load(i64_type, slot, &name)?,
+                InputKind::Float => {
+                    let f64_type = self.context.f64_type();
+                    self.builder.build_load(f64_type, slot, &name)?
+                },
+                InputKind::Bool => {
+                    let v = self.builder.build_load(i64_type, slot, &name)?.into_int_value();
+                    self.builder.build_int_compare(IntPredicate::NE, v, i64_type.const_zero(), &name)?.into()
+                },
+                InputKind::Color => {
+                    let v = self.builder.build_load(i64_type, slot, &name)?.into_int_value();
+                    self.builder.build_int_truncate(v, self.context.i32_type(), &name)?.into()
+                },
+                InputKind::String | InputKind::Timeframe => self.builder.build_load(ptr_type, slot, &name)?,
+            };
+            self.store(Storage::State(offset), value)?;
+        }
+        self.builder.build_unconditional_branch(end)?;
+
+        self.builder.position_at_end(end);
+        self.builder.build_return(None)?;
+        Ok(())
+    }
+
+    /// Compiles a copy of a user function for a call, see `Function`.
+    fn function(&mut self, name: &str) -> Result<FunctionValue<'ctx>, CodegenError> {
+        let signature = self.checker.function(name).cloned()
+            .ok_or_else(|| CodegenError::Unsupported(format!("function {}", name)))?;
+        let mut param_types: Vec<BasicMetadataTypeEnum> = vec![self.context.ptr_type(AddressSpace::default()).into()];
+        for (_, t) in &signature.params {
+            param_types.push(self.llvm_type(t)?.into());
+        }
+        let fn_type = match &signature.returns {
+            Type::Void => self.context.void_type().fn_type(&param_types, false),
+            t => self.llvm_type(t)?.fn_type(&param_types, false)
+        };
+        let function = self.module.add_function(name, fn_type, None);
+        let (body, globals) = {
+            let f = &self.functions[name];
+            (f.body, f.globals.clone())
+        };
+
+        let saved_block = self.builder.get_insert_block();
+        let saved_name = self.function_name.replace(name.to_string());
+        let saved_scopes = mem::replace(&mut self.scopes, vec![globals, HashMap::new()]);
+        self.builder.position_at_end(self.context.append_basic_block(function, "entry"));
+
+        for (i, (param, t)) in signature.params.iter().enumerate() {
+            let storage = self.declare(param, t)?;
+            self.store(storage, function.get_nth_param(i as u32 + 1).unwrap())?;
+        }
+        let result = self.block(body)?;
+        self.scopes = saved_scopes;
+
+        match (&signature.returns, result) {
+            (Type::Void, _) => { self.builder.build_return(None)?; },
+            (t, Some((v, vt))) => {
+                let v = self.cast(v, &vt, t)?;
+                self.builder.build_return(Some(&v))?;
+            },
+            (t, None) => return Err(CodegenError::Unsupported(format!("function {} returning {} without a value", name, t)))
+        }
+
+        self.function_name = saved_name;
+        if let Some(block) = saved_block {
+            self.builder.position_at_end(block);
+        }
+        Ok(function)
+    }
+
+    fn block(&mut self, statements: &'a [Statement]) -> Result<Option<Value<'ctx>>, CodegenError> {
+        self.scopes.push(HashMap::new());
+        let mut result = Ok(None);
+        for statement in statements {
+            result = self.statement(statement);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.scopes.pop();
+        result
+    }
+
+    fn declaration(&mut self, var: &Var, value: &'a Expr) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let (v, vt) = self.value(value)?;
+        let t = Type::from_var_type(&var.0).unwrap_or_else(|| vt.clone());
+        let v = self.cast(v, &vt, &t)?;
+        let storage = self.declare(&var.1, &t)?;
+        self.store(storage, v)?;
+        Ok(Some((v, t)))
+    }
+
+    /// `var` declarations keep their value between bars, the initializer runs once.
+    /// `varip` ones also keep it when a bar is rolled back.
+    fn persistent_declaration(&mut self, var: &Var, value: &'a Expr, varip: bool) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let bool_type = self.context.bool_type();
+        let flag = Storage::State(self.allocate(&Type::Bool));
+
+        let function = self.current_function();
+        let init = self.context.append_basic_block(function, "var.init");
+        let cont = self.context.append_basic_block(function, "var.cont");
+        let initialized = self.load(flag, bool_type.into(), "initialized")?.into_int_value();
+        self.builder.build_conditional_branch(initialized, cont, init)?;
+
+        self.builder.position_at_end(init);
+        let (v, vt) = self.value(value)?;
+        let t = Type::from_var_type(&var.0).unwrap_or_else(|| vt.clone());
+        let v = self.cast(v, &vt, &t)?;
+        let offset = self.allocate(&t);
+        self.store(Storage::State(offset), v)?;
+        self.store(flag, bool_type.const_int(1, false).into())?;
+        self.builder.build_unconditional_branch(cont)?;
+
+        self.builder.position_at_end(cont);
+        self.scopes.last_mut().unwrap().insert(var.1.to_string(), (Storage::State(offset), t.clone()));
+        self.output(&var.1, &t, Storage::State(offset));
+        if !varip {
+            let Storage::State(flag) = flag else { unreachable!() };
+            self.saved.extend([(flag, Type::Bool), (offset, t.clone())]);
+        }
+        let llvm_type = self.llvm_type(&t)?;
+        Ok(Some((self.load(Storage::State(offset), llvm_type, &var.1)?, t)))
+    }
+
+    /// Compiles a statement, locating its errors at it.
+    fn statement(&mut self, statement: &'a Statement) -> Result<Option<Value<'ctx>>, CodegenError> {
+        self.statement_value(statement).map_err(|e| e.at(statement.location()))
+    }
+
+    fn statement_value(&mut self, statement: &'a Statement) -> Result<Option<Value<'ctx>>, CodegenError> {
+        Ok(match statement {
+            Statement::Import(..) | Statement::TypeDef(..) | Statement::EnumDef(..) => None,
+            Statement::UnpackTuple(names, value) => {
+                let (v, t) = self.value(value)?;
+                let items = match &t {
+                    Type::Tuple(items) => items.clone(),
+                    t => return Err(CodegenError::Type(TypeError::NotATuple(t.clone())))
+                };
+                for (i, (name, item)) in names.iter().zip(&items).enumerate() {
+                    let element = self.builder.build_extract_value(v.into_struct_value(), i as u32, name)?;
+                    let storage = self.declare(name, item)?;
+                    self.store(storage, element)?;
+                }
+                Some((v, t))
+            },
+            Statement::ConstDef(var, value)
+            | Statement::SeriesDef(var, value)
+            | Statement::VarLet(var, value) => self.declaration(var, value)?,
+            Statement::VarDef(var, value) => self.persistent_declaration(var, value, false)?,
+            Statement::VarIpDef(var, value) => self.persistent_declaration(var, value, true)?,
+            Statement::VarAssign(name, value) => {
+                let (storage, t) = self.lookup(name)
+                    .ok_or_else(|| CodegenError::Unsupported(format!("assignment to {}", name)))?;
+                let (v, vt) = self.value(value)?;
+                let v = self.cast(v, &vt, &t)?;
+                self.store(storage, v)?;
+                Some((v, t))
+            },
+            Statement::ForTo(var, start, end, body, by) => {
+                self.for_to(var, start, end, body, by.as_deref())?;
+                None
+            },
+            Statement::ForIn(..) => return Err(CodegenError::Unsupported("for ... in loops".to_string())),
+            Statement::While(condition, body) => {
+                let site = self.loop_start()?;
+                let function = self.current_function();
+                let cond_block = self.context.append_basic_block(function, "while.cond");
+                let body_block = self.context.append_basic_block(function, "while.body");
+                let exit = self.context.append_basic_block(function, "while.exit");
+                self.builder.build_unconditional_branch(cond_block)?;
+
+                self.builder.position_at_end(cond_block);
+                let c = self.value(condition)?.0.into_int_value();
+                let next = self.loop_next(site)?;
+                let c = self.builder.build_and(c, next, "continue")?;
+                self.builder.build_conditional_branch(c, body_block, exit)?;
+
+                self.builder.position_at_end(body_block);
+                self.block(body)?;
+                self.builder.build_unconditional_branch(cond_block)?;
+
+                self.builder.position_at_end(exit);
+                None
+            },
+            Statement::Export(definition) => self.statement(definition)?,
+            Statement::FnDef(name, params, body) => {
+                let globals = self.scopes[0].clone();
+                self.functions.insert(name.clone(), Function { params, body, globals });
+                None
+            },
+            Statement::Expression(e) => self.expr(e)?,
+        })
+    }
+
+    fn for_to(&mut self, var: &Var, start: &'a Expr, end: &'a Expr, body: &'a [Statement], by: Option<&'a Expr>) -> Result<(), CodegenError> {
+        let t = Type::from_var_type(&var.0).unwrap_or(Type::Int);
+        let llvm_type = self.llvm_type(&t)?;
+
+        self.scopes.push(HashMap::new());
+        let (v, vt) = self.value(start)?;
+        let v = self.cast(v, &vt, &t)?;
+        let counter = self.declare(&var.1, &t)?;
+        self.store(counter, v)?;
+        let (v, vt) = self.value(end)?;
+        let end = self.cast(v, &vt, &t)?;
+        let step = match by {
+            Some(by) => {
+                let (v, vt) = self.value(by)?;
+                self.cast(v, &vt, &t)?
+            },
+            None => self.cast(self.context.i64_type().const_int(1, false).into(), &Type::Int, &t)?
+        };
+
+        let site = self.loop_start()?;
+        let function = self.current_function();
+        let cond_block = self.context.append_basic_block(function, "for.cond");
+        let body_block = self.context.append_basic_block(function, "for.body");
+        let exit = self.context.append_basic_block(function, "for.exit");
+        self.builder.build_unconditional_branch(cond_block)?;
+
+        self.builder.position_at_end(cond_block);
+        let i = self.load(counter, llvm_type, &var.1)?;
+        let c = self.compare(&Opcode::Lte, (i, t.clone()), (end, t.clone()))?.into_int_value();
+        let next = self.loop_next(site)?;
+        let c = self.builder.build_and(c, next, "continue")?;
+        self.builder.build_conditional_branch(c, body_block, exit)?;
+
+        self.builder.position_at_end(body_block);
+        self.block(body)?;
+        let i = self.load(counter, llvm_type, &var.1)?;
+        let next = self.arithmetic(&Opcode::Add, (i, t.clone()), (step, t.clone()))?.0;
+        self.store(counter, next)?;
+        self.builder.build_unconditional_branch(cond_block)?;
+
+        self.builder.position_at_end(exit);
+        self.scopes.pop();
+        Ok(())
+    }
+
+    /// Calls `runtime::LOOP_START` with a new call site, which `loop_next` takes.
+    fn loop_start(&mut self) -> Result<IntValue<'ctx>, CodegenError> {
+        let site = self.context.i32_type().const_int(self.callsites as u64, false);
+        self.callsites += 1;
+        let function = self.builtin(runtime::LOOP_START, &[], &Type::Void)?;
+        self.builder.build_call(function, &[site.into()], "")?;
+        Ok(site)
+    }
+
+    /// Whether the loop at `site` may run another iteration, see `runtime::LOOP_NEXT`.
+    fn loop_next(&mut self, site: IntValue<'ctx>) -> Result<IntValue<'ctx>, CodegenError> {
+        let function = self.builtin(runtime::LOOP_NEXT, &[], &Type::Bool)?;
+        let call = self.builder.build_call(function, &[site.into()], "next")?;
+        Ok(call.try_as_basic_value().left().unwrap().into_int_value())
+    }
+
+    fn na_int(&self) -> IntValue<'ctx> {
+        self.context.i64_type().const_int(NA_INT as u64, true)
+    }
+
+    fn cast(&self, value: BasicValueEnum<'ctx>, from: &Type, to: &Type) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        Ok(match (from, to) {
+            (Type::Int, Type::Float) => {
+                let f64_type = self.context.f64_type();
+                let float = self.builder.build_signed_int_to_float(value.into_int_value(), f64_type, "cast")?;
+                let na = self.builder.build_int_compare(IntPredicate::EQ, value.into_int_value(), self.na_int(), "na")?;
+                self.builder.build_select(na, f64_type.const_float(f64::NAN), float, "cast")?
+            },
+            (Type::Na, Type::Na | Type::Float) => value,
+            (Type::Na, Type::Int) => self.na_int().into(),
+            (Type::Na, t) => self.llvm_type(t)?.const_zero(),
+            (Type::Tuple(from), Type::Tuple(to)) if from != to => {
+                let mut result = self.llvm_type(&Type::Tuple(to.clone()))?.into_struct_type().get_undef();
+                for (i, (f, t)) in from.iter().zip(to).enumerate() {
+                    let element = self.builder.build_extract_value(value.into_struct_value(), i as u32, "element")?;
+                    let element = self.cast(element, f, t)?;
+                    result = self.builder.build_insert_value(result, element, i as u32, "tuple")?.into_struct_value();
+                }
+                result.into()
+            },
+            _ => value
+        })
+    }
+
+    fn value(&mut self, expr: &'a Expr) -> Result<Value<'ctx>, CodegenError> {
+        self.expr(expr)?.ok_or_else(|| CodegenError::Unsupported("use of an expression without a value".to_string()))
+    }
+
+    fn expr(&mut self, expr: &'a Expr) -> Result<Option<Value<'ctx>>, CodegenError> {
+        Ok(Some(match expr {
+            Expr::Identifier(name) => self.identifier(name)?,
+            Expr::String(s) => {
+                let ptr = self.builder.build_global_string_ptr(s, "str")?.as_pointer_value();
+                (ptr.into(), Type::String)
+            },
+            Expr::Bool(b) => (self.context.bool_type().const_int(*b as u64, false).into(), Type::Bool),
+            Expr::Int(i) => (self.context.i64_type().const_int(*i as u64, true).into(), Type::Int),
+            Expr::Float(f) => (self.context.f64_type().const_float(*f).into(), Type::Float),
+            Expr::HashColor(c) => (self.color(c).into(), Type::Color),
+            Expr::MakeTuple(items) => {
+                let values = items.iter().map(|e| self.value(e)).collect::<Result<Vec<_>, _>>()?;
+                let t = Type::Tuple(values.iter().map(|v| v.1.clone()).collect());
+                let mut tuple = self.llvm_type(&t)?.into_struct_type().get_undef();
+                for (i, (v, _)) in values.into_iter().enumerate() {
+                    tuple = self.builder.build_insert_value(tuple, v, i as u32, "tuple")?.into_struct_value();
+                }
+                (tuple.into(), t)
+            },
+            Expr::Op(left, Opcode::TernaryElse, otherwise) => {
+                let (condition, then) = match left.as_ref() {
+                    Expr::Op(condition, Opcode::TernaryIf, then) => (condition, then),
+                    _ => return Err(CodegenError::Unsupported("`:` without `?`".to_string()))
+                };
+                let c = self.value(condition)?.0.into_int_value();
+                let (a, at) = self.value(then)?;
+                let (b, bt) = self.value(otherwise)?;
+                let t = at.unify(&bt).ok_or(CodegenError::Type(TypeError::Mismatch { expected: at.clone(), found: bt.clone() }))?;
+                let a = self.cast(a, &at, &t)?;
+                let b = self.cast(b, &bt, &t)?;
+                (self.builder.build_select(c, a, b, "select")?, t)
+            },
+            Expr::Op(_, Opcode::TernaryIf, _) => return Err(CodegenError::Unsupported("`?` without `:`".to_string())),
+            Expr::Op(left, op, right) => {
+                let l = self.value(left)?;
+                let r = self.value(right)?;
+                match op {
+                    Opcode::And => (self.builder.build_and(l.0.into_int_value(), r.0.into_int_value(), "and")?.into(), Type::Bool),
+                    Opcode::Or => (self.builder.build_or(l.0.into_int_value(), r.0.into_int_value(), "or")?.into(), Type::Bool),
+                    Opcode::Equal | Opcode::NotEqual | Opcode::Greater | Opcode::Gte | Opcode::Less | Opcode::Lte =>
+                        (self.compare(op, l, r)?, Type::Bool),
+                    _ => self.arithmetic(op, l, r)?
+                }
+            },
+            Expr::If(condition, then, otherwise) => return self.if_expr(condition, then, otherwise.as_deref()),
+            Expr::Switch(subject, variants) => return self.switch(subject.as_deref(), variants),
+            Expr::Not(e) => {
+                let v = self.value(e)?.0.into_int_value();
+                (self.builder.build_not(v, "not")?.into(), Type::Bool)
+            },
+            Expr::Negative(e) => match self.value(e)? {
+                (v, Type::Int) => (self.builder.build_int_neg(v.into_int_value(), "neg")?.into(), Type::Int),
+                (v, t) => (self.builder.build_float_neg(v.into_float_value(), "neg")?.into(), t)
+            },
+            Expr::FnCall(_, _, args) if self.plots.contains_key(&(expr as *const Expr)) => return self.plot(expr, args),
+            Expr::FnCall(name, _, args) => return self.call(name, args),
+            Expr::MethodCall(object, _, _, _) if object == "input" => self.input(expr)?,
+            Expr::MethodCall(_, _, _, args) if self.requests.contains_key(&(expr as *const Expr)) => return self.security(expr, args),
+            Expr::MethodCall(object, name, _, args) => {
+                let path = format!("{}.{}", object, name);
+                return match drawings::call(&path) {
+                    Some(call) => self.drawing(call, &path, args),
+                    None => self.builtin_call(&path, args)
+                };
+            },
+            Expr::Index(name, index) => self.history(name, index)?,
+            Expr::PropertyAccess(object, property) => return self.property(object, property),
+        }))
+    }
+
+    /// Records the value and color of a plot on the current bar, see `plots::RECORD`.
+    fn plot(&mut self, expr: &Expr, args: &'a CallArguments) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let (id, kind) = self.plots[&(expr as *const Expr)];
+        let f64_type = self.context.f64_type();
+        let value = match kind.value_param().and_then(|p| plots::argument(kind, args, p)) {
+            Some(e) => match self.value(e)? {
+                (v, Type::Bool) => self.builder.build_unsigned_int_to_float(v.into_int_value(), f64_type, "value")?.into(),
+                (v, t) => self.cast(v, &t, &Type::Float)?
+            },
+            None => f64_type.const_float(f64::NAN).into()
+        };
+        let color = match plots::argument(kind, args, "color") {
+            Some(e) => match self.value(e)? {
+                (v, Type::Color) => v,
+                (v, t) => self.cast(v, &t, &Type::Color)?
+            },
+            None => self.context.i32_type().const_zero().into()
+        };
+
+        let signature = plots::record_signature();
+        let function = self.builtin(plots::RECORD, &signature.params, &signature.returns)?;
+        let plot = self.context.i32_type().const_int(id as u64, false);
+        self.builder.build_call(function, &[plot.into(), value.into(), color.into()], "")?;
+        let handle = self.context.i64_type().const_int(id as u64, false);
+        let returns = builtins::function(kind.function()).unwrap().returns;
+        Ok((returns != Type::Void).then(|| (handle.into(), returns)))
+    }
+
+    /// Passes the value of the expression of a `request.security()` call on the
+    /// current bar to `security::REQUEST` and converts back what it returns.
+    fn security(&mut self, expr: &Expr, args: &'a CallArguments) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let request = self.requests[&(expr as *const Expr)];
+        let f64_type = self.context.f64_type();
+        let (v, t) = self.value(security::expression(args).unwrap())?;
+        let value: BasicValueEnum = match &t {
+            Type::Bool | Type::Color => self.builder.build_unsigned_int_to_float(v.into_int_value(), f64_type, "value")?.into(),
+            Type::Int | Type::Float | Type::Na => self.cast(v, &t, &Type::Float)?,
+            t => return Err(CodegenError::Unsupported(format!("request.security() of {}", t)))
+        };
+
+        let signature = security::request_signature();
+        let function = self.builtin(security::REQUEST, &signature.params, &signature.returns)?;
+        let request = self.context.i32_type().const_int(request as u64, false);
+        let call = self.builder.build_call(function, &[request.into(), value.into()], "requested")?;
+        let requested = call.try_as_basic_value().left().unwrap().into_float_value();
+
+        /* `na` is NaN as a double, `NA_INT` as an int and 0 for the other types */
+        let na = self.builder.build_float_compare(FloatPredicate::UNO, requested, requested, "na")?;
+        let result: BasicValueEnum = match &t {
+            Type::Int => {
+                let int = self.builder.build_float_to_signed_int(requested, self.context.i64_type(), "int")?;
+                self.builder.build_select(na, self.na_int(), int, "requested")?
+            },
+            Type::Color => {
+                let color = self.builder.build_float_to_unsigned_int(requested, self.context.i32_type(), "color")?;
+                self.builder.build_select(na, self.context.i32_type().const_zero(), color, "requested")?
+            },
+            Type::Bool => self.builder.build_float_compare(FloatPredicate::ONE, requested, f64_type.const_zero(), "requested")?.into(),
+            _ => requested.into()
+        };
+        Ok(Some((result, t)))
+    }
+
+    /// Calls a builtin of `drawings` with `first` in place of a call site.
+    fn drawing_import(&self, name: &str, first: u32, args: &[BasicMetadataValueEnum<'ctx>]) -> Result<Option<BasicValueEnum<'ctx>>, CodegenError> {
+        let signature = drawings::import(name).unwrap();
+        let function = self.builtin(name, &signature.params, &signature.returns)?;
+        let mut values = vec![self.context.i32_type().const_int(first as u64, false).into()];
+        values.extend_from_slice(args);
+        Ok(self.builder.build_call(function, &values, "drawing")?.try_as_basic_value().left())
+    }
+
+    /// Evaluates the id of a drawing object, `na` is 0.
+    fn drawing_id(&mut self, kind: DrawingKind, expr: Option<&'a Expr>) -> Result<inkwell::values::IntValue<'ctx>, CodegenError> {
+        let (v, t) = self.value(expr.unwrap())?;
+        Ok(self.cast(v, &t, &drawings::handle_type(kind))?.into_int_value())
+    }
+
+    /// Sets a property of a drawing object to the value of `expr`.
+    fn drawing_property(&mut self, kind: DrawingKind, name: &str, id: inkwell::values::IntValue<'ctx>, expr: &'a Expr) -> Result<(), CodegenError> {
+        let property = drawings::property(kind, name).unwrap();
+        let t = drawings::properties().swap_remove(property as usize).2;
+        let (v, vt) = self.value(expr)?;
+        let v = self.cast(v, &vt, &t)?;
+        let f64_type = self.context.f64_type();
+        let (import, value): (_, BasicValueEnum) = match t {
+            Type::String => (drawings::SET_STRING, v),
+            Type::Int => (drawings::SET, self.cast(v, &Type::Int, &Type::Float)?),
+            Type::Bool | Type::Color => (drawings::SET, self.builder.build_unsigned_int_to_float(v.into_int_value(), f64_type, "value")?.into()),
+            _ => (drawings::SET, v)
+        };
+        self.drawing_import(import, property, &[id.into(), value.into()])?;
+        Ok(())
+    }
+
+    /// Lowers a call of a drawing function to the builtins of `drawings`.
+    fn drawing(&mut self, call: Call, name: &str, args: &'a CallArguments) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let signature = builtins::function(name).unwrap();
+        let exprs = builtins::arguments(name, args);
+        let properties = signature.params.iter().map(|p| p.0.as_str()).zip(exprs.iter().copied());
+        match call {
+            Call::New(kind) => {
+                let id = self.drawing_import(drawings::NEW, kind.index(), &[])?.unwrap().into_int_value();
+                for (param, expr) in properties {
+                    let Some(expr) = expr else { continue };
+                    match expr {
+                        Expr::MakeTuple(points) if param == "points" => for point in points {
+                            let point = self.drawing_id(DrawingKind::Point, Some(point))?;
+                            self.drawing_import(drawings::POINT, 0, &[id.into(), point.into()])?;
+                        },
+                        _ if param == "points" => return Err(CodegenError::Unsupported("points of polyline.new other than an array literal".to_string())),
+                        _ => self.drawing_property(kind, param, id, expr)?
+                    }
+                }
+                Ok(Some((id.into(), signature.returns)))
+            },
+            Call::Set(kind) => {
+                let id = self.drawing_id(kind, exprs[0])?;
+                for (param, expr) in properties.skip(1) {
+                    self.drawing_property(kind, param, id, expr.unwrap())?;
+                }
+                Ok(None)
+            },
+            Call::Get(kind, property) => {
+                let id = self.drawing_id(kind, exprs[0])?;
+                let property = drawings::property(kind, &property).unwrap();
+                let value = self.drawing_import(drawings::GET, property, &[id.into()])?.unwrap();
+                let value = match signature.returns {
+                    Type::Int => self.builder.build_float_to_signed_int(value.into_float_value(), self.context.i64_type(), "value")?.into(),
+                    _ => value
+                };
+                Ok(Some((value, signature.returns)))
+            },
+            Call::Delete => {
+                let (v, t) = self.value(exprs[0].unwrap())?;
+                let id = self.cast(v, &t, &signature.params[0].1)?;
+                self.drawing_import(drawings::DELETE, 0, &[id.into()])?;
+                Ok(None)
+            },
+            Call::Cell => {
+                let table = self.drawing_id(DrawingKind::Table, exprs[0])?;
+                let mut position = vec![table.into()];
+                for expr in &exprs[1..3] {
+                    let (v, t) = self.value(expr.unwrap())?;
+                    position.push(self.cast(v, &t, &Type::Int)?.into());
+                }
+                let cell = self.drawing_import(drawings::CELL, 0, &position)?.unwrap().into_int_value();
+                for (param, expr) in properties.skip(3) {
+                    if let Some(expr) = expr {
+                        self.drawing_property(DrawingKind::Cell, param, cell, expr)?;
+                    }
+                }
+                Ok(None)
+            },
         }
     }
 
-    pub fn ir() -> String {
+    fn color(&self, c: &RGBA) -> inkwell::values::IntValue<'ctx> {
+        self.context.i32_type().const_int(c.packed() as u64, false)
+    }
+
+    fn constant(&self, c: &Constant) -> BasicValueEnum<'ctx> {
+        match c {
+            Constant::String(s) => {
+                let bytes = self.context.const_string(s.as_bytes(), true);
+                let global = self.module.add_global(bytes.get_type(), Some(AddressSpace::default()), "str");
+                global.set_initializer(&bytes);
+                global.set_constant(true);
+                global.as_pointer_value().into()
+            },
+            Constant::Bool(b) => self.context.bool_type().const_int(*b as u64, false).into(),
+            Constant::Int(i) => self.context.i64_type().const_int(*i as u64, true).into(),
+            Constant::Float(f) => self.context.f64_type().const_float(*f).into(),
+            Constant::Color(c) => self.color(c).into(),
+        }
+    }
+
+    /// The type of an input in the state block and the value compiled in.
+    fn input_value(&self, id: usize) -> Result<(Type, BasicValueEnum<'ctx>), CodegenError> {
+        let input = self.inputs.values().find(|i| i.0 == id).map(|i| &i.1).unwrap();
+        Ok(match (&input.kind, &self.input_values[id]) {
+            (InputKind::Source, Constant::String(s)) => {
+                let index = SOURCES.iter().position(|x| x == s).unwrap_or(3);
+                (Type::Int, self.context.i64_type().const_int(index as u64, false).into())
+            },
+            (_, value) => (input.get_type(), self.constant(value))
+        })
+    }
+
+    fn input(&mut self, expr: &Expr) -> Result<Value<'ctx>, CodegenError> {
+        let (id, input) = self.inputs.get(&(expr as *const Expr)).cloned().unwrap();
+        let (t, _) = self.input_value(id)?;
+        let offset = match self.input_slots.get(&id) {
+            Some(offset) => *offset,
+            None => {
+                let offset = self.allocate(&t);
+                self.input_slots.insert(id, offset);
+                offset
+            }
+        };
+        let llvm_type = self.llvm_type(&t)?;
+        let v = self.load(Storage::State(offset), llvm_type, &format!("input.{}", id))?;
+        if input.kind != InputKind::Source {
+            return Ok((v, t));
+        }
+
+        /* Select the chosen series */
+        let mut result = self.bar_value(SOURCES[0])?.unwrap().0;
+        for (i, source) in SOURCES.iter().enumerate().skip(1) {
+            let index = self.context.i64_type().const_int(i as u64, false);
+            let chosen = self.builder.build_int_compare(IntPredicate::EQ, v.into_int_value(), index, "chosen")?;
+            let value = self.bar_value(source)?.unwrap().0;
+            result = self.builder.build_select(chosen, value, result, "source")?;
+        }
+        Ok((result, Type::Float))
+    }
 
+    /// Loads a field of the current bar (`close`) or an average of fields (`hl2`).
+    fn bar_value(&self, name: &str) -> Result<Option<Value<'ctx>>, CodegenError> {
+        if let Some(field) = BAR_FIELDS.iter().position(|f| *f == name) {
+            let ptr_type = self.context.ptr_type(AddressSpace::default());
+            let bar = self.load(Storage::State(BAR), ptr_type.into(), "bar")?.into_pointer_value();
+            let bar_type = self.bar_type();
+            let ptr = self.builder.build_struct_gep(bar_type, bar, field as u32, name)?;
+            let t = builtins::variable(name).unwrap();
+            let llvm_type = self.llvm_type(&t)?;
+            return Ok(Some((self.builder.build_load(llvm_type, ptr, name)?, t)));
+        }
+
+        let fields: &[&str] = match name {
+            "hl2" => &["high", "low"],
+            "hlc3" => &["high", "low", "close"],
+            "ohlc4" => &["open", "high", "low", "close"],
+            _ => return Ok(None)
+        };
+        let mut sum = self.bar_value(fields[0])?.unwrap().0.into_float_value();
+        for field in &fields[1..] {
+            let v = self.bar_value(field)?.unwrap().0.into_float_value();
+            sum = self.builder.build_float_add(sum, v, name)?;
+        }
+        let count = self.context.f64_type().const_float(fields.len() as f64);
+        Ok(Some((self.builder.build_float_div(sum, count, name)?.into(), Type::Float)))
+    }
+
+    fn identifier(&mut self, name: &str) -> Result<Value<'ctx>, CodegenError> {
+        if let Some((storage, t)) = self.lookup(name) {
+            let llvm_type = self.llvm_type(&t)?;
+            return Ok((self.load(storage, llvm_type, name)?, t));
+        }
+
+        if let Some(v) = self.bar_value(name)? {
+            return Ok(v);
+        }
+
+        match name {
+            "bar_index" => Ok((self.load(Storage::State(BAR_INDEX), self.context.i64_type().into(), name)?, Type::Int)),
+            "na" => Ok((self.context.f64_type().const_float(f64::NAN).into(), Type::Na)),
+            // `hour` is `hour(time)`
+            _ if calendar::FIELDS.contains(&name) => {
+                let signature = builtins::function(name).unwrap();
+                let function = self.builtin(name, &signature.params, &signature.returns)?;
+                let time = self.bar_value("time")?.unwrap().0;
+                let timezone = self.context.ptr_type(AddressSpace::default()).const_null();
+                Ok(self.call_builtin(function, vec![time.into(), timezone.into()], Type::Int)?.unwrap())
+            },
+            _ => Err(CodegenError::Type(TypeError::UndefinedVariable(name.to_string())))
+        }
+    }
+
+    fn compare(&self, op: &Opcode, l: Value<'ctx>, r: Value<'ctx>) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        let int = matches!((&l.1, &r.1), (Type::Int, Type::Int) | (Type::Bool, Type::Bool) | (Type::Color, Type::Color));
+        if int {
+            let predicate = match op {
+                Opcode::Equal => IntPredicate::EQ,
+                Opcode::NotEqual => IntPredicate::NE,
+                Opcode::Greater => IntPredicate::SGT,
+                Opcode::Gte => IntPredicate::SGE,
+                Opcode::Less => IntPredicate::SLT,
+                _ => IntPredicate::SLE,
+            };
+            return Ok(self.builder.build_int_compare(predicate, l.0.into_int_value(), r.0.into_int_value(), "cmp")?.into());
+        }
+
+        if !l.1.is_numeric() && l.1 != Type::Na || !r.1.is_numeric() && r.1 != Type::Na {
+            return Err(CodegenError::Unsupported(format!("comparison of {} and {}", l.1, r.1)));
+        }
+        let a = self.cast(l.0, &l.1, &Type::Float)?.into_float_value();
+        let b = self.cast(r.0, &r.1, &Type::Float)?.into_float_value();
+        let predicate = match op {
+            Opcode::Equal => FloatPredicate::OEQ,
+            Opcode::NotEqual => FloatPredicate::UNE,
+            Opcode::Greater => FloatPredicate::OGT,
+            Opcode::Gte => FloatPredicate::OGE,
+            Opcode::Less => FloatPredicate::OLT,
+            _ => FloatPredicate::OLE,
+        };
+        Ok(self.builder.build_float_compare(predicate, a, b, "cmp")?.into())
+    }
+
+    fn arithmetic(&self, op: &Opcode, l: Value<'ctx>, r: Value<'ctx>) -> Result<Value<'ctx>, CodegenError> {
+        if l.1 == Type::Int && r.1 == Type::Int {
+            let (a, b) = (l.0.into_int_value(), r.0.into_int_value());
+            /* `na` operands and zero divisors give `na`, the divisor is 1 then so
+             * that neither a division by zero nor `NA_INT / -1` is executed */
+            let na_a = self.builder.build_int_compare(IntPredicate::EQ, a, self.na_int(), "na")?;
+            let na_b = self.builder.build_int_compare(IntPredicate::EQ, b, self.na_int(), "na")?;
+            let na = self.builder.build_or(na_a, na_b, "na")?;
+            let (v, na) = match op {
+                Opcode::Add => (self.builder.build_int_add(a, b, "add")?, na),
+                Opcode::Sub => (self.builder.build_int_sub(a, b, "sub")?, na),
+                Opcode::Mul => (self.builder.build_int_mul(a, b, "mul")?, na),
+                _ => {
+                    let zero = self.builder.build_int_compare(IntPredicate::EQ, b, b.get_type().const_zero(), "zero")?;
+                    let na = self.builder.build_or(na, zero, "na")?;
+                    let divisor = self.builder.build_select(na, b.get_type().const_int(1, false), b, "divisor")?.into_int_value();
+                    match op {
+                        Opcode::Div => (self.builder.build_int_signed_div(a, divisor, "div")?, na),
+                        _ => (self.builder.build_int_signed_rem(a, divisor, "mod")?, na),
+                    }
+                },
+            };
+            let v = self.builder.build_select(na, self.na_int(), v, "int")?;
+            return Ok((v, Type::Int));
+        }
+
+        if !l.1.is_numeric() && l.1 != Type::Na || !r.1.is_numeric() && r.1 != Type::Na {
+            return Err(CodegenError::Unsupported(format!("arithmetic on {} and {}", l.1, r.1)));
+        }
+        let a = self.cast(l.0, &l.1, &Type::Float)?.into_float_value();
+        let b = self.cast(r.0, &r.1, &Type::Float)?.into_float_value();
+        let v = match op {
+            Opcode::Add => self.builder.build_float_add(a, b, "add")?,
+            Opcode::Sub => self.builder.build_float_sub(a, b, "sub")?,
+            Opcode::Mul => self.builder.build_float_mul(a, b, "mul")?,
+            Opcode::Div => self.builder.build_float_div(a, b, "div")?,
+            _ => self.builder.build_float_rem(a, b, "mod")?,
+        };
+        Ok((v.into(), Type::Float))
+    }
+
+    /// Joins the ends of branches, producing a value if every branch has one.
+    fn merge(&mut self, ends: Vec<(BasicBlock<'ctx>, Option<Value<'ctx>>)>) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let mut types = ends.iter().map(|(_, v)| v.as_ref().map(|v| v.1.clone()));
+        let t = match types.next().flatten() {
+            Some(first) => types.try_fold(first, |t, vt| vt.and_then(|vt| t.unify(&vt))),
+            None => None
+        };
+        let t = t.filter(|t| *t != Type::Void);
+
+        let merge = self.context.append_basic_block(self.current_function(), "merge");
+        let mut incoming = vec![];
+        for (block, v) in ends {
+            self.builder.position_at_end(block);
+            if let (Some(t), Some((v, vt))) = (&t, v) {
+                incoming.push((self.cast(v, &vt, t)?, self.builder.get_insert_block().unwrap()));
+            }
+            self.builder.build_unconditional_branch(merge)?;
+        }
+
+        self.builder.position_at_end(merge);
+        Ok(match t {
+            Some(t) => {
+                let phi = self.builder.build_phi(self.llvm_type(&t)?, "phi")?;
+                for (v, block) in &incoming {
+                    phi.add_incoming(&[(v as &dyn BasicValue<'ctx>, *block)]);
+                }
+                Some((phi.as_basic_value(), t))
+            },
+            None => None
+        })
+    }
+
+    fn if_expr(&mut self, condition: &'a Expr, then: &'a [Statement], otherwise: Option<&'a [Statement]>) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let c = self.value(condition)?.0.into_int_value();
+        let function = self.current_function();
+        let then_block = self.context.append_basic_block(function, "if.then");
+        let else_block = self.context.append_basic_block(function, "if.else");
+        self.builder.build_conditional_branch(c, then_block, else_block)?;
+
+        self.builder.position_at_end(then_block);
+        let t = self.block(then)?;
+        let then_end = self.builder.get_insert_block().unwrap();
+
+        self.builder.position_at_end(else_block);
+        let e = match otherwise {
+            Some(otherwise) => self.block(otherwise)?,
+            None => None
+        };
+        let else_end = self.builder.get_insert_block().unwrap();
+
+        self.merge(vec![(then_end, t), (else_end, e)])
+    }
+
+    fn switch(&mut self, subject: Option<&'a Expr>, variants: &'a [(Option<Box<Expr>>, Box<Statement>)]) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let subject = match subject {
+            Some(s) => Some(self.value(s)?),
+            None => None
+        };
+
+        let function = self.current_function();
+        let mut ends = vec![];
+        let mut exhaustive = false;
+        for (case, body) in variants {
+            let case = match case {
+                Some(case) => case,
+                None => {
+                    let v = self.block(std::slice::from_ref(body))?;
+                    ends.push((self.builder.get_insert_block().unwrap(), v));
+                    exhaustive = true;
+                    break;
+                }
+            };
+
+            let c = match &subject {
+                Some(s) => {
+                    let v = self.value(case)?;
+                    self.compare(&Opcode::Equal, s.clone(), v)?
+                },
+                None => self.value(case)?.0
+            };
+            let body_block = self.context.append_basic_block(function, "switch.case");
+            let next = self.context.append_basic_block(function, "switch.next");
+            self.builder.build_conditional_branch(c.into_int_value(), body_block, next)?;
+
+            self.builder.position_at_end(body_block);
+            let v = self.block(std::slice::from_ref(body))?;
+            ends.push((self.builder.get_insert_block().unwrap(), v));
+            self.builder.position_at_end(next);
+        }
+        if !exhaustive {
+            ends.push((self.builder.get_insert_block().unwrap(), None));
+        }
+
+        self.merge(ends)
+    }
+
+    fn arguments(&mut self, name: &str, params: &[(String, Type)], args: &'a CallArguments, defaults: Option<&'a [VarParam]>) -> Result<Vec<BasicMetadataValueEnum<'ctx>>, CodegenError> {
+        let mut exprs: Vec<Option<&'a Expr>> = vec![None; params.len()];
+        for (i, (key, value)) in args.iter().enumerate() {
+            let index = match key {
+                Some(key) => params.iter().position(|p| p.0 == *key)
+                    .ok_or_else(|| CodegenError::Type(TypeError::UnknownArgument { function: name.to_string(), argument: key.to_string() }))?,
+                None => i
+            };
+            exprs[index] = Some(value);
+        }
+
+        let mut values = vec![];
+        for (i, (_, t)) in params.iter().enumerate() {
+            let expr = exprs[i].or_else(|| defaults.and_then(|d| d[i].1.as_deref()));
+            let (v, vt) = match expr {
+                Some(expr) => self.value(expr)?,
+                None => (self.context.f64_type().const_float(f64::NAN).into(), Type::Na)
+            };
+            values.push(self.cast(v, &vt, t)?.into());
+        }
+        Ok(values)
+    }
+
+    fn call(&mut self, name: &str, args: &'a CallArguments) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let defaults = match self.functions.get(name) {
+            Some(f) => f.params,
+            None => return self.builtin_call(name, args)
+        };
+        let signature = self.checker.function(name).cloned().unwrap();
+        let mut args = self.arguments(name, &signature.params, args, Some(defaults))?;
+        args.insert(0, self.state().into());
+        let function = self.function(name)?;
+        let call = self.builder.build_call(function, &args, "call")?;
+        Ok(call.try_as_basic_value().left().map(|v| (v, signature.returns)))
+    }
+
+    /// Declares a builtin, tuple results are written through a trailing pointer.
+    fn builtin(&self, name: &str, params: &[(String, Type)], returns: &Type) -> Result<FunctionValue<'ctx>, CodegenError> {
+        if let Some(f) = self.module.get_function(name) {
+            return Ok(f);
+        }
+        let mut param_types: Vec<BasicMetadataTypeEnum> = vec![self.context.i32_type().into()];
+        for (_, t) in params {
+            param_types.push(self.llvm_type(t)?.into());
+        }
+        let fn_type = match returns {
+            Type::Void => self.context.void_type().fn_type(&param_types, false),
+            Type::Tuple(_) => {
+                param_types.push(self.context.ptr_type(AddressSpace::default()).into());
+                self.context.void_type().fn_type(&param_types, false)
+            },
+            t => self.llvm_type(t)?.fn_type(&param_types, false)
+        };
+        Ok(self.module.add_function(name, fn_type, None))
+    }
+
+    fn call_builtin(&mut self, function: FunctionValue<'ctx>, mut values: Vec<BasicMetadataValueEnum<'ctx>>, returns: Type) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let callsite = self.callsites;
+        self.callsites += 1;
+        values.insert(0, self.context.i32_type().const_int(callsite as u64, false).into());
+
+        if let Type::Tuple(_) = returns {
+            let llvm_type = self.llvm_type(&returns)?;
+            let result = self.alloca("result", llvm_type)?;
+            values.push(result.into());
+            self.builder.build_call(function, &values, "")?;
+            return Ok(Some((self.builder.build_load(llvm_type, result, "result")?, returns)));
+        }
+        let call = self.builder.build_call(function, &values, "call")?;
+        Ok(call.try_as_basic_value().left().map(|v| (v, returns)))
+    }
+
+    fn builtin_call(&mut self, name: &str, args: &'a CallArguments) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let signature = builtins::function(name)
+            .ok_or_else(|| CodegenError::Type(TypeError::UnknownFunction(name.to_string())))?;
+        let function = self.builtin(name, &signature.params, &signature.returns)?;
+        let values = self.arguments(name, &signature.params, args, None)?;
+        self.call_builtin(function, values, signature.returns)
+    }
+
+    /// Builtin constants (`strategy.long`) and variables read from the host (`strategy.position_size`).
+    fn property(&mut self, object: &str, property: &str) -> Result<Option<Value<'ctx>>, CodegenError> {
+        let path = format!("{}.{}", object, property);
+        if let Some(c) = builtins::constant(&path) {
+            return Ok(Some((self.constant(&c), c.get_type())));
+        }
+        match builtins::variable(&path) {
+            Some(t) => {
+                let function = self.builtin(&path, &[], &t)?;
+                self.call_builtin(function, vec![], t)
+            },
+            None => Err(CodegenError::Unsupported(path))
+        }
     }
 }
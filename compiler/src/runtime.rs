@@ -0,0 +1,918 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::{sync::OnceLock, time::Instant};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    mem,
+    time::Duration,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    calendar::{Session, Timezone},
+    declaration::ScriptMetadata,
+    drawings::{Drawing, Drawings},
+    error::CodegenError,
+    optimizer::Metrics,
+    plots::Plot,
+    timeframe::{Resampler, Timeframe},
+    types::{Type, RGBA},
+};
+
+/// The bar structure passed to `on_bar`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Bar {
+    pub time: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+/// `na` as an int, casts to float turn it into NaN.
+pub const NA_INT: i64 = i64::MIN;
+
+/// Builtins compiled loops call with a call site of their own, `LOOP_START`
+/// before the loop and `LOOP_NEXT` before every iteration. It returns false
+/// once the loop ran longer than the loop limit, see `Runtime::loop_next`.
+#[cfg(feature = "llvm")]
+pub const LOOP_START: &str = "loop.start";
+#[cfg(feature = "llvm")]
+pub const LOOP_NEXT: &str = "loop.next";
+
+/// How long a loop may run on a bar unless configured otherwise, like Pine.
+pub const LOOP_LIMIT: Duration = Duration::from_millis(500);
+
+/// Time on a monotonic clock, which WebAssembly modules import from the loader.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> Duration {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now() -> Duration {
+    #[link(wasm_import_module = "env")]
+    extern "C" {
+        /// Milliseconds, like `performance.now()`.
+        fn ninescript_now() -> f64;
+    }
+    Duration::from_secs_f64(unsafe { ninescript_now() } / 1000.0)
+}
+
+/// A trade of a live feed, see `Stream::on_tick`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub struct Tick {
+    pub time: i64,
+    pub price: f64,
+    pub volume: f64,
+}
+
+impl Tick {
+    /// A bar of the single trade.
+    pub fn bar(&self) -> Bar {
+        Bar { time: self.time, open: self.price, high: self.price, low: self.price, close: self.price, volume: self.volume }
+    }
+}
+
+/// Where the script runs within the bars, for `barstate.*`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BarState {
+    /// Running on a tick of a live feed rather than on the history.
+    pub realtime: bool,
+    /// The first execution on the bar.
+    pub new: bool,
+    /// The last execution on the bar, with its closing values.
+    pub confirmed: bool,
+    /// The bar is the last one so far.
+    pub last: bool,
+}
+
+impl BarState {
+    /// A bar of the history, which runs once on its closing values.
+    pub fn history(last: bool) -> Self {
+        Self { realtime: false, new: true, confirmed: true, last }
+    }
+}
+
+/// A top level variable of the script, readable by hosts after every bar.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Output {
+    pub name: String,
+    #[serde(rename = "type", with = "type_name")]
+    pub t: Type,
+}
+
+/// Types of outputs by name, which are all scalar.
+mod type_name {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(t: &Type, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(t)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Type, D::Error> {
+        Ok(Type::from_name(&String::deserialize(deserializer)?))
+    }
+}
+
+/// Values of one output over all bars.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Series {
+    pub name: String,
+    pub values: Vec<f64>,
+}
+
+/// What a plot drew on every bar.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlotSeries {
+    #[serde(flatten)]
+    pub plot: Plot,
+    /// `NaN` where nothing was drawn, 1 and 0 for the conditions of shapes.
+    pub values: Vec<f64>,
+    /// `None` for `na` colors and bars the plot was not drawn on.
+    pub colors: Vec<Option<RGBA>>,
+}
+
+/// An alert fired by `alert()` or `alertcondition()`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Alert {
+    pub bar_index: usize,
+    /// Time of the bar the alert fired on.
+    pub time: i64,
+    /// The title of `alertcondition()`, `None` for `alert()`.
+    pub title: Option<String>,
+    /// The message with its placeholders replaced, see `Runtime::alert`.
+    pub message: String,
+}
+
+/// What running a script over bars produced.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RunResult {
+    pub outputs: Vec<Series>,
+    pub plots: Vec<PlotSeries>,
+    /// Drawing objects left at the end of the run.
+    pub drawings: Vec<Drawing>,
+    /// Present for `strategy()` scripts.
+    pub strategy: Option<StrategyReport>,
+    pub alerts: Vec<Alert>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Trade {
+    pub id: String,
+    /// Positive for long trades, negative for short ones.
+    pub qty: f64,
+    pub entry_bar: usize,
+    pub entry_price: f64,
+    pub exit_bar: Option<usize>,
+    pub exit_price: Option<f64>,
+    /// Realized profit after commission, `None` while the trade is open.
+    pub profit: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StrategyReport {
+    pub trades: Vec<Trade>,
+    /// Equity at the close of every bar.
+    pub equity: Vec<f64>,
+    pub metrics: Metrics,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Order {
+    Entry { id: String, direction: i64, qty: f64 },
+    Close { id: String },
+    CloseAll,
+}
+
+/// Simulates the orders of a strategy, orders placed on a bar fill at the open of the next one.
+#[derive(Clone, Debug)]
+struct Broker {
+    initial_capital: f64,
+    default_qty: f64,
+    pyramiding: usize,
+    /// Commission in percent of the traded value.
+    commission: f64,
+    pending: Vec<Order>,
+    trades: Vec<Trade>,
+    realized: f64,
+    equity: Vec<f64>,
+}
+
+impl Broker {
+    fn new(metadata: &ScriptMetadata) -> Self {
+        Self {
+            initial_capital: metadata.initial_capital,
+            default_qty: metadata.default_qty_value,
+            pyramiding: metadata.pyramiding.max(0) as usize,
+            commission: metadata.commission,
+            pending: vec![],
+            trades: vec![],
+            realized: 0.0,
+            equity: vec![],
+        }
+    }
+
+    fn open_trades(&self) -> impl Iterator<Item = &Trade> {
+        self.trades.iter().filter(|t| t.exit_bar.is_none())
+    }
+
+    fn position_size(&self) -> f64 {
+        /* Not `sum()`, which is -0.0 without open trades */
+        self.open_trades().fold(0.0, |size, t| size + t.qty)
+    }
+
+    fn exit(&mut self, index: usize, bar: usize, price: f64) {
+        let commission = self.commission / 100.0;
+        let trade = &mut self.trades[index];
+        let fees = (trade.entry_price + price) * trade.qty.abs() * commission;
+        let profit = (price - trade.entry_price) * trade.qty - fees;
+        trade.exit_bar = Some(bar);
+        trade.exit_price = Some(price);
+        trade.profit = Some(profit);
+        self.realized += profit;
+    }
+
+    fn close(&mut self, bar: usize, price: f64, id: Option<&str>) {
+        for i in 0..self.trades.len() {
+            let trade = &self.trades[i];
+            if trade.exit_bar.is_none() && id.is_none_or(|id| trade.id == id) {
+                self.exit(i, bar, price);
+            }
+        }
+    }
+
+    fn fill(&mut self, bar: usize, price: f64) {
+        for order in std::mem::take(&mut self.pending) {
+            match order {
+                Order::Entry { id, direction, qty } => {
+                    let position = self.position_size();
+                    if position * (direction as f64) < 0.0 {
+                        self.close(bar, price, None);
+                    } else if self.open_trades().count() >= self.pyramiding.max(1) {
+                        continue;
+                    }
+                    let qty = if qty.is_nan() { self.default_qty } else { qty };
+                    self.trades.push(Trade {
+                        id,
+                        qty: qty * direction.signum() as f64,
+                        entry_bar: bar,
+                        entry_price: price,
+                        exit_bar: None,
+                        exit_price: None,
+                        profit: None,
+                    });
+                },
+                Order::Close { id } => self.close(bar, price, Some(&id)),
+                Order::CloseAll => self.close(bar, price, None),
+            }
+        }
+    }
+
+    fn mark(&mut self, close: f64) {
+        let open = self.open_trades().map(|t| (close - t.entry_price) * t.qty).sum::<f64>();
+        self.equity.push(self.initial_capital + self.realized + open);
+    }
+
+    fn report(self) -> StrategyReport {
+        StrategyReport {
+            metrics: Metrics::from_equity(&self.equity),
+            trades: self.trades,
+            equity: self.equity,
+        }
+    }
+}
+
+/// Running state of one stateful builtin call.
+#[derive(Clone, Debug, Default)]
+struct Slot {
+    window: VecDeque<f64>,
+    value: f64,
+    count: usize,
+    /// Arguments of the previous call.
+    previous: Option<(f64, f64)>,
+}
+
+impl Slot {
+    /// Keeps the last `length` values, `None` until there are that many.
+    fn window(&mut self, x: f64, length: i64) -> Option<&VecDeque<f64>> {
+        let length = length.max(1) as usize;
+        self.window.push_back(x);
+        while self.window.len() > length {
+            self.window.pop_front();
+        }
+        (self.window.len() == length).then_some(&self.window)
+    }
+
+    /// Exponential smoothing seeded with the simple average of the first `length` values.
+    fn smooth(&mut self, x: f64, length: i64, alpha: f64) -> f64 {
+        if x.is_nan() {
+            return if self.count >= length as usize { self.value } else { f64::NAN };
+        }
+        self.count += 1;
+        if self.count < length as usize {
+            self.value += x;
+            f64::NAN
+        } else if self.count == length as usize {
+            self.value = (self.value + x) / length as f64;
+            self.value
+        } else {
+            self.value = alpha * x + (1.0 - alpha) * self.value;
+            self.value
+        }
+    }
+
+    fn ema(&mut self, x: f64, length: i64) -> f64 {
+        self.smooth(x, length.max(1), 2.0 / (length.max(1) as f64 + 1.0))
+    }
+
+    fn rma(&mut self, x: f64, length: i64) -> f64 {
+        self.smooth(x, length.max(1), 1.0 / length.max(1) as f64)
+    }
+
+    fn sma(&mut self, x: f64, length: i64) -> f64 {
+        self.window(x, length).map_or(f64::NAN, |w| w.iter().sum::<f64>() / w.len() as f64)
+    }
+
+    fn stdev(&mut self, x: f64, length: i64) -> f64 {
+        self.window(x, length).map_or(f64::NAN, |w| {
+            let mean = w.iter().sum::<f64>() / w.len() as f64;
+            (w.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / w.len() as f64).sqrt()
+        })
+    }
+
+    /// The value passed on the previous call, then remembers `x`.
+    fn shift(&mut self, x: f64) -> f64 {
+        let previous = self.previous.map_or(f64::NAN, |p| p.0);
+        self.previous = Some((x, f64::NAN));
+        previous
+    }
+}
+
+/// Implementations of the builtins, shared by the execution backends.
+///
+/// Stateful builtins (`ta.*`) keep their state per call site, the id compiled
+/// code passes as first argument.
+#[derive(Clone, Debug)]
+pub struct Runtime {
+    bar: Bar,
+    barstate: BarState,
+    previous: Option<Bar>,
+    bar_index: usize,
+    sites: HashMap<u32, Vec<Slot>>,
+    broker: Option<Broker>,
+    plots: Vec<PlotSeries>,
+    /// Value and packed color of every plot on the current bar.
+    drawn: Vec<(f64, u32)>,
+    drawings: Drawings,
+    /// What `request.security()` returns on every bar, by request.
+    requested: Vec<Vec<f64>>,
+    /// Values of the requested expressions on every bar, while running over a feed.
+    recorded: Option<Vec<Vec<f64>>>,
+    /// Timeframe of the bars, for `timeframe.*`.
+    timeframe: Option<Timeframe>,
+    /// Exchange timezone and regular trading hours of the bars.
+    timezone: Timezone,
+    session: Session,
+    symbol: String,
+    /// Title and message of the alerts of the current bar, sent once its plots are known.
+    pending: Vec<(Option<String>, String)>,
+    /// Call sites of `alert()` which fired on the current bar.
+    fired: HashSet<u32>,
+    alerts: Vec<Alert>,
+    loop_limit: Duration,
+    /// When the loops running at a call site started and their iterations so far.
+    loops: HashMap<u32, (Duration, u32)>,
+    /// Decimal places of the outputs and plots, see `ScriptMetadata::precision`.
+    precision: Option<i64>,
+}
+
+impl Runtime {
+    pub fn new(metadata: &ScriptMetadata) -> Self {
+        Self {
+            bar: Bar::default(),
+            barstate: BarState::default(),
+            previous: None,
+            bar_index: 0,
+            sites: HashMap::new(),
+            broker: metadata.is_strategy().then(|| Broker::new(metadata)),
+            plots: vec![],
+            drawn: vec![],
+            drawings: Drawings::new(metadata),
+            requested: vec![],
+            recorded: None,
+            timeframe: None,
+            timezone: Timezone::utc(),
+            session: Session::default(),
+            symbol: String::new(),
+            pending: vec![],
+            fired: HashSet::new(),
+            alerts: vec![],
+            loop_limit: LOOP_LIMIT,
+            loops: HashMap::new(),
+            precision: metadata.precision,
+        }
+    }
+
+    pub fn with_plots(mut self, plots: &[Plot]) -> Self {
+        self.plots = plots.iter()
+            .map(|plot| PlotSeries { plot: plot.clone(), values: vec![], colors: vec![] })
+            .collect();
+        self
+    }
+
+    /// The timeframe of the bars, `timeframe.*` are `na` or false if it cannot be parsed.
+    pub fn with_timeframe(mut self, timeframe: &str) -> Self {
+        self.timeframe = Timeframe::parse(timeframe).ok();
+        self
+    }
+
+    /// The symbol of the bars, for the `{{ticker}}` placeholder of alerts.
+    pub fn with_symbol(mut self, symbol: &str) -> Self {
+        self.symbol = symbol.to_string();
+        self
+    }
+
+    /// The exchange timezone and regular trading hours of the bars, for `time()`, `hour` and `session.*`.
+    pub fn with_calendar(mut self, timezone: Timezone, session: Session) -> Self {
+        self.timezone = timezone;
+        self.session = session;
+        self
+    }
+
+    /// Serves `request.security()` with values aligned to the bars of the run, see `security::prefetch`.
+    pub fn with_requested(mut self, requested: Vec<Vec<f64>>) -> Self {
+        self.requested = requested;
+        self
+    }
+
+    pub fn with_loop_limit(mut self, limit: Duration) -> Self {
+        self.loop_limit = limit;
+        self
+    }
+
+    /// Records the expressions of `requests` requests on every bar instead, for runs over feeds.
+    pub fn recording(mut self, requests: usize) -> Self {
+        self.recorded = Some(vec![vec![]; requests]);
+        self
+    }
+
+    /// The values recorded for each request, see `recording`.
+    pub fn into_recorded(self) -> Vec<Vec<f64>> {
+        self.recorded.unwrap_or_default()
+    }
+
+    /// Starts a bar, filling the orders of the previous one at its open.
+    pub fn start_bar(&mut self, bar: &Bar, barstate: BarState) {
+        self.bar = *bar;
+        self.barstate = barstate;
+        if barstate.new {
+            self.fired.clear();
+        }
+        for series in self.recorded.iter_mut().flatten() {
+            series.push(f64::NAN);
+        }
+        self.drawn = vec![(f64::NAN, 0); self.plots.len()];
+        if let Some(broker) = &mut self.broker {
+            broker.fill(self.bar_index, bar.open);
+        }
+    }
+
+    pub fn end_bar(&mut self) {
+        if let Some(broker) = &mut self.broker {
+            broker.mark(self.bar.close);
+        }
+        for (title, message) in mem::take(&mut self.pending) {
+            let message = self.placeholders(&message);
+            self.alerts.push(Alert { bar_index: self.bar_index, time: self.bar.time, title, message });
+        }
+        for (series, (value, color)) in self.plots.iter_mut().zip(&self.drawn) {
+            series.values.push(*value);
+            series.colors.push((*color != 0).then(|| RGBA::from_packed(*color)));
+        }
+        self.previous = Some(self.bar);
+        self.bar_index += 1;
+    }
+
+    /// Ends the run, adding the plots and strategy report to the outputs of the backend
+    /// and rounding both to the declared precision.
+    pub fn into_result(mut self, mut outputs: Vec<Series>) -> RunResult {
+        if let Some(precision) = self.precision {
+            let scale = 10f64.powi(precision as i32);
+            let values = outputs.iter_mut().map(|o| &mut o.values).chain(self.plots.iter_mut().map(|p| &mut p.values));
+            for value in values.flatten() {
+                *value = (*value * scale).round() / scale;
+            }
+        }
+        RunResult {
+            outputs,
+            plots: self.plots,
+            drawings: self.drawings.into_output(),
+            strategy: self.broker.map(Broker::report),
+            alerts: self.alerts,
+        }
+    }
+
+    /// A copy to `restore` the start of the bar from. The drawings are left
+    /// out, they record their own changes from here.
+    pub fn save(&mut self) -> Runtime {
+        let drawings = mem::take(&mut self.drawings);
+        let saved = self.clone();
+        self.drawings = drawings;
+        self.drawings.save();
+        saved
+    }
+
+    /// Goes back to `saved`, from `save` at the start of the bar, keeping the
+    /// alerts which fired since.
+    pub fn restore(&mut self, saved: &Runtime) {
+        let (fired, alerts, drawings) = (mem::take(&mut self.fired), mem::take(&mut self.alerts), mem::take(&mut self.drawings));
+        *self = saved.clone();
+        self.fired = fired;
+        self.alerts = alerts;
+        self.drawings = drawings;
+        self.drawings.restore();
+    }
+
+    pub fn loop_start(&mut self, site: u32) {
+        self.loops.insert(site, (now(), 0));
+    }
+
+    /// Fails once the loop at `site` ran longer than the loop limit, the clock
+    /// is read every 1024 iterations.
+    pub fn loop_next(&mut self, site: u32) -> Result<(), CodegenError> {
+        let Some((start, iterations)) = self.loops.get_mut(&site) else { return Ok(()) };
+        *iterations = iterations.wrapping_add(1);
+        if *iterations % 1024 == 0 && now().saturating_sub(*start) > self.loop_limit {
+            return Err(CodegenError::LoopLimit(self.loop_limit));
+        }
+        Ok(())
+    }
+
+    /// Alerts fired so far.
+    pub fn alerts(&self) -> &[Alert] {
+        &self.alerts
+    }
+
+    /// `barstate.*` of the current bar.
+    pub fn barstate(&self) -> BarState {
+        self.barstate
+    }
+
+    /// Objects created by the drawing builtins.
+    pub fn drawings(&mut self) -> &mut Drawings {
+        &mut self.drawings
+    }
+
+    fn slots(&mut self, site: u32, count: usize) -> &mut [Slot] {
+        let slots = self.sites.entry(site).or_default();
+        slots.resize_with(count, Slot::default);
+        slots
+    }
+
+    fn true_range(&self) -> f64 {
+        let bar = &self.bar;
+        match &self.previous {
+            Some(p) => (bar.high - bar.low).max((bar.high - p.close).abs()).max((bar.low - p.close).abs()),
+            None => bar.high - bar.low
+        }
+    }
+
+    pub fn na(x: f64) -> bool {
+        x.is_nan()
+    }
+
+    pub fn nz(source: f64, replacement: f64) -> f64 {
+        match (source.is_nan(), replacement.is_nan()) {
+            (false, _) => source,
+            (true, false) => replacement,
+            (true, true) => 0.0
+        }
+    }
+
+    /// `color.new`, colors are packed `0xRRGGBBAA` with 0 for `na`.
+    pub fn color_new(color: u32, transp: f64) -> u32 {
+        if color == 0 {
+            return 0;
+        }
+        RGBA::from_packed(color).with_transparency(Runtime::nz(transp, 0.0)).packed()
+    }
+
+    /// `color.rgb`, channels are clamped to 0-255.
+    pub fn color_rgb(red: f64, green: f64, blue: f64, transp: f64) -> u32 {
+        if red.is_nan() || green.is_nan() || blue.is_nan() {
+            return 0;
+        }
+        let channel = |c: f64| c.clamp(0.0, 255.0).round() as u8;
+        RGBA::from_transparency(channel(red), channel(green), channel(blue), Runtime::nz(transp, 0.0)).packed()
+    }
+
+    /// `color.r`, `color.g`, `color.b` and `color.t` by index, `NaN` for `na`.
+    pub fn color_component(color: u32, component: usize) -> f64 {
+        if color == 0 {
+            return f64::NAN;
+        }
+        let color = RGBA::from_packed(color);
+        [color.0 as f64, color.1 as f64, color.2 as f64, color.transparency()][component]
+    }
+
+    /// `color.from_gradient`, `na` if the value or a bound is.
+    pub fn color_from_gradient(value: f64, bottom_value: f64, top_value: f64, bottom_color: u32, top_color: u32) -> u32 {
+        if value.is_nan() || bottom_value.is_nan() || top_value.is_nan() || bottom_color == 0 || top_color == 0 {
+            return 0;
+        }
+        let position = if top_value == bottom_value { 1.0 } else { (value - bottom_value) / (top_value - bottom_value) };
+        RGBA::from_packed(bottom_color).mix(&RGBA::from_packed(top_color), position).packed()
+    }
+
+    pub fn sma(&mut self, site: u32, source: f64, length: i64) -> f64 {
+        self.slots(site, 1)[0].sma(source, length)
+    }
+
+    pub fn ema(&mut self, site: u32, source: f64, length: i64) -> f64 {
+        self.slots(site, 1)[0].ema(source, length)
+    }
+
+    pub fn rma(&mut self, site: u32, source: f64, length: i64) -> f64 {
+        self.slots(site, 1)[0].rma(source, length)
+    }
+
+    pub fn wma(&mut self, site: u32, source: f64, length: i64) -> f64 {
+        self.slots(site, 1)[0].window(source, length).map_or(f64::NAN, |w| {
+            let weights = (w.len() * (w.len() + 1) / 2) as f64;
+            w.iter().enumerate().map(|(i, v)| v * (i + 1) as f64).sum::<f64>() / weights
+        })
+    }
+
+    pub fn stdev(&mut self, site: u32, source: f64, length: i64) -> f64 {
+        self.slots(site, 1)[0].stdev(source, length)
+    }
+
+    pub fn rsi(&mut self, site: u32, source: f64, length: i64) -> f64 {
+        let slots = self.slots(site, 3);
+        let change = source - slots[0].shift(source);
+        if change.is_nan() {
+            return f64::NAN;
+        }
+        let up = slots[1].rma(change.max(0.0), length);
+        let down = slots[2].rma((-change).max(0.0), length);
+        if down == 0.0 {
+            100.0
+        } else if up == 0.0 {
+            0.0
+        } else {
+            100.0 - 100.0 / (1.0 + up / down)
+        }
+    }
+
+    pub fn atr(&mut self, site: u32, length: i64) -> f64 {
+        let tr = self.true_range();
+        self.slots(site, 1)[0].rma(tr, length)
+    }
+
+    fn cross(&mut self, site: u32, a: f64, b: f64) -> Option<(f64, f64)> {
+        let slot = &mut self.slots(site, 1)[0];
+        slot.previous.replace((a, b))
+    }
+
+    pub fn crossover(&mut self, site: u32, source1: f64, source2: f64) -> bool {
+        self.cross(site, source1, source2).is_some_and(|(a, b)| a <= b && source1 > source2)
+    }
+
+    pub fn crossunder(&mut self, site: u32, source1: f64, source2: f64) -> bool {
+        self.cross(site, source1, source2).is_some_and(|(a, b)| a >= b && source1 < source2)
+    }
+
+    /// `[macd, signal, histogram]`
+    pub fn macd(&mut self, site: u32, source: f64, fastlen: i64, slowlen: i64, siglen: i64) -> [f64; 3] {
+        let slots = self.slots(site, 3);
+        let macd = slots[0].ema(source, fastlen) - slots[1].ema(source, slowlen);
+        let signal = slots[2].ema(macd, siglen);
+        [macd, signal, macd - signal]
+    }
+
+    /// `[basis, upper, lower]`
+    pub fn bb(&mut self, site: u32, series: f64, length: i64, mult: f64) -> [f64; 3] {
+        let slots = self.slots(site, 2);
+        let basis = slots[0].sma(series, length);
+        let deviation = mult * slots[1].stdev(series, length);
+        [basis, basis + deviation, basis - deviation]
+    }
+
+    /// `[basis, upper, lower]`
+    pub fn kc(&mut self, site: u32, series: f64, length: i64, mult: f64) -> [f64; 3] {
+        let tr = self.true_range();
+        let slots = self.slots(site, 2);
+        let basis = slots[0].ema(series, length);
+        let range = slots[1].ema(tr, length) * mult;
+        [basis, basis + range, basis - range]
+    }
+
+    /// `[plus, minus, adx]`
+    pub fn dmi(&mut self, site: u32, di_length: i64, adx_smoothing: i64) -> [f64; 3] {
+        let tr = self.true_range();
+        let (bar, previous) = (self.bar, self.previous);
+        let (plus_dm, minus_dm) = match previous {
+            Some(p) => {
+                let (up, down) = (bar.high - p.high, p.low - bar.low);
+                (if up > down && up > 0.0 { up } else { 0.0 }, if down > up && down > 0.0 { down } else { 0.0 })
+            },
+            None => (f64::NAN, f64::NAN)
+        };
+        let slots = self.slots(site, 4);
+        let range = slots[0].rma(tr, di_length);
+        let plus = 100.0 * slots[1].rma(plus_dm, di_length) / range;
+        let minus = 100.0 * slots[2].rma(minus_dm, di_length) / range;
+        let sum = plus + minus;
+        let adx = 100.0 * slots[3].rma((plus - minus).abs() / if sum == 0.0 { 1.0 } else { sum }, adx_smoothing);
+        [plus, minus, adx]
+    }
+
+    fn order(&mut self, order: Order) {
+        if let Some(broker) = &mut self.broker {
+            broker.pending.push(order);
+        }
+    }
+
+    /// `direction` is `strategy.long` (1) or `strategy.short` (-1), a `na` qty uses `default_qty_value`.
+    pub fn entry(&mut self, id: &str, direction: i64, qty: f64) {
+        self.order(Order::Entry { id: id.to_string(), direction, qty });
+    }
+
+    pub fn close(&mut self, id: &str) {
+        self.order(Order::Close { id: id.to_string() });
+    }
+
+    pub fn close_all(&mut self) {
+        self.order(Order::CloseAll);
+    }
+
+    pub fn position_size(&self) -> f64 {
+        self.broker.as_ref().map_or(0.0, Broker::position_size)
+    }
+
+    /// `request.security()` with index `request` evaluating to `value` on the
+    /// current bar, see `security::REQUEST`. Requests made while recording get
+    /// their own value back.
+    pub fn security(&mut self, request: u32, value: f64) -> f64 {
+        match &mut self.recorded {
+            Some(recorded) => {
+                if let Some(last) = recorded.get_mut(request as usize).and_then(|s| s.last_mut()) {
+                    *last = value;
+                }
+                value
+            },
+            None => self.requested.get(request as usize).and_then(|s| s.get(self.bar_index)).copied().unwrap_or(f64::NAN)
+        }
+    }
+
+    /// The timeframe of the bars, for `timeframe.*` variables.
+    pub fn timeframe(&self) -> Option<&Timeframe> {
+        self.timeframe.as_ref()
+    }
+
+    /// `timeframe.in_seconds()`, of the timeframe of the bars for an empty string.
+    pub fn in_seconds(&self, timeframe: &str) -> i64 {
+        let timeframe = match timeframe {
+            "" => self.timeframe,
+            s => Timeframe::parse(s).ok()
+        };
+        timeframe.and_then(|t| t.in_seconds()).unwrap_or(0)
+    }
+
+    /// The timezone named by a string, the exchange's for an empty one.
+    fn zone(&self, timezone: &str) -> Option<Timezone> {
+        match timezone {
+            "" => Some(self.timezone),
+            s => Timezone::parse(s).ok()
+        }
+    }
+
+    /// `hour(time, timezone)` and the other parts of a date in `calendar::FIELDS`,
+    /// `na` for an invalid timezone or time.
+    pub fn calendar(&self, field: &str, time: i64, timezone: &str) -> i64 {
+        if time == NA_INT {
+            return NA_INT;
+        }
+        self.zone(timezone).and_then(|zone| zone.datetime(time).field(field)).unwrap_or(NA_INT)
+    }
+
+    /// `time(timeframe, session, timezone)`, the start of the bar of `timeframe`
+    /// containing the current bar, `na` outside of the session given.
+    pub fn time(&self, timeframe: &str, session: &str, timezone: &str) -> i64 {
+        let time = self.bar.time;
+        let outside = match (session, self.zone(timezone)) {
+            ("", Some(_)) => false,
+            (session, Some(zone)) => Session::parse(session).map_or(true, |s| !s.contains(time, &zone)),
+            (_, None) => true,
+        };
+        let timeframe = match timeframe {
+            "" => self.timeframe,
+            s => match Timeframe::parse(s) {
+                Ok(timeframe) => Some(timeframe),
+                Err(_) => return NA_INT
+            }
+        };
+        if outside {
+            return NA_INT;
+        }
+        let (hour, minute) = self.session.open();
+        timeframe
+            .and_then(|t| Resampler::new(t).with_timezone(self.timezone).with_session_start(hour, minute).start(time))
+            .unwrap_or(time)
+    }
+
+    /// `session.ismarket`, whether the current bar is in regular trading hours.
+    pub fn is_market(&self) -> bool {
+        self.session.contains(self.bar.time, &self.timezone)
+    }
+
+    /// `session.ispremarket`, whether the current bar is before regular trading hours.
+    pub fn is_premarket(&self) -> bool {
+        self.session.is_before(self.bar.time, &self.timezone)
+    }
+
+    /// `session.ispostmarket`, whether the current bar is after regular trading hours.
+    pub fn is_postmarket(&self) -> bool {
+        self.session.is_after(self.bar.time, &self.timezone)
+    }
+
+    /// `alert(message, freq)` at call site `site`, `freq` is a constant of
+    /// `alert.freq_*` without prefix. Alerts fire once per bar by default,
+    /// on every call for `freq_all` and on the close of the bar for
+    /// `freq_once_per_bar_close`. Runs over the feeds of `request.security()`
+    /// do not fire alerts.
+    ///
+    /// Messages can hold the placeholders `{{open}}`, `{{high}}`, `{{low}}`,
+    /// `{{close}}`, `{{volume}}`, `{{time}}`, `{{ticker}}`, `{{interval}}` and
+    /// `{{plot_0}}` or `{{plot("title")}}` for the values of plots.
+    pub fn alert(&mut self, site: u32, message: &str, freq: &str) {
+        let fire = match freq {
+            "freq_all" => true,
+            "freq_once_per_bar_close" => self.barstate.confirmed,
+            _ => self.fired.insert(site),
+        };
+        if fire && self.recorded.is_none() {
+            self.pending.push((None, message.to_string()));
+        }
+    }
+
+    /// `alertcondition(condition, title, message)`, fires on the close of the
+    /// bars where `condition` holds, with placeholders like `alert`.
+    pub fn alertcondition(&mut self, condition: bool, title: &str, message: &str) {
+        if condition && self.barstate.confirmed && self.recorded.is_none() {
+            self.pending.push((Some(title.to_string()), message.to_string()));
+        }
+    }
+
+    /// Replaces the placeholders of an alert message, unknown ones are left as they are.
+    fn placeholders(&self, message: &str) -> String {
+        let mut text = String::new();
+        let mut rest = message;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}").map(|end| start + end) else { break };
+            text.push_str(&rest[..start]);
+            match self.placeholder(rest[start + 2..end].trim()) {
+                Some(value) => text.push_str(&value),
+                None => text.push_str(&rest[start..end + 2]),
+            }
+            rest = &rest[end + 2..];
+        }
+        text + rest
+    }
+
+    fn placeholder(&self, name: &str) -> Option<String> {
+        let bar = &self.bar;
+        let plot = |i: usize| self.drawn.get(i).map(|(value, _)| value.to_string());
+        Some(match name {
+            "open" => bar.open.to_string(),
+            "high" => bar.high.to_string(),
+            "low" => bar.low.to_string(),
+            "close" => bar.close.to_string(),
+            "volume" => bar.volume.to_string(),
+            "ticker" => self.symbol.clone(),
+            "interval" => self.timeframe.map(|t| t.to_string()).unwrap_or_default(),
+            "time" => {
+                let t = Timezone::utc().datetime(bar.time);
+                format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", t.year, t.month, t.day, t.hour, t.minute, t.second)
+            },
+            _ => match name.strip_prefix("plot_") {
+                Some(index) => plot(index.parse().ok()?)?,
+                None => {
+                    let title = name.strip_prefix("plot(")?.strip_suffix(')')?.trim().trim_matches('"');
+                    plot(self.plots.iter().position(|s| s.plot.title.as_deref() == Some(title))?)?
+                }
+            }
+        })
+    }
+
+    /// What the plot with index `plot` draws on the current bar, see `plots::RECORD`.
+    pub fn plot(&mut self, plot: u32, value: f64, color: u32) {
+        if let Some(drawn) = self.drawn.get_mut(plot as usize) {
+            *drawn = (value, color);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{ast::{Expr, Name, Statement, VarParam}, builtins, error::{NameError, Warning}};
+
+/// What a name refers to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symbol {
+    Global,
+    Local,
+    Parameter,
+    Builtin,
+}
+
+/// Resolves the names of a script through nested scopes.
+///
+/// Function bodies, loop bodies and every branch of an `if` or `switch` get a
+/// scope of their own. Names have to be declared before they are used, a
+/// declaration hiding one of an outer scope or a builtin variable is reported
+/// as a warning. Errors and warnings point at the name where it was parsed.
+pub struct NameResolver {
+    scopes: Vec<HashMap<String, Symbol>>,
+    functions: HashSet<String>,
+    /// User defined types and enums.
+    types: HashSet<String>,
+    /// Symbols of identifiers and indexed names, keyed by the address of their expression.
+    symbols: HashMap<usize, Symbol>,
+    warnings: Vec<Warning>,
+}
+
+impl Default for NameResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NameResolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            functions: HashSet::new(),
+            types: HashSet::new(),
+            symbols: HashMap::new(),
+            warnings: vec![],
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Statement]) -> Result<(), NameError> {
+        statements.iter().try_for_each(|s| self.statement(s))
+    }
+
+    /// What an identifier, indexed name or property owner refers to, available after `resolve`.
+    #[cfg(test)]
+    pub fn symbol(&self, expr: &Expr) -> Option<Symbol> {
+        self.symbols.get(&(expr as *const Expr as usize)).copied()
+    }
+
+    /// Shadowing declarations found by `resolve`.
+    pub fn into_warnings(self) -> Vec<Warning> {
+        self.warnings
+    }
+
+    fn lookup(&self, name: &str) -> Option<Symbol> {
+        self.scopes.iter().rev().find_map(|s| s.get(name)).copied()
+            .or_else(|| builtins::variable(name).map(|_| Symbol::Builtin))
+    }
+
+    fn declare(&mut self, name: &Name, symbol: Symbol) -> Result<(), NameError> {
+        if self.scopes.last().unwrap().contains_key(name.as_str()) {
+            return Err(NameError::Redeclaration(name.to_string(), name.location()));
+        }
+        /* Parameters start the scope of a function, they hide globals on purpose */
+        if symbol != Symbol::Parameter && self.lookup(name).is_some() {
+            self.warnings.push(Warning::Shadowing(name.to_string(), name.location()));
+        }
+        self.scopes.last_mut().unwrap().insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    /// Symbol of a variable declared in the current scope.
+    fn variable(&self) -> Symbol {
+        if self.scopes.len() == 1 { Symbol::Global } else { Symbol::Local }
+    }
+
+    fn reference(&mut self, expr: &Expr, name: &Name) -> Result<(), NameError> {
+        let symbol = self.lookup(name).ok_or_else(|| NameError::Undefined(name.to_string(), name.location()))?;
+        self.symbols.insert(expr as *const Expr as usize, symbol);
+        Ok(())
+    }
+
+    /// Resolves a block in its own scope, starting with `locals`.
+    fn block(&mut self, statements: &[Statement], locals: Vec<(&Name, Symbol)>) -> Result<(), NameError> {
+        self.scopes.push(HashMap::new());
+        let result = locals.into_iter().try_for_each(|(name, symbol)| self.declare(name, symbol))
+            .and_then(|_| self.resolve(statements));
+        self.scopes.pop();
+        result
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), NameError> {
+        match statement {
+            Statement::Import(..) => {},
+            Statement::UnpackTuple(names, value) => {
+                self.expr(value)?;
+                for name in names {
+                    self.declare(name, self.variable())?;
+                }
+            },
+            Statement::ConstDef(var, value)
+            | Statement::SeriesDef(var, value)
+            | Statement::VarIpDef(var, value)
+            | Statement::VarDef(var, value)
+            | Statement::VarLet(var, value) => {
+                self.expr(value)?;
+                self.declare(&var.1, self.variable())?;
+            },
+            Statement::VarAssign(name, value) => {
+                match self.lookup(name) {
+                    None => return Err(NameError::UndeclaredAssignment(name.to_string(), name.location())),
+                    Some(Symbol::Builtin) => return Err(NameError::BuiltinAssignment(name.to_string(), name.location())),
+                    Some(_) => {}
+                }
+                self.expr(value)?;
+            },
+            Statement::TypeDef(name, _) | Statement::EnumDef(name, _) => {
+                self.types.insert(name.clone());
+            },
+            Statement::ForTo(var, start, end, body, by) => {
+                for bound in [Some(start), Some(end), by.as_ref()].into_iter().flatten() {
+                    self.expr(bound)?;
+                }
+                self.block(body, vec![(&var.1, Symbol::Local)])?;
+            },
+            Statement::ForIn(var, object, body) => {
+                self.expr(object)?;
+                self.block(body, vec![(&var.1, Symbol::Local)])?;
+            },
+            Statement::While(condition, body) => {
+                self.expr(condition)?;
+                self.block(body, vec![])?;
+            },
+            Statement::FnDef(name, params, body) => {
+                for default in params.iter().filter_map(|p| p.1.as_ref()) {
+                    self.expr(default)?;
+                }
+                let params = params.iter().map(|VarParam(var, _)| (&var.1, Symbol::Parameter)).collect();
+                self.block(body, params)?;
+                self.functions.insert(name.clone());
+            },
+            Statement::Export(definition) => self.statement(definition)?,
+            Statement::Expression(e) => self.expr(e)?,
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), NameError> {
+        match expr {
+            Expr::Identifier(name) => self.reference(expr, name)?,
+            Expr::Index(name, index) => {
+                self.expr(index)?;
+                self.reference(expr, name)?;
+            },
+            Expr::String(_) | Expr::Bool(_) | Expr::Int(_) | Expr::Float(_) | Expr::HashColor(_) => {},
+            Expr::MakeTuple(items) => items.iter().try_for_each(|e| self.expr(e))?,
+            Expr::Op(a, _, b) => {
+                self.expr(a)?;
+                self.expr(b)?;
+            },
+            Expr::Not(e) | Expr::Negative(e) => self.expr(e)?,
+            Expr::If(condition, then, otherwise) => {
+                self.expr(condition)?;
+                self.block(then, vec![])?;
+                if let Some(otherwise) = otherwise {
+                    self.block(otherwise, vec![])?;
+                }
+            },
+            Expr::Switch(subject, variants) => {
+                if let Some(subject) = subject {
+                    self.expr(subject)?;
+                }
+                for (case, body) in variants {
+                    if let Some(case) = case {
+                        self.expr(case)?;
+                    }
+                    self.block(std::slice::from_ref(body), vec![])?;
+                }
+            },
+            Expr::FnCall(name, _, args) => {
+                if !self.functions.contains(name.as_str()) && builtins::function(name).is_none() {
+                    return Err(NameError::UndefinedFunction(name.to_string(), name.location()));
+                }
+                args.iter().try_for_each(|(_, e)| self.expr(e))?;
+            },
+            /* Methods are looked up by path, the object is a namespace or a type */
+            Expr::MethodCall(_, _, _, args) => args.iter().try_for_each(|(_, e)| self.expr(e))?,
+            Expr::PropertyAccess(object, property) => {
+                let builtin = builtins::variable(&format!("{}.{}", object, property)).is_some();
+                if !builtin && !self.types.contains(object.as_str()) {
+                    self.reference(expr, object)?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,592 @@
+use std::{cell::RefCell, collections::HashMap, mem, time::Duration};
+#[cfg(feature = "llvm")]
+use std::rc::Rc;
+
+#[cfg(feature = "llvm")]
+use inkwell::context::Context;
+
+#[cfg(feature = "llvm")]
+use crate::{
+    ast::Statement,
+    bindings,
+    error::JitError,
+    jit::{Jit, JitScript},
+    processor::Processor,
+    security::Request,
+    target::{self, Artifact},
+};
+#[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+use crate::abi;
+#[cfg(target_arch = "wasm32")]
+use crate::{abi::Executable, annotation};
+use crate::{
+    ast::{FunctionDoc, Program},
+    declaration::ScriptMetadata,
+    error::{CodegenError, Diagnostic, Diagnostics, InputError},
+    inputs::Input,
+    interpreter::{self, Interpreter},
+    modules::Resolver,
+    plots::Plot,
+    runtime::{Alert, Bar, BarState, Output, RunResult, Runtime, Series, Tick, LOOP_LIMIT},
+    scopes::NameResolver,
+    security::Feeds,
+    timeframe::{Resampler, Timeframe},
+    types::Constant,
+};
+
+/// Scripts without imports the JIT keeps compiled on every thread.
+#[cfg(feature = "llvm")]
+const CACHED_SCRIPTS: usize = 16;
+
+#[cfg(feature = "llvm")]
+thread_local! {
+    /// Scripts compiled on this thread with their source and loop limit, the most recently used last.
+    static CACHE: RefCell<Vec<(String, Duration, Rc<Compiled>)>> = const { RefCell::new(vec![]) };
+}
+
+#[cfg(feature = "llvm")]
+self_cell::self_cell!(
+    /// A script compiled by the JIT in a context of its own, which it keeps alive.
+    pub(crate) struct Compiled {
+        owner: Context,
+        #[covariant]
+        dependent: JitScript,
+    }
+);
+
+#[cfg(feature = "llvm")]
+impl Compiled {
+    fn compile(statements: Vec<Statement>, loop_limit: Duration) -> Result<Self, JitError> {
+        Self::try_new(Context::create(), |context| {
+            let mut jit = Jit::new(context);
+            jit.set_loop_limit(loop_limit);
+            jit.compile(statements)
+        })
+    }
+
+    /// Compiles `statements` parsed from `src`, or takes them from the cache of the thread.
+    pub(crate) fn cached(src: &str, statements: Vec<Statement>, loop_limit: Duration) -> Result<Rc<Self>, JitError> {
+        CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if let Some(i) = cache.iter().position(|(s, l, _)| s == src && *l == loop_limit) {
+                let entry = cache.remove(i);
+                cache.push(entry);
+            } else {
+                let compiled = Rc::new(Self::compile(statements, loop_limit)?);
+                if cache.len() == CACHED_SCRIPTS {
+                    cache.remove(0);
+                }
+                cache.push((src.to_string(), loop_limit, compiled));
+            }
+            Ok(cache.last().unwrap().2.clone())
+        })
+    }
+
+    fn script(&self) -> &JitScript<'_> {
+        self.borrow_dependent()
+    }
+}
+
+/// How compiled scripts are executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Machine code generated in memory, needs the `llvm` feature.
+    Jit,
+    /// Walks the syntax tree, slower but available in every build.
+    Interpreter,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        if cfg!(feature = "llvm") { Backend::Jit } else { Backend::Interpreter }
+    }
+}
+
+impl Backend {
+    /// The backends this build can run, the default first.
+    pub fn available() -> &'static [Backend] {
+        if cfg!(feature = "llvm") { &[Backend::Jit, Backend::Interpreter] } else { &[Backend::Interpreter] }
+    }
+}
+
+/// Compiles scripts with non default options, `Script::compile` uses the defaults.
+#[derive(Clone, Debug, Default)]
+pub struct Compiler {
+    backend: Backend,
+    /// Libraries are loaded once for every script compiled.
+    resolver: RefCell<Resolver>,
+    loop_limit: Option<Duration>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Resolves the imports of scripts with `resolver`, scripts without imports need none.
+    pub fn with_resolver(mut self, resolver: Resolver) -> Self {
+        self.resolver = RefCell::new(resolver);
+        self
+    }
+
+    /// Fails runs with a loop running longer than `limit` on a bar, `runtime::LOOP_LIMIT` by default.
+    pub fn with_loop_limit(mut self, limit: Duration) -> Self {
+        self.loop_limit = Some(limit);
+        self
+    }
+
+    pub fn compile(&self, src: &str) -> Result<Script, Diagnostics> {
+        let error = |e: Diagnostic| Diagnostics::error(src, e);
+        let statements = crate::parse(src).map_err(|e| error(e.into()))?;
+        let statements = self.resolver.borrow_mut().link(statements).map_err(|e| error(e.into()))?;
+        let program = Program::new(src, statements).map_err(|e| error(e.into()))?;
+        let mut names = NameResolver::new();
+        names.resolve(&program.statements).map_err(|e| error(e.into()))?;
+        let mut warnings = Diagnostics::new(src);
+        for warning in program.warnings.into_iter().chain(names.into_warnings()) {
+            warnings.push(warning);
+        }
+
+        let loop_limit = self.loop_limit.unwrap_or(LOOP_LIMIT);
+        #[cfg(feature = "llvm")]
+        let statements = program.statements.clone();
+        let engine = match self.backend {
+            #[cfg(feature = "llvm")]
+            Backend::Jit => {
+                /* Linked scripts are not cached by source */
+                let imports = program.statements.iter().any(|s| matches!(s, Statement::Import(..)));
+                let compiled = if imports {
+                    Compiled::compile(program.statements, loop_limit).map(Rc::new)
+                } else {
+                    Compiled::cached(src, program.statements, loop_limit)
+                };
+                Engine::Jit(compiled.map_err(|e| error(e.into()))?)
+            },
+            #[cfg(not(feature = "llvm"))]
+            Backend::Jit => return Err(error(Diagnostic::error("the JIT needs the llvm feature", None))),
+            Backend::Interpreter => {
+                let interpreter = Interpreter::new(program.statements).map_err(|e| error(e.into()))?;
+                Engine::Interpreter(Box::new(interpreter.with_loop_limit(loop_limit)))
+            },
+        };
+        Ok(Script {
+            engine,
+            source: src.to_string(),
+            version: program.version,
+            description: program.description,
+            functions: program.functions,
+            #[cfg(feature = "llvm")]
+            statements,
+            warnings,
+        })
+    }
+}
+
+enum Engine {
+    #[cfg(feature = "llvm")]
+    Jit(Rc<Compiled>),
+    /// Linked into a WebAssembly module with the runtime, see `wasm`.
+    #[cfg(target_arch = "wasm32")]
+    Linked(Box<Executable>),
+    Interpreter(Box<Interpreter>),
+}
+
+/// A compiled script, the entry point for hosts embedding the compiler.
+///
+/// Scripts compiled by the JIT stay on the thread that compiled them.
+pub struct Script {
+    engine: Engine,
+    /// The source runtime errors are reported against.
+    source: String,
+    version: i32,
+    description: Option<String>,
+    functions: Vec<FunctionDoc>,
+    /// The linked script, compiled ahead of time on demand.
+    #[cfg(feature = "llvm")]
+    statements: Vec<Statement>,
+    warnings: Diagnostics,
+}
+
+impl Script {
+    /// Compiles a script for the default backend, the JIT when it is available.
+    pub fn compile(src: &str) -> Result<Self, Diagnostics> {
+        Compiler::new().compile(src)
+    }
+
+    /// The script of a WebAssembly module, whose errors have no source.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn linked(executable: Executable) -> Self {
+        Script {
+            engine: Engine::Linked(Box::new(executable)),
+            source: String::new(),
+            version: annotation::VERSION,
+            description: None,
+            functions: vec![],
+            warnings: Diagnostics::new(""),
+        }
+    }
+
+    pub fn metadata(&self) -> &ScriptMetadata {
+        match &self.engine {
+            #[cfg(feature = "llvm")]
+            Engine::Jit(compiled) => compiled.script().metadata(),
+            #[cfg(target_arch = "wasm32")]
+            Engine::Linked(executable) => executable.metadata(),
+            Engine::Interpreter(interpreter) => interpreter.metadata(),
+        }
+    }
+
+    pub fn inputs(&self) -> &[Input] {
+        match &self.engine {
+            #[cfg(feature = "llvm")]
+            Engine::Jit(compiled) => compiled.script().inputs(),
+            #[cfg(target_arch = "wasm32")]
+            Engine::Linked(executable) => executable.inputs(),
+            Engine::Interpreter(interpreter) => interpreter.inputs(),
+        }
+    }
+
+    pub fn outputs(&self) -> &[Output] {
+        match &self.engine {
+            #[cfg(feature = "llvm")]
+            Engine::Jit(compiled) => compiled.script().outputs(),
+            #[cfg(target_arch = "wasm32")]
+            Engine::Linked(executable) => executable.outputs(),
+            Engine::Interpreter(interpreter) => interpreter.outputs(),
+        }
+    }
+
+    pub fn plots(&self) -> &[Plot] {
+        match &self.engine {
+            #[cfg(feature = "llvm")]
+            Engine::Jit(compiled) => compiled.script().plots(),
+            #[cfg(target_arch = "wasm32")]
+            Engine::Linked(executable) => executable.plots(),
+            Engine::Interpreter(interpreter) => interpreter.plots(),
+        }
+    }
+
+    /// The language version of its `//@version` annotation, the current one without.
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    /// Its `//@description` annotation.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// The functions documented by `//@function`, `//@param` and `//@returns` annotations.
+    pub fn functions(&self) -> &[FunctionDoc] {
+        &self.functions
+    }
+
+    /// Problems which did not stop compilation.
+    pub fn warnings(&self) -> &Diagnostics {
+        &self.warnings
+    }
+
+    /// The default values of the inputs.
+    pub fn params(&self) -> Params {
+        Params {
+            schema: self.inputs().to_vec(),
+            values: self.inputs().iter().map(|i| i.default.clone()).collect(),
+        }
+    }
+
+    /// Runs the script over `bars` from its initial state.
+    pub fn run(&self, bars: &[Bar], params: &Params) -> Result<RunResult, Diagnostics> {
+        self.run_with(bars, params, &Feeds::default())
+    }
+
+    /// Runs the script like `run`, serving `request.security()` from `feeds`.
+    pub fn run_with(&self, bars: &[Bar], params: &Params, feeds: &Feeds) -> Result<RunResult, Diagnostics> {
+        let error = |e: Diagnostic| Diagnostics::error(&self.source, e);
+        match &self.engine {
+            #[cfg(feature = "llvm")]
+            Engine::Jit(compiled) => compiled.script().run_with(bars, &params.values, feeds).map_err(|e| error(e.into())),
+            #[cfg(target_arch = "wasm32")]
+            Engine::Linked(executable) => executable.run_with(bars, &params.values, feeds).map_err(|e| error(e.into())),
+            Engine::Interpreter(interpreter) => interpreter.run_with(bars, &params.values, feeds).map_err(|e| error(e.into())),
+        }
+    }
+
+    /// Runs the script over the `history` bars like `run_with`, then on the
+    /// ticks of a live feed, see `Stream`.
+    pub fn stream(&self, history: &[Bar], params: &Params, feeds: &Feeds) -> Result<Stream<'_>, Diagnostics> {
+        let error = |e: CodegenError| Diagnostics::error(&self.source, Diagnostic::from(e));
+        /* Without a timeframe, bars only close with `on_bar_close` */
+        let timeframe = match feeds.timeframe.as_str() {
+            "" => Timeframe::Ticks(u32::MAX),
+            timeframe => Timeframe::parse(timeframe).map_err(|e| Diagnostics::error(&self.source, Diagnostic::error(e.to_string(), None)))?,
+        };
+        let run = match &self.engine {
+            #[cfg(feature = "llvm")]
+            Engine::Jit(compiled) => Run::Native(Box::new(compiled.script().start(history, &params.values, feeds).map_err(error)?)),
+            #[cfg(target_arch = "wasm32")]
+            Engine::Linked(executable) => Run::Native(Box::new(executable.start(history, &params.values, feeds).map_err(error)?)),
+            Engine::Interpreter(interpreter) => Run::Interpreter(Box::new(interpreter.start(history, &params.values, feeds).map_err(error)?)),
+        };
+        let (hour, minute) = feeds.session.open();
+        let resampler = Resampler::new(timeframe).with_timezone(feeds.timezone).with_session_start(hour, minute);
+        /* The last bar of a period still takes the ticks which fall inside it */
+        let resumed = history.last().filter(|bar| resampler.start(bar.time).is_some()).copied();
+        let mut stream = Stream {
+            run,
+            source: &self.source,
+            resampler,
+            bars: vec![],
+            outputs: self.outputs().iter().map(|o| Series { name: o.name.clone(), values: vec![] }).collect(),
+            open: false,
+            resumed: false,
+            callback: None,
+            delivered: 0,
+        };
+        for (i, bar) in history.iter().enumerate() {
+            let last = i + 1 == history.len();
+            if last && resumed.is_some() {
+                stream.run.save();
+            }
+            stream.push(bar, BarState::history(last))?;
+        }
+        if let Some(bar) = resumed {
+            stream.resampler.resume(bar);
+            stream.open = true;
+            stream.resumed = true;
+        }
+        stream.delivered = stream.run.runtime().alerts().len();
+        Ok(stream)
+    }
+}
+
+/// Ahead of time compilation, with the inputs of `params` as defaults.
+#[cfg(feature = "llvm")]
+impl Script {
+    fn processor(&self, params: &Params) -> Processor {
+        Processor::new(self.statements.clone()).with_inputs(params.values.clone())
+    }
+
+    fn requests(&self) -> &[Request] {
+        match &self.engine {
+            Engine::Jit(compiled) => compiled.script().requests(),
+            Engine::Interpreter(interpreter) => interpreter.requests(),
+        }
+    }
+
+    fn error(&self, e: CodegenError) -> Diagnostics {
+        Diagnostics::error(&self.source, Diagnostic::from(e))
+    }
+
+    /// The LLVM IR of the script.
+    pub fn ir(&self, params: &Params) -> Result<String, Diagnostics> {
+        self.processor(params).ir().map_err(|e| self.error(e))
+    }
+
+    /// A WebAssembly module, which `javascript` loads.
+    pub fn wasm(&self, params: &Params) -> Result<Vec<u8>, Diagnostics> {
+        target::wasm(&self.processor(params)).map_err(|e| self.error(e))
+    }
+
+    /// A native `artifact` for x86-64 Linux, whose exports are declared by `header`.
+    pub fn native(&self, params: &Params, artifact: Artifact, prefix: Option<&str>) -> Result<Vec<u8>, Diagnostics> {
+        target::native(&self.processor(params), target::X86_64_LINUX, artifact, prefix).map_err(|e| self.error(e))
+    }
+
+    /// The C header of `native` with the same `prefix`.
+    pub fn header(&self, params: &Params, prefix: Option<&str>) -> Result<String, Diagnostics> {
+        target::header(&self.processor(params), prefix).map_err(|e| self.error(e))
+    }
+
+    /// An ES module running the module of `wasm`.
+    pub fn javascript(&self) -> String {
+        bindings::javascript(self.metadata(), self.inputs(), self.outputs(), self.plots(), self.requests())
+    }
+
+    /// The TypeScript declarations of `javascript`.
+    pub fn typescript(&self) -> String {
+        bindings::typescript(self.metadata(), self.inputs(), self.outputs(), self.plots())
+    }
+}
+
+/// A run of either backend, one bar at a time.
+enum Run<'a> {
+    #[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+    Native(Box<abi::State<'a>>),
+    Interpreter(Box<interpreter::State<'a>>),
+}
+
+impl Run<'_> {
+    fn runtime(&self) -> &Runtime {
+        match self {
+            #[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+            Run::Native(state) => state.runtime(),
+            Run::Interpreter(state) => state.runtime(),
+        }
+    }
+
+    fn save(&mut self) {
+        match self {
+            #[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+            Run::Native(state) => state.save(),
+            Run::Interpreter(state) => state.save(),
+        }
+    }
+
+    fn restore(&mut self) {
+        match self {
+            #[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+            Run::Native(state) => state.restore(),
+            Run::Interpreter(state) => state.restore(),
+        }
+    }
+}
+
+type Callback<'a> = Box<dyn FnMut(&Alert) + 'a>;
+
+/// A script running on a live feed, made by `Script::stream`.
+///
+/// Every tick updates the last bar, which runs again from the state it
+/// started with, except for `varip` variables. Once it closes it runs a last
+/// time with `barstate.isconfirmed` and is committed. Bars close when the
+/// ticks reach the timeframe of the feeds, or with `on_bar_close`.
+/// `request.security()` is `na` on realtime bars. Alerts fired on ticks stay
+/// fired when the bar is rolled back.
+pub struct Stream<'a> {
+    run: Run<'a>,
+    source: &'a str,
+    resampler: Resampler,
+    bars: Vec<Bar>,
+    outputs: Vec<Series>,
+    /// Whether the last bar still takes ticks.
+    open: bool,
+    /// Whether the open bar is the last one of the history, which no tick changed yet.
+    resumed: bool,
+    callback: Option<Callback<'a>>,
+    /// Number of alerts passed to `callback`, or fired on the history.
+    delivered: usize,
+}
+
+impl<'a> Stream<'a> {
+    /// Calls `callback` with every alert fired from now on, those of the history are in `result`.
+    pub fn on_alert(&mut self, callback: impl FnMut(&Alert) + 'a) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Adds a trade to the last bar, closing it first if the trade starts the next one.
+    pub fn on_tick(&mut self, tick: Tick) -> Result<(), Diagnostics> {
+        if let Some(bar) = self.resampler.push(&tick.bar()) {
+            self.close(bar)?;
+        }
+        match self.resampler.current().copied() {
+            Some(bar) => self.realtime(bar, false),
+            None => Ok(())
+        }
+    }
+
+    /// Closes the last bar, the next tick starts a new one.
+    pub fn on_bar_close(&mut self) -> Result<(), Diagnostics> {
+        match self.resampler.take() {
+            Some(bar) => self.close(bar),
+            None => Ok(())
+        }
+    }
+
+    /// The history and realtime bars, the last one possibly open.
+    pub fn bars(&self) -> &[Bar] {
+        &self.bars
+    }
+
+    /// What the script produced so far, as of the last tick on an open bar.
+    pub fn result(&self) -> RunResult {
+        self.run.runtime().clone().into_result(self.outputs.clone())
+    }
+
+    /// Runs `bar` a last time as confirmed, unless it is the last bar of the
+    /// history which already ran so.
+    fn close(&mut self, bar: Bar) -> Result<(), Diagnostics> {
+        if mem::take(&mut self.resumed) {
+            self.open = false;
+            return Ok(());
+        }
+        self.realtime(bar, true)
+    }
+
+    /// Runs `bar` as the last one, again if it is open.
+    fn realtime(&mut self, bar: Bar, confirmed: bool) -> Result<(), Diagnostics> {
+        let new = !self.open;
+        if self.open {
+            self.bars.pop();
+            for series in &mut self.outputs {
+                series.values.pop();
+            }
+        }
+        if new {
+            self.run.save();
+        } else {
+            self.run.restore();
+        }
+        self.open = !confirmed;
+        self.resumed = false;
+        self.push(&bar, BarState { realtime: true, new, confirmed, last: true })
+    }
+
+    fn push(&mut self, bar: &Bar, barstate: BarState) -> Result<(), Diagnostics> {
+        let error = |e: CodegenError| Diagnostics::error(self.source, Diagnostic::from(e));
+        let values = match &mut self.run {
+            #[cfg(any(feature = "llvm", target_arch = "wasm32"))]
+            Run::Native(state) => state.bar(bar, barstate).map_err(error)?,
+            Run::Interpreter(state) => state.bar(bar, barstate).map_err(error)?,
+        };
+        for (series, value) in self.outputs.iter_mut().zip(values) {
+            series.values.push(value);
+        }
+        self.bars.push(*bar);
+        let alerts = self.run.runtime().alerts();
+        if let Some(callback) = &mut self.callback {
+            alerts[self.delivered..].iter().for_each(callback);
+        }
+        self.delivered = alerts.len();
+        Ok(())
+    }
+}
+
+/// Input values of a run, checked against the inputs of the script as they are set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Params {
+    schema: Vec<Input>,
+    values: Vec<Constant>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&Constant> {
+        self.schema.iter().position(|i| i.name == name).map(|i| &self.values[i])
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<Constant>) -> Result<&mut Self, InputError> {
+        let i = self.schema.iter().position(|i| i.name == name).ok_or_else(|| InputError::UnknownInput(name.to_string()))?;
+        self.values[i] = self.schema[i].validate(value.into())?;
+        Ok(self)
+    }
+
+    /// Sets the values of a JSON object keyed by input name, colors are `#RRGGBB[AA]` strings.
+    pub fn set_json(&mut self, json: &str) -> Result<&mut Self, InputError> {
+        let object: HashMap<String, serde_json::Value> = serde_json::from_str(json).map_err(|e| InputError::Json(e.to_string()))?;
+        let mut values = self.values.clone();
+        for (name, value) in object {
+            let i = self.schema.iter().position(|i| i.name == name).ok_or(InputError::UnknownInput(name))?;
+            values[i] = self.schema[i].value_from_json(&value)?;
+        }
+        self.values = values;
+        Ok(self)
+    }
+
+    /// Values in the order of `Script::inputs`.
+    pub fn values(&self) -> &[Constant] {
+        &self.values
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ast::{CallArguments, Expr, Node, Statement},
+    builtins,
+    calendar::{Session, Timezone},
+    error::{CodegenError, RequestError},
+    inputs,
+    runtime::Bar,
+    timeframe::{Resampler, Timeframe},
+    types::{Constant, Signature, Type},
+};
+
+/// Builtin that compiled `request.security()` calls call with the index of the
+/// request in place of a call site and the value of the expression on the
+/// current bar. It returns the value requested for the bar, see `Runtime::security`.
+#[cfg(feature = "llvm")]
+pub const REQUEST: &str = "request.value";
+
+#[cfg(feature = "llvm")]
+pub fn request_signature() -> Signature {
+    Signature {
+        params: vec![("value".to_string(), Type::Float)],
+        required: 1,
+        returns: Type::Float,
+    }
+}
+
+/// `request.security()`, which returns the type of its expression.
+pub fn signature(expression: Type) -> Signature {
+    let params = [
+        ("symbol", Type::String), ("timeframe", Type::String), ("expression", expression.clone()), ("gaps", Type::String),
+        ("lookahead", Type::String), ("ignore_invalid_symbol", Type::Bool), ("currency", Type::String),
+    ];
+    Signature {
+        params: params.into_iter().map(|(name, t)| (name.to_string(), t)).collect(),
+        required: 3,
+        returns: expression,
+    }
+}
+
+/// The expression passed to a `request.security()` call.
+pub(crate) fn expression(args: &CallArguments) -> Option<&Expr> {
+    builtins::arguments("request.security", args)[2]
+}
+
+/// Where the symbol or timeframe of a request comes from.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Argument {
+    /// `syminfo.tickerid`, `timeframe.period` or an empty string.
+    Chart,
+    Constant(String),
+    /// A `string` input, by schema index.
+    Input(usize),
+}
+
+impl Argument {
+    fn resolve<'a>(&'a self, values: &'a [Constant], chart: &'a str) -> &'a str {
+        match self {
+            Argument::Chart => chart,
+            Argument::Constant(s) => s,
+            Argument::Input(i) => match &values[*i] {
+                Constant::String(s) if !s.is_empty() => s,
+                _ => chart
+            },
+        }
+    }
+}
+
+/// A `request.security()` call, the data it needs has to be known before the run.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Request {
+    pub symbol: Argument,
+    pub timeframe: Argument,
+    /// `barmerge.lookahead_on`, values of the bars of the feed show on every
+    /// bar they contain instead of once they are complete.
+    pub lookahead: bool,
+    /// `barmerge.gaps_on`, values only show on the bar they become available on
+    /// and are `na` until the next one instead of being repeated.
+    pub gaps: bool,
+}
+
+impl Request {
+    /// The symbol and timeframe of the feed, given the input values of a run.
+    pub fn feed<'a>(&'a self, values: &'a [Constant], feeds: &'a Feeds) -> (&'a str, &'a str) {
+        (self.symbol.resolve(values, &feeds.symbol), self.timeframe.resolve(values, &feeds.timeframe))
+    }
+}
+
+/// Finds the `request.security()` calls of a script, keyed by the address of the call expression.
+pub(crate) fn collect(statements: &[Statement]) -> Result<Vec<(*const Expr, Request)>, CodegenError> {
+    let inputs = inputs::collect(statements)?;
+    let inputs = inputs.iter().enumerate().map(|(i, (e, _))| (*e, i)).collect::<HashMap<_, _>>();
+    let mut variables = HashMap::new();
+    let mut calls = vec![];
+    for statement in statements {
+        let location = statement.location();
+        statement.walk(&mut |node| match node {
+            Node::Statement(Statement::VarLet(var, e))
+            | Node::Statement(Statement::VarDef(var, e))
+            | Node::Statement(Statement::ConstDef(var, e))
+            | Node::Statement(Statement::SeriesDef(var, e)) => {
+                if let Some(input) = inputs.get(&(e.as_ref() as *const Expr)) {
+                    variables.insert(var.1.clone(), *input);
+                }
+            },
+            Node::Expr(e @ Expr::MethodCall(object, name, _, args)) if object == "request" && name == "security" => {
+                calls.push((e as *const Expr, args, e.location().or(location)));
+            },
+            _ => {}
+        });
+    }
+
+    let mut requests = vec![];
+    for (e, args, location) in calls {
+        let exprs = builtins::arguments("request.security", args);
+        let invalid = |argument: &str| CodegenError::from(RequestError::InvalidArgument(argument.to_string())).at(location);
+        let argument = |i: usize, chart: &str| {
+            let invalid = || invalid(["symbol", "timeframe"][i]);
+            Ok(match exprs[i].ok_or_else(invalid)? {
+                Expr::String(s) if s.is_empty() => Argument::Chart,
+                Expr::String(s) => Argument::Constant(s.clone()),
+                Expr::PropertyAccess(object, property) if format!("{}.{}", object, property) == chart => Argument::Chart,
+                Expr::Identifier(name) => Argument::Input(*variables.get(name).ok_or_else(invalid)?),
+                _ => return Err(invalid())
+            })
+        };
+        let merge = |i: usize, param: &str| match exprs[i] {
+            None => Ok(false),
+            Some(Expr::PropertyAccess(object, property))
+                if object == "barmerge" && property.starts_with(param) && builtins::constant(&format!("barmerge.{}", property)).is_some() =>
+                Ok(property.ends_with("_on")),
+            Some(_) => Err(invalid(param))
+        };
+        requests.push((e, Request {
+            symbol: argument(0, "syminfo.tickerid")?,
+            timeframe: argument(1, "timeframe.period")?,
+            gaps: merge(3, "gaps")?,
+            lookahead: merge(4, "lookahead")?,
+        }));
+    }
+    Ok(requests)
+}
+
+/// The requests of a script, in source order.
+#[cfg(feature = "llvm")]
+pub fn extract(statements: &[Statement]) -> Result<Vec<Request>, CodegenError> {
+    Ok(collect(statements)?.into_iter().map(|r| r.1).collect())
+}
+
+/// Bars of other symbols and timeframes for `request.security()`.
+///
+/// `symbol` and `timeframe` name the bars a script runs over, requests for
+/// them are served from those bars. Requests for the symbol at a longer
+/// timeframe without bars of their own are served from the bars resampled.
+/// `timezone` and `session`, the exchange's and its regular trading hours,
+/// are the ones of all bars.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Feeds {
+    pub symbol: String,
+    pub timeframe: String,
+    pub timezone: Timezone,
+    pub session: Session,
+    bars: HashMap<(String, String), Vec<Bar>>,
+}
+
+impl Feeds {
+    pub fn new(symbol: &str, timeframe: &str) -> Self {
+        Self { symbol: symbol.to_string(), timeframe: timeframe.to_string(), ..Self::default() }
+    }
+
+    pub fn with(mut self, symbol: &str, timeframe: &str, bars: Vec<Bar>) -> Self {
+        self.insert(symbol, timeframe, bars);
+        self
+    }
+
+    pub fn insert(&mut self, symbol: &str, timeframe: &str, bars: Vec<Bar>) {
+        self.bars.insert((symbol.to_string(), timeframe.to_string()), bars);
+    }
+
+    pub fn get(&self, symbol: &str, timeframe: &str) -> Option<&[Bar]> {
+        self.bars.get(&(symbol.to_string(), timeframe.to_string())).map(Vec::as_slice)
+    }
+
+    /// Builds bars of `timeframe` from the bars a script runs over, if it is longer than theirs.
+    fn resampler(&self, timeframe: &str) -> Option<Resampler> {
+        let (to, from) = (Timeframe::parse(timeframe).ok()?, Timeframe::parse(&self.timeframe).ok()?);
+        let (hour, minute) = self.session.open();
+        to.resamples(&from).then(|| Resampler::new(to).with_timezone(self.timezone).with_session_start(hour, minute))
+    }
+}
+
+/// When every bar ends, the start of the next one. The last bar is assumed to
+/// last as long as the one before it, a single bar never ends.
+fn ends(bars: &[Bar]) -> Vec<i64> {
+    let mut ends = bars.windows(2).map(|w| w[1].time).collect::<Vec<_>>();
+    ends.extend(match bars {
+        [.., before, last] => Some(last.time + (last.time - before.time)),
+        [_] => Some(i64::MAX),
+        [] => None
+    });
+    ends
+}
+
+/// For every bar of the chart, the bar of the feed whose value it shows.
+///
+/// Without lookahead that is the last bar of the feed which ended by the end
+/// of the chart bar, so values never come from bars which are still open.
+/// With lookahead it is the bar of the feed containing the start of the chart
+/// bar. With gaps a value only shows on the first chart bar it would.
+pub fn align(chart: &[Bar], feed: &[Bar], lookahead: bool, gaps: bool) -> Vec<Option<usize>> {
+    let (chart_ends, feed_ends) = (ends(chart), ends(feed));
+    let mut aligned = Vec::with_capacity(chart.len());
+    let mut next = 0;
+    let mut previous = None;
+    for (bar, end) in chart.iter().zip(chart_ends) {
+        let shown = |k: usize| if lookahead { feed[k].time <= bar.time } else { feed_ends[k] <= end };
+        while next < feed.len() && shown(next) {
+            next += 1;
+        }
+        let current = next.checked_sub(1);
+        aligned.push(if gaps && current == previous { None } else { current });
+        previous = current;
+    }
+    aligned
+}
+
+/// The values `request.security()` returns on every bar of a run over `bars`, by request.
+///
+/// `record` runs the script over the bars of a feed and its timeframe and
+/// returns the values of the expressions of all requests on each bar. It is
+/// called once per distinct feed.
+pub(crate) fn prefetch<E: From<RequestError>>(
+    requests: &[Request],
+    values: &[Constant],
+    feeds: &Feeds,
+    bars: &[Bar],
+    mut record: impl FnMut(&[Bar], &str) -> Result<Vec<Vec<f64>>, E>,
+) -> Result<Vec<Vec<f64>>, E> {
+    let mut resampled = HashMap::new();
+    for request in requests {
+        let (symbol, timeframe) = request.feed(values, feeds);
+        if symbol == feeds.symbol && feeds.get(symbol, timeframe).is_none() {
+            if let Some(resampler) = feeds.resampler(timeframe) {
+                resampled.entry(timeframe).or_insert_with(|| resampler.resample(bars));
+            }
+        }
+    }
+
+    let mut requested = vec![vec![]; requests.len()];
+    let mut recorded: HashMap<(&str, &str), Vec<Vec<f64>>> = HashMap::new();
+    for (i, request) in requests.iter().enumerate() {
+        let (symbol, timeframe) = request.feed(values, feeds);
+        let feed = match (symbol, timeframe) {
+            (s, t) if s == feeds.symbol && t == feeds.timeframe => bars,
+            (s, t) => feeds.get(s, t)
+                .or_else(|| resampled.get(t).filter(|_| s == feeds.symbol).map(Vec::as_slice))
+                .ok_or_else(|| RequestError::MissingFeed { symbol: s.to_string(), timeframe: t.to_string() })?
+        };
+        if let Entry::Vacant(entry) = recorded.entry((symbol, timeframe)) {
+            entry.insert(record(feed, timeframe)?);
+        }
+        let series = &recorded[&(symbol, timeframe)][i];
+        requested[i] = align(bars, feed, request.lookahead, request.gaps).into_iter()
+            .map(|k| k.map_or(f64::NAN, |k| series[k]))
+            .collect();
+    }
+    Ok(requested)
+}
@@ -0,0 +1,282 @@
+use std::{
+    env,
+    ffi::OsStr,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use inkwell::{
+    context::Context,
+    module::{Linkage, Module},
+    targets::{CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple},
+    OptimizationLevel,
+};
+
+use crate::{abi::{self, ScriptInfo}, error::CodegenError, inputs::InputKind, processor::Processor};
+
+pub const WASM32: &str = "wasm32-unknown-unknown";
+pub const X86_64_LINUX: &str = "x86_64-unknown-linux-gnu";
+
+/// Functions every compiled script exports, see `Processor::compile`.
+pub const EXPORTS: [&str; 6] = ["state_size", "init", "on_bar", "get_outputs", "save", "restore"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Artifact {
+    /// A relocatable `.o` file.
+    Object,
+    /// A `.so` linked with `cc`, or the compiler named by `NINESCRIPT_CC`.
+    SharedLibrary,
+    /// A `.a` archive made with `ar`, or the archiver named by `NINESCRIPT_AR`.
+    StaticLibrary,
+}
+
+/// File name of the static library of the runtime, which `cargo build -p ninescript-runtime` builds.
+pub const RUNTIME_LIBRARY: &str = "libninescript_runtime.a";
+
+fn machine(triple: &str, reloc: RelocMode) -> Result<TargetMachine, CodegenError> {
+    Target::initialize_all(&InitializationConfig::default());
+    let triple = TargetTriple::create(triple);
+    let target = Target::from_triple(&triple).map_err(|e| CodegenError::Llvm(e.to_string()))?;
+    target.create_target_machine(&triple, "generic", "", OptimizationLevel::Default, reloc, CodeModel::Default)
+        .ok_or_else(|| CodegenError::Llvm(format!("no target machine for {}", triple)))
+}
+
+/// Emits `module` as an object file for `triple`.
+pub fn object(module: &Module, triple: &str) -> Result<Vec<u8>, CodegenError> {
+    let reloc = if triple.starts_with("wasm") { RelocMode::Default } else { RelocMode::PIC };
+    let machine = machine(triple, reloc)?;
+    module.set_triple(&machine.get_triple());
+    module.set_data_layout(&machine.get_target_data().get_data_layout());
+    let buffer = machine.write_to_memory_buffer(module, FileType::Object).map_err(|e| CodegenError::Llvm(e.to_string()))?;
+    Ok(buffer.as_slice().to_vec())
+}
+
+/// Hides everything but the exports, which are renamed to `prefix_name` if
+/// given, so several scripts can be linked into one binary.
+fn internalize(module: &Module, prefix: Option<&str>) {
+    for global in module.get_globals() {
+        global.set_linkage(Linkage::Internal);
+    }
+    for function in module.get_functions().filter(|f| f.count_basic_blocks() > 0) {
+        let name = function.get_name().to_string_lossy().to_string();
+        match (EXPORTS.contains(&name.as_str()), prefix) {
+            (false, _) => function.set_linkage(Linkage::Internal),
+            (true, Some(prefix)) => function.as_global_value().set_name(&format!("{}_{}", prefix, name)),
+            (true, None) => {}
+        }
+    }
+}
+
+/// Names the builtins after the symbols the runtime library defines for them, see `abi`.
+fn link_runtime(module: &Module) {
+    for function in module.get_functions().filter(|f| f.count_basic_blocks() == 0) {
+        let name = function.get_name().to_string_lossy().to_string();
+        function.as_global_value().set_name(&format!("{}{}", abi::SYMBOL_PREFIX, name));
+    }
+}
+
+/// The runtime library at the path in `variable`, or where cargo builds it
+/// for `target` next to the running executable, for the host without one.
+fn runtime_library(variable: &str, target: Option<&str>) -> Result<PathBuf, CodegenError> {
+    if let Ok(path) = env::var(variable) {
+        return Ok(PathBuf::from(path));
+    }
+    let executable = env::current_exe().map_err(|e| CodegenError::Link(e.to_string()))?;
+    /* Tests run from `target/<profile>/deps`, libraries for other targets are in `target/<target>/<profile>` */
+    executable.ancestors().skip(1).take(2)
+        .filter_map(|dir| match target {
+            Some(target) => Some(dir.parent()?.join(target).join(dir.file_name()?).join(RUNTIME_LIBRARY)),
+            None => Some(dir.join(RUNTIME_LIBRARY)),
+        })
+        .find(|f| f.is_file())
+        .ok_or_else(|| {
+            let target = target.map(|t| format!(" --target {}", t)).unwrap_or_default();
+            CodegenError::Link(format!("{} not found, build it with cargo build -p ninescript-runtime{} or set {}", RUNTIME_LIBRARY, target, variable))
+        })
+}
+
+/// A file in the temporary directory, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(extension: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("ninescript-{}-{}.{}", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed), extension);
+        Self(env::temp_dir().join(name))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Runs an external tool, `variable` overrides its name. Reports its output on failure.
+fn run(variable: &str, default: &str, args: &[&OsStr]) -> Result<(), CodegenError> {
+    let tool = env::var(variable).unwrap_or_else(|_| default.to_string());
+    let result = Command::new(&tool)
+        .args(args)
+        .output()
+        .map_err(|e| CodegenError::Link(format!("{}: {}", tool, e)))?;
+    if !result.status.success() {
+        return Err(CodegenError::Link(String::from_utf8_lossy(&result.stderr).to_string()));
+    }
+    Ok(())
+}
+
+/// Compiles a script into a standalone `wasm32-unknown-unknown` module.
+///
+/// The module contains the runtime built for WebAssembly, found at
+/// `NINESCRIPT_WASM_RUNTIME` or like the one of `native`. It exports
+/// `state_size`, `init`, `on_bar`, `get_outputs`, `save`, `restore`, its
+/// `memory` and the functions the loader of `bindings::javascript` calls, and
+/// imports the clock `env.ninescript_now`. Links with `wasm-ld`, or the linker
+/// named by `NINESCRIPT_WASM_LD`.
+pub fn wasm(processor: &Processor) -> Result<Vec<u8>, CodegenError> {
+    let context = Context::create();
+    let module = processor.compile(&context)?;
+    internalize(&module, None);
+    link_runtime(&module);
+    let runtime = runtime_library("NINESCRIPT_WASM_RUNTIME", Some(WASM32))?;
+
+    let input = TempFile::new("o");
+    let output = TempFile::new("wasm");
+    fs::write(input.path(), object(&module, WASM32)?).map_err(|e| CodegenError::Link(e.to_string()))?;
+
+    let exports = EXPORTS.iter().chain(&abi::LOADER_FUNCTIONS).map(|e| format!("--export={}", e)).collect::<Vec<_>>();
+    /* The stack size Rust links WebAssembly with */
+    let mut args = vec![OsStr::new("--no-entry"), OsStr::new("-z"), OsStr::new("stack-size=1048576")];
+    args.extend(exports.iter().map(OsStr::new));
+    args.extend([OsStr::new("-o"), output.path().as_os_str(), input.path().as_os_str(), runtime.as_os_str()]);
+    run("NINESCRIPT_WASM_LD", "wasm-ld", &args)?;
+    fs::read(output.path()).map_err(|e| CodegenError::Link(e.to_string()))
+}
+
+/// Compiles a script for a native `triple` such as `X86_64_LINUX`.
+///
+/// The exports follow the C calling convention, see `header` for their
+/// declarations and those of the runtime. Shared and static libraries
+/// contain the runtime library, found at `NINESCRIPT_RUNTIME` or next to the
+/// running executable, objects have to be linked with it. Hosts linking static libraries also link `-lpthread -ldl -lm`.
+pub fn native(processor: &Processor, triple: &str, artifact: Artifact, prefix: Option<&str>) -> Result<Vec<u8>, CodegenError> {
+    let context = Context::create();
+    let module = processor.compile(&context)?;
+    internalize(&module, prefix);
+    link_runtime(&module);
+    let object = object(&module, triple)?;
+    if artifact == Artifact::Object {
+        return Ok(object);
+    }
+
+    let input = TempFile::new("o");
+    fs::write(input.path(), object).map_err(|e| CodegenError::Link(e.to_string()))?;
+    let runtime = runtime_library("NINESCRIPT_RUNTIME", None)?;
+    let output = match artifact {
+        Artifact::SharedLibrary => {
+            let output = TempFile::new("so");
+            /* Only the parts of the runtime the script calls are linked, and the functions of hosts */
+            let keep = abi::HOST_FUNCTIONS.map(|f| format!("-Wl,-u,{}", f));
+            let mut args = vec![OsStr::new("-shared"), OsStr::new("-o"), output.path().as_os_str(), input.path().as_os_str()];
+            args.extend(keep.iter().map(OsStr::new));
+            args.extend([runtime.as_os_str(), OsStr::new("-lpthread"), OsStr::new("-ldl"), OsStr::new("-lm")]);
+            run("NINESCRIPT_CC", "cc", &args)?;
+            output
+        },
+        _ => {
+            let output = TempFile::new("a");
+            fs::copy(&runtime, output.path()).map_err(|e| CodegenError::Link(format!("{}: {}", runtime.display(), e)))?;
+            run("NINESCRIPT_AR", "ar", &[OsStr::new("rs"), output.path().as_os_str(), input.path().as_os_str()])?;
+            output
+        }
+    };
+    fs::read(output.path()).map_err(|e| CodegenError::Link(e.to_string()))
+}
+
+fn c_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+/// A C header declaring the exports of `native`, the indices of the outputs
+/// and the runtime, with the description of the script runs are created from.
+pub fn header(processor: &Processor, prefix: Option<&str>) -> Result<String, CodegenError> {
+    let info = ScriptInfo {
+        metadata: processor.metadata()?,
+        inputs: processor.inputs()?,
+        outputs: processor.outputs()?,
+        plots: processor.plots()?,
+        requests: processor.requests()?,
+    };
+    let script = serde_json::to_string(&info).map_err(|e| CodegenError::Unsupported(e.to_string()))?;
+    let (inputs, outputs) = (&info.inputs, &info.outputs);
+    let symbol = |name: &str| match prefix {
+        Some(prefix) => format!("{}_{}", prefix, name),
+        None => name.to_string()
+    };
+    let macro_prefix = c_name(prefix.unwrap_or("ninescript")).to_uppercase();
+
+    let mut h = String::new();
+    writeln!(h, "#ifndef {}_H", macro_prefix).unwrap();
+    writeln!(h, "#define {}_H\n", macro_prefix).unwrap();
+    writeln!(h, "#include <stdbool.h>\n#include <stdint.h>\n").unwrap();
+    writeln!(h, "#ifndef NINESCRIPT_BAR\n#define NINESCRIPT_BAR").unwrap();
+    writeln!(h, "struct ninescript_bar {{\n    int64_t time;").unwrap();
+    for field in ["open", "high", "low", "close", "volume"] {
+        writeln!(h, "    double {};", field).unwrap();
+    }
+    writeln!(h, "}};\n#endif\n").unwrap();
+
+    writeln!(h, "/* Parameter slots read by init(), NULL takes the defaults:").unwrap();
+    for (i, input) in inputs.iter().enumerate() {
+        let slot = match input.kind {
+            InputKind::Float => "double",
+            InputKind::Bool => "int64_t, 0 or 1",
+            InputKind::Color => "uint32_t, 0xRRGGBBAA",
+            InputKind::Source => "int64_t, index of open, high, low, close, volume, hl2, hlc3, ohlc4",
+            InputKind::String | InputKind::Timeframe => "const char *",
+            InputKind::Int => "int64_t",
+        };
+        writeln!(h, " * [{}] {} ({})", i, input.name, slot).unwrap();
+    }
+    writeln!(h, " */").unwrap();
+    writeln!(h, "/* Bytes of the state of a run, which every function takes, aligned to 8 */").unwrap();
+    writeln!(h, "uint64_t {}(void);", symbol("state_size")).unwrap();
+    writeln!(h, "void {}(void *state, const uint64_t *params);", symbol("init")).unwrap();
+    writeln!(h, "void {}(void *state, const struct ninescript_bar *bar);", symbol("on_bar")).unwrap();
+    writeln!(h, "const double *{}(void *state);", symbol("get_outputs")).unwrap();
+    writeln!(h, "/* Rolls the bar back to the last save(), for running it again on every tick */").unwrap();
+    writeln!(h, "void {}(void *state);", symbol("save")).unwrap();
+    writeln!(h, "void {}(void *state);\n", symbol("restore")).unwrap();
+
+    writeln!(h, "#define {}_OUTPUTS {}", macro_prefix, outputs.len()).unwrap();
+    for (i, output) in outputs.iter().enumerate() {
+        writeln!(h, "#define {}_OUTPUT_{} {}", macro_prefix, c_name(&output.name).to_uppercase(), i).unwrap();
+    }
+
+    writeln!(h, "\n/* Passed to ninescript_run_new() */").unwrap();
+    writeln!(h, "#define {}_SCRIPT \"{}\"", macro_prefix, script.replace('\\', "\\\\").replace('"', "\\\"")).unwrap();
+
+    writeln!(h, "\n#ifndef NINESCRIPT_RUNTIME\n#define NINESCRIPT_RUNTIME").unwrap();
+    writeln!(h, "/* A run of a script, whose builtins the runtime serves. Every bar runs as").unwrap();
+    writeln!(h, " * ninescript_start_bar(), on_bar() and ninescript_end_bar() on one thread. */").unwrap();
+    writeln!(h, "struct ninescript_run;").unwrap();
+    writeln!(h, "/* NULL if the script is invalid, request.security() is na in these runs */").unwrap();
+    writeln!(h, "struct ninescript_run *ninescript_run_new(const char *script, const char *symbol, const char *timeframe);").unwrap();
+    writeln!(h, "void ninescript_start_bar(struct ninescript_run *run, const struct ninescript_bar *bar, bool last);").unwrap();
+    writeln!(h, "/* The first error of a builtin during the bar or NULL, valid until the next call */").unwrap();
+    writeln!(h, "const char *ninescript_end_bar(struct ninescript_run *run);").unwrap();
+    writeln!(h, "/* The plots, drawings, strategy report and alerts so far as JSON, valid until the next call */").unwrap();
+    writeln!(h, "const char *ninescript_result(struct ninescript_run *run);").unwrap();
+    writeln!(h, "void ninescript_run_free(struct ninescript_run *run);").unwrap();
+    writeln!(h, "#endif").unwrap();
+
+    writeln!(h, "\n#endif").unwrap();
+    Ok(h)
+}
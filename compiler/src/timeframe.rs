@@ -0,0 +1,267 @@
+use core::{fmt, str::FromStr};
+
+use crate::{
+    calendar::{self, Timezone, DAY, HOUR, MINUTE, SECOND},
+    error::TimeframeError,
+    runtime::Bar,
+};
+
+/// The period of the bars of a chart, written like `timeframe.period`: `"5S"`,
+/// `"60"` (minutes), `"D"`, `"2W"`, `"3M"` or `"100T"` (ticks). Volume and
+/// range bars, which close once they traded or spanned a size, are `"1000V"`
+/// and `"2.5R"`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timeframe {
+    Ticks(u32),
+    Seconds(u32),
+    Minutes(u32),
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    Volume(f64),
+    Range(f64),
+}
+
+impl Timeframe {
+    pub fn parse(s: &str) -> Result<Self, TimeframeError> {
+        let invalid = || TimeframeError::Invalid(s.to_string());
+        let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+        let (multiplier, unit) = s.split_at(split);
+        if !multiplier.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+            return Err(invalid());
+        }
+        let count = || match multiplier {
+            "" => Ok(1),
+            m => m.parse::<u32>().ok().filter(|m| *m > 0).ok_or_else(invalid),
+        };
+        let size = || multiplier.parse::<f64>().ok().filter(|m| m.is_finite() && *m > 0.0).ok_or_else(invalid);
+        Ok(match unit {
+            "" if !multiplier.is_empty() => Timeframe::Minutes(count()?),
+            "T" => Timeframe::Ticks(count()?),
+            "S" => Timeframe::Seconds(count()?),
+            "H" => Timeframe::Minutes(count()?.checked_mul(60).ok_or_else(invalid)?),
+            "D" => Timeframe::Days(count()?),
+            "W" => Timeframe::Weeks(count()?),
+            "M" => Timeframe::Months(count()?),
+            "V" => Timeframe::Volume(size()?),
+            "R" => Timeframe::Range(size()?),
+            _ => return Err(invalid())
+        })
+    }
+
+    /// `timeframe.multiplier`, the number of units in a bar.
+    pub fn multiplier(&self) -> f64 {
+        match *self {
+            Timeframe::Ticks(n) | Timeframe::Seconds(n) | Timeframe::Minutes(n)
+            | Timeframe::Days(n) | Timeframe::Weeks(n) | Timeframe::Months(n) => n as f64,
+            Timeframe::Volume(size) | Timeframe::Range(size) => size,
+        }
+    }
+
+    /// `timeframe.in_seconds()`, months last 30.4167 days. Bars which do not
+    /// close after some time have no duration.
+    pub fn in_seconds(&self) -> Option<i64> {
+        let unit = match self {
+            Timeframe::Seconds(_) => 1,
+            Timeframe::Minutes(_) => 60,
+            Timeframe::Days(_) => 86400,
+            Timeframe::Weeks(_) => 604800,
+            Timeframe::Months(_) => 2628003,
+            Timeframe::Ticks(_) | Timeframe::Volume(_) | Timeframe::Range(_) => return None,
+        };
+        Some(unit * self.multiplier() as i64)
+    }
+
+    /// Whether bars of the timeframe can be built from bars of `from`, which
+    /// have to be shorter unless either do not close after some time.
+    pub fn resamples(&self, from: &Timeframe) -> bool {
+        match (self.in_seconds(), from.in_seconds()) {
+            (Some(to), Some(from)) => to >= from,
+            _ => true
+        }
+    }
+
+    pub fn is_ticks(&self) -> bool {
+        matches!(self, Timeframe::Ticks(_))
+    }
+
+    pub fn is_seconds(&self) -> bool {
+        matches!(self, Timeframe::Seconds(_))
+    }
+
+    pub fn is_minutes(&self) -> bool {
+        matches!(self, Timeframe::Minutes(_))
+    }
+
+    pub fn is_intraday(&self) -> bool {
+        self.is_ticks() || self.is_seconds() || self.is_minutes()
+    }
+
+    pub fn is_daily(&self) -> bool {
+        matches!(self, Timeframe::Days(_))
+    }
+
+    pub fn is_weekly(&self) -> bool {
+        matches!(self, Timeframe::Weeks(_))
+    }
+
+    pub fn is_monthly(&self) -> bool {
+        matches!(self, Timeframe::Months(_))
+    }
+
+    pub fn is_dwm(&self) -> bool {
+        self.is_daily() || self.is_weekly() || self.is_monthly()
+    }
+}
+
+impl FromStr for Timeframe {
+    type Err = TimeframeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Timeframe::parse(s)
+    }
+}
+
+impl fmt::Display for Timeframe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = |f: &mut fmt::Formatter<'_>, n: u32, unit: &str| match n {
+            1 => write!(f, "{}", unit),
+            n => write!(f, "{}{}", n, unit),
+        };
+        match *self {
+            Timeframe::Ticks(n) => write!(f, "{}T", n),
+            Timeframe::Seconds(n) => write!(f, "{}S", n),
+            Timeframe::Minutes(n) => write!(f, "{}", n),
+            Timeframe::Days(n) => unit(f, n, "D"),
+            Timeframe::Weeks(n) => unit(f, n, "W"),
+            Timeframe::Months(n) => unit(f, n, "M"),
+            Timeframe::Volume(size) => write!(f, "{}V", size),
+            Timeframe::Range(size) => write!(f, "{}R", size),
+        }
+    }
+}
+
+/// Aggregates bars into bars of a longer timeframe, one bar at a time.
+///
+/// Bars of timeframes which close after some time start at the boundaries of
+/// their period in the timezone, days at the start of the session and weeks
+/// on Mondays. Sessions starting in the afternoon belong to the next day, like
+/// the overnight sessions of futures. Times are milliseconds since the epoch.
+#[derive(Clone, Debug)]
+pub struct Resampler {
+    timeframe: Timeframe,
+    timezone: Timezone,
+    /// Start of the session, in milliseconds after midnight.
+    session: i64,
+    current: Option<Bar>,
+    ticks: u32,
+}
+
+impl Resampler {
+    pub fn new(timeframe: Timeframe) -> Self {
+        Self { timeframe, timezone: Timezone::utc(), session: 0, current: None, ticks: 0 }
+    }
+
+    pub fn with_timezone(mut self, timezone: Timezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn with_session_start(mut self, hour: u32, minute: u32) -> Self {
+        self.session = (hour as i64 * HOUR + minute as i64 * MINUTE).rem_euclid(DAY);
+        self
+    }
+
+    /// The start of the bar containing `time`, for timeframes which close after some time.
+    pub fn start(&self, time: i64) -> Option<i64> {
+        let offset = self.timezone.offset(time);
+        let overnight = (self.session >= 12 * HOUR) as i64;
+        let since = time + offset - self.session;
+        let day = since.div_euclid(DAY);
+        let open = |day: i64| (day - overnight) * DAY + self.session;
+        let local = match self.timeframe {
+            Timeframe::Seconds(_) | Timeframe::Minutes(_) => {
+                let length = self.timeframe.in_seconds()? * SECOND;
+                match length < DAY {
+                    true => day * DAY + self.session + (since - day * DAY) / length * length,
+                    false => self.session + since.div_euclid(length) * length,
+                }
+            },
+            Timeframe::Days(n) => open((day + overnight).div_euclid(n as i64) * n as i64),
+            Timeframe::Weeks(n) => {
+                let week = (day + overnight + 3).div_euclid(7);
+                open(week.div_euclid(n as i64) * n as i64 * 7 - 3)
+            },
+            Timeframe::Months(n) => {
+                let (year, month, _) = calendar::civil(day + overnight);
+                let month = (year * 12 + month - 1).div_euclid(n as i64) * n as i64;
+                open(calendar::days(month.div_euclid(12), month.rem_euclid(12) + 1, 1))
+            },
+            Timeframe::Ticks(_) | Timeframe::Volume(_) | Timeframe::Range(_) => return None,
+        };
+        Some(self.timezone.from_local(local))
+    }
+
+    /// Adds a bar, returning the bar it completed. Bars closing after some
+    /// time complete once a bar of the next period arrives, others with the
+    /// bar which makes them reach their size.
+    pub fn push(&mut self, bar: &Bar) -> Option<Bar> {
+        let mut completed = None;
+        let start = self.start(bar.time);
+        match (&mut self.current, start) {
+            (Some(current), Some(start)) if current.time == start => merge(current, bar),
+            (current @ Some(_), Some(_)) => completed = current.take(),
+            (Some(current), None) => merge(current, bar),
+            _ => {}
+        }
+        let current = self.current.get_or_insert(Bar { time: start.unwrap_or(bar.time), ..*bar });
+        self.ticks += 1;
+        let full = match self.timeframe {
+            Timeframe::Ticks(n) => self.ticks >= n,
+            Timeframe::Volume(size) => current.volume >= size,
+            Timeframe::Range(size) => current.high - current.low >= size,
+            _ => false
+        };
+        if full {
+            self.ticks = 0;
+            return self.current.take();
+        }
+        completed
+    }
+
+    /// Goes on building `bar`, which takes the bars of its period pushed next.
+    pub fn resume(&mut self, bar: Bar) {
+        self.current = Some(Bar { time: self.start(bar.time).unwrap_or(bar.time), ..bar });
+        self.ticks = 0;
+    }
+
+    /// The bar still being built.
+    pub fn current(&self) -> Option<&Bar> {
+        self.current.as_ref()
+    }
+
+    /// Takes the bar still being built, the next one starts a new bar.
+    pub fn take(&mut self) -> Option<Bar> {
+        self.ticks = 0;
+        self.current.take()
+    }
+
+    /// The bar still being built, as the last one.
+    pub fn finish(self) -> Option<Bar> {
+        self.current
+    }
+
+    /// All bars of the timeframe built from `bars`, the last one possibly incomplete.
+    pub fn resample(mut self, bars: &[Bar]) -> Vec<Bar> {
+        let mut resampled = bars.iter().filter_map(|bar| self.push(bar)).collect::<Vec<_>>();
+        resampled.extend(self.finish());
+        resampled
+    }
+}
+
+fn merge(bar: &mut Bar, next: &Bar) {
+    bar.high = bar.high.max(next.high);
+    bar.low = bar.low.min(next.low);
+    bar.close = next.close;
+    bar.volume += next.volume;
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::types::RGBA;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -47,6 +49,8 @@ pub enum Tok {
     While,
     Switch,
     Import,
+    As,
+    Export,
     Var,
     VarIp,
     Const,
@@ -56,10 +60,13 @@ pub enum Tok {
     True,
     False,
 
-    FunctionMarker,
-    UnpackTupleMarker,
-    MethodCallMarker,
-    VarDeclarationMarker,
+    /* Trivia */
+    Whitespace,
+    Comment,
+    /// A line break which does not end a statement.
+    LineBreak,
+    /// A character which is not part of the language.
+    Unknown,
 
     /* Math operators */
     Plus,
@@ -74,3 +81,78 @@ pub enum Tok {
     OpenBrackets,
     CloseBrackets,
 }
+
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Tok::Identifier { name } => return write!(f, "`{}`", name),
+            Tok::HashColor(color) => return write!(f, "`{}`", color),
+            Tok::Int { value } => return write!(f, "`{}`", value),
+            Tok::Float { value } => return write!(f, "`{}`", value),
+            Tok::String { value } => return write!(f, "string {:?}", value),
+            Tok::NewLine => return write!(f, "end of line"),
+            Tok::StartOfFile => return write!(f, "start of input"),
+            Tok::EndOfFile => return write!(f, "end of input"),
+            Tok::Indent => return write!(f, "indentation"),
+            Tok::Dedent => return write!(f, "end of block"),
+            Tok::Whitespace => return write!(f, "whitespace"),
+            Tok::Comment => return write!(f, "comment"),
+            Tok::LineBreak => return write!(f, "line break"),
+            Tok::Unknown => return write!(f, "invalid token"),
+            Tok::Colon => ":",
+            Tok::Semicolon => ";",
+            Tok::Less => "<",
+            Tok::Greater => ">",
+            Tok::Equal => "=",
+            Tok::Set => ":=",
+            Tok::And => "and",
+            Tok::Or => "or",
+            Tok::DoubleEqual => "==",
+            Tok::EqualAdd => "+=",
+            Tok::EqualSub => "-=",
+            Tok::EqualDiv => "/=",
+            Tok::EqualMul => "*=",
+            Tok::Gte => ">=",
+            Tok::Lte => "<=",
+            Tok::Follow => "=>",
+            Tok::NotEqual => "!=",
+            Tok::Not => "not",
+            Tok::ExclamationMark => "!",
+            Tok::QuestionMark => "?",
+            Tok::Dot => ".",
+            Tok::Comma => ",",
+            Tok::Backslash => "\\",
+            Tok::If => "if",
+            Tok::Else => "else",
+            Tok::For => "for",
+            Tok::To => "to",
+            Tok::In => "in",
+            Tok::By => "by",
+            Tok::While => "while",
+            Tok::Switch => "switch",
+            Tok::Import => "import",
+            Tok::As => "as",
+            Tok::Export => "export",
+            Tok::Var => "var",
+            Tok::VarIp => "varip",
+            Tok::Const => "const",
+            Tok::Type => "type",
+            Tok::Enum => "enum",
+            Tok::Series => "series",
+            Tok::True => "true",
+            Tok::False => "false",
+            Tok::Plus => "+",
+            Tok::Minus => "-",
+            Tok::Asterisk => "*",
+            Tok::Slash => "/",
+            Tok::Mod => "%",
+            Tok::At => "@",
+            Tok::Hash => "#",
+            Tok::OpenParenthesis => "(",
+            Tok::CloseParenthesis => ")",
+            Tok::OpenBrackets => "[",
+            Tok::CloseBrackets => "]",
+        };
+        write!(f, "`{}`", text)
+    }
+}
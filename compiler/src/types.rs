@@ -1,2 +1,325 @@
+use core::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::ast::{Expr, VarType};
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct RGBA(pub u8, pub u8, pub u8, pub u8);
+
+impl fmt::Display for RGBA {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}{:02X}", self.0, self.1, self.2, self.3)
+    }
+}
+
+impl RGBA {
+    /// Parses `#RRGGBB` or `#RRGGBBAA`.
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let hex = s.strip_prefix('#')?;
+        if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+            return None;
+        }
+        let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        let alpha = if hex.len() == 8 { byte(6)? } else { 255 };
+        Some(RGBA(byte(0)?, byte(2)?, byte(4)?, alpha))
+    }
+
+    /// The color as compiled code stores it, `0xRRGGBBAA`.
+    pub fn packed(&self) -> u32 {
+        (self.0 as u32) << 24 | (self.1 as u32) << 16 | (self.2 as u32) << 8 | self.3 as u32
+    }
+
+    pub fn from_packed(packed: u32) -> Self {
+        let [r, g, b, a] = packed.to_be_bytes();
+        RGBA(r, g, b, a)
+    }
+
+    /// A color with Pine's transparency, from 0 (opaque) to 100 (invisible).
+    pub fn from_transparency(r: u8, g: u8, b: u8, transparency: f64) -> Self {
+        RGBA(r, g, b, (255.0 * (1.0 - transparency.clamp(0.0, 100.0) / 100.0)).round() as u8)
+    }
+
+    /// The transparency from 0 to 100, rounded to a whole percent so that
+    /// `from_transparency` round trips.
+    pub fn transparency(&self) -> f64 {
+        (100.0 * (255 - self.3) as f64 / 255.0).round()
+    }
+
+    pub fn with_transparency(&self, transparency: f64) -> Self {
+        RGBA::from_transparency(self.0, self.1, self.2, transparency)
+    }
+
+    /// The color `position` of the way from `self` to `other`, interpolating
+    /// every channel and the transparency.
+    pub fn mix(&self, other: &RGBA, position: f64) -> Self {
+        let position = position.clamp(0.0, 1.0);
+        let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * position).round() as u8;
+        let transparency = self.transparency() + (other.transparency() - self.transparency()) * position;
+        RGBA::from_transparency(channel(self.0, other.0), channel(self.1, other.1), channel(self.2, other.2), transparency)
+    }
+
+    /// The builtin `color.*` constants.
+    pub fn named(name: &str) -> Option<Self> {
+        RGBA::from_hex(match name {
+            "aqua" => "#00BCD4",
+            "black" => "#363A45",
+            "blue" => "#2196F3",
+            "fuchsia" => "#E040FB",
+            "gray" => "#787B86",
+            "green" => "#4CAF50",
+            "lime" => "#00E676",
+            "maroon" => "#880E4F",
+            "navy" => "#311B92",
+            "olive" => "#808000",
+            "orange" => "#FF9800",
+            "purple" => "#9C27B0",
+            "red" => "#FF5252",
+            "silver" => "#B2B5BE",
+            "teal" => "#00897B",
+            "white" => "#FFFFFF",
+            "yellow" => "#FFEB3B",
+            _ => return None
+        })
+    }
+}
+
+impl Serialize for RGBA {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RGBA {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        RGBA::from_hex(&s).ok_or_else(|| de::Error::custom(format!("invalid color {}", s)))
+    }
+}
+
+/// A value known at compile time, like arguments of declarations and inputs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constant {
+    String(String),
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Color(RGBA),
+}
+
+impl Constant {
+    pub fn from_expr(expr: &Expr) -> Option<Self> {
+        Some(match expr {
+            Expr::String(s) => Constant::String(s.clone()),
+            Expr::Bool(b) => Constant::Bool(*b),
+            Expr::Int(i) => Constant::Int(*i),
+            Expr::Float(f) => Constant::Float(*f),
+            Expr::HashColor(c) => Constant::Color(c.clone()),
+            Expr::Negative(e) => match Constant::from_expr(e)? {
+                Constant::Int(i) => Constant::Int(-i),
+                Constant::Float(f) => Constant::Float(-f),
+                _ => return None
+            },
+            _ => return None
+        })
+    }
+
+    pub fn get_type(&self) -> Type {
+        match self {
+            Constant::String(_) => Type::String,
+            Constant::Bool(_) => Type::Bool,
+            Constant::Int(_) => Type::Int,
+            Constant::Float(_) => Type::Float,
+            Constant::Color(_) => Type::Color,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Constant::Int(i) => Some(*i as f64),
+            Constant::Float(f) => Some(*f),
+            _ => None
+        }
+    }
+
+    /// Converts the constant to `t`, promoting `int` to `float`.
+    pub fn cast(self, t: &Type) -> Option<Self> {
+        match (self, t) {
+            (Constant::Int(i), Type::Float) => Some(Constant::Float(i as f64)),
+            (c, t) if c.get_type() == *t => Some(c),
+            _ => None
+        }
+    }
+}
+
+impl From<i64> for Constant {
+    fn from(i: i64) -> Self {
+        Constant::Int(i)
+    }
+}
+
+impl From<f64> for Constant {
+    fn from(f: f64) -> Self {
+        Constant::Float(f)
+    }
+}
+
+impl From<bool> for Constant {
+    fn from(b: bool) -> Self {
+        Constant::Bool(b)
+    }
+}
+
+impl From<&str> for Constant {
+    fn from(s: &str) -> Self {
+        Constant::String(s.to_string())
+    }
+}
+
+impl From<String> for Constant {
+    fn from(s: String) -> Self {
+        Constant::String(s)
+    }
+}
+
+impl From<RGBA> for Constant {
+    fn from(c: RGBA) -> Self {
+        Constant::Color(c)
+    }
+}
+
+impl Serialize for Constant {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Constant::String(s) => serializer.serialize_str(s),
+            Constant::Bool(b) => serializer.serialize_bool(*b),
+            Constant::Int(i) => serializer.serialize_i64(*i),
+            Constant::Float(f) => serializer.serialize_f64(*f),
+            Constant::Color(c) => serializer.collect_str(c),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Constant {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Bool(bool),
+            Int(i64),
+            Float(f64),
+            String(String),
+        }
+        /* Colors are serialized as `#RRGGBBAA` strings */
+        Ok(match Value::deserialize(deserializer)? {
+            Value::Bool(b) => Constant::Bool(b),
+            Value::Int(i) => Constant::Int(i),
+            Value::Float(f) => Constant::Float(f),
+            Value::String(s) => RGBA::from_hex(&s).map_or(Constant::String(s), Constant::Color),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    String,
+    Color,
+    Na,
+    Void,
+    Tuple(Vec<Type>),
+    Array(Box<Type>),
+    Named(String),
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "bool" => Type::Bool,
+            "string" => Type::String,
+            "color" => Type::Color,
+            _ => Type::Named(name.to_string())
+        }
+    }
+
+    /// Type written in a declaration, `None` if it has to be inferred from the value.
+    pub fn from_var_type(var_type: &VarType) -> Option<Self> {
+        let name = var_type.0.as_ref()?;
+        Some(match (name.as_str(), &var_type.1) {
+            ("array", Some(param)) => Type::Array(Box::new(Type::from_name(param))),
+            _ => Type::from_name(name)
+        })
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    /// Whether values are ids of plots or drawing objects, compiled as `int` with 0 for `na`.
+    pub fn is_handle(&self) -> bool {
+        matches!(self, Type::Named(name) if matches!(name.as_str(), "plot" | "label" | "line" | "box" | "table" | "polyline" | "chart.point"))
+    }
+
+    /// Whether a value of type `other` can be stored where `self` is expected.
+    /// Follows pine: `int` is promoted to `float` and `na` fits everywhere.
+    pub fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (_, Type::Na) => true,
+            (Type::Float, Type::Int) => true,
+            (Type::Tuple(a), Type::Tuple(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.accepts(b)),
+            /* `[1, 2, 3]` literals double as array literals, like `options` of inputs */
+            (Type::Array(item), Type::Tuple(items)) => items.iter().all(|t| item.accepts(t)),
+            (a, b) => a == b
+        }
+    }
+
+    /// Common type of two branches (if/else, switch, ternary).
+    pub fn unify(&self, other: &Type) -> Option<Type> {
+        if self.accepts(other) {
+            Some(if *self == Type::Na { other.clone() } else { self.clone() })
+        } else if other.accepts(self) {
+            Some(other.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::Color => write!(f, "color"),
+            Type::Na => write!(f, "na"),
+            Type::Void => write!(f, "void"),
+            Type::Tuple(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Type::Array(item) => write!(f, "array<{}>", item),
+            Type::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Signature of a user defined or builtin function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub params: Vec<(String, Type)>,
+    /// Number of leading parameters without a default value.
+    pub required: usize,
+    pub returns: Type,
+}
@@ -0,0 +1,218 @@
+//! The functions the loader of a WebAssembly module calls, see
+//! `bindings::javascript`. The module links the script with the runtime, so
+//! its runs are those of `Script`, with arguments and replies passed as JSON.
+
+use std::{
+    cell::{Cell, RefCell},
+    ffi::{c_char, CString},
+    mem,
+    rc::Rc,
+    slice,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    abi::{Executable, Exports, ScriptInfo},
+    calendar::{Session, Timezone},
+    error::{Diagnostic, Diagnostics},
+    export,
+    runtime::{Alert, Bar, Tick, LOOP_LIMIT},
+    script::{Params, Script, Stream},
+    security::Feeds,
+};
+
+/* The exports of the script, see `Processor::compile` */
+extern "C" {
+    fn state_size() -> u64;
+    fn init(state: *mut u64, params: *const u64);
+    fn on_bar(state: *mut u64, bar: *const Bar);
+    fn get_outputs(state: *mut u64) -> *const f64;
+    fn save(state: *mut u64);
+    fn restore(state: *mut u64);
+}
+
+/// A stream and the alerts it fired since the loader last asked.
+struct Live {
+    stream: Stream<'static>,
+    alerts: Rc<RefCell<Vec<Alert>>>,
+}
+
+thread_local! {
+    /// The script of the module, see `ninescript_load`.
+    static SCRIPT: Cell<Option<&'static Script>> = const { Cell::new(None) };
+    /// Streams by handle, `None` once freed.
+    static STREAMS: RefCell<Vec<Option<Live>>> = const { RefCell::new(vec![]) };
+    /// The last reply, valid until the next call.
+    static REPLY: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// The arguments of `run()` and `stream()` in the loader.
+#[derive(Deserialize)]
+struct Request {
+    bars: Vec<Bar>,
+    #[serde(default)]
+    inputs: Map<String, Value>,
+    #[serde(default)]
+    feeds: FeedsRequest,
+}
+
+/// `Feeds` as the TypeScript declarations describe them.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct FeedsRequest {
+    symbol: String,
+    timeframe: String,
+    timezone: Option<String>,
+    session: Option<String>,
+    bars: Vec<Feed>,
+}
+
+#[derive(Deserialize)]
+struct Feed {
+    symbol: String,
+    timeframe: String,
+    bars: Vec<Bar>,
+}
+
+fn script() -> Result<&'static Script, String> {
+    SCRIPT.with(Cell::get).ok_or_else(|| "the script is not loaded".to_string())
+}
+
+fn errors(diagnostics: Diagnostics) -> String {
+    diagnostics.iter().map(Diagnostic::to_string).collect::<Vec<_>>().join("\n")
+}
+
+/// Reads the bars, input values and feeds of a run.
+fn request(script: &Script, json: &str) -> Result<(Vec<Bar>, Params, Feeds), String> {
+    let request: Request = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let mut params = script.params();
+    params.set_json(&Value::Object(request.inputs).to_string()).map_err(|e| e.to_string())?;
+    let mut feeds = Feeds::new(&request.feeds.symbol, &request.feeds.timeframe);
+    if let Some(timezone) = &request.feeds.timezone {
+        feeds.timezone = Timezone::parse(timezone).map_err(|e| e.to_string())?;
+    }
+    if let Some(session) = &request.feeds.session {
+        feeds.session = Session::parse(session).map_err(|e| e.to_string())?;
+    }
+    for feed in request.feeds.bars {
+        feeds.insert(&feed.symbol, &feed.timeframe, feed.bars);
+    }
+    Ok((request.bars, params, feeds))
+}
+
+fn with_stream(stream: usize, f: impl FnOnce(&mut Live) -> Result<Value, String>) -> Result<Value, String> {
+    STREAMS.with(|streams| match streams.borrow_mut().get_mut(stream) {
+        Some(Some(live)) => f(live),
+        _ => Err(format!("no stream {}", stream))
+    })
+}
+
+/// `{"ok": value}` or `{"error": message}`, valid until the next call.
+fn reply(result: Result<Value, String>) -> *const c_char {
+    let json = match result {
+        Ok(value) => json!({ "ok": value }),
+        Err(error) => json!({ "error": error }),
+    };
+    REPLY.with(|reply| {
+        let mut reply = reply.borrow_mut();
+        *reply = CString::new(json.to_string()).unwrap_or_default();
+        reply.as_ptr()
+    })
+}
+
+/// A string the loader wrote to memory from `ninescript_alloc`.
+unsafe fn text<'a>(ptr: *const u8, len: usize) -> &'a str {
+    std::str::from_utf8(slice::from_raw_parts(ptr, len)).unwrap_or_default()
+}
+
+/// `len` bytes for the loader to write an argument to, freed with `ninescript_free`.
+#[no_mangle]
+extern "C" fn ninescript_alloc(len: usize) -> *mut u8 {
+    let mut bytes = Vec::<u8>::with_capacity(len);
+    let ptr = bytes.as_mut_ptr();
+    mem::forget(bytes);
+    ptr
+}
+
+#[no_mangle]
+unsafe extern "C" fn ninescript_free(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+/// Loads the script of the module, which `info` describes, see `ScriptInfo`.
+#[no_mangle]
+unsafe extern "C" fn ninescript_load(info: *const u8, len: usize) -> *const c_char {
+    reply(serde_json::from_str::<ScriptInfo>(text(info, len)).map_err(|e| e.to_string()).map(|info| {
+        let exports = Exports { state_size, init, on_bar, get_outputs, save, restore };
+        let script = Script::linked(Executable::new(exports, info, LOOP_LIMIT));
+        SCRIPT.with(|s| s.set(Some(Box::leak(Box::new(script)))));
+        Value::Null
+    }))
+}
+
+/// Runs the script over the bars of `request`, replies with the `Result` of the TypeScript declarations.
+#[no_mangle]
+unsafe extern "C" fn ninescript_run(request: *const u8, len: usize) -> *const c_char {
+    reply(script().and_then(|script| {
+        let (bars, params, feeds) = self::request(script, text(request, len))?;
+        let result = script.run_with(&bars, &params, &feeds).map_err(errors)?;
+        Ok(export::typed(script.outputs(), &result))
+    }))
+}
+
+/// Runs the script over the history of `request`, replies with the handle of the stream.
+#[no_mangle]
+unsafe extern "C" fn ninescript_stream(request: *const u8, len: usize) -> *const c_char {
+    reply(script().and_then(|script| {
+        let (bars, params, feeds) = self::request(script, text(request, len))?;
+        let mut stream = script.stream(&bars, &params, &feeds).map_err(errors)?;
+        let alerts = Rc::new(RefCell::new(vec![]));
+        let fired = alerts.clone();
+        stream.on_alert(move |alert| fired.borrow_mut().push(alert.clone()));
+        STREAMS.with(|streams| {
+            let mut streams = streams.borrow_mut();
+            streams.push(Some(Live { stream, alerts }));
+            Ok(json!(streams.len() - 1))
+        })
+    }))
+}
+
+/// Passes a `Tick` to a stream, replies with the alerts it fired.
+#[no_mangle]
+unsafe extern "C" fn ninescript_tick(stream: usize, tick: *const u8, len: usize) -> *const c_char {
+    reply(with_stream(stream, |live| {
+        let tick: Tick = serde_json::from_str(text(tick, len)).map_err(|e| e.to_string())?;
+        live.stream.on_tick(tick).map_err(errors)?;
+        Ok(json!(live.alerts.take()))
+    }))
+}
+
+/// Closes the last bar of a stream, replies with the alerts it fired.
+#[no_mangle]
+extern "C" fn ninescript_bar_close(stream: usize) -> *const c_char {
+    reply(with_stream(stream, |live| {
+        live.stream.on_bar_close().map_err(errors)?;
+        Ok(json!(live.alerts.take()))
+    }))
+}
+
+#[no_mangle]
+extern "C" fn ninescript_bars(stream: usize) -> *const c_char {
+    reply(with_stream(stream, |live| Ok(json!(live.stream.bars()))))
+}
+
+#[no_mangle]
+extern "C" fn ninescript_stream_result(stream: usize) -> *const c_char {
+    reply(script().and_then(|script| with_stream(stream, |live| Ok(export::typed(script.outputs(), &live.stream.result())))))
+}
+
+#[no_mangle]
+extern "C" fn ninescript_stream_free(stream: usize) {
+    STREAMS.with(|streams| {
+        if let Some(live) = streams.borrow_mut().get_mut(stream) {
+            *live = None;
+        }
+    });
+}
@@ -0,0 +1,149 @@
+#!/usr/bin/env python3
+"""Writes src/timezones.txt from the TZif files of a tz database.
+
+    python3 timezones.py [/usr/share/zoneinfo] > src/timezones.txt
+
+Zones keep the transitions since 1970 their POSIX TZ rule does not reproduce,
+the rule is evaluated like `calendar::Rule` in Rust.
+"""
+
+import datetime
+import os
+import struct
+import sys
+
+HOUR = 3600
+DAY = 24 * HOUR
+END = 2**31
+
+
+def days(year, month, day):
+    return datetime.date(year, month, day).toordinal() - datetime.date(1970, 1, 1).toordinal()
+
+
+def civil_year(day):
+    return datetime.date.fromordinal(day + datetime.date(1970, 1, 1).toordinal()).year
+
+
+def offset(s):
+    sign = -1 if s.startswith("-") else 1
+    total = 0
+    for i, field in enumerate(s.lstrip("+-").split(":")):
+        total += int(field) * [HOUR, 60, 1][i]
+    return sign * total
+
+
+def split_name(s):
+    if s.startswith("<"):
+        return s[s.index(">") + 1:]
+    end = 0
+    while end < len(s) and s[end].isalpha():
+        end += 1
+    return s[end:]
+
+
+def split_offset(s):
+    end = 0
+    while end < len(s) and (s[end].isdigit() or s[end] in "+-:"):
+        end += 1
+    return s[:end], s[end:]
+
+
+def transition(s):
+    date, _, time = s.partition("/")
+    month, week, weekday = (int(f) for f in date[1:].split("."))
+    return month, week, weekday, offset(time) if time else 2 * HOUR
+
+
+def local(rule, year):
+    month, week, weekday, time = rule
+    first = days(year, month, 1)
+    length = days(year + month // 12, month % 12 + 1, 1) - first
+    day = (weekday + 1 - ((first + 4) % 7 + 1)) % 7 + (week - 1) * 7
+    if day >= length:
+        day -= 7
+    return (first + day) * DAY + time
+
+
+def rule(s):
+    standard, s = split_offset(split_name(s))
+    standard = -offset(standard)
+    if not s:
+        return lambda time: standard
+    daylight, s = split_offset(split_name(s))
+    daylight = -offset(daylight) if daylight else standard + HOUR
+    start, end = (transition(t) for t in s[1:].split(","))
+
+    def at(time):
+        year = civil_year((time + standard) // DAY)
+        begin = local(start, year) - standard
+        until = local(end, year) - daylight
+        saving = begin <= time < until if begin < until else time < until or begin <= time
+        return daylight if saving else standard
+    return at
+
+
+def tzif(path):
+    """Transitions as (time, offset) of the 64 bit data of a TZif file, and its footer."""
+    data = open(path, "rb").read()
+    if data[:4] != b"TZif" or data[4] < ord("2"):
+        return None
+    header = ">4x1s15x6l"
+    _, isut, isstd, leap, count, types, chars = struct.unpack_from(header, data)
+    start = 44 + count * 5 + types * 6 + chars + leap * 8 + isstd + isut
+    _, isut, isstd, leap, count, types, chars = struct.unpack_from(header, data, start)
+    start += 44
+    times = struct.unpack_from(">%dq" % count, data, start)
+    indices = data[start + count * 8:start + count * 9]
+    start += count * 9
+    offsets = [struct.unpack_from(">l", data, start + i * 6)[0] for i in range(types)]
+    footer = data.rsplit(b"\n", 2)[1].decode()
+    return [(t, offsets[i]) for t, i in zip(times, indices)], offsets[0], footer
+
+
+def history(transitions, initial, at):
+    """The offset at 1970, the transitions since and the time `at` takes over."""
+    changes = [(-END, initial)] + [t for t in transitions if t[0] < END]
+    until = None
+    for i in reversed(range(len(changes))):
+        begin = max(changes[i][0], 0)
+        end = changes[i + 1][0] if i + 1 < len(changes) else END
+        if end <= 0:
+            break
+        samples = [begin, end - 1] + list(range(begin, end, DAY))
+        if any(at(t) != changes[i][1] for t in samples):
+            break
+        until = begin
+    since = [t for t in changes if 0 < t[0] and (until is None or t[0] < until)]
+    first = [t for t in changes if t[0] <= 0][-1][1]
+    return first, since, until
+
+
+def main(root):
+    print("# Zones of the IANA tz database (version %s) with the POSIX TZ rule they" % version(root))
+    print("# currently follow, the footers of their TZif files. Rules taking over later")
+    print("# than 1970 are preceded by the offset at 1970-01-01, the transitions since as")
+    print("# `time:offset` and the time the rule takes over, in seconds. Written by timezones.py.")
+    names = []
+    for directory, subdirectories, files in os.walk(root):
+        subdirectories[:] = [d for d in subdirectories if d not in ("posix", "right")]
+        names += [os.path.relpath(os.path.join(directory, f), root) for f in files]
+    for name in sorted(names):
+        zone = tzif(os.path.join(root, name))
+        if zone is None or name in ("Factory", "localtime", "posixrules"):
+            continue
+        transitions, initial, footer = zone
+        first, since, until = history(transitions, initial, rule(footer))
+        line = [name, footer]
+        if until != 0:
+            line += [str(first)] + ["%d:%d" % t for t in since] + [str(until if until is not None else END)]
+        print(" ".join(line))
+
+
+def version(root):
+    with open(os.path.join(root, "tzdata.zi")) as f:
+        return f.readline().split()[-1]
+
+
+if __name__ == "__main__":
+    main(sys.argv[1] if len(sys.argv) > 1 else "/usr/share/zoneinfo")